@@ -0,0 +1,58 @@
+#ifndef PORTPICK_H
+#define PORTPICK_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Returned instead of a count when the arguments are invalid or the call failed.
+#define PORTPICK_ERROR -1
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Finds `count` free ports in the default ranges (1024-65535), avoiding the `n_forbidden`
+// ports at `forbidden`, and writes them in ascending order to `out`.
+//
+// Returns how many ports were written: `count`, or fewer (possibly 0) if there aren't
+// enough free ports, or no continuous block of `count` when `continuous` is set. Returns
+// [`PORTPICK_ERROR`] if `out_cap` is less than `count` or a pointer is null.
+//
+// # Safety
+//
+// `forbidden` must point to `n_forbidden` readable values and `out` to `out_cap` writable
+// ones; either may be null when its length is 0.
+intptr_t portpick_find(const uint16_t *forbidden,
+                       size_t n_forbidden,
+                       uint16_t count,
+                       bool continuous,
+                       uint16_t *out,
+                       size_t out_cap);
+
+// Parses the services-file text in the `len` bytes at `content` (`/etc/services` or Nmap
+// format) and writes the TCP ports it names to `out`, in ascending order. Entries named
+// `unknown` are skipped.
+//
+// Returns how many ports the text names, which may exceed `out_cap`; only the first
+// `out_cap` are written, so call again with a larger buffer to get them all. Returns
+// [`PORTPICK_ERROR`] if the text isn't UTF-8 or a pointer is null.
+//
+// # Safety
+//
+// `content` must point to `len` readable bytes and `out` to `out_cap` writable values;
+// either may be null when its length is 0.
+intptr_t portpick_parse_services(const uint8_t *content,
+                                 size_t len,
+                                 uint16_t *out,
+                                 size_t out_cap);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* PORTPICK_H */
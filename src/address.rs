@@ -0,0 +1,112 @@
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+
+/// How far away an address is, for features that only make sense close to home.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressScope {
+    /// This machine: loopback, or the unspecified address, which connects to it
+    Loopback,
+    /// A private, shared (CGNAT), or link-local network
+    Private,
+    Public,
+}
+
+impl fmt::Display for AddressScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AddressScope::Loopback => "loopback",
+            AddressScope::Private => "private",
+            AddressScope::Public => "public",
+        })
+    }
+}
+
+/// Classifies `ip`; IPv4-mapped IPv6 addresses are judged by their IPv4 address.
+pub fn classify_address(ip: IpAddr) -> AddressScope {
+    match ip {
+        IpAddr::V4(ip) => classify_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => classify_v4(mapped),
+            None => classify_v6(ip),
+        },
+    }
+}
+
+fn classify_v4(ip: Ipv4Addr) -> AddressScope {
+    let [first, second, ..] = ip.octets();
+    if ip.is_loopback() || ip.is_unspecified() {
+        AddressScope::Loopback
+    } else if ip.is_private() || ip.is_link_local() || (first == 100 && (64..128).contains(&second))
+    {
+        AddressScope::Private
+    } else {
+        AddressScope::Public
+    }
+}
+
+fn classify_v6(ip: Ipv6Addr) -> AddressScope {
+    let first = ip.segments()[0];
+    if ip.is_loopback() || ip.is_unspecified() {
+        AddressScope::Loopback
+    } else if first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80 {
+        // Unique local (fc00::/7) and link-local (fe80::/10)
+        AddressScope::Private
+    } else {
+        AddressScope::Public
+    }
+}
+
+/// A host name or address together with the addresses it resolved to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedHost {
+    pub host: String,
+    /// Never empty, in resolver order without duplicates.
+    pub addresses: Vec<IpAddr>,
+}
+
+impl ResolvedHost {
+    /// The address to scan: the first IPv4 one if there is any, since that is what `localhost`
+    /// listeners are most often bound to, else the first.
+    pub fn primary(&self) -> IpAddr {
+        self.addresses
+            .iter()
+            .copied()
+            .find(IpAddr::is_ipv4)
+            .unwrap_or(self.addresses[0])
+    }
+
+    pub fn scope(&self) -> AddressScope {
+        classify_address(self.primary())
+    }
+}
+
+/// Resolves `host` with the system resolver; a literal address is taken as is. Fails, naming
+/// `host`, when it doesn't resolve to anything.
+pub fn resolve_host(host: &str) -> Result<ResolvedHost> {
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = literal.parse::<IpAddr>() {
+        return Ok(ResolvedHost {
+            host: host.to_string(),
+            addresses: vec![ip],
+        });
+    }
+    let mut addresses: Vec<IpAddr> = Vec::new();
+    for address in (host, 0)
+        .to_socket_addrs()
+        .with_context(|| format!("Could not resolve '{}'", host))?
+    {
+        if !addresses.contains(&address.ip()) {
+            addresses.push(address.ip());
+        }
+    }
+    if addresses.is_empty() {
+        bail!("Could not resolve '{}': no addresses found", host);
+    }
+    Ok(ResolvedHost {
+        host: host.to_string(),
+        addresses,
+    })
+}
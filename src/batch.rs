@@ -0,0 +1,197 @@
+use crate::project::ports_near;
+use crate::{DEFAULT_PORT_RANGES, find_available_ports_in, parse_port_spec, validate_request};
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// One pick read by `portpick batch`, from a mini-syntax line such as
+/// `3c within=9000-9999 name=cluster` or the same as a JSON object.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchRequest {
+    #[serde(default = "one_port")]
+    pub count: u16,
+    #[serde(default)]
+    pub continuous: bool,
+    /// Port spec to search [default: 1024-65535].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub within: Option<String>,
+    /// Prefer the free ports closest to this one instead of the lowest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub near: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+fn one_port() -> u16 {
+    1
+}
+
+impl Default for BatchRequest {
+    fn default() -> Self {
+        BatchRequest {
+            count: 1,
+            continuous: false,
+            within: None,
+            near: None,
+            name: None,
+        }
+    }
+}
+
+impl BatchRequest {
+    /// Parses one input line; blank lines and `#` comments give `None`.
+    ///
+    /// Lines starting with `{` are JSON. Otherwise the line is whitespace-separated words: an
+    /// optional count first (`3`, or `3c` for a continuous block), then any of `within=SPEC`,
+    /// `near=PORT`, and `name=NAME`.
+    pub fn parse_line(line: &str) -> Result<Option<Self>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let request = if line.starts_with('{') {
+            serde_json::from_str(line).context("Invalid JSON request")?
+        } else {
+            let mut request = BatchRequest::default();
+            for (index, word) in line.split_whitespace().enumerate() {
+                match word.split_once('=') {
+                    Some(("within", spec)) => request.within = Some(spec.to_string()),
+                    Some(("near", port)) => {
+                        request.near = Some(
+                            u16::from_str(port)
+                                .with_context(|| format!("Invalid near port '{}'", port))?,
+                        )
+                    }
+                    Some(("name", name)) => request.name = Some(name.to_string()),
+                    Some((key, _)) => {
+                        bail!("Unknown key '{}' (expected within=, near=, or name=)", key)
+                    }
+                    None if index == 0 => {
+                        let (count, continuous) = match word.strip_suffix('c') {
+                            Some(count) => (count, true),
+                            None => (word, false),
+                        };
+                        request.count = u16::from_str(count)
+                            .with_context(|| format!("Invalid count '{}'", word))?;
+                        request.continuous = continuous;
+                    }
+                    None => bail!("Unexpected '{}'; only the count may stand alone", word),
+                }
+            }
+            request
+        };
+        request.validate()?;
+        Ok(Some(request))
+    }
+
+    /// The search ranges: `within`, or `default_ranges`.
+    pub fn ranges(&self, default_ranges: &[(u16, u16)]) -> Result<Vec<(u16, u16)>> {
+        match &self.within {
+            Some(spec) => parse_port_spec(spec).context("Invalid within"),
+            None => Ok(default_ranges.to_vec()),
+        }
+    }
+
+    /// Rejects requests no set of free ports could satisfy.
+    pub fn validate(&self) -> Result<()> {
+        if self.count == 0 {
+            bail!("Asks for 0 ports");
+        }
+        validate_request(
+            self.count,
+            self.continuous,
+            &self.ranges(&DEFAULT_PORT_RANGES)?,
+        )?;
+        Ok(())
+    }
+}
+
+/// The outcome of one line of a batch: its ports, or why it got none.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchResult {
+    /// 1-based line number in the input.
+    pub line: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BatchResult {
+    pub fn failed(line: usize, name: Option<String>, error: impl Into<String>) -> Self {
+        BatchResult {
+            line,
+            name,
+            ports: Vec::new(),
+            error: Some(error.into()),
+        }
+    }
+}
+
+/// Picks ports for every request in order, each avoiding `forbidden_ports` and the ports given
+/// to earlier requests; requests without `within` search `default_ranges`. Lines that didn't
+/// parse, and requests that can't be met, become error results; the rest of the batch is still
+/// resolved.
+pub fn resolve_batch(
+    requests: &[(usize, Result<BatchRequest, String>)],
+    forbidden_ports: &HashSet<u16>,
+    default_ranges: &[(u16, u16)],
+) -> Vec<BatchResult> {
+    let mut forbidden = forbidden_ports.clone();
+    let mut results = Vec::with_capacity(requests.len());
+    for (line, request) in requests {
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => {
+                results.push(BatchResult::failed(*line, None, e.clone()));
+                continue;
+            }
+        };
+        let picked = request.ranges(default_ranges).and_then(|ranges| {
+            validate_request(request.count, request.continuous, &ranges)?;
+            match request.near {
+                Some(anchor) => Ok(ports_near(
+                    &forbidden,
+                    request.count,
+                    request.continuous,
+                    &ranges,
+                    anchor,
+                )),
+                None => {
+                    find_available_ports_in(&forbidden, request.count, request.continuous, &ranges)
+                }
+            }
+        });
+        let result = match picked {
+            Ok(ports) if ports.len() >= usize::from(request.count) => {
+                forbidden.extend(&ports);
+                BatchResult {
+                    line: *line,
+                    name: request.name.clone(),
+                    ports,
+                    error: None,
+                }
+            }
+            Ok(_) => BatchResult::failed(
+                *line,
+                request.name.clone(),
+                format!(
+                    "Could not find {} {}port(s)",
+                    request.count,
+                    if request.continuous {
+                        "continuous "
+                    } else {
+                        ""
+                    }
+                ),
+            ),
+            Err(e) => BatchResult::failed(*line, request.name.clone(), format!("{:#}", e)),
+        };
+        results.push(result);
+    }
+    results
+}
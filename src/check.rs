@@ -0,0 +1,90 @@
+use crate::{DEFAULT_PORT_RANGES, PortSet, Provenance, RejectionReason};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Why a specific port can't be suggested.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BlockReason {
+    /// Listed by a services source; `names` is empty when the names weren't kept.
+    KnownService { names: Vec<String> },
+    /// Reported as listening by the local port scan.
+    LocallyInUse,
+    /// Ruled out explicitly by the caller.
+    Excluded,
+    /// Not inside any of the search ranges.
+    OutOfRange,
+}
+
+/// Whether a port could be suggested, and if not, every reason why not.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PortStatus {
+    Free,
+    Blocked(Vec<BlockReason>),
+}
+
+impl PortStatus {
+    pub fn is_free(&self) -> bool {
+        matches!(self, PortStatus::Free)
+    }
+}
+
+/// Everything [`check_port`] consults: where ports were forbidden from, what the services
+/// sources call them, explicit exclusions, and the ranges suggestions come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForbiddenContext {
+    pub provenance: Provenance,
+    pub service_names: HashMap<u16, Vec<String>>,
+    pub excluded: PortSet,
+    pub ranges: Vec<(u16, u16)>,
+}
+
+impl ForbiddenContext {
+    /// A context with nothing forbidden yet, searching `ranges`.
+    pub fn new(ranges: &[(u16, u16)]) -> Self {
+        ForbiddenContext {
+            provenance: Provenance::default(),
+            service_names: HashMap::new(),
+            excluded: PortSet::new(),
+            ranges: ranges.to_vec(),
+        }
+    }
+}
+
+impl Default for ForbiddenContext {
+    fn default() -> Self {
+        Self::new(&DEFAULT_PORT_RANGES)
+    }
+}
+
+/// Checks a single port against `ctx`, collecting every reason it is blocked.
+///
+/// Reasons are reported in a fixed order: out of range, known service, locally in use,
+/// excluded.
+pub fn check_port(port: u16, ctx: &ForbiddenContext) -> PortStatus {
+    let mut reasons = Vec::new();
+    if !ctx
+        .ranges
+        .iter()
+        .any(|&(start, end)| (start..=end).contains(&port))
+    {
+        reasons.push(BlockReason::OutOfRange);
+    }
+    for reason in ctx.provenance.reasons(port) {
+        reasons.push(match reason {
+            RejectionReason::KnownService => BlockReason::KnownService {
+                names: ctx.service_names.get(&port).cloned().unwrap_or_default(),
+            },
+            RejectionReason::LocallyInUse => BlockReason::LocallyInUse,
+        });
+    }
+    if ctx.excluded.contains(port) {
+        reasons.push(BlockReason::Excluded);
+    }
+    if reasons.is_empty() {
+        PortStatus::Free
+    } else {
+        PortStatus::Blocked(reasons)
+    }
+}
@@ -0,0 +1,637 @@
+use crate::{
+    BUILTIN_PROFILES, BUILTIN_SERVICE_PORTS, ForbiddenContext, PlannedSource, PortSet,
+    RejectionReason, Reporter, WarningCode, is_interrupted,
+};
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::panic::resume_unwind;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Somewhere forbidden ports come from: a services list, a port scan, or a test's fixed set.
+///
+/// Sources are `Send` because [`collect_forbidden`] runs them all at once.
+pub trait PortSource: Send {
+    fn name(&self) -> &str;
+
+    /// What a port from this source is blocked as.
+    fn rejection_reason(&self) -> RejectionReason;
+
+    /// The ports this source forbids. Sources that know what a port is called add the names
+    /// to `names`.
+    fn collect(&mut self, names: &mut HashMap<u16, Vec<String>>) -> Result<HashSet<u16>>;
+
+    /// Whether this source is a list of known services, counted toward
+    /// [`CollectOptions::min_known_ports`].
+    fn lists_services(&self) -> bool {
+        false
+    }
+
+    /// Where this source would read from, for a dry run; it must not read it.
+    fn plan(&self) -> PlannedSource {
+        PlannedSource {
+            name: self.name().to_string(),
+            location: None,
+            cache: None,
+        }
+    }
+
+    /// Sources tried before this one's ports were taken, as by a [`SourceChain`].
+    fn passed_over(&self) -> &[String] {
+        &[]
+    }
+}
+
+/// [`BUILTIN_SERVICE_PORTS`], the last resort when no services list can be read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BuiltinServicesSource;
+
+impl PortSource for BuiltinServicesSource {
+    fn name(&self) -> &str {
+        "builtin"
+    }
+
+    fn rejection_reason(&self) -> RejectionReason {
+        RejectionReason::KnownService
+    }
+
+    fn collect(&mut self, names: &mut HashMap<u16, Vec<String>>) -> Result<HashSet<u16>> {
+        for &(port, name) in BUILTIN_SERVICE_PORTS {
+            names.entry(port).or_default().push(name.to_string());
+        }
+        Ok(BUILTIN_SERVICE_PORTS
+            .iter()
+            .map(|&(port, _)| port)
+            .collect())
+    }
+
+    fn lists_services(&self) -> bool {
+        true
+    }
+
+    fn plan(&self) -> PlannedSource {
+        PlannedSource::new(
+            self.name(),
+            format!("built-in list of {} ports", BUILTIN_SERVICE_PORTS.len()),
+        )
+    }
+}
+
+/// The ports of built-in `--profile`s, named after their labels, e.g. `vite dev server`
+/// for 5173 with `devtools`. Several profiles make one source; a port in more than one is
+/// forbidden once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileSource {
+    profiles: Vec<&'static str>,
+    ports: Vec<(u16, &'static str)>,
+}
+
+impl ProfileSource {
+    /// The source for `profiles`, in order; an unknown name is an error listing the built-in
+    /// ones.
+    pub fn new<S: AsRef<str>>(profiles: &[S]) -> Result<Self> {
+        let mut source = ProfileSource {
+            profiles: Vec::new(),
+            ports: Vec::new(),
+        };
+        for profile in profiles {
+            let profile = profile.as_ref();
+            let Some((name, ports)) = BUILTIN_PROFILES.iter().find(|(name, _)| *name == profile)
+            else {
+                bail!(
+                    "Unknown profile '{}' (built-in profiles: {})",
+                    profile,
+                    BUILTIN_PROFILES
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            };
+            if !source.profiles.contains(name) {
+                source.profiles.push(name);
+                source.ports.extend(ports.iter().copied());
+            }
+        }
+        Ok(source)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ports.is_empty()
+    }
+
+    /// The profiles' ports, each once.
+    pub fn ports(&self) -> impl Iterator<Item = u16> + '_ {
+        self.ports.iter().map(|&(port, _)| port)
+    }
+}
+
+impl PortSource for ProfileSource {
+    fn name(&self) -> &str {
+        "profile"
+    }
+
+    fn rejection_reason(&self) -> RejectionReason {
+        RejectionReason::KnownService
+    }
+
+    fn collect(&mut self, names: &mut HashMap<u16, Vec<String>>) -> Result<HashSet<u16>> {
+        for &(port, label) in &self.ports {
+            let known = names.entry(port).or_default();
+            if !known.iter().any(|name| name == label) {
+                known.push(label.to_string());
+            }
+        }
+        Ok(self.ports().collect())
+    }
+
+    fn plan(&self) -> PlannedSource {
+        PlannedSource::new(
+            self.name(),
+            format!(
+                "profiles {} ({} ports)",
+                self.profiles.join(", "),
+                self.ports().collect::<PortSet>().len()
+            ),
+        )
+    }
+}
+
+/// Sources tried one at a time, in order, until one gives at least one port; that one's ports
+/// are all the chain forbids, and the rest never run. A source that fails or finds nothing is
+/// warned about while another is left to try.
+///
+/// Before it runs the chain is named `source-order`; afterwards it takes the name of the
+/// source that supplied the ports, and [`PortSource::passed_over`] lists those tried before.
+pub struct SourceChain<'a> {
+    links: Vec<Box<dyn PortSource + 'a>>,
+    reporter: Arc<Reporter>,
+    supplier: Option<usize>,
+    passed_over: Vec<String>,
+}
+
+impl<'a> SourceChain<'a> {
+    pub fn new(links: Vec<Box<dyn PortSource + 'a>>, reporter: Arc<Reporter>) -> Self {
+        SourceChain {
+            links,
+            reporter,
+            supplier: None,
+            passed_over: Vec::new(),
+        }
+    }
+
+    /// The names of the sources, in the order they are tried.
+    pub fn order(&self) -> Vec<&str> {
+        self.links.iter().map(|link| link.name()).collect()
+    }
+}
+
+impl PortSource for SourceChain<'_> {
+    fn name(&self) -> &str {
+        match self.supplier {
+            Some(index) => self.links[index].name(),
+            None => "source-order",
+        }
+    }
+
+    fn rejection_reason(&self) -> RejectionReason {
+        self.supplier
+            .or((!self.links.is_empty()).then_some(0))
+            .map(|index| self.links[index].rejection_reason())
+            .unwrap_or(RejectionReason::KnownService)
+    }
+
+    fn collect(&mut self, names: &mut HashMap<u16, Vec<String>>) -> Result<HashSet<u16>> {
+        let mut problems = Vec::new();
+        for index in 0..self.links.len() {
+            let link = &mut self.links[index];
+            // Only the supplier's names are kept, not those of a list that failed halfway.
+            let mut found = HashMap::new();
+            let problem = match link.collect(&mut found) {
+                Ok(ports) if !ports.is_empty() => {
+                    names.extend(found);
+                    self.supplier = Some(index);
+                    return Ok(ports);
+                }
+                Ok(_) => format!("'{}' found no ports", link.name()),
+                Err(e) => format!("'{}' failed: {:#}", link.name(), e),
+            };
+            if let Some(next) = self.links.get(index + 1) {
+                self.reporter.warn_with(
+                    WarningCode::SourceFallback,
+                    format!("Source {}; trying '{}'.", problem, next.name()),
+                );
+            }
+            self.passed_over.push(self.links[index].name().to_string());
+            problems.push(problem);
+        }
+        bail!(
+            "No source in the order {} gave any ports ({})",
+            self.order().join(", "),
+            problems.join("; ")
+        )
+    }
+
+    fn lists_services(&self) -> bool {
+        self.links.iter().any(|link| link.lists_services())
+    }
+
+    fn plan(&self) -> PlannedSource {
+        let links: Vec<String> = self
+            .links
+            .iter()
+            .map(|link| {
+                let plan = link.plan();
+                match plan.location {
+                    Some(location) => format!("{} {}", plan.name, location),
+                    None => plan.name,
+                }
+            })
+            .collect();
+        PlannedSource::new(self.name(), links.join(", then "))
+    }
+
+    fn passed_over(&self) -> &[String] {
+        &self.passed_over
+    }
+}
+
+/// What [`collect_forbidden`] gathers from and how.
+#[derive(Default)]
+pub struct CollectOptions<'a> {
+    /// Sources to run, each on its own thread; their results are combined in this order.
+    /// Disabled sources should be left out.
+    pub sources: Vec<Box<dyn PortSource + 'a>>,
+    /// Also forbid every port within this distance of a named service port.
+    pub avoid_family: u16,
+    /// Sources not started by then are skipped, and a source failing after it counts as
+    /// timed out rather than as an error.
+    pub deadline: Option<Instant>,
+    /// Tried one at a time, in order, once the sources have run, until the services lists
+    /// come to `min_known_ports`; each one tried is warned about.
+    pub fallbacks: Vec<Box<dyn PortSource + 'a>>,
+    /// Fewer known-service ports than this, after the fallbacks, is a [`TooFewKnownPorts`]
+    /// error. When set, a failing services list is warned about instead of ending collection.
+    /// 0 turns the check off.
+    pub min_known_ports: usize,
+}
+
+/// The services lists came to fewer ports than [`CollectOptions::min_known_ports`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TooFewKnownPorts {
+    pub found: usize,
+    pub min: usize,
+    /// What went wrong with each services list, e.g. `'system' found no ports`.
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for TooFewKnownPorts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The services lists gave {} known-service port(s), fewer than the {} required",
+            self.found, self.min
+        )?;
+        if !self.problems.is_empty() {
+            write!(f, " ({})", self.problems.join("; "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TooFewKnownPorts {}
+
+/// How many ports one source contributed, before deduplication.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SourceCount {
+    pub name: String,
+    pub ports: usize,
+    /// Sources of a `--source-order` chain tried before this one, which failed or found
+    /// nothing.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub passed_over: Vec<String>,
+}
+
+/// How many ports two sources both forbade.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SharedPorts {
+    pub first: String,
+    pub second: String,
+    pub ports: usize,
+}
+
+/// For `-v`, e.g. `nmap and system share 289 ports`.
+impl fmt::Display for SharedPorts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} and {} share {} port{}",
+            self.first,
+            self.second,
+            self.ports,
+            if self.ports == 1 { "" } else { "s" }
+        )
+    }
+}
+
+/// Where the forbidden ports came from: per-source counts, and how many ports more than one
+/// source forbade.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SourceBreakdown {
+    /// Sources that ran, in order, followed by `avoid-family` when that is set.
+    pub sources: Vec<SourceCount>,
+    /// The sum of the per-source counts minus `total`.
+    pub overlap: usize,
+    /// Each pair of sources that forbade some of the same ports, in source order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shared: Vec<SharedPorts>,
+    pub total: usize,
+    /// Sources skipped because of Ctrl-C.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<String>,
+    /// The source that was running or due when the deadline passed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timed_out_during: Option<String>,
+}
+
+impl SourceBreakdown {
+    /// One line such as `system 312, nmap 14203, local 37; overlap 295; total 14257`, with
+    /// e.g. `cache 27000 (after nmap)` for a source a chain fell through to.
+    pub fn summary(&self) -> String {
+        let sources: Vec<String> = self
+            .sources
+            .iter()
+            .map(|source| match source.passed_over.is_empty() {
+                true => format!("{} {}", source.name, source.ports),
+                false => format!(
+                    "{} {} (after {})",
+                    source.name,
+                    source.ports,
+                    source.passed_over.join(", ")
+                ),
+            })
+            .collect();
+        format!(
+            "{}; overlap {}; total {}",
+            if sources.is_empty() {
+                "no sources".to_string()
+            } else {
+                sources.join(", ")
+            },
+            self.overlap,
+            self.total
+        )
+    }
+}
+
+/// Runs every source in `opts` concurrently, recording in `ctx` why each port is forbidden and
+/// what the sources call it.
+///
+/// Results are taken in source order. A source error is returned as is, unless the deadline
+/// has passed by then; in that case, and when the deadline passed before the sources started,
+/// collection stops and the breakdown names the source in `timed_out_during`.
+///
+/// With `min_known_ports` set, the fallbacks are then tried while the services lists are
+/// short of it, and an error names what each list lacked if they still are.
+pub fn collect_forbidden(
+    opts: CollectOptions,
+    ctx: &mut ForbiddenContext,
+    reporter: &Reporter,
+) -> Result<(PortSet, SourceBreakdown)> {
+    let deadline_passed = || {
+        opts.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    };
+    let mut breakdown = SourceBreakdown::default();
+    let mut to_run = Vec::new();
+    for source in opts.sources {
+        let name = source.name().to_string();
+        if is_interrupted() {
+            reporter.info(format!("Provider '{}': skipped after Ctrl-C", name));
+            breakdown.skipped.push(name);
+            continue;
+        }
+        if deadline_passed() {
+            breakdown.timed_out_during = Some(name);
+            break;
+        }
+        to_run.push(source);
+    }
+
+    // Sources don't depend on each other, so a download need not wait for a scan.
+    let started = Instant::now();
+    let results: Vec<_> = std::thread::scope(|scope| {
+        let running: Vec<_> = to_run
+            .into_iter()
+            .map(|mut source| {
+                scope.spawn(move || {
+                    let started = Instant::now();
+                    let mut names = HashMap::new();
+                    let ports = source.collect(&mut names);
+                    (source, ports, names, started.elapsed())
+                })
+            })
+            .collect();
+        running
+            .into_iter()
+            .map(|source| source.join().unwrap_or_else(|panic| resume_unwind(panic)))
+            .collect()
+    });
+    if results.len() > 1 {
+        reporter.info(format!(
+            "Ran {} providers in parallel in {:.1}s ({:.1}s one after another).",
+            results.len(),
+            started.elapsed().as_secs_f64(),
+            results
+                .iter()
+                .map(|(_, _, _, took)| took.as_secs_f64())
+                .sum::<f64>()
+        ));
+    }
+
+    let checked = opts.min_known_ports > 0;
+    let mut tally = Tally {
+        forbidden: PortSet::new(),
+        sets: Vec::new(),
+        breakdown,
+        known_ports: HashSet::new(),
+        problems: Vec::new(),
+        checked,
+    };
+    for (source, ports, names, took) in results {
+        if let Err(e) = &ports
+            && deadline_passed()
+        {
+            reporter.verbose_warn(format!("Warning: {:#}", e));
+            tally.breakdown.timed_out_during = Some(source.name().to_string());
+            break;
+        }
+        tally.record(source.as_ref(), ports, names, took, ctx, reporter)?;
+    }
+
+    // A timed-out or interrupted run already carries on with whatever it has.
+    if checked && tally.breakdown.timed_out_during.is_none() && !is_interrupted() {
+        for mut fallback in opts.fallbacks {
+            let found = tally.known_ports.len();
+            if found >= opts.min_known_ports {
+                break;
+            }
+            reporter.warn_with(
+                WarningCode::SourceFallback,
+                format!(
+                    "The services lists gave {} known-service port(s); falling back to '{}'.",
+                    found,
+                    fallback.name()
+                ),
+            );
+            let started = Instant::now();
+            let mut names = HashMap::new();
+            let ports = fallback.collect(&mut names);
+            tally.record(
+                fallback.as_ref(),
+                ports,
+                names,
+                started.elapsed(),
+                ctx,
+                reporter,
+            )?;
+        }
+        if tally.known_ports.len() < opts.min_known_ports {
+            return Err(TooFewKnownPorts {
+                found: tally.known_ports.len(),
+                min: opts.min_known_ports,
+                problems: tally.problems,
+            }
+            .into());
+        }
+    }
+    let Tally {
+        mut forbidden,
+        mut breakdown,
+        sets,
+        ..
+    } = tally;
+    for (i, (first, first_ports)) in sets.iter().enumerate() {
+        for (second, second_ports) in &sets[i + 1..] {
+            let ports = first_ports.overlap(second_ports);
+            if ports > 0 {
+                breakdown.shared.push(SharedPorts {
+                    first: first.clone(),
+                    second: second.clone(),
+                    ports,
+                });
+            }
+        }
+    }
+
+    if opts.avoid_family > 0 {
+        let named: PortSet = ctx.service_names.keys().copied().collect();
+        let family = named.expand(opts.avoid_family);
+        reporter.info(format!(
+            "--avoid-family {}: {} named service ports widen to {} ports in {} ranges.",
+            opts.avoid_family,
+            named.len(),
+            family.len(),
+            family.ranges().count()
+        ));
+        ctx.provenance
+            .record_all(&family.to_hash_set(), RejectionReason::KnownService);
+        breakdown.sources.push(SourceCount {
+            name: "avoid-family".to_string(),
+            ports: family.len(),
+            passed_over: Vec::new(),
+        });
+        forbidden = forbidden.union(&family);
+    }
+
+    breakdown.total = forbidden.len();
+    breakdown.overlap = breakdown
+        .sources
+        .iter()
+        .map(|source| source.ports)
+        .sum::<usize>()
+        - breakdown.total;
+    Ok((forbidden, breakdown))
+}
+
+/// What [`collect_forbidden`] has gathered so far.
+struct Tally {
+    forbidden: PortSet,
+    /// Each source's ports, for [`SourceBreakdown::shared`].
+    sets: Vec<(String, PortSet)>,
+    breakdown: SourceBreakdown,
+    /// Ports from sources that list services.
+    known_ports: HashSet<u16>,
+    /// What each services list lacked, for [`TooFewKnownPorts`].
+    problems: Vec<String>,
+    /// Whether a failing services list is a warning rather than an error.
+    checked: bool,
+}
+
+impl Tally {
+    /// Adds one source's result, recording in `ctx` why its ports are forbidden and what
+    /// they are called.
+    fn record(
+        &mut self,
+        source: &dyn PortSource,
+        ports: Result<HashSet<u16>>,
+        names: HashMap<u16, Vec<String>>,
+        took: Duration,
+        ctx: &mut ForbiddenContext,
+        reporter: &Reporter,
+    ) -> Result<()> {
+        let name = source.name().to_string();
+        reporter.record_timing(name.clone(), took);
+        let ports = match ports {
+            Ok(ports) => ports,
+            Err(e) if self.checked && source.lists_services() => {
+                reporter.warn_with(
+                    WarningCode::SourceUnavailable,
+                    format!("Provider '{}' failed: {:#}", name, e),
+                );
+                self.problems.push(format!("'{}' failed: {:#}", name, e));
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        reporter.info(format!(
+            "Provider '{}': enabled, {} ports ({:.1}s){}",
+            name,
+            ports.len(),
+            took.as_secs_f64(),
+            match source.passed_over() {
+                [] => String::new(),
+                passed_over => format!(", after {} gave none", passed_over.join(", ")),
+            }
+        ));
+        for (port, port_names) in names {
+            let known = ctx.service_names.entry(port).or_default();
+            for name in port_names {
+                if !known.contains(&name) {
+                    known.push(name);
+                }
+            }
+        }
+        ctx.provenance.record_all(&ports, source.rejection_reason());
+        if source.lists_services() {
+            if ports.is_empty() {
+                self.problems.push(format!("'{}' found no ports", name));
+            }
+            self.known_ports.extend(ports.iter().copied());
+        }
+        let set = PortSet::from(&ports);
+        self.forbidden = self.forbidden.union(&set);
+        self.breakdown.sources.push(SourceCount {
+            name: name.clone(),
+            ports: ports.len(),
+            passed_over: source.passed_over().to_vec(),
+        });
+        self.sets.push((name, set));
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use anyhow::{Context, Result, bail};
+use serde_yaml::{Mapping, Value};
+
+/// File name docker compose merges over `docker-compose.yml` without being told to.
+pub const COMPOSE_OVERRIDE_FILE: &str = "docker-compose.override.yml";
+
+/// The services a compose file defines, in file order.
+pub fn compose_service_names(content: &str) -> Result<Vec<String>> {
+    let document: Value =
+        serde_yaml::from_str(content).context("Failed to parse the compose file")?;
+    let Some(services) = document.get("services") else {
+        bail!("The compose file has no services");
+    };
+    let Some(services) = services.as_mapping() else {
+        bail!("'services' in the compose file is not a mapping");
+    };
+    Ok(services
+        .keys()
+        .filter_map(|key| key.as_str().map(str::to_string))
+        .collect())
+}
+
+/// The container port a `ports` entry publishes, in short (`"8080:80"`, `"127.0.0.1:8080:80/tcp"`,
+/// `80`) or long (`target: 80`) syntax. `None` for port ranges and entries it can't read.
+fn published_container_port(entry: &Value) -> Option<u16> {
+    match entry {
+        Value::Number(number) => number.as_u64().and_then(|port| u16::try_from(port).ok()),
+        Value::String(mapping) => {
+            let mapping = mapping.split('/').next()?;
+            mapping.rsplit(':').next()?.parse().ok()
+        }
+        Value::Mapping(mapping) => published_container_port(mapping.get("target")?),
+        _ => None,
+    }
+}
+
+/// The `ports` entries of `service` in an existing override file that already publish one of
+/// `container_ports`, which writing new mappings for them would silently replace.
+pub fn override_conflicts(
+    existing: &str,
+    service: &str,
+    container_ports: &[u16],
+) -> Result<Vec<String>> {
+    let document = parse_override(existing)?;
+    let Some(ports) = document
+        .get("services")
+        .and_then(|services| services.get(service))
+        .and_then(|service| service.get("ports"))
+        .and_then(Value::as_sequence)
+    else {
+        return Ok(Vec::new());
+    };
+    Ok(ports
+        .iter()
+        .filter(|entry| {
+            published_container_port(entry).is_some_and(|port| container_ports.contains(&port))
+        })
+        .map(|entry| match entry {
+            Value::String(mapping) => mapping.clone(),
+            other => serde_yaml::to_string(other)
+                .unwrap_or_default()
+                .trim_end()
+                .replace('\n', " "),
+        })
+        .collect())
+}
+
+fn parse_override(content: &str) -> Result<Value> {
+    let document: Value =
+        serde_yaml::from_str(content).context("Failed to parse the override file")?;
+    match document {
+        // An empty file parses as null.
+        Value::Null => Ok(Value::Mapping(Mapping::new())),
+        Value::Mapping(_) => Ok(document),
+        _ => bail!("The override file is not a mapping"),
+    }
+}
+
+/// Quotes a list item that is a bare short-syntax port mapping such as `- 8080:22`, which
+/// YAML 1.1 parsers read as a base-60 number.
+fn quote_port_mapping(line: &str) -> String {
+    let Some((indent, item)) = line.split_once("- ") else {
+        return line.to_string();
+    };
+    let ports = item.split('/').next().unwrap_or(item);
+    let is_mapping = indent.trim().is_empty()
+        && ports.contains(':')
+        && ports
+            .split(':')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()));
+    if is_mapping {
+        format!("{}- \"{}\"", indent, item)
+    } else {
+        line.to_string()
+    }
+}
+
+/// A compose override file publishing `mappings` (host port, container port) for `service`.
+///
+/// With `existing`, the mappings are merged into that override: every other key, service,
+/// and `ports` entry is kept. Entries that already publish one of the container ports are an
+/// error unless `overwrite` is set, which replaces them. Comments in `existing` are not kept.
+pub fn merge_compose_override(
+    existing: Option<&str>,
+    service: &str,
+    mappings: &[(u16, u16)],
+    overwrite: bool,
+) -> Result<String> {
+    let container_ports: Vec<u16> = mappings.iter().map(|&(_, container)| container).collect();
+    let mut document = match existing {
+        Some(existing) => {
+            let conflicts = override_conflicts(existing, service, &container_ports)?;
+            if !conflicts.is_empty() && !overwrite {
+                bail!(
+                    "Service '{}' already publishes {}; pass --overwrite to replace {}.",
+                    service,
+                    conflicts.join(", "),
+                    if conflicts.len() == 1 { "it" } else { "them" }
+                );
+            }
+            parse_override(existing)?
+        }
+        None => Value::Mapping(Mapping::new()),
+    };
+
+    let root = document
+        .as_mapping_mut()
+        .context("The override file is not a mapping")?;
+    let services = entry_mapping(root, "services")?;
+    let service_entry = entry_mapping(services, service)?;
+    let ports = service_entry
+        .entry(Value::from("ports"))
+        .or_insert_with(|| Value::Sequence(Vec::new()));
+    if ports.is_null() {
+        *ports = Value::Sequence(Vec::new());
+    }
+    let ports = ports
+        .as_sequence_mut()
+        .with_context(|| format!("'ports' of service '{}' is not a list", service))?;
+    ports.retain(|entry| {
+        published_container_port(entry).is_none_or(|port| !container_ports.contains(&port))
+    });
+    ports.extend(
+        mappings
+            .iter()
+            .map(|(host, container)| Value::from(format!("{}:{}", host, container))),
+    );
+
+    let rendered = serde_yaml::to_string(&document)?;
+    Ok(rendered
+        .lines()
+        .map(|line| quote_port_mapping(line) + "\n")
+        .collect())
+}
+
+/// The mapping under `key` in `parent`, created if missing or null.
+fn entry_mapping<'a>(parent: &'a mut Mapping, key: &str) -> Result<&'a mut Mapping> {
+    let value = parent
+        .entry(Value::from(key))
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    if value.is_null() {
+        *value = Value::Mapping(Mapping::new());
+    }
+    value
+        .as_mapping_mut()
+        .with_context(|| format!("'{}' in the override file is not a mapping", key))
+}
@@ -0,0 +1,177 @@
+use crate::{BUILTIN_PROFILES, parse_http_header, parse_port_spec};
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Environment variable naming the config file, used when `--config` isn't given.
+pub const CONFIG_FILE_ENV: &str = "PORTPICK_CONFIG";
+
+/// `$XDG_CONFIG_HOME/portpick`, falling back to `~/.config/portpick`.
+pub fn config_dir() -> Option<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("portpick"))
+}
+
+/// The config file read when neither `--config` nor [`CONFIG_FILE_ENV`] names one:
+/// `config.toml` in [`config_dir`].
+pub fn default_config_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.toml"))
+}
+
+/// A portpick `config.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Named profiles, activated with `--profile NAME`.
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    /// How the Nmap services list is downloaded.
+    pub http: HttpConfig,
+}
+
+/// The `[http]` table.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// `User-Agent` in place of [`DEFAULT_USER_AGENT`](crate::DEFAULT_USER_AGENT), as for
+    /// `--user-agent`.
+    pub user_agent: Option<String>,
+    /// Request headers as `Name: value`, as for `--http-header`.
+    pub headers: Vec<String>,
+}
+
+/// One `[profiles.NAME]` table.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfileConfig {
+    /// Other profiles, built-in or from the file, applied before this one.
+    pub extends: Vec<String>,
+    /// Port specs never suggested, as for `--exclude`.
+    pub exclude: Vec<String>,
+    /// Port specs to search, as for `--within`.
+    pub within: Vec<String>,
+}
+
+/// What a list of `--profile` names comes to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResolvedProfiles {
+    /// Built-in profiles whose ports are forbidden, in first-use order.
+    pub builtin: Vec<String>,
+    /// The search ranges of the last profile that sets any.
+    pub within: Option<String>,
+    /// Every profile's excluded ports.
+    pub exclude: Option<String>,
+}
+
+impl Config {
+    /// Parses and validates a `config.toml`.
+    pub fn from_toml(content: &str) -> Result<Self> {
+        let config: Config = toml::from_str(content).context("Failed to parse the config file")?;
+        for (name, profile) in &config.profiles {
+            if BUILTIN_PROFILES.iter().any(|(builtin, _)| builtin == name) {
+                bail!(
+                    "Profile '{}' is built in; give yours another name and list '{}' in its extends",
+                    name,
+                    name
+                );
+            }
+            for spec in profile.exclude.iter().chain(&profile.within) {
+                parse_port_spec(spec)
+                    .with_context(|| format!("Invalid port spec in profile '{}'", name))?;
+            }
+        }
+        for header in &config.http.headers {
+            parse_http_header(header).context("Invalid header in [http]")?;
+        }
+        Ok(config)
+    }
+
+    /// Reads `path`. A missing file is an empty config unless `required` is set.
+    pub fn load(path: &Path, required: bool) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Self::from_toml(&content)
+                .with_context(|| format!("Invalid config file '{}'", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => {
+                Ok(Config::default())
+            }
+            Err(e) => Err(anyhow::Error::new(e)
+                .context(format!("Failed to read config file '{}'", path.display()))),
+        }
+    }
+
+    /// Resolves `names`, applied in order: built-in profiles add their ports, and profiles
+    /// from the file add their `exclude` to each other's while a later `within` replaces an
+    /// earlier one. A profile's `extends` are applied before the profile itself, each profile
+    /// at most once.
+    ///
+    /// Unknown names and `extends` cycles are errors.
+    pub fn resolve_profiles<S: AsRef<str>>(&self, names: &[S]) -> Result<ResolvedProfiles> {
+        let mut resolved = ResolvedProfiles::default();
+        let mut exclude: Vec<String> = Vec::new();
+        let mut applied: Vec<String> = Vec::new();
+        for name in names {
+            self.apply(
+                name.as_ref(),
+                &mut Vec::new(),
+                &mut applied,
+                &mut resolved,
+                &mut exclude,
+            )?;
+        }
+        if !exclude.is_empty() {
+            resolved.exclude = Some(exclude.join(","));
+        }
+        Ok(resolved)
+    }
+
+    fn apply(
+        &self,
+        name: &str,
+        chain: &mut Vec<String>,
+        applied: &mut Vec<String>,
+        resolved: &mut ResolvedProfiles,
+        exclude: &mut Vec<String>,
+    ) -> Result<()> {
+        if chain.iter().any(|seen| seen == name) {
+            bail!(
+                "Profile '{}' extends itself: {} -> {}",
+                name,
+                chain.join(" -> "),
+                name
+            );
+        }
+        if applied.iter().any(|seen| seen == name) {
+            return Ok(());
+        }
+        if BUILTIN_PROFILES.iter().any(|(builtin, _)| *builtin == name) {
+            resolved.builtin.push(name.to_string());
+        } else {
+            let Some(profile) = self.profiles.get(name) else {
+                bail!(
+                    "Unknown profile '{}' (available: {})",
+                    name,
+                    BUILTIN_PROFILES
+                        .iter()
+                        .map(|(builtin, _)| builtin.to_string())
+                        .chain(self.profiles.keys().cloned())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            };
+            chain.push(name.to_string());
+            for parent in &profile.extends {
+                self.apply(parent, chain, applied, resolved, exclude)?;
+            }
+            chain.pop();
+            exclude.extend(profile.exclude.iter().cloned());
+            if !profile.within.is_empty() {
+                resolved.within = Some(profile.within.join(","));
+            }
+        }
+        applied.push(name.to_string());
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::path::Path;
+
+/// The container runtime portpick appears to be running under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerKind {
+    Docker,
+    Podman,
+    Kubernetes,
+    Lxc,
+    /// A container whose runtime couldn't be told, e.g. from `container=oci`.
+    Other,
+}
+
+impl fmt::Display for ContainerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ContainerKind::Docker => "Docker",
+            ContainerKind::Podman => "Podman",
+            ContainerKind::Kubernetes => "Kubernetes",
+            ContainerKind::Lxc => "LXC",
+            ContainerKind::Other => "a container",
+        })
+    }
+}
+
+/// A detected container and the evidence for it, such as `/.dockerenv`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerInfo {
+    pub kind: ContainerKind,
+    pub evidence: String,
+}
+
+/// The marker file a runtime leaves in its containers, checked with `exists`: Docker's
+/// `/.dockerenv` or Podman's `/run/.containerenv`.
+pub fn container_from_marker_files(exists: impl Fn(&Path) -> bool) -> Option<ContainerInfo> {
+    [
+        ("/.dockerenv", ContainerKind::Docker),
+        ("/run/.containerenv", ContainerKind::Podman),
+    ]
+    .into_iter()
+    .find(|(path, _)| exists(Path::new(path)))
+    .map(|(path, kind)| ContainerInfo {
+        kind,
+        evidence: path.to_string(),
+    })
+}
+
+/// The `container` environment variable that Podman, LXC, and systemd-nspawn set for the
+/// processes they start.
+pub fn container_from_env(value: Option<&str>) -> Option<ContainerInfo> {
+    let value = value.map(str::trim).filter(|value| !value.is_empty())?;
+    let kind = match value.to_ascii_lowercase().as_str() {
+        "docker" => ContainerKind::Docker,
+        "podman" => ContainerKind::Podman,
+        "lxc" | "lxc-libvirt" => ContainerKind::Lxc,
+        _ => ContainerKind::Other,
+    };
+    Some(ContainerInfo {
+        kind,
+        evidence: format!("$container={}", value),
+    })
+}
+
+/// Runtime hints in `/proc/1/cgroup` content. Only cgroup v1 paths carry them; under cgroup v2
+/// a container sees itself at `0::/`.
+pub fn container_from_cgroup(content: &str) -> Option<ContainerInfo> {
+    content.lines().find_map(|line| {
+        let path = line.splitn(3, ':').nth(2)?;
+        let kind = if path.contains("kubepods") {
+            ContainerKind::Kubernetes
+        } else if path.contains("libpod") {
+            ContainerKind::Podman
+        } else if path.contains("docker") {
+            ContainerKind::Docker
+        } else if path.starts_with("/lxc") {
+            ContainerKind::Lxc
+        } else {
+            return None;
+        };
+        Some(ContainerInfo {
+            kind,
+            evidence: format!("/proc/1/cgroup ({})", path),
+        })
+    })
+}
+
+/// The IPv4 default gateway in `/proc/net/route` content, which inside a container is usually
+/// the host.
+pub fn default_gateway(proc_net_route: &str) -> Option<Ipv4Addr> {
+    proc_net_route.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (destination, gateway) = (fields.get(1)?, fields.get(2)?);
+        if *destination != "00000000" {
+            return None;
+        }
+        // The kernel prints the network-order address as a native integer.
+        let gateway = u32::from_str_radix(gateway, 16).ok()?;
+        (gateway != 0).then(|| Ipv4Addr::from(gateway.to_ne_bytes()))
+    })
+}
+
+/// Checks each heuristic in turn against this machine: marker files, `$container`, then
+/// `/proc/1/cgroup`.
+pub fn detect_container() -> Option<ContainerInfo> {
+    container_from_marker_files(|path| path.exists())
+        .or_else(|| container_from_env(std::env::var("container").ok().as_deref()))
+        .or_else(|| {
+            container_from_cgroup(&std::fs::read_to_string("/proc/1/cgroup").unwrap_or_default())
+        })
+}
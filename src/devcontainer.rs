@@ -0,0 +1,460 @@
+//! Targeted edits to a VS Code `devcontainer.json`, which is JSON with comments and trailing
+//! commas. The file is scanned for the spans of its values rather than parsed and
+//! reserialized, so comments, formatting, and key order outside the inserted text stay as
+//! they were.
+
+use anyhow::{Result, bail};
+use serde_json::Value;
+
+/// Where `--write-devcontainer` looks when given no path.
+pub const DEFAULT_DEVCONTAINER_PATH: &str = ".devcontainer/devcontainer.json";
+
+/// A value in the file and the byte span it covers.
+#[derive(Debug)]
+struct Node {
+    start: usize,
+    /// One past the value's last byte; for objects and arrays, one past the closing bracket.
+    end: usize,
+    kind: Kind,
+}
+
+#[derive(Debug)]
+enum Kind {
+    /// Each member's key, the offset of its opening quote, and its value.
+    Object {
+        members: Vec<(String, usize, Node)>,
+        trailing_comma: bool,
+    },
+    Array {
+        items: Vec<Node>,
+        trailing_comma: bool,
+    },
+    Scalar(Value),
+}
+
+impl Node {
+    fn member(&self, key: &str) -> Option<&Node> {
+        match &self.kind {
+            Kind::Object { members, .. } => members
+                .iter()
+                .find(|(name, _, _)| name == key)
+                .map(|(_, _, value)| value),
+            _ => None,
+        }
+    }
+
+    /// The end of the last member or item, and whether a comma follows it.
+    fn last_item(&self) -> (Option<usize>, bool) {
+        match &self.kind {
+            Kind::Object {
+                members,
+                trailing_comma,
+            } => (
+                members.last().map(|(_, _, value)| value.end),
+                *trailing_comma,
+            ),
+            Kind::Array {
+                items,
+                trailing_comma,
+            } => (items.last().map(|item| item.end), *trailing_comma),
+            Kind::Scalar(_) => (None, false),
+        }
+    }
+
+    /// Where the last member or item starts.
+    fn last_item_start(&self) -> Option<usize> {
+        match &self.kind {
+            Kind::Object { members, .. } => members.last().map(|&(_, start, _)| start),
+            Kind::Array { items, .. } => items.last().map(|item| item.start),
+            Kind::Scalar(_) => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn line(&self, pos: usize) -> usize {
+        self.text[..pos].matches('\n').count() + 1
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    /// Skips whitespace and `//` and `/* */` comments.
+    fn skip_trivia(&mut self) -> Result<()> {
+        loop {
+            let rest = &self.text[self.pos..];
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if let Some(comment) = trimmed.strip_prefix("/*") {
+                match comment.find("*/") {
+                    Some(end) => self.pos += end + 4,
+                    None => bail!("Unterminated comment on line {}", self.line(self.pos)),
+                }
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        self.skip_trivia()?;
+        if self.peek() != Some(byte) {
+            bail!(
+                "Expected '{}' on line {}",
+                char::from(byte),
+                self.line(self.pos)
+            );
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Node> {
+        self.skip_trivia()?;
+        let start = self.pos;
+        match self.peek() {
+            Some(b'{') => self.container(start, b'}'),
+            Some(b'[') => self.container(start, b']'),
+            Some(b'"') => {
+                let value = self.string()?;
+                Ok(Node {
+                    start,
+                    end: self.pos,
+                    kind: Kind::Scalar(Value::String(value)),
+                })
+            }
+            Some(_) => {
+                let len = self.text[start..]
+                    .find(|c: char| c.is_whitespace() || ",]}/".contains(c))
+                    .unwrap_or(self.text.len() - start);
+                let raw = &self.text[start..start + len];
+                let Ok(value) = serde_json::from_str::<Value>(raw) else {
+                    let shown: String = match raw {
+                        "" => self.text[start..].chars().take(1).collect(),
+                        _ => raw.to_string(),
+                    };
+                    bail!("Unexpected '{}' on line {}", shown, self.line(start));
+                };
+                self.pos += len;
+                Ok(Node {
+                    start,
+                    end: self.pos,
+                    kind: Kind::Scalar(value),
+                })
+            }
+            None => bail!("Unexpected end of file"),
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let start = self.pos;
+        let mut escaped = false;
+        for (offset, c) in self.text[start + 1..].char_indices() {
+            match (escaped, c) {
+                (true, _) => escaped = false,
+                (false, '\\') => escaped = true,
+                (false, '"') => {
+                    self.pos = start + 1 + offset + 1;
+                    return serde_json::from_str(&self.text[start..self.pos]).map_err(|e| {
+                        anyhow::anyhow!("Invalid string on line {}: {}", self.line(start), e)
+                    });
+                }
+                _ => {}
+            }
+        }
+        bail!("Unterminated string on line {}", self.line(start))
+    }
+
+    /// An object or array whose opening bracket is at `start`, closed by `close`.
+    fn container(&mut self, start: usize, close: u8) -> Result<Node> {
+        self.pos += 1;
+        let mut members = Vec::new();
+        let mut items = Vec::new();
+        let mut trailing_comma = false;
+        loop {
+            self.skip_trivia()?;
+            if self.peek() == Some(close) {
+                self.pos += 1;
+                break;
+            }
+            let started = !members.is_empty() || !items.is_empty();
+            if started && !trailing_comma {
+                bail!(
+                    "Expected ',' or '{}' on line {}",
+                    char::from(close),
+                    self.line(self.pos)
+                );
+            }
+            if close == b'}' {
+                if self.peek() != Some(b'"') {
+                    bail!("Expected a key on line {}", self.line(self.pos));
+                }
+                let key_start = self.pos;
+                let key = self.string()?;
+                self.expect(b':')?;
+                members.push((key, key_start, self.value()?));
+            } else {
+                items.push(self.value()?);
+            }
+            self.skip_trivia()?;
+            trailing_comma = self.peek() == Some(b',');
+            if trailing_comma {
+                self.pos += 1;
+            }
+        }
+        let kind = match close {
+            b'}' => Kind::Object {
+                members,
+                trailing_comma,
+            },
+            _ => Kind::Array {
+                items,
+                trailing_comma,
+            },
+        };
+        Ok(Node {
+            start,
+            end: self.pos,
+            kind,
+        })
+    }
+}
+
+/// Parses the whole file, which must be one object.
+fn parse(text: &str) -> Result<Node> {
+    let mut parser = Parser {
+        text,
+        pos: if text.starts_with('\u{feff}') { 3 } else { 0 },
+    };
+    let root = parser.value()?;
+    parser.skip_trivia()?;
+    if parser.pos != text.len() {
+        bail!(
+            "Unexpected text after the closing brace on line {}",
+            parser.line(parser.pos)
+        );
+    }
+    if !matches!(root.kind, Kind::Object { .. }) {
+        bail!("The file is not a JSON object");
+    }
+    Ok(root)
+}
+
+/// Text to insert at a byte offset.
+struct Insert {
+    at: usize,
+    text: String,
+    /// Replaces this many bytes from `at`.
+    replace: usize,
+}
+
+/// The indentation `line_start` begins with.
+fn indentation(text: &str, line_start: usize) -> &str {
+    let line = &text[line_start..];
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+fn line_start(text: &str, pos: usize) -> usize {
+    text[..pos].rfind('\n').map_or(0, |newline| newline + 1)
+}
+
+/// Appends `items` (rendered members or array items) to `container`. Multi-line containers get
+/// one item per line at the indentation of the last one; others get them inline. A trailing
+/// comma after the last item is kept.
+fn append(text: &str, container: &Node, items: &[String], inserts: &mut Vec<Insert>) {
+    if items.is_empty() {
+        return;
+    }
+    let close = container.end - 1;
+    let (last_end, trailing_comma) = container.last_item();
+    let close_line = line_start(text, close);
+    let own_line = close_line > container.start && text[close_line..close].trim().is_empty();
+    if !own_line {
+        let joined = items.join(", ");
+        let text = match (last_end, &container.kind) {
+            (Some(last_end), _) => {
+                inserts.push(Insert {
+                    at: last_end,
+                    text: format!(", {}", joined),
+                    replace: 0,
+                });
+                return;
+            }
+            (None, Kind::Object { .. }) => format!(" {} ", joined),
+            (None, _) => joined,
+        };
+        inserts.push(Insert {
+            at: close,
+            text,
+            replace: 0,
+        });
+        return;
+    }
+
+    let eol = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let unit = if text.contains("\n\t") { "\t" } else { "  " };
+    let indent = match container
+        .last_item_start()
+        .map(|start| line_start(text, start))
+    {
+        Some(line) if line > container.start => indentation(text, line).to_string(),
+        _ => format!("{}{}", indentation(text, close_line), unit),
+    };
+    if let Some(last_end) = last_end
+        && !trailing_comma
+    {
+        inserts.push(Insert {
+            at: last_end,
+            text: ",".to_string(),
+            replace: 0,
+        });
+    }
+    let lines: String = items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let comma = if index + 1 < items.len() || trailing_comma {
+                ","
+            } else {
+                ""
+            };
+            format!("{}{}{}{}", indent, item, comma, eol)
+        })
+        .collect();
+    inserts.push(Insert {
+        at: close_line,
+        text: lines,
+        replace: 0,
+    });
+}
+
+fn quote(text: &str) -> String {
+    serde_json::to_string(text).unwrap_or_default()
+}
+
+/// Adds `ports` to the `forwardPorts` of a devcontainer.json, skipping ports already listed,
+/// and for each port with a name, a `portsAttributes` entry labeling it.
+///
+/// A port whose existing label differs is an error unless `overwrite` is set, which replaces
+/// the label. Only the inserted or replaced text changes; comments, trailing commas, and the
+/// rest of the file are kept.
+pub fn update_devcontainer(
+    content: &str,
+    ports: &[(u16, Option<String>)],
+    overwrite: bool,
+) -> Result<String> {
+    let root = parse(content)?;
+    let mut inserts = Vec::new();
+    let mut new_members = Vec::new();
+
+    let forwarded: Vec<u16> = match root.member("forwardPorts") {
+        Some(Node {
+            kind: Kind::Array { items, .. },
+            ..
+        }) => items
+            .iter()
+            .filter_map(|item| match &item.kind {
+                Kind::Scalar(Value::Number(number)) => number.as_u64(),
+                Kind::Scalar(Value::String(port)) => port.parse().ok(),
+                _ => None,
+            })
+            .filter_map(|port| u16::try_from(port).ok())
+            .collect(),
+        Some(_) => bail!("'forwardPorts' is not a list"),
+        None => Vec::new(),
+    };
+    let mut new_ports: Vec<String> = Vec::new();
+    for (port, _) in ports {
+        let port = port.to_string();
+        if !forwarded
+            .iter()
+            .any(|forwarded| forwarded.to_string() == port)
+            && !new_ports.contains(&port)
+        {
+            new_ports.push(port);
+        }
+    }
+    match root.member("forwardPorts") {
+        Some(forward_ports) => append(content, forward_ports, &new_ports, &mut inserts),
+        None if !new_ports.is_empty() => {
+            new_members.push(format!("\"forwardPorts\": [{}]", new_ports.join(", ")))
+        }
+        None => {}
+    }
+
+    let attributes = root.member("portsAttributes");
+    if attributes.is_some_and(|attributes| !matches!(attributes.kind, Kind::Object { .. })) {
+        bail!("'portsAttributes' is not an object");
+    }
+    let mut new_attributes = Vec::new();
+    let mut conflicts = Vec::new();
+    for (port, name) in ports {
+        let Some(name) = name else {
+            continue;
+        };
+        let key = port.to_string();
+        let entry = format!("{}: {{ \"label\": {} }}", quote(&key), quote(name));
+        match attributes.and_then(|attributes| attributes.member(&key)) {
+            None if !new_attributes.contains(&entry) => new_attributes.push(entry),
+            None => {}
+            Some(
+                existing @ Node {
+                    kind: Kind::Object { .. },
+                    ..
+                },
+            ) => match existing.member("label") {
+                Some(Node {
+                    kind: Kind::Scalar(Value::String(label)),
+                    ..
+                }) if label == name => {}
+                Some(label) if overwrite => inserts.push(Insert {
+                    at: label.start,
+                    text: quote(name),
+                    replace: label.end - label.start,
+                }),
+                Some(label) => {
+                    conflicts.push(format!("{} as {}", key, &content[label.start..label.end]))
+                }
+                None => append(
+                    content,
+                    existing,
+                    &[format!("\"label\": {}", quote(name))],
+                    &mut inserts,
+                ),
+            },
+            Some(_) => bail!("'portsAttributes' entry '{}' is not an object", key),
+        }
+    }
+    if !conflicts.is_empty() {
+        bail!(
+            "'portsAttributes' already labels {}; pass --overwrite to relabel {}",
+            conflicts.join(", "),
+            if conflicts.len() == 1 { "it" } else { "them" }
+        );
+    }
+    match attributes {
+        Some(attributes) => append(content, attributes, &new_attributes, &mut inserts),
+        None if !new_attributes.is_empty() => new_members.push(format!(
+            "\"portsAttributes\": {{ {} }}",
+            new_attributes.join(", ")
+        )),
+        None => {}
+    }
+    append(content, &root, &new_members, &mut inserts);
+
+    // Apply from the end so earlier offsets stay valid.
+    inserts.sort_by_key(|insert| std::cmp::Reverse(insert.at));
+    let mut updated = content.to_string();
+    for insert in inserts {
+        updated.replace_range(insert.at..insert.at + insert.replace, &insert.text);
+    }
+    Ok(updated)
+}
@@ -0,0 +1,251 @@
+use crate::{Reporter, parse_services_content};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+#[cfg(feature = "native")]
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// Outcome of one `portpick doctor` check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    /// Works, but something is missing or degraded.
+    Warn,
+    /// portpick can't work as configured until this is fixed.
+    Fail,
+}
+
+/// One line of the `portpick doctor` report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DoctorCheck {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// What to do about a warning or failure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl DoctorCheck {
+    pub fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        DoctorCheck {
+            name: name.into(),
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    pub fn warn(
+        name: impl Into<String>,
+        detail: impl Into<String>,
+        hint: impl Into<String>,
+    ) -> Self {
+        DoctorCheck {
+            name: name.into(),
+            status: CheckStatus::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    pub fn fail(
+        name: impl Into<String>,
+        detail: impl Into<String>,
+        hint: impl Into<String>,
+    ) -> Self {
+        DoctorCheck {
+            name: name.into(),
+            status: CheckStatus::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// Resolves `program` like a shell would: paths are taken as they are, bare names are looked up
+/// in the directories of `path` (a `$PATH` value).
+pub fn find_program(program: &str, path: Option<&OsStr>) -> Option<PathBuf> {
+    if program.contains(std::path::MAIN_SEPARATOR) {
+        let candidate = PathBuf::from(program);
+        return candidate.is_file().then_some(candidate);
+    }
+    std::env::split_paths(path?)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Checks that an external tool is installed and reports the first line of its version output.
+///
+/// A missing `required` tool fails the check; an optional one only warns.
+pub fn check_tool(
+    program: &str,
+    version_args: &[&str],
+    path: Option<&OsStr>,
+    required: bool,
+    hint: &str,
+) -> DoctorCheck {
+    let Some(location) = find_program(program, path) else {
+        let detail = format!("{} not found on PATH", program);
+        return if required {
+            DoctorCheck::fail(program, detail, hint)
+        } else {
+            DoctorCheck::warn(program, detail, hint)
+        };
+    };
+    let version = Command::new(&location)
+        .args(version_args)
+        .stdin(Stdio::null())
+        .output()
+        .ok()
+        .and_then(|output| {
+            // Some tools (lsof) print their version to stderr.
+            let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+            let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+            stdout
+                .lines()
+                .chain(stderr.lines())
+                .map(str::trim)
+                // Skip headings such as lsof's "lsof version information:".
+                .find(|line| !line.is_empty() && !line.ends_with(':'))
+                .map(String::from)
+        })
+        .unwrap_or_else(|| "version unknown".to_string());
+    DoctorCheck::pass(program, format!("{} ({})", version, location.display()))
+}
+
+/// Checks that the system services file can be read and lists at least one port.
+pub fn check_services_file(path: &Path) -> DoctorCheck {
+    const NAME: &str = "services file";
+    let hint = "install your distribution's services file (e.g. the netbase package) or pass --services-file";
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            return DoctorCheck::fail(
+                NAME,
+                format!("{} is unreadable: {}", path.display(), e),
+                hint,
+            );
+        }
+    };
+    match parse_services_content(&content, "services file", &Reporter::silent(), false) {
+        Ok(ports) if ports.is_empty() => {
+            DoctorCheck::warn(NAME, format!("{} lists no TCP ports", path.display()), hint)
+        }
+        Ok(ports) => DoctorCheck::pass(
+            NAME,
+            format!("{}: {} TCP ports", path.display(), ports.len()),
+        ),
+        Err(e) => DoctorCheck::fail(
+            NAME,
+            format!("{} could not be parsed: {}", path.display(), e),
+            hint,
+        ),
+    }
+}
+
+/// Checks the cached Nmap services list: a missing cache is fine, an unparsable one is not.
+pub fn check_services_cache(path: &Path, now: SystemTime) -> DoctorCheck {
+    const NAME: &str = "nmap cache";
+    let refresh = "delete it or refresh it with --source nmap";
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return DoctorCheck::pass(
+                NAME,
+                format!(
+                    "{} not created yet (only --source cache needs it)",
+                    path.display()
+                ),
+            );
+        }
+        Err(e) => {
+            return DoctorCheck::fail(
+                NAME,
+                format!("{} is unreadable: {}", path.display(), e),
+                refresh,
+            );
+        }
+    };
+    let age = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| now.duration_since(modified).ok());
+    let age = match age {
+        Some(age) => format!("{} days old", age.as_secs() / (24 * 60 * 60)),
+        None => "age unknown".to_string(),
+    };
+    match parse_services_content(&content, "nmap cache", &Reporter::silent(), false) {
+        Ok(ports) if !ports.is_empty() => DoctorCheck::pass(
+            NAME,
+            format!("{}: {} TCP ports, {}", path.display(), ports.len(), age),
+        ),
+        _ => DoctorCheck::fail(
+            NAME,
+            format!("{} lists no usable ports ({})", path.display(), age),
+            refresh,
+        ),
+    }
+}
+
+/// Checks that files can be created in `dir`, or in its nearest existing ancestor if `dir`
+/// would have to be created first. Nothing is left behind.
+pub fn check_writable_dir(name: &str, dir: &Path) -> DoctorCheck {
+    let hint = format!("make {} writable or point it elsewhere", dir.display());
+    let Some(existing) = dir
+        .ancestors()
+        .map(|ancestor| match ancestor.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => ancestor,
+        })
+        .find(|ancestor| ancestor.is_dir())
+    else {
+        return DoctorCheck::fail(
+            name,
+            format!("{} has no existing parent", dir.display()),
+            hint,
+        );
+    };
+    let probe = existing.join(format!(".portpick-doctor-{}", std::process::id()));
+    match fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+            let detail = if existing == dir {
+                format!("{} is writable", dir.display())
+            } else {
+                format!("{} can be created", dir.display())
+            };
+            DoctorCheck::pass(name, detail)
+        }
+        Err(e) => DoctorCheck::fail(
+            name,
+            format!("{} is not writable: {}", existing.display(), e),
+            hint,
+        ),
+    }
+}
+
+/// Checks that `url` answers within `timeout`.
+#[cfg(feature = "native")]
+pub fn check_url(name: &str, url: &str, timeout: Duration) -> DoctorCheck {
+    let hint = "check network access or use --source system / --source nmap-cache";
+    let response = crate::client_builder()
+        .timeout(timeout)
+        .build()
+        .and_then(|client| client.head(url).send());
+    match response {
+        Ok(response) if response.status().is_success() => {
+            DoctorCheck::pass(name, format!("{} is reachable", url))
+        }
+        Ok(response) => DoctorCheck::warn(
+            name,
+            format!("{} answered {}", url, response.status()),
+            hint,
+        ),
+        Err(e) => DoctorCheck::warn(name, format!("{} is unreachable: {}", url, e), hint),
+    }
+}
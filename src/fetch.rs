@@ -0,0 +1,287 @@
+use crate::validate_services_content;
+use anyhow::{Context, Result, bail};
+use indicatif::{HumanBytes, ProgressBar};
+use reqwest::Url;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+
+#[cfg(all(feature = "native-tls", feature = "rustls"))]
+compile_error!("the `native-tls` and `rustls` features are mutually exclusive; enable only one");
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("enable a TLS backend for downloads: the `native-tls` or `rustls` feature");
+
+/// Size of each read from the response body; progress advances once per chunk.
+const READ_CHUNK_SIZE: usize = 16 * 1024;
+
+/// The TLS stack this build downloads with, chosen by the `native-tls` or `rustls` feature.
+pub const TLS_BACKEND: &str = if cfg!(feature = "rustls") {
+    "rustls"
+} else {
+    "native-tls"
+};
+
+/// The `User-Agent` downloads send unless another is configured.
+pub const DEFAULT_USER_AGENT: &str = concat!("portpick/", env!("CARGO_PKG_VERSION"));
+
+/// How many redirects a download follows before giving up.
+pub const MAX_REDIRECTS: usize = 5;
+
+/// The largest body a download reads unless told otherwise: 10 MiB, several times the size
+/// of the Nmap services list.
+pub const DEFAULT_MAX_DOWNLOAD_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Parses a size such as `10M`, `512KiB`, or `1048576`. `K`, `M`, and `G` (with or without a
+/// trailing `B` or `iB`) are powers of 1024.
+pub fn parse_byte_size(spec: &str) -> Result<u64> {
+    let spec = spec.trim();
+    let split = spec
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(spec.len());
+    let (number, unit) = spec.split_at(split);
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("Invalid size '{}': expected a number such as 10M", spec))?;
+    let shift = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        _ => bail!("Invalid size '{}': unknown unit '{}'", spec, unit.trim()),
+    };
+    number
+        .checked_mul(1 << shift)
+        .with_context(|| format!("Invalid size '{}': too large", spec))
+}
+
+/// A services list fetched by [`download_services_to`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Downloaded {
+    pub content: Vec<u8>,
+    /// The SHA-256 of `content`, in lowercase hex.
+    pub sha256: String,
+}
+
+/// An HTTP client builder using [`TLS_BACKEND`], sending [`DEFAULT_USER_AGENT`] and following
+/// redirects as [`redirect_policy`] allows.
+///
+/// `rustls` needs no system TLS library, which suits static musl builds.
+pub fn client_builder() -> reqwest::blocking::ClientBuilder {
+    let builder = reqwest::blocking::Client::builder()
+        .user_agent(DEFAULT_USER_AGENT)
+        .redirect(redirect_policy(|_, _| {}));
+    #[cfg(feature = "rustls")]
+    let builder = builder.use_rustls_tls();
+    #[cfg(feature = "native-tls")]
+    let builder = builder.use_native_tls();
+    builder
+}
+
+/// Parses a request header given as `Name: value`.
+pub fn parse_http_header(spec: &str) -> Result<(HeaderName, HeaderValue)> {
+    let Some((name, value)) = spec.split_once(':') else {
+        bail!("Invalid header '{}': expected 'Name: value'", spec);
+    };
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .with_context(|| format!("Invalid header name '{}'", name.trim()))?;
+    let mut value = HeaderValue::from_str(value.trim())
+        .with_context(|| format!("Invalid value for header '{}'", name))?;
+    // Keeps values out of reqwest's own debug output too.
+    value.set_sensitive(true);
+    Ok((name, value))
+}
+
+/// The headers `specs` set, each `Name: value`. A later header replaces an earlier one of the
+/// same name.
+pub fn header_map<S: AsRef<str>>(specs: &[S]) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for spec in specs {
+        let (name, value) = parse_http_header(spec.as_ref())?;
+        headers.insert(name, value);
+    }
+    Ok(headers)
+}
+
+/// `headers` for display, with their values masked since they often carry credentials.
+pub fn masked_headers(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .keys()
+        .map(|name| format!("{}: ****", name))
+        .collect()
+}
+
+/// Whether a download that has already been redirected `hops` times may go on from `from`
+/// to `to`: at most [`MAX_REDIRECTS`] hops, and never from `https` down to `http`.
+pub fn check_redirect(from: &Url, to: &Url, hops: usize) -> Result<(), String> {
+    if hops >= MAX_REDIRECTS {
+        return Err(format!(
+            "too many redirects (more than {}), last to '{}'",
+            MAX_REDIRECTS, to
+        ));
+    }
+    if from.scheme() == "https" && to.scheme() != "https" {
+        return Err(format!(
+            "refusing to follow a redirect from '{}' down to '{}'",
+            from, to
+        ));
+    }
+    Ok(())
+}
+
+/// A redirect policy enforcing [`check_redirect`] that calls `on_redirect` with each hop it
+/// follows.
+pub fn redirect_policy(
+    on_redirect: impl Fn(&Url, &Url) + Send + Sync + 'static,
+) -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(move |attempt| {
+        let Some(from) = attempt.previous().last().cloned() else {
+            return attempt.follow();
+        };
+        match check_redirect(&from, attempt.url(), attempt.previous().len() - 1) {
+            Ok(()) => {
+                on_redirect(&from, attempt.url());
+                attempt.follow()
+            }
+            Err(reason) => attempt.error(reason),
+        }
+    })
+}
+
+/// Downloads `url` as text, streaming the body so `progress` can follow along.
+///
+/// The bar's length is set from `Content-Length` when the server sends one, and it is
+/// cleared once the body has been read.
+pub fn download_text(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    progress: &ProgressBar,
+) -> Result<String> {
+    let body = download_bytes(client, url, progress)?;
+    String::from_utf8(body).with_context(|| format!("Response from '{}' is not UTF-8", url))
+}
+
+/// Downloads a services list with [`download_bytes`], checked with
+/// [`validate_services_content`] so that a body that isn't one is an error instead of
+/// something to cache.
+///
+/// gzip and brotli bodies are decompressed on the way in, whether or not the server was asked
+/// for them.
+pub fn download_services(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    progress: &ProgressBar,
+) -> Result<Vec<u8>> {
+    download_services_to(
+        client,
+        url,
+        progress,
+        DEFAULT_MAX_DOWNLOAD_SIZE,
+        &mut std::io::sink(),
+    )
+    .map(|downloaded| downloaded.content)
+}
+
+/// [`download_services`], also writing each chunk to `sink` as it arrives and hashing the
+/// body on the way. A body larger than `max_size` bytes, whether announced by
+/// `Content-Length` or found while reading, is an error; `sink` may then hold part of it.
+pub fn download_services_to(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    progress: &ProgressBar,
+    max_size: u64,
+    sink: &mut dyn Write,
+) -> Result<Downloaded> {
+    let (content, content_type, sha256) = download(client, url, progress, max_size, sink)?;
+    if content_type
+        .as_deref()
+        .is_some_and(|content_type| content_type.starts_with("text/html"))
+    {
+        bail!(
+            "Response from '{}' is an HTML page, not a services list. A captive portal or proxy \
+             login page may be intercepting the download; sign in or download from another network.",
+            url
+        );
+    }
+    validate_services_content(&content)
+        .with_context(|| format!("Response from '{}' is not a services list", url))?;
+    Ok(Downloaded { content, sha256 })
+}
+
+/// Like [`download_text`], for callers that decode the body themselves.
+pub fn download_bytes(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    progress: &ProgressBar,
+) -> Result<Vec<u8>> {
+    let sink = &mut std::io::sink();
+    Ok(download(client, url, progress, DEFAULT_MAX_DOWNLOAD_SIZE, sink)?.0)
+}
+
+/// The body of `url`, the `Content-Type` it was served with, and the body's SHA-256. The body
+/// is copied to `sink` chunk by chunk.
+fn download(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    progress: &ProgressBar,
+    max_size: u64,
+    sink: &mut dyn Write,
+) -> Result<(Vec<u8>, Option<String>, String)> {
+    let too_large = || {
+        anyhow::anyhow!(
+            "Response from '{}' is larger than the download limit of {}",
+            url,
+            HumanBytes(max_size)
+        )
+    };
+    let mut response = client
+        .get(url)
+        .send()
+        .with_context(|| format!("Failed to send request to '{}'", url))?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "Failed to download '{}'. Status: {}",
+            url,
+            response.status()
+        ));
+    }
+    if let Some(length) = response.content_length() {
+        if length > max_size {
+            return Err(too_large());
+        }
+        progress.set_length(length);
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_ascii_lowercase());
+
+    let mut body = Vec::new();
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0; READ_CHUNK_SIZE];
+    loop {
+        let read = response
+            .read(&mut chunk)
+            .with_context(|| format!("Failed to read response body from '{}'", url))?;
+        if read == 0 {
+            break;
+        }
+        // Decompressed bodies have no trustworthy length up front, so count as we go.
+        if (body.len() + read) as u64 > max_size {
+            return Err(too_large());
+        }
+        let chunk = &chunk[..read];
+        sink.write_all(chunk)
+            .context("Failed to write the downloaded data")?;
+        hasher.update(chunk);
+        body.extend_from_slice(chunk);
+        progress.inc(read as u64);
+    }
+    progress.finish_and_clear();
+    let sha256 = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok((body, content_type, sha256))
+}
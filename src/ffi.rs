@@ -0,0 +1,116 @@
+//! C API for embedding portpick, built with the `ffi` feature:
+//!
+//! ```sh
+//! cargo rustc --lib --release --features ffi --crate-type cdylib
+//! ```
+//!
+//! `include/portpick.h` declares these functions; regenerate it with
+//! `cbindgen --config cbindgen.toml --output include/portpick.h` after changing them. No
+//! function panics across the boundary: a panic is caught and reported as
+//! [`PORTPICK_ERROR`], like any other failure.
+
+use crate::{Reporter, find_available_ports_in, parse_services_content};
+use std::collections::HashSet;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+/// Returned instead of a count when the arguments are invalid or the call failed.
+pub const PORTPICK_ERROR: isize = -1;
+
+/// Runs `body`, turning a panic or an error into [`PORTPICK_ERROR`].
+fn guarded(body: impl FnOnce() -> Option<usize>) -> isize {
+    match catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Some(count)) => isize::try_from(count).unwrap_or(PORTPICK_ERROR),
+        _ => PORTPICK_ERROR,
+    }
+}
+
+/// Views `len` elements at `ptr`; a null pointer is only accepted for an empty slice.
+unsafe fn slice_from<'a, T>(ptr: *const T, len: usize) -> Option<&'a [T]> {
+    if len == 0 {
+        Some(&[])
+    } else if ptr.is_null() {
+        None
+    } else {
+        // SAFETY: the caller vouches for `len` readable elements at `ptr`.
+        Some(unsafe { std::slice::from_raw_parts(ptr, len) })
+    }
+}
+
+/// Finds `count` free ports in the default ranges (1024-65535), avoiding the `n_forbidden`
+/// ports at `forbidden`, and writes them in ascending order to `out`.
+///
+/// Returns how many ports were written: `count`, or fewer (possibly 0) if there aren't
+/// enough free ports, or no continuous block of `count` when `continuous` is set. Returns
+/// [`PORTPICK_ERROR`] if `out_cap` is less than `count` or a pointer is null.
+///
+/// # Safety
+///
+/// `forbidden` must point to `n_forbidden` readable values and `out` to `out_cap` writable
+/// ones; either may be null when its length is 0.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn portpick_find(
+    forbidden: *const u16,
+    n_forbidden: usize,
+    count: u16,
+    continuous: bool,
+    out: *mut u16,
+    out_cap: usize,
+) -> isize {
+    guarded(|| {
+        if out_cap < usize::from(count) || (count > 0 && out.is_null()) {
+            return None;
+        }
+        // SAFETY: forwarded from this function's contract.
+        let forbidden: HashSet<u16> = unsafe { slice_from(forbidden, n_forbidden) }?
+            .iter()
+            .copied()
+            .collect();
+        let ports =
+            find_available_ports_in(&forbidden, count, continuous, &crate::DEFAULT_PORT_RANGES)
+                .ok()?;
+        for (index, &port) in ports.iter().enumerate() {
+            // SAFETY: `ports` holds at most `count` <= `out_cap` entries.
+            unsafe { out.add(index).write(port) };
+        }
+        Some(ports.len())
+    })
+}
+
+/// Parses the services-file text in the `len` bytes at `content` (`/etc/services` or Nmap
+/// format) and writes the TCP ports it names to `out`, in ascending order. Entries named
+/// `unknown` are skipped.
+///
+/// Returns how many ports the text names, which may exceed `out_cap`; only the first
+/// `out_cap` are written, so call again with a larger buffer to get them all. Returns
+/// [`PORTPICK_ERROR`] if the text isn't UTF-8 or a pointer is null.
+///
+/// # Safety
+///
+/// `content` must point to `len` readable bytes and `out` to `out_cap` writable values;
+/// either may be null when its length is 0.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn portpick_parse_services(
+    content: *const u8,
+    len: usize,
+    out: *mut u16,
+    out_cap: usize,
+) -> isize {
+    guarded(|| {
+        if out_cap > 0 && out.is_null() {
+            return None;
+        }
+        // SAFETY: forwarded from this function's contract.
+        let content = std::str::from_utf8(unsafe { slice_from(content, len) }?).ok()?;
+        let mut ports: Vec<u16> =
+            parse_services_content(content, "services buffer", &Reporter::silent(), false)
+                .ok()?
+                .into_iter()
+                .collect();
+        ports.sort_unstable();
+        for (index, &port) in ports.iter().take(out_cap).enumerate() {
+            // SAFETY: at most `out_cap` entries are written.
+            unsafe { out.add(index).write(port) };
+        }
+        Some(ports.len())
+    })
+}
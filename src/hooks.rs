@@ -0,0 +1,231 @@
+//! Provider hooks: executables in a drop-in directory, each run as a source of forbidden ports.
+//!
+//! A hook is run without arguments and with this environment:
+//!
+//! - `PORTPICK_PROTOCOL`: `tcp`
+//! - `PORTPICK_RANGES`: the search ranges, e.g. `1024-49151,49152-65535`
+//! - `PORTPICK_ADDRESS`: the address the local scan looks at
+//!
+//! It prints the ports it forbids as port specs (`8080`, `9000-9100`), separated by commas,
+//! spaces, or newlines; `#` starts a comment. Exiting non-zero or running past its timeout is
+//! a failure.
+
+use crate::{PlannedSource, PortSource, RejectionReason, config_dir, interrupt, parse_port_spec};
+use anyhow::{Context, Result, bail};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Environment variable naming the hook directory in place of [`default_providers_dir`].
+pub const PROVIDERS_DIR_ENV: &str = "PORTPICK_PROVIDERS_DIR";
+
+/// How long a hook may run before it counts as failed.
+pub const DEFAULT_PROVIDER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `providers.d` in the config directory.
+pub fn default_providers_dir() -> Option<PathBuf> {
+    Some(config_dir()?.join("providers.d"))
+}
+
+/// The executables in `dir`, by file name. Hidden files, editor backups (`~`), and files
+/// without an execute bit are skipped; a missing directory has no hooks.
+pub fn discover_hooks(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(anyhow::Error::new(e).context(format!(
+                "Failed to read provider directory '{}'",
+                dir.display()
+            )));
+        }
+    };
+    let mut hooks = Vec::new();
+    for entry in entries {
+        let entry = entry
+            .with_context(|| format!("Failed to read provider directory '{}'", dir.display()))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || name.ends_with('~') {
+            continue;
+        }
+        let path = entry.path();
+        if is_executable(&path) {
+            hooks.push(path);
+        }
+    }
+    hooks.sort();
+    Ok(hooks)
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Ports from the port specs a hook printed.
+pub fn parse_hook_output(output: &str) -> Result<HashSet<u16>> {
+    let mut ports = HashSet::new();
+    for (index, line) in output.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        for spec in line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|spec| !spec.is_empty())
+        {
+            let ranges = parse_port_spec(spec)
+                .with_context(|| format!("Invalid port spec on line {}", index + 1))?;
+            for (start, end) in ranges {
+                ports.extend(start..=end);
+            }
+        }
+    }
+    Ok(ports)
+}
+
+/// One hook, run as a [`PortSource`] named after its file (without the extension). Its
+/// ports are blocked as known services called by that name.
+#[derive(Debug, Clone)]
+pub struct ProviderHook {
+    name: String,
+    path: PathBuf,
+    env: Vec<(String, String)>,
+    timeout: Duration,
+}
+
+impl ProviderHook {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+        ProviderHook {
+            name,
+            path,
+            env: Vec::new(),
+            timeout: DEFAULT_PROVIDER_TIMEOUT,
+        }
+    }
+
+    /// Sets `PORTPICK_PROTOCOL`, `PORTPICK_RANGES`, and `PORTPICK_ADDRESS` for the hook.
+    pub fn with_context(mut self, protocol: &str, ranges: &[(u16, u16)], address: &str) -> Self {
+        let ranges: Vec<String> = ranges
+            .iter()
+            .map(|&(start, end)| match start == end {
+                true => start.to_string(),
+                false => format!("{}-{}", start, end),
+            })
+            .collect();
+        self.env = vec![
+            ("PORTPICK_PROTOCOL".to_string(), protocol.to_string()),
+            ("PORTPICK_RANGES".to_string(), ranges.join(",")),
+            ("PORTPICK_ADDRESS".to_string(), address.to_string()),
+        ];
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Runs the hook, killing it once the timeout passes, and returns its stdout.
+    fn run(&self) -> Result<String> {
+        let mut command = Command::new(&self.path);
+        command
+            .envs(self.env.iter().map(|(key, value)| (key, value)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // Its own process group, so a timeout also stops whatever a script started.
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("Failed to run '{}'", self.path.display()))?;
+        // Read on threads so a hook writing more than a pipe holds doesn't block on us.
+        let mut stdout = child
+            .stdout
+            .take()
+            .context("Hook stdout was not captured")?;
+        let mut stderr = child
+            .stderr
+            .take()
+            .context("Hook stderr was not captured")?;
+        let stdout = std::thread::spawn(move || {
+            let mut buffer = Vec::new();
+            let _ = stdout.read_to_end(&mut buffer);
+            buffer
+        });
+        let stderr = std::thread::spawn(move || {
+            let mut buffer = Vec::new();
+            let _ = stderr.read_to_end(&mut buffer);
+            buffer
+        });
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                interrupt::kill_process_group(child.id());
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!(
+                    "'{}' did not finish within {:.1}s",
+                    self.path.display(),
+                    self.timeout.as_secs_f64()
+                );
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        let stdout = String::from_utf8_lossy(&stdout.join().unwrap_or_default()).into_owned();
+        let stderr = String::from_utf8_lossy(&stderr.join().unwrap_or_default()).into_owned();
+        if !status.success() {
+            bail!(
+                "'{}' exited with {}{}",
+                self.path.display(),
+                status,
+                match stderr.trim() {
+                    "" => String::new(),
+                    message => format!(": {}", message),
+                }
+            );
+        }
+        Ok(stdout)
+    }
+}
+
+impl PortSource for ProviderHook {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn rejection_reason(&self) -> RejectionReason {
+        RejectionReason::KnownService
+    }
+
+    fn collect(&mut self, names: &mut HashMap<u16, Vec<String>>) -> Result<HashSet<u16>> {
+        let output = self.run()?;
+        let ports = parse_hook_output(&output)
+            .with_context(|| format!("Invalid output from '{}'", self.path.display()))?;
+        for &port in &ports {
+            names.entry(port).or_default().push(self.name.clone());
+        }
+        Ok(ports)
+    }
+
+    fn plan(&self) -> PlannedSource {
+        PlannedSource::new(&self.name, self.path.display().to_string())
+    }
+}
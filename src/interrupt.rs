@@ -0,0 +1,65 @@
+#[cfg(feature = "native")]
+use anyhow::{Context, Result};
+use std::process::Child;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// Ctrl-C presses seen so far.
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+/// Process group of the external command currently running, or 0 if there is none.
+static CHILD_GROUP: AtomicU32 = AtomicU32::new(0);
+
+/// Exit code after a second Ctrl-C, following the shell convention of 128 + SIGINT.
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Installs the Ctrl-C handler.
+///
+/// The first Ctrl-C only sets a flag: the running command is stopped and portpick continues
+/// with whatever it has collected. A second one kills the command and exits immediately.
+#[cfg(feature = "native")]
+pub fn install_interrupt_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTS.fetch_add(1, Ordering::SeqCst) > 0 {
+            kill_running_child();
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+    })
+    .context("Failed to install the Ctrl-C handler")
+}
+
+/// Whether Ctrl-C has been pressed.
+pub fn is_interrupted() -> bool {
+    INTERRUPTS.load(Ordering::SeqCst) > 0
+}
+
+/// Records `child` as the command to kill on a second Ctrl-C. It must lead its own process
+/// group.
+pub(crate) fn register_child(child: &Child) {
+    CHILD_GROUP.store(child.id(), Ordering::SeqCst);
+}
+
+pub(crate) fn clear_child() {
+    CHILD_GROUP.store(0, Ordering::SeqCst);
+}
+
+#[cfg(feature = "native")]
+fn kill_running_child() {
+    let group = CHILD_GROUP.swap(0, Ordering::SeqCst);
+    if group != 0 {
+        kill_process_group(group);
+    }
+}
+
+/// Sends SIGTERM to every process in the group led by `pid`, so helpers the command spawned
+/// go too.
+#[cfg(unix)]
+pub(crate) fn kill_process_group(pid: u32) {
+    if let Ok(pid) = libc::pid_t::try_from(pid) {
+        // SAFETY: killpg only sends a signal; a stale group id at worst fails with ESRCH.
+        unsafe {
+            libc::killpg(pid, libc::SIGTERM);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn kill_process_group(_pid: u32) {}
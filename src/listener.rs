@@ -0,0 +1,187 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// A listening TCP socket: the address it is bound to and its port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Listener {
+    pub address: IpAddr,
+    pub port: u16,
+}
+
+/// The program holding a listening socket, as far as `ss -p` or `lsof` could tell.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessHint {
+    pub program: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+}
+
+/// A listening socket with the process behind it; `process` is `None` when the tool wasn't
+/// allowed to see it, as for other users' sockets without root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenerProcess {
+    pub listener: Listener,
+    pub process: Option<ProcessHint>,
+}
+
+/// Whether a socket listening on `listener` stops us from binding the same port on `bind`.
+///
+/// Equal addresses conflict, and a wildcard conflicts with every address of its family. The
+/// IPv6 wildcard is assumed to be dual-stack (the Linux default), so it also conflicts with
+/// every IPv4 address, in either role. IPv4-mapped IPv6 addresses count as IPv4.
+pub fn addresses_conflict(listener: IpAddr, bind: IpAddr) -> bool {
+    let (listener, bind) = (listener.to_canonical(), bind.to_canonical());
+    if listener == bind {
+        return true;
+    }
+    let is_v6_wildcard = |address: IpAddr| address == IpAddr::V6(Ipv6Addr::UNSPECIFIED);
+    if is_v6_wildcard(listener) || is_v6_wildcard(bind) {
+        return true;
+    }
+    match (listener, bind) {
+        (IpAddr::V4(listener), IpAddr::V4(bind)) => {
+            listener.is_unspecified() || bind.is_unspecified()
+        }
+        _ => false,
+    }
+}
+
+/// Ports that can't be bound on `bind` because of `listeners`.
+pub fn ports_conflicting_with(listeners: &[Listener], bind: IpAddr) -> HashSet<u16> {
+    listeners
+        .iter()
+        .filter(|listener| addresses_conflict(listener.address, bind))
+        .map(|listener| listener.port)
+        .collect()
+}
+
+/// Extracts listening sockets from `/proc/net/tcp` or `/proc/net/tcp6` content.
+///
+/// Only sockets in the LISTEN state (`0A`) count; header lines and malformed rows are skipped.
+/// Addresses are decoded assuming a little-endian kernel, as on x86 and ARM.
+pub fn parse_proc_net_listeners(content: &str) -> Vec<Listener> {
+    const TCP_LISTEN: &str = "0A";
+    content
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (local_address, state) = (fields.get(1)?, fields.get(3)?);
+            if *state != TCP_LISTEN {
+                return None;
+            }
+            let (address_hex, port_hex) = local_address.rsplit_once(':')?;
+            Some(Listener {
+                address: decode_proc_address(address_hex)?,
+                port: u16::from_str_radix(port_hex, 16).ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Decodes the hex address column: one (IPv4) or four (IPv6) 32-bit words in host byte order.
+fn decode_proc_address(hex: &str) -> Option<IpAddr> {
+    let word = |chunk: &str| u32::from_str_radix(chunk, 16).ok().map(u32::to_le_bytes);
+    match hex.len() {
+        8 => Some(IpAddr::V4(Ipv4Addr::from(word(hex)?))),
+        32 => {
+            let mut octets = [0u8; 16];
+            for (index, chunk) in octets.chunks_mut(4).enumerate() {
+                chunk.copy_from_slice(&word(hex.get(index * 8..index * 8 + 8)?)?);
+            }
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
+
+/// Extracts listening sockets from `ss -Hlnt` output.
+///
+/// The local address is the fourth column, e.g. `127.0.0.53%lo:53`, `[::]:22`, or `*:631`.
+/// `*` is read as the dual-stack wildcard.
+pub fn parse_ss_listeners(output: &str) -> Vec<Listener> {
+    parse_ss_listener_processes(output)
+        .into_iter()
+        .map(|entry| entry.listener)
+        .collect()
+}
+
+/// Like [`parse_ss_listeners`], for `ss -Hlntp` output, whose last column names the owning
+/// processes, e.g. `users:(("nginx",pid=812,fd=6),("nginx",pid=811,fd=6))`. The first one is
+/// kept.
+pub fn parse_ss_listener_processes(output: &str) -> Vec<ListenerProcess> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let local = line.split_whitespace().nth(3)?;
+            let (address, port) = local.rsplit_once(':')?;
+            let address = address.trim_start_matches('[').trim_end_matches(']');
+            // Drop a scope such as `%lo` or `%eth0`.
+            let address = address.split('%').next()?;
+            let address = match address {
+                "*" => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                other => IpAddr::from_str(other).ok()?,
+            };
+            Some(ListenerProcess {
+                listener: Listener {
+                    address,
+                    port: u16::from_str(port).ok()?,
+                },
+                process: line
+                    .split_once("users:((")
+                    .and_then(|(_, users)| parse_ss_user(users)),
+            })
+        })
+        .collect()
+}
+
+/// Reads `"nginx",pid=812,fd=6)...` into the program name and PID.
+fn parse_ss_user(users: &str) -> Option<ProcessHint> {
+    let rest = users.strip_prefix('"')?;
+    let (program, rest) = rest.split_once('"')?;
+    let fields = rest.split(')').next()?;
+    let pid = fields
+        .split(',')
+        .find_map(|field| field.strip_prefix("pid="))
+        .and_then(|pid| pid.parse().ok());
+    Some(ProcessHint {
+        program: program.to_string(),
+        pid,
+    })
+}
+
+/// Extracts listening sockets from `lsof -nP -iTCP -sTCP:LISTEN` output.
+///
+/// Every row names its process: COMMAND and PID are the first two columns, and NAME, the
+/// last before `(LISTEN)`, holds the address, e.g. `*:22`, `127.0.0.1:5432`, or `[::1]:6379`.
+/// `*` is read as the dual-stack wildcard. The header and other rows are skipped.
+pub fn parse_lsof_listeners(output: &str) -> Vec<ListenerProcess> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.last() != Some(&"(LISTEN)") || fields.len() < 4 {
+                return None;
+            }
+            let name = fields[fields.len() - 2];
+            let (address, port) = name.rsplit_once(':')?;
+            let address = address.trim_start_matches('[').trim_end_matches(']');
+            let address = match address {
+                "*" => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                other => IpAddr::from_str(other.split('%').next()?).ok()?,
+            };
+            Some(ListenerProcess {
+                listener: Listener {
+                    address,
+                    port: u16::from_str(port).ok()?,
+                },
+                process: Some(ProcessHint {
+                    // lsof escapes spaces and other odd bytes in names as `\x20`.
+                    program: fields[0].replace("\\x20", " "),
+                    pid: fields[1].parse().ok(),
+                }),
+            })
+        })
+        .collect()
+}
@@ -1,485 +1,4328 @@
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use colored::*;
+use indicatif::HumanBytes;
 use rand::prelude::IndexedRandom; // For the .choose() method on slices
-use std::collections::HashSet;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::process::Command;
-use std::str::FromStr;
+use std::io::{IsTerminal, Read, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 // Import functions from the library crate
-use portpick::{find_available_ports, parse_services_content};
+use portpick::{
+    AddressScope, Announced, BatchRequest, BlockReason, BlockStrategy, BuildInfo,
+    BuiltinServicesSource, CONFIG_FILE_ENV, CacheInfo, CachedScanner, CheckStatus, CollectOptions,
+    Config, ContainerInfo, ContainerScanner, DEFAULT_CACHE_LOCK_WAIT, DEFAULT_DEVCONTAINER_PATH,
+    DEFAULT_ENV_TEMPLATE, DEFAULT_MASSCAN_RATE, DEFAULT_NEIGHBORHOOD, DEFAULT_PORT_RANGES,
+    DEFAULT_SCAN_CACHE_TTL, DEFAULT_USER_AGENT, DesktopNotifier, DoctorCheck, EnvCase, EnvNaming,
+    FallbackScanner, FileStamp, ForbiddenContext, GroupAllocation, LogFormat, MasscanScanner,
+    NEIGHBORHOOD_KEY, NmapCache, NmapScanner, PROJECT_FILE, PROVIDERS_DIR_ENV, ParsedServices,
+    PickOptions, PickOutcome, Plan, PlannedSource, PortScanner, PortSet, PortSource, PortStatus,
+    Privileges, ProcessHint, ProfileSource, ProjectSpec, Protocol, Provenance, ProviderHook,
+    Refresh, RejectionReason, Reporter, RustScanScanner, SCHEMA_VERSION, SESSION_FORBID_ENV,
+    SYSTEM_SERVICES_PATH, ScanCache, ScanTarget, ScannerChoice, ServicesCache, Snapshot, SortOrder,
+    SourceChain, SourceKind, SsScanner, Suggestion, SystemClock, SystemCommandRunner,
+    TooFewKnownPorts, WaitQuorum, WarningCode, WindowsHostScanner, bind_succeeds, byte_bar,
+    check_port, check_schema_version, check_services_cache, check_services_file, check_tool,
+    check_url, check_writable_dir, classify_address, client_builder, collect_forbidden,
+    compose_service_names, connect_succeeds, count_free, decode_services_text, default_config_path,
+    default_gateway, default_providers_dir, describe_age, detect_ci, detect_container,
+    discover_hooks, download_services_to, env_var_names, header_map, install_interrupt_handler,
+    is_interrupted, is_wsl, listening_processes, local_hostname, masked_headers,
+    merge_compose_override, notify_completion, occupancy_bands, override_conflicts,
+    parse_byte_size, parse_env_assignments, parse_http_header, parse_service_frequencies,
+    parse_services_map, parse_session_forbid, ports_above_frequency, redirect_policy, render_caddy,
+    render_compose, render_docker_mappings, render_env, render_grep, render_markdown,
+    render_neighborhood, render_nginx, render_systemd_units, render_template, resolve_batch,
+    resolve_host, sanitized_command_line, session_export_line, session_forbid_value, spinner,
+    template_placeholders, timing_summary, unprivileged_port_start, update_devcontainer,
+    update_env_file, update_managed_block, validate_request, validate_services_content,
+    wait_for_ports, warn_unbindable,
+};
 
-const SYSTEM_SERVICES_PATH: &str = "/etc/services"; // Standard path for system services file
 const REMOTE_NMAP_SERVICES_URL: &str = "https://svn.nmap.org/nmap/nmap-services"; // URL for official Nmap services
-const LOCAL_NMAP_CACHE_PATH: &str = "src/nmap-services.cache"; // Path for the local Nmap services cache
+const RUSTSCAN_PROGRAM_ENV: &str = "PORTPICK_RUSTSCAN"; // Overrides the rustscan binary that is run
+const LOG_FILE_ENV: &str = "PORTPICK_LOG"; // --log-file when the flag isn't given
+const MIN_KNOWN_SERVICE_PORTS: usize = 1; // Fewer known-service ports needs --allow-empty-sources
+
+/// Set when --background-refresh left a stale Nmap cache to refresh once the results are out.
+static BACKGROUND_REFRESH_DUE: AtomicBool = AtomicBool::new(false);
+
+/// Set when --notify has a run to announce once the results are out.
+static PENDING_ANNOUNCEMENT: Mutex<Option<Completion>> = Mutex::new(None);
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    /// Human-readable list
+    Text,
+    /// Suggestions serialized as JSON
+    Json,
+    /// Suggestions serialized as YAML
+    Yaml,
+    /// One JSON object per suggestion, then a `"type":"summary"` object (JSON Lines). The
+    /// lines are written once every port is picked, sorted, and verified, not during the search
+    Jsonl,
+    /// A docker-compose `services:` fragment (requires --names)
+    Compose,
+    /// systemd `.socket` units with one ListenStream= per port
+    Systemd,
+    /// nginx upstream/server blocks proxying to the ports
+    Nginx,
+    /// Caddyfile site blocks with reverse_proxy to the ports
+    Caddy,
+    /// NAME=PORT lines for .env files
+    Env,
+    /// `export NAME=PORT` lines for sourcing in a shell
+    Shell,
+    /// A GitHub-flavored Markdown table, for PR descriptions and wiki pages
+    Markdown,
+    /// One nmap-style line with fixed labels, for grep and awk:
+    /// `Host: ... Ports: ... Continuous: ... Source: ... Forbidden: ...`
+    Grep,
+}
+
+/// When to color terminal output.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ColorChoice {
+    /// Color when writing to a terminal that allows it
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Summarize how many ports are forbidden across the search ranges
+    Stats(StatsArgs),
+    /// Show what the services sources call the given ports and whether they are in use locally
+    Whatis(WhatisArgs),
+    /// Check that the tools, files, and directories portpick relies on are usable
+    Doctor(DoctorArgs),
+    /// Resolve the ports a project's portpick.yaml asks for into an env file
+    Apply(ApplyArgs),
+    /// Pick ports and substitute them for the {{PORT_<NAME>}} placeholders in a template file
+    Render(RenderArgs),
+    /// Pick host ports for a compose service's container ports and emit a
+    /// docker-compose.override.yml publishing them, or merge them into one with --out
+    ComposeOverride(ComposeOverrideArgs),
+    /// Save the forbidden ports to a file, or list what changed since one was saved
+    Snapshot(SnapshotArgs),
+    /// Wait until ports are free, e.g. for an old process to let go of them, or with
+    /// --until used until a service listens on them. Gives up with code 2 when --timeout is
+    /// reached
+    Wait(WaitArgs),
+    /// Report whether ports are free, every reason if not, and which process listens on them.
+    /// Exits with 4 if any is blocked
+    Check(CheckArgs),
+    /// Pick ports for many requests read from stdin, one per line, in a single scan. Each
+    /// request's ports are forbidden for the ones after it
+    Batch(BatchArgs),
+    /// Print the JSON Schema of --output json and yaml for --schema-version, for validating
+    /// what portpick emits
+    Schema,
+    /// Render man pages from the command-line definition
+    #[clap(hide = true)]
+    Man(ManArgs),
+}
+
+#[derive(Args, Debug)]
+struct ApplyArgs {
+    /// Project file listing the ports to resolve
+    #[clap(long, value_name = "PATH", default_value = PROJECT_FILE)]
+    file: PathBuf,
+
+    /// Env file the resolved ports are written to; other lines in it are kept
+    #[clap(long, value_name = "PATH", default_value = ".ports.env")]
+    env_file: PathBuf,
+
+    /// Only check that the ports already in the env file are still free, exiting with 1 if not
+    #[clap(long)]
+    check_only: bool,
+}
+
+#[derive(Args, Debug)]
+struct RenderArgs {
+    /// Template whose {{PORT_<NAME>}} placeholders are replaced, named by --names or --spec
+    #[clap(long, value_name = "PATH")]
+    template: PathBuf,
+
+    /// Write the result here instead of stdout; the file is replaced atomically
+    #[clap(long, value_name = "PATH")]
+    out: Option<PathBuf>,
+
+    /// Replace --out if it already exists
+    #[clap(long, requires = "out")]
+    overwrite: bool,
+
+    /// Leave placeholders no picked port matches as they are instead of failing
+    #[clap(long)]
+    keep_unknown: bool,
+}
+
+#[derive(Args, Debug)]
+struct ComposeOverrideArgs {
+    /// Compose file defining the service
+    #[clap(long, value_name = "PATH", default_value = "docker-compose.yml")]
+    compose_file: PathBuf,
+
+    /// Service whose container ports are published
+    #[clap(long, value_name = "NAME")]
+    service: String,
+
+    /// Comma-separated container ports, each given its own picked host port
+    #[clap(long, required = true, value_delimiter = ',')]
+    container_port: Vec<u16>,
+
+    /// Override file to merge into, keeping its other keys, and replace atomically instead of
+    /// printing to stdout (e.g., docker-compose.override.yml)
+    #[clap(long, value_name = "PATH")]
+    out: Option<PathBuf>,
+
+    /// Replace ports entries of --out that already publish one of the container ports
+    #[clap(long, requires = "out")]
+    overwrite: bool,
+}
+
+#[derive(Args, Debug)]
+#[clap(group(clap::ArgGroup::new("mode").required(true).multiple(true)))]
+struct SnapshotArgs {
+    /// Write the current forbidden ports and their sources to this file
+    #[clap(long, value_name = "PATH", group = "mode")]
+    save: Option<PathBuf>,
+
+    /// List ports that became used, became free, or changed source since this snapshot
+    #[clap(long, value_name = "PATH", group = "mode")]
+    diff: Option<PathBuf>,
+}
+
+/// What `wait` waits for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum WaitUntil {
+    /// Nothing holds the port any more
+    Free,
+    /// Something listens on the port
+    Used,
+}
+
+/// How `wait` decides whether a port is in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum WaitProbe {
+    /// Try to bind the port on --bind-address (default 0.0.0.0)
+    Bind,
+    /// Try to connect to the port on --address (default 127.0.0.1)
+    Connect,
+    /// Scan --address with the configured scanner
+    Scanner,
+}
+
+#[derive(Args, Debug)]
+struct WaitArgs {
+    /// Ports to wait for (repeat or comma-separate)
+    #[clap(
+        long = "port",
+        value_name = "PORT",
+        required = true,
+        value_delimiter = ','
+    )]
+    ports: Vec<u16>,
+
+    /// Whether to wait for the ports to be released or for a service to listen on them
+    #[clap(long, value_enum, default_value_t = WaitUntil::Free)]
+    until: WaitUntil,
+
+    /// Time between polls (e.g., 1s, 250ms)
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration, default_value = "1s")]
+    interval: Duration,
+
+    /// Wait until every port is ready (the default)
+    #[clap(long, conflicts_with = "any")]
+    all: bool,
+
+    /// Wait until at least one port is ready
+    #[clap(long)]
+    any: bool,
+
+    /// How to tell whether a port is in use [default: bind with --until free, connect with
+    /// --until used]
+    #[clap(long, value_enum)]
+    probe: Option<WaitProbe>,
+
+    /// How long each connection attempt of --probe connect may take
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration, default_value = "1s")]
+    connect_timeout: Duration,
+
+    /// Don't print a progress dot per poll
+    #[clap(short, long)]
+    quiet: bool,
+}
+
+#[derive(Args, Debug)]
+struct ManArgs {
+    /// Write portpick.1 and one page per subcommand here instead of printing portpick.1
+    #[clap(long, value_name = "DIR")]
+    out_dir: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct DoctorArgs {
+    /// Don't check that the Nmap services URL is reachable
+    #[clap(long)]
+    offline: bool,
+}
+
+#[derive(Args, Debug)]
+struct WhatisArgs {
+    /// Ports to look up
+    #[clap(required = true, value_name = "PORT")]
+    ports: Vec<u16>,
+}
+
+#[derive(Args, Debug)]
+struct BatchArgs {
+    /// Fail on the first malformed line instead of reporting it as an error result
+    #[clap(long)]
+    strict: bool,
+}
+
+#[derive(Args, Debug)]
+struct CheckArgs {
+    /// Ports to check
+    #[clap(required = true, value_name = "PORT", value_delimiter = ',')]
+    ports: Vec<u16>,
+}
+
+#[derive(Args, Debug)]
+struct StatsArgs {
+    /// Also print forbidden-port counts per 1000-port band with a bar
+    #[clap(long)]
+    bands: bool,
+}
 
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)] // -h will now default to help
+#[clap(author, version, about, long_about = None, disable_version_flag = true)] // -h will now default to help
 struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Print version information; with --verbose or --output json, also the build and cache
+    /// provenance
+    #[clap(short = 'V', long)]
+    version: bool,
+
     /// Target address for RustScan to scan (e.g., 127.0.0.1, localhost, example.com)
-    #[clap(short = 'a', long)]
+    #[clap(short = 'a', long, global = true)]
     address: Option<String>,
 
-    /// Source for the list of known service ports [possible values: system, nmap, cache]
-    #[clap(short = 's', long, default_value = "system")]
+    /// Source for the list of known service ports [possible values: system, nmap, cache, all]
+    ///
+    /// `system` reads the services file (/etc/services unless --services-file says otherwise).
+    /// `nmap` downloads the Nmap project's nmap-services list, which names far more ports, and
+    /// saves a copy to $XDG_CACHE_HOME/portpick/nmap-services.cache (~/.cache/portpick by
+    /// default). `cache` reads that copy without going online,
+    /// falling back to the system file if there is none. `all` avoids the ports of every list:
+    /// the services file, the built-in list, and the cache while it is within --cache-max-age
+    /// or a fresh download otherwise. Unknown values mean `system`.
+    #[clap(short = 's', long, default_value = "system", global = true)]
     source: String,
 
-    /// Number of ports to find
-    #[clap(short, long, default_value_t = 1)]
-    number_of_ports: u16,
+    /// Services lists to try in turn until one gives ports, e.g. cache,nmap,builtin,system
+    ///
+    /// Unlike --source, only the first list that can be read and names at least one port is
+    /// used; the rest are never read. `cache` here doesn't fall back to the system file by
+    /// itself, and isn't refreshed when stale. Replaces --source, --system, and --nmap.
+    #[clap(long, value_name = "LIST", value_parser = parse_source_order, global = true)]
+    source_order: Option<SourceOrder>,
+
+    /// Number of ports to find [default: 1, or the number of --names]
+    #[clap(short, long)]
+    number_of_ports: Option<u16>,
+
+    /// Comma-separated names to attach to the suggested ports, in order (e.g., web,db)
+    #[clap(long, value_delimiter = ',')]
+    names: Vec<String>,
+
+    /// Allocate labeled groups, each as `name:count` with a `c` suffix for a continuous block
+    /// (e.g., web:1,db:1,workers:3c)
+    #[clap(
+        long,
+        value_name = "SPEC",
+        conflicts_with_all = ["names", "number_of_ports", "continuous", "random", "verify", "check", "count_only"]
+    )]
+    spec: Option<String>,
+
+    /// Comma-separated container ports for --output compose and markdown, paired with ports in order
+    /// [default: same as the host port]
+    #[clap(long, value_delimiter = ',')]
+    container_port: Vec<u16>,
 
     /// Require the found ports to be a continuous block
+    ///
+    /// The ports are consecutive, e.g. 40000-40002 for -n 3. A block never spans two --within
+    /// ranges, so it can be no larger than the largest one; a request that can't fit is rejected
+    /// before anything is scanned.
     #[clap(short, long)]
     continuous: bool,
 
-    /// Output ports in Docker-compose format (e.g., 8080:)
-    #[clap(short, long)]
-    docker_format: bool,
+    /// Also pick K backups for each port, for when one turns out to be unusable
+    ///
+    /// Alternates overlap neither each other nor the suggested ports. With --continuous each
+    /// backup is a whole block. They are picked first fit and are not bind-checked.
+    #[clap(long, value_name = "K", default_value_t = 0)]
+    alternates: u16,
+
+    /// Output ports in Docker-compose format (e.g., 8080:)
+    #[clap(short, long)]
+    docker_format: bool,
+
+    /// With --docker-format, pair the ports with container ports counting up from this one,
+    /// in output order (e.g., 40000:8000, 40001:8001)
+    #[clap(long, value_name = "PORT", requires = "docker_format")]
+    container_port_base: Option<u16>,
+
+    /// With --container-port-base, print consecutive ascending host ports as one compose range
+    /// mapping (e.g., 40000-40002:8000-8002)
+    #[clap(long, requires = "container_port_base")]
+    range_syntax: bool,
+
+    /// Print all ports on one line, without headers (e.g., for `docker run -p $(...)`)
+    #[clap(long, conflicts_with = "docker_format")]
+    inline: bool,
+
+    /// Keep the header, list markers, and color even when stdout is not a terminal
+    #[clap(long, visible_alias = "no-auto-plain")]
+    pretty: bool,
+
+    /// Leave out the dimmed note after each port in the human output saying which range it is
+    /// in: registered (1024-49151) or dynamic (49152-65535), and with --within, which of its
+    /// ranges
+    #[clap(long)]
+    no_annotations: bool,
+
+    /// Terminate each port with a NUL byte instead of a newline, with no header (for xargs -0)
+    #[clap(long, conflicts_with_all = ["docker_format", "inline"])]
+    print0: bool,
+
+    /// Announce the suggested ports with a desktop notification when the run took longer than
+    /// --notify-after, ringing the terminal bell where no notification can be shown
+    #[clap(long)]
+    notify: bool,
+
+    /// How long a run takes before --notify announces it
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration, default_value = "10s", requires = "notify")]
+    notify_after: Duration,
+
+    /// Also print `export PORTPICK_SESSION_FORBID=<ports>` after the results: the ports
+    /// $PORTPICK_SESSION_FORBID already held plus the ones just picked. Later runs that see it
+    /// avoid them, so calls in one shell session or Makefile don't hand out the same port.
+    /// Without this flag the line goes to stderr whenever the variable is set. Works with
+    /// --output text and shell:
+    ///
+    ///     export PORTPICK_SESSION_FORBID=
+    ///     eval "$(portpick --emit-session --output shell --names web)"
+    #[clap(long, verbatim_doc_comment, conflicts_with = "print0")]
+    emit_session: bool,
+
+    /// Separator between ports with --inline
+    #[clap(long, value_name = "SEP", default_value = " ", requires = "inline")]
+    joiner: String,
+
+    /// Enable verbose output; -vv also maps the ports around each suggestion
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Ports on each side of a suggestion the -vv neighborhood map shows
+    #[clap(long, value_name = "PORTS", default_value_t = DEFAULT_NEIGHBORHOOD)]
+    neighborhood: u16,
+
+    /// Append every diagnostic, verbose or not, to this file with a timestamp and level.
+    /// Defaults to $PORTPICK_LOG
+    #[clap(long, value_name = "PATH", global = true)]
+    log_file: Option<PathBuf>,
+
+    /// Line format for --log-file
+    #[clap(long, value_enum, value_name = "FORMAT", default_value_t = LogFormat::Text, global = true)]
+    log_format: LogFormat,
+
+    /// Force port suggestion even if local port checking (e.g., rustscan) fails.
+    /// This may result in less accurate suggestions.
+    ///
+    /// Without it a failed scan is an error, since the suggestions could collide with ports that
+    /// are in use. With it portpick warns, continues with the services list alone, and adds a
+    /// W_SCANNER_FAILED warning to machine-readable output. Use --skip-local-scan to not scan at
+    /// all.
+    #[clap(short, long, global = true)]
+    force: bool,
+
+    /// Scan as usual in CI pipelines instead of bind-probing the suggestions
+    #[clap(long, global = true)]
+    no_ci_detection: bool,
+
+    /// Inside a container, scan and read the services file as usual instead of bind-probing
+    /// the suggestions and using the built-in services list
+    #[clap(long, global = true)]
+    no_container_detection: bool,
+
+    /// Don't scan for locally used ports at all; only known services are avoided
+    #[clap(
+        long,
+        visible_alias = "no-local",
+        global = true,
+        overrides_with = "local"
+    )]
+    skip_local_scan: bool,
+
+    /// Scan for locally used ports (the default; overrides an earlier --no-local)
+    #[clap(long, global = true, overrides_with = "skip_local_scan")]
+    local: bool,
+
+    /// Pick candidates first and check only those for local use, instead of scanning every
+    /// port up front; busy candidates are replaced and checked in turn
+    #[clap(long)]
+    fast: bool,
+
+    /// Print which sources would be read, with which scanner on which address, the search
+    /// ranges, and the selection strategy, then exit without reading, fetching, scanning, or
+    /// picking anything
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Read the system services file even if --source picks the Nmap list
+    #[clap(long, global = true, overrides_with = "no_system")]
+    system: bool,
+
+    /// Don't read the system services file
+    #[clap(long, global = true, overrides_with = "system")]
+    no_system: bool,
+
+    /// Also use the Nmap services list (fetched, or cached with --source cache)
+    #[clap(long, global = true, overrides_with = "no_nmap")]
+    nmap: bool,
+
+    /// Don't use the Nmap services list
+    #[clap(long, global = true, overrides_with = "nmap")]
+    no_nmap: bool,
+
+    /// Delete the Nmap services cache when it fails validation (it is ignored either way)
+    #[clap(long, global = true)]
+    prune_bad_cache: bool,
+
+    /// With --source cache, refresh the Nmap services cache first when it is older than this
+    /// (e.g. 7d, 12h); a failed refresh warns and uses the old copy
+    #[clap(long, value_name = "AGE", value_parser = parse_age, default_value = "7d", global = true)]
+    cache_max_age: Duration,
+
+    /// Refresh a cache older than --cache-max-age after printing the results, in a detached
+    /// process, instead of before; this run uses the old copy
+    #[clap(long, global = true)]
+    background_refresh: bool,
+
+    /// Only refresh the Nmap services cache; what --background-refresh runs
+    #[clap(long, global = true, hide = true)]
+    refresh_nmap_cache: bool,
+
+    /// Carry on when no services list gives any ports, keeping suggestions off only a small
+    /// built-in list of common services
+    #[clap(long, global = true)]
+    allow_empty_sources: bool,
+
+    /// Detect used ports inside another network namespace instead of scanning --address.
+    /// Supported: container:<name|id> (via `docker exec`)
+    #[clap(long, value_name = "SPEC", global = true)]
+    netns: Option<String>,
+
+    /// Address the ports will be bound on; listeners elsewhere only block a port if their
+    /// address conflicts (wildcards conflict with everything). Uses `ss` instead of rustscan.
+    #[clap(long, value_name = "IP", global = true)]
+    bind_address: Option<IpAddr>,
+
+    /// Backend for the local scan: auto picks ss with --bind-address, and otherwise the first of
+    /// rustscan, nmap, and masscan that is installed and can run
+    #[clap(long, value_enum, default_value_t = ScannerChoice::Auto, global = true)]
+    scanner: ScannerChoice,
+
+    /// Packets per second for --scanner masscan
+    #[clap(long, value_name = "PPS", default_value_t = DEFAULT_MASSCAN_RATE, global = true)]
+    masscan_rate: u32,
+
+    /// Have nmap give up on the host after DURATION (its --host-timeout), e.g. 30s
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration, global = true)]
+    scan_timeout: Option<Duration>,
+
+    /// Reuse a local scan of the same target from the last SECONDS seconds (0 disables)
+    #[clap(long, value_name = "SECONDS", default_value_t = DEFAULT_SCAN_CACHE_TTL.as_secs(), global = true)]
+    scan_cache_ttl: u64,
+
+    /// Ignore services lists and avoid only ports the local scan finds in use
+    #[clap(long, global = true, conflicts_with = "skip_local_scan")]
+    local_only: bool,
+
+    /// Also forbid the N ports above and below every named service (e.g., 3 keeps
+    /// 5429-5435 clear of PostgreSQL's 5432)
+    #[clap(long, value_name = "N", default_value_t = 0, global = true)]
+    avoid_family: u16,
+
+    /// Let the Nmap services list forbid only ports seen open more often than F (0-1, e.g.
+    /// 0.0005), whatever their name. Turns the Nmap list on (fetched, or cached with
+    /// --source cache); ports it doesn't list count as never open
+    #[clap(long, value_name = "F", value_parser = parse_frequency, global = true)]
+    max_frequency: Option<f64>,
+
+    /// Also forbid ports whose service name is "unknown" (common in nmap-services)
+    #[clap(long, global = true)]
+    include_unknown: bool,
+
+    /// Output format for the suggested ports
+    #[clap(short, long, value_enum, default_value_t = OutputFormat::Text, global = true)]
+    output: OutputFormat,
+
+    /// Layout of --output json, yaml, and jsonl. Additions keep the version; a breaking change
+    /// gets a new one that is only emitted when asked for here
+    #[clap(long, value_name = "N", value_parser = parse_schema_version_arg, default_value_t = SCHEMA_VERSION, global = true)]
+    schema_version: u32,
+
+    /// With --output systemd, put all ports into a single socket unit
+    #[clap(long)]
+    one_unit: bool,
+
+    /// With --output systemd, write the units into DIR instead of printing them
+    /// [default DIR: ~/.config/systemd/user]
+    #[clap(long, value_name = "DIR", num_args = 0..=1)]
+    output_file: Option<Option<PathBuf>>,
+
+    /// Virtual host for --output nginx/caddy; named ports become <name>.<SERVER_NAME>
+    #[clap(long, default_value = "localhost")]
+    server_name: String,
+
+    /// Prefix substituted for {PREFIX} in --env-template (e.g., APP_)
+    #[clap(long, default_value = "")]
+    env_prefix: String,
+
+    /// Letter case of derived environment variable names
+    #[clap(long, value_enum, default_value_t = EnvCase::Upper)]
+    env_case: EnvCase,
+
+    /// Template for environment variable names used by --output env/shell and --write-env.
+    /// {NAME} is the port's --names entry with non-alphanumerics turned into `_`.
+    #[clap(long, default_value = DEFAULT_ENV_TEMPLATE)]
+    env_template: String,
+
+    /// Also write NAME=PORT assignments into this .env file, updating existing entries
+    #[clap(long, value_name = "PATH")]
+    write_env: Option<PathBuf>,
+
+    /// Also write `export NAME=PORT` lines into a direnv .envrc, inside a `# portpick begin`
+    /// ... `# portpick end` block that later runs replace [default PATH: .envrc]
+    #[clap(long, value_name = "PATH", num_args = 0..=1)]
+    write_envrc: Option<Option<PathBuf>>,
+
+    /// Also add the ports to forwardPorts in a devcontainer.json, labeled with --names in
+    /// portsAttributes; comments and formatting are kept
+    /// [default PATH: .devcontainer/devcontainer.json]
+    #[clap(long, value_name = "PATH", num_args = 0..=1)]
+    write_devcontainer: Option<Option<PathBuf>>,
+
+    /// Replace portsAttributes labels in --write-devcontainer that name a port differently
+    #[clap(long, requires = "write_devcontainer")]
+    overwrite: bool,
+
+    /// Pick ports at random from the search ranges instead of the lowest available
+    #[clap(long)]
+    random: bool,
+
+    /// Confirm each suggested port is free by binding it (on --bind-address, or all interfaces)
+    #[clap(long, conflicts_with = "random")]
+    verify: bool,
+
+    /// Seed for --random and --strategy random, making the picks reproducible
+    #[clap(long)]
+    seed: Option<u64>,
+
+    /// Where --continuous places the block: the first free interval that fits, the smallest
+    /// (best), or a random one
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = BlockStrategy::First,
+        requires = "continuous",
+        conflicts_with_all = ["random", "verify"]
+    )]
+    strategy: BlockStrategy,
+
+    /// Order of the reported ports; `none` keeps selection order (which --names follows)
+    #[clap(long, value_enum, default_value_t = SortOrder::Asc)]
+    sort: SortOrder,
+
+    /// Only report how many ports (and continuous blocks of --number-of-ports) are free
+    #[clap(long)]
+    count_only: bool,
+
+    /// Port ranges to search, in order of preference (e.g., 20000-29999 or 3000-3999,8000-8999)
+    /// [default: 1024-49151,49152-65535]
+    #[clap(long, value_name = "SPEC")]
+    within: Option<String>,
+
+    /// Search from port 1 when --within isn't given, instead of from 1024 or where
+    /// net.ipv4.ip_unprivileged_port_start says binding stops needing privileges. Warns when
+    /// this process lacks root or CAP_NET_BIND_SERVICE, so binding a suggested port would fail
+    #[clap(long)]
+    allow_privileged: bool,
+
+    /// Never suggest these ports, e.g. `8080,9000-9100` (same syntax as --within)
+    #[clap(long, value_name = "SPEC", global = true)]
+    exclude: Option<String>,
+
+    /// Apply these profiles, in order: built-in ones forbid their ports (`devtools` covers
+    /// common development servers and databases: 3000, 5173, 5432, 9229, ...), and ones
+    /// defined in the config file set --exclude and --within under the flags given here.
+    /// Repeat or comma-separate to combine
+    #[clap(long, value_name = "NAME", value_delimiter = ',', global = true)]
+    profile: Vec<String>,
+
+    /// Don't run the provider hooks in $PORTPICK_PROVIDERS_DIR
+    /// (~/.config/portpick/providers.d)
+    #[clap(long, global = true)]
+    no_providers: bool,
+
+    /// How long each provider hook may run before it counts as failed
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration, default_value = "10s", global = true)]
+    provider_timeout: Duration,
+
+    /// Config file defining profiles and download settings. Defaults to $PORTPICK_CONFIG,
+    /// else $XDG_CONFIG_HOME/portpick/config.toml (~/.config/portpick/config.toml)
+    #[clap(long, value_name = "PATH", global = true)]
+    config: Option<PathBuf>,
+
+    /// Request header sent when downloading the Nmap services list, as 'Name: value'
+    /// (repeatable; -v shows the names, never the values)
+    #[clap(long = "http-header", value_name = "HEADER", value_parser = parse_header_arg, global = true)]
+    http_header: Vec<String>,
+
+    /// User-Agent sent when downloading the Nmap services list [default: portpick/VERSION]
+    #[clap(long, value_name = "STRING", global = true)]
+    user_agent: Option<String>,
+
+    /// Where the Nmap services list is downloaded from, e.g. an internal mirror
+    #[clap(long, value_name = "URL", default_value = REMOTE_NMAP_SERVICES_URL, global = true)]
+    nmap_url: String,
+
+    /// Largest Nmap services download accepted, e.g. 512K or 20M (K, M, G are powers of 1024);
+    /// a larger one is abandoned and nothing is cached
+    #[clap(long, value_name = "SIZE", default_value = "10M", value_parser = parse_size_arg, global = true)]
+    max_download_size: u64,
+
+    /// Instead of suggesting ports, report whether these ports are free and why not
+    /// (exits 1 if any is blocked)
+    #[clap(
+        long,
+        value_name = "PORT",
+        value_delimiter = ',',
+        conflicts_with = "count_only"
+    )]
+    check: Vec<u16>,
+
+    /// With --count-only, exit non-zero if fewer than N blocks are free
+    #[clap(long, value_name = "N", requires = "count_only")]
+    at_least: Option<u32>,
+
+    /// Upper bound for the whole run, covering fetching, scanning, and selection
+    /// (e.g., 20s, 500ms, 2m). Exits with code 2 when reached
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration, global = true)]
+    timeout: Option<Duration>,
+
+    /// When --timeout is reached, continue with the forbidden ports collected so far
+    #[clap(long, requires = "timeout", global = true)]
+    timeout_degrade: bool,
+
+    /// When to color output
+    #[clap(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto, global = true)]
+    color: ColorChoice,
+
+    /// Services file read by --source system
+    #[clap(long, value_name = "PATH", default_value = SYSTEM_SERVICES_PATH, global = true)]
+    services_file: PathBuf,
+}
+
+// parse_services_content moved to lib.rs
+
+/// Service names of forbidden TCP ports, merged across services sources.
+type ServiceNames = HashMap<u16, Vec<String>>;
+
+/// How often each listed TCP port is seen open, from services lists that carry Nmap frequencies.
+type Frequencies = HashMap<u16, f64>;
+
+/// Parses services content into forbidden ports, also recording the named entries in `names`.
+fn parse_services_into(
+    content: &str,
+    source_description: &str,
+    reporter: &Reporter,
+    include_unknown: bool,
+    names: &mut ServiceNames,
+    frequencies: &mut Frequencies,
+) -> Result<HashSet<u16>> {
+    let parsed = ParsedServices::parse(content, source_description, reporter, include_unknown)?;
+    Ok(record_parsed_services(parsed, names, frequencies))
+}
+
+/// Merges the names and frequencies of `parsed` into the shared ones, returning its ports.
+fn record_parsed_services(
+    parsed: ParsedServices,
+    names: &mut ServiceNames,
+    frequencies: &mut Frequencies,
+) -> HashSet<u16> {
+    for (port, port_names) in parsed.names {
+        let known = names.entry(port).or_default();
+        for name in port_names {
+            if !known.contains(&name) {
+                known.push(name);
+            }
+        }
+    }
+    record_frequencies(parsed.frequencies, frequencies);
+    parsed.ports
+}
+
+/// Merges `found` into `frequencies`, keeping the higher value where sources disagree.
+fn record_frequencies(found: Frequencies, frequencies: &mut Frequencies) {
+    for (port, frequency) in found {
+        let known = frequencies.entry(port).or_insert(frequency);
+        *known = known.max(frequency);
+    }
+}
+
+/// Records the names `content` gives each of `ports` in `names`.
+fn record_service_names(
+    content: &str,
+    ports: &HashSet<u16>,
+    names: &mut ServiceNames,
+) -> Result<()> {
+    for (port, port_names) in parse_services_map(content, Protocol::Tcp, false)? {
+        if !ports.contains(&port) {
+            continue;
+        }
+        let known = names.entry(port).or_default();
+        for name in port_names {
+            if !known.contains(&name) {
+                known.push(name);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses an Nmap services list into forbidden ports: every listed port, or with
+/// --max-frequency only those seen open more often than that.
+fn parse_nmap_into(
+    cli: &Cli,
+    content: &str,
+    source_description: &str,
+    reporter: &Reporter,
+    names: &mut ServiceNames,
+    frequencies_seen: &mut Frequencies,
+) -> Result<HashSet<u16>> {
+    let Some(max_frequency) = cli.max_frequency else {
+        return parse_services_into(
+            content,
+            source_description,
+            reporter,
+            cli.include_unknown,
+            names,
+            frequencies_seen,
+        );
+    };
+    let frequencies = parse_service_frequencies(content, Protocol::Tcp);
+    if frequencies.is_empty() {
+        return Err(anyhow::anyhow!(
+            "The {} has no open frequencies for --max-frequency.",
+            source_description
+        ));
+    }
+    let ports = ports_above_frequency(&frequencies, max_frequency);
+    reporter.info(format!(
+        "--max-frequency {}: forbidding {} of the {} TCP ports in the {}; {} seen open less often stay available.",
+        max_frequency,
+        ports.len(),
+        frequencies.len(),
+        source_description,
+        frequencies.len() - ports.len()
+    ));
+    record_service_names(content, &ports, names)?;
+    record_frequencies(frequencies, frequencies_seen);
+    Ok(ports)
+}
+
+/// Reads the services file at `path`, reusing the parse cached in the state directory while
+/// the file is unchanged.
+fn read_system_services_ports(
+    path: &Path,
+    reporter: &Reporter,
+    include_unknown: bool,
+    names: &mut ServiceNames,
+    frequencies: &mut Frequencies,
+) -> Result<HashSet<u16>> {
+    let cache = state_dir().map(ServicesCache::new);
+    if let Some(cache) = &cache {
+        match cache.load(path, include_unknown) {
+            Ok(Some(parsed)) => {
+                reporter.info(format!(
+                    "Using the cached parse of {} ({} ports); the file is unchanged.",
+                    path.display(),
+                    parsed.ports.len()
+                ));
+                return Ok(record_parsed_services(parsed, names, frequencies));
+            }
+            Ok(None) => {}
+            Err(e) => reporter.verbose_warn(format!(
+                "Warning: Ignoring unreadable services cache: {:#}",
+                e
+            )),
+        }
+    }
+
+    reporter.info(format!(
+        "Reading port data from system services file: {}",
+        path.display()
+    ));
+    // Stamped before reading, so an edit made meanwhile isn't cached as this content.
+    let stamp = FileStamp::of(path);
+    let file_content = read_services_file(path, reporter).with_context(|| {
+        format!(
+            "Failed to read system services file at '{}'",
+            path.display()
+        )
+    })?;
+    let parsed = ParsedServices::parse(
+        &file_content,
+        "system services file",
+        reporter,
+        include_unknown,
+    )?;
+    if let (Some(cache), Ok(stamp)) = (&cache, stamp)
+        && let Err(e) = cache.store(path, include_unknown, stamp, &parsed)
+    {
+        reporter.verbose_warn(format!(
+            "Warning: Could not cache the parsed services file: {:#}",
+            e
+        ));
+    }
+    Ok(record_parsed_services(parsed, names, frequencies))
+}
+
+/// How long to wait for another run's hold on the Nmap cache: [`DEFAULT_CACHE_LOCK_WAIT`],
+/// or less if --timeout comes sooner.
+fn cache_lock_wait(deadline: Option<Instant>) -> Duration {
+    match deadline {
+        Some(deadline) => {
+            DEFAULT_CACHE_LOCK_WAIT.min(deadline.saturating_duration_since(Instant::now()))
+        }
+        None => DEFAULT_CACHE_LOCK_WAIT,
+    }
+}
+
+/// Downloads the Nmap services list and caches it, unless another run refreshes the cache
+/// first, in which case its copy is used.
+fn refresh_nmap_cache(cli: &Cli, reporter: &Reporter, deadline: Option<Instant>) -> Result<String> {
+    let cache = NmapCache::new(nmap_cache_path());
+    let refresh = cache.refresh(cache_lock_wait(deadline), reporter, |sink| {
+        fetch_remote_nmap_services(cli, reporter, deadline, sink)
+    })?;
+    match &refresh {
+        Refresh::Fetched { saved: Err(e), .. } => reporter.warn_with(
+            WarningCode::CacheWriteFailed,
+            format!(
+                "Failed to save fetched Nmap services to cache at {}: {:#}",
+                nmap_cache_path().display(),
+                e
+            ),
+        ),
+        Refresh::Fetched { saved: Ok(()), .. } => reporter.success(format!(
+            "Successfully cached Nmap services to {}",
+            nmap_cache_path().display()
+        )),
+        Refresh::Reused(_) => {}
+    }
+    Ok(decode_or_warn(
+        refresh.content(),
+        &nmap_cache_path().display().to_string(),
+        reporter,
+    ))
+}
+
+/// Refreshes the Nmap services cache if it is older than --cache-max-age, or with
+/// --background-refresh leaves that for after the results are printed. A failed refresh is
+/// warned about, and the old copy used.
+fn refresh_stale_cache(cli: &Cli, reporter: &Reporter, deadline: Option<Instant>) {
+    let cache = NmapCache::new(nmap_cache_path());
+    let now = SystemTime::now();
+    if !cache.is_stale(cli.cache_max_age, now) {
+        return;
+    }
+    let age = describe_age(cache.age(now).unwrap_or_default());
+    if cli.background_refresh {
+        reporter.info(format!(
+            "The Nmap services cache at {} is {} old; using it and refreshing it after printing the results.",
+            nmap_cache_path().display(), age
+        ));
+        BACKGROUND_REFRESH_DUE.store(true, Ordering::SeqCst);
+        return;
+    }
+    reporter.info(format!(
+        "The Nmap services cache at {} is {} old, past --cache-max-age; refreshing it from {}.",
+        nmap_cache_path().display(),
+        age,
+        cli.nmap_url
+    ));
+    if let Err(e) = refresh_nmap_cache(cli, reporter, deadline) {
+        reporter.warn_with(
+            WarningCode::CacheStale,
+            format!(
+                "Could not refresh the Nmap services cache at {}, which is {} old: {:#}. Using it as it is.",
+                nmap_cache_path().display(), age, e
+            ),
+        );
+    }
+}
+
+/// Where `--source all` reads the Nmap list: the cache while it is within --cache-max-age,
+/// otherwise a download, which falls back to the cache if it fails.
+fn freshest_nmap_source(cli: &Cli) -> &'static str {
+    let cache = NmapCache::new(nmap_cache_path());
+    let now = SystemTime::now();
+    match cache.age(now).is_some() && !cache.is_stale(cli.cache_max_age, now) {
+        true => "cache",
+        false => "nmap",
+    }
+}
+
+/// Runs `portpick --refresh-nmap-cache` with this run's arguments, detached and with no
+/// output, so the results aren't held up by the download.
+fn spawn_background_refresh() -> std::io::Result<()> {
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    command
+        .arg("--refresh-nmap-cache")
+        .args(std::env::args_os().skip(1))
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    // Out of the terminal's process group, so a Ctrl-C meant for the shell doesn't reach it.
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    command.spawn().map(drop)
+}
+
+/// Reads the Nmap services cache, waiting for a refresh in progress to finish first.
+///
+/// A cache that fails [`validate_services_content`] (a truncated download, a captive portal's
+/// page) or no longer matches the SHA-256 recorded when it was saved is an `InvalidData`
+/// error, and with --prune-bad-cache it is deleted.
+fn read_nmap_cache(
+    cli: &Cli,
+    reporter: &Reporter,
+    deadline: Option<Instant>,
+) -> std::io::Result<String> {
+    let cache = NmapCache::new(nmap_cache_path());
+    let loaded = match cache.load(cache_lock_wait(deadline), reporter) {
+        Ok(bytes) => validate_services_content(&bytes)
+            .map(|_| bytes)
+            .map_err(|e| format!("{:#}", e)),
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => Err(e.to_string()),
+        Err(e) => return Err(e),
+    };
+    let bytes = match loaded {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            if cli.prune_bad_cache {
+                match cache.remove() {
+                    Ok(()) => reporter.info(format!(
+                        "Deleted the invalid Nmap services cache at {}.",
+                        cache.path().display()
+                    )),
+                    Err(e) => reporter.warn(format!(
+                        "Warning: Could not delete the invalid Nmap services cache at {}: {}",
+                        cache.path().display(),
+                        e
+                    )),
+                }
+            }
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+        }
+    };
+    Ok(decode_or_warn(
+        &bytes,
+        &nmap_cache_path().display().to_string(),
+        reporter,
+    ))
+}
+
+/// Downloads the Nmap services list, writing it to `sink` as it arrives.
+fn fetch_remote_nmap_services(
+    cli: &Cli,
+    reporter: &Reporter,
+    deadline: Option<Instant>,
+    sink: &mut dyn Write,
+) -> Result<Vec<u8>> {
+    reporter.info(format!(
+        "Fetching Nmap services data from: {}",
+        cli.nmap_url
+    ));
+
+    // The policy must outlive this call, so hops are collected and reported afterwards.
+    let redirects = Arc::new(Mutex::new(Vec::new()));
+    let hops = Arc::clone(&redirects);
+    let headers = header_map(&cli.http_header)?;
+    let user_agent = cli.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+    reporter.detail(format!("User-Agent: {}", user_agent));
+    for header in masked_headers(&headers) {
+        reporter.detail(header);
+    }
+    let mut client = client_builder()
+        .user_agent(user_agent)
+        .default_headers(headers)
+        .redirect(redirect_policy(move |from, to| {
+            if let Ok(mut hops) = hops.lock() {
+                hops.push(format!("Redirected from {} to {}", from, to));
+            }
+        }));
+    if let Some(deadline) = deadline {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(anyhow::anyhow!(
+                "--timeout deadline reached before the download started"
+            ));
+        }
+        client = client.timeout(remaining);
+    }
+    let client = client.build().context("Failed to build reqwest client")?;
+
+    let progress = byte_bar(show_progress(cli), "Downloading nmap-services");
+    let result = download_services_to(
+        &client,
+        &cli.nmap_url,
+        &progress,
+        cli.max_download_size,
+        sink,
+    );
+    progress.finish_and_clear();
+    for hop in redirects
+        .lock()
+        .map(|hops| hops.clone())
+        .unwrap_or_default()
+    {
+        reporter.detail(hop);
+    }
+    let downloaded = result?;
+    reporter.detail(format!(
+        "Downloaded {} (SHA-256 {})",
+        HumanBytes(downloaded.content.len() as u64),
+        downloaded.sha256
+    ));
+    Ok(downloaded.content)
+}
+
+/// Decodes services data with [`decode_services_text`], warning when bytes that aren't
+/// UTF-8 had to be replaced.
+fn decode_or_warn(bytes: &[u8], origin: &str, reporter: &Reporter) -> String {
+    let (text, lossy) = decode_services_text(bytes);
+    if lossy {
+        reporter.warn(format!(
+            "Warning: '{}' is not valid UTF-8; unreadable bytes were replaced",
+            origin
+        ));
+    }
+    text
+}
+
+/// Reads a services file, tolerating a byte order mark and bytes that aren't UTF-8.
+fn read_services_file(path: impl AsRef<Path>, reporter: &Reporter) -> std::io::Result<String> {
+    let path = path.as_ref();
+    let bytes = fs::read(path)?;
+    Ok(decode_or_warn(
+        &bytes,
+        &path.display().to_string(),
+        reporter,
+    ))
+}
+
+/// Parses a --max-download-size value.
+fn parse_size_arg(value: &str) -> Result<u64, String> {
+    parse_byte_size(value).map_err(|e| format!("{:#}", e))
+}
+
+fn parse_schema_version_arg(value: &str) -> Result<u32, String> {
+    let version = value
+        .trim()
+        .parse::<u32>()
+        .map_err(|_| format!("'{}' is not a schema version", value))?;
+    check_schema_version(version).map_err(|e| format!("{:#}", e))
+}
+
+/// Checks a --http-header value, keeping it as given.
+fn parse_header_arg(value: &str) -> Result<String, String> {
+    parse_http_header(value)
+        .map(|_| value.to_string())
+        .map_err(|e| format!("{:#}", e))
+}
+
+/// Parses an open frequency between 0 and 1.
+fn parse_frequency(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(frequency) if (0.0..=1.0).contains(&frequency) => Ok(frequency),
+        _ => Err(format!(
+            "invalid frequency '{}': expected a number from 0 to 1",
+            value
+        )),
+    }
+}
+
+/// Parses a duration such as `20s`, `500ms`, or `2m`; a bare number means seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{}': expected e.g. 20s, 500ms, 2m", value))?;
+    match unit {
+        "" | "s" => Ok(Duration::from_secs(number)),
+        "ms" => Ok(Duration::from_millis(number)),
+        "m" => number
+            .checked_mul(60)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("invalid duration '{}': too long", value)),
+        _ => Err(format!(
+            "invalid duration unit '{}': expected ms, s, or m",
+            unit
+        )),
+    }
+}
+
+/// Parses a cache age such as `7d` or `12h`, or anything [`parse_duration`] takes.
+fn parse_age(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, hours) = match (value.strip_suffix('d'), value.strip_suffix('h')) {
+        (Some(days), _) => (days, 24),
+        (_, Some(hours)) => (hours, 1),
+        _ => return parse_duration(value),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(hours * 60 * 60))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid age '{}': expected e.g. 7d, 12h, 30m", value))
+}
+
+fn deadline_passed(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+/// Reacts to --timeout expiring during `phase`: exit with code 2, or with --timeout-degrade
+/// carry on with what was collected.
+fn on_timeout(cli: &Cli, reporter: &Reporter, phase: &str) {
+    if cli.timeout_degrade {
+        reporter.warn_with(WarningCode::Timeout, format!(
+            "--timeout reached during {}; continuing with the forbidden ports collected so far (--timeout-degrade). Suggestions may collide with ports in use.",
+            phase
+        ));
+    } else {
+        reporter.error(format!(
+            "Error: --timeout reached during {}. Use --timeout-degrade to continue with partial data.",
+            phase
+        ));
+        std::process::exit(2);
+    }
+}
+
+/// Whether to draw progress on stderr: only for people watching a terminal, and never
+/// alongside machine-readable output.
+fn show_progress(cli: &Cli) -> bool {
+    std::io::stderr().is_terminal() && cli.output == OutputFormat::Text
+}
+
+// get_locally_used_ports moved to lib.rs as RustScanScanner
+
+/// A contributor to the forbidden set, toggled with --<name>/--no-<name>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Provider {
+    /// The system services file (--services-file)
+    System,
+    /// The Nmap services list, fetched or read from the cache depending on --source
+    Nmap,
+    /// Ports found in use by the local scan
+    Local,
+}
+
+/// A services list --source-order can name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OrderedSource {
+    Cache,
+    Nmap,
+    Builtin,
+    System,
+}
+
+impl OrderedSource {
+    const ALL: [OrderedSource; 4] = [
+        OrderedSource::Cache,
+        OrderedSource::Nmap,
+        OrderedSource::Builtin,
+        OrderedSource::System,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            OrderedSource::Cache => "cache",
+            OrderedSource::Nmap => "nmap",
+            OrderedSource::Builtin => "builtin",
+            OrderedSource::System => "system",
+        }
+    }
+}
+
+/// The services lists --source-order tries, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SourceOrder(Vec<OrderedSource>);
+
+/// Parses --source-order: source names separated by commas, each listed once.
+fn parse_source_order(value: &str) -> Result<SourceOrder, String> {
+    let mut order = Vec::new();
+    for name in value.split(',').map(str::trim) {
+        let Some(source) = OrderedSource::ALL
+            .into_iter()
+            .find(|source| source.name().eq_ignore_ascii_case(name))
+        else {
+            return Err(format!(
+                "unknown source '{}': expected {}",
+                name,
+                OrderedSource::ALL.map(OrderedSource::name).join(", ")
+            ));
+        };
+        if order.contains(&source) {
+            return Err(format!("'{}' is listed more than once", source.name()));
+        }
+        order.push(source);
+    }
+    Ok(SourceOrder(order))
+}
+
+/// A [`Provider`] run with the command line's settings, as collect_forbidden expects it.
+struct CliSource<'a> {
+    provider: Provider,
+    /// The provider's name, or what it stands in for as a fallback.
+    name: &'static str,
+    cli: &'a Cli,
+    source: &'a str,
+    /// In a --source-order chain, where a cache that can't be used is an error for the chain
+    /// to move past rather than a reason to read the system file.
+    chained: bool,
+    reporter: &'a Arc<Reporter>,
+    deadline: Option<Instant>,
+    /// Shared by every source, since [`PortSource`] only passes names along.
+    frequencies: &'a Mutex<Frequencies>,
+}
+
+impl PortSource for CliSource<'_> {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn rejection_reason(&self) -> RejectionReason {
+        self.provider.rejection_reason()
+    }
+
+    fn collect(&mut self, names: &mut ServiceNames) -> Result<HashSet<u16>> {
+        // Not as a fallback, where the download has just failed.
+        if self.provider == Provider::Nmap
+            && self.source == "cache"
+            && self.name == self.provider.name()
+        {
+            refresh_stale_cache(self.cli, self.reporter, self.deadline);
+        }
+        // Collected apart and merged after, so sources running alongside don't wait on the lock.
+        let mut frequencies = Frequencies::new();
+        let ports = if self.chained && self.provider == Provider::Nmap && self.source == "cache" {
+            let content =
+                read_nmap_cache(self.cli, self.reporter, self.deadline).with_context(|| {
+                    format!(
+                        "Could not use the Nmap services cache at {}",
+                        nmap_cache_path().display()
+                    )
+                })?;
+            parse_nmap_cache(self.cli, &content, self.reporter, names, &mut frequencies)?
+        } else {
+            self.provider.collect(
+                self.cli,
+                self.source,
+                self.reporter,
+                self.deadline,
+                names,
+                &mut frequencies,
+            )?
+        };
+        if let Ok(mut shared) = self.frequencies.lock() {
+            record_frequencies(frequencies, &mut shared);
+        }
+        Ok(ports)
+    }
+
+    fn lists_services(&self) -> bool {
+        self.provider != Provider::Local
+    }
+
+    fn plan(&self) -> PlannedSource {
+        let cache = || CacheInfo::inspect(&nmap_cache_path(), SystemTime::now());
+        match self.provider {
+            Provider::System => {
+                PlannedSource::new(self.name, self.cli.services_file.display().to_string())
+            }
+            Provider::Nmap if self.source == "cache" => {
+                PlannedSource::new(self.name, nmap_cache_path().display().to_string())
+                    .with_cache(cache())
+            }
+            Provider::Nmap => PlannedSource::new(self.name, &self.cli.nmap_url).with_cache(cache()),
+            Provider::Local => PlannedSource::new(self.name, planned_scan(self.cli)),
+        }
+    }
+}
+
+impl Provider {
+    const ALL: [Provider; 3] = [Provider::System, Provider::Nmap, Provider::Local];
+
+    fn name(self) -> &'static str {
+        match self {
+            Provider::System => "system",
+            Provider::Nmap => "nmap",
+            Provider::Local => "local",
+        }
+    }
+
+    /// Whether the provider runs: explicit toggles win over --source, and --local-only turns
+    /// off every services list.
+    fn is_enabled(self, cli: &Cli, source: &str) -> bool {
+        let (on, off, by_default) = match self {
+            Provider::System => (
+                cli.system,
+                cli.no_system,
+                source == "system" || source == "all",
+            ),
+            Provider::Nmap => (
+                cli.nmap || cli.max_frequency.is_some(),
+                cli.no_nmap,
+                source != "system",
+            ),
+            Provider::Local => return !cli.skip_local_scan,
+        };
+        !cli.local_only && !off && (on || by_default)
+    }
+
+    fn rejection_reason(self) -> RejectionReason {
+        match self {
+            Provider::System | Provider::Nmap => RejectionReason::KnownService,
+            Provider::Local => RejectionReason::LocallyInUse,
+        }
+    }
+
+    fn collect(
+        self,
+        cli: &Cli,
+        source: &str,
+        reporter: &Arc<Reporter>,
+        deadline: Option<Instant>,
+        names: &mut ServiceNames,
+        frequencies: &mut Frequencies,
+    ) -> Result<HashSet<u16>> {
+        match self {
+            Provider::System => system_services(cli, reporter, names, frequencies),
+            Provider::Nmap if source == "cache" => {
+                reporter.info(format!(
+                    "Source 'cache': Attempting to use cached Nmap services from {}...",
+                    nmap_cache_path().display()
+                ));
+                match read_nmap_cache(cli, reporter, deadline) {
+                    Ok(cached_content) => {
+                        parse_nmap_cache(cli, &cached_content, reporter, names, frequencies)
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                        reporter.warn_with(
+                            WarningCode::SourceFallback,
+                            format!(
+                                "Ignoring the Nmap services cache at {}: {}. Falling back to system services.{}",
+                                nmap_cache_path().display(),
+                                e,
+                                if cli.prune_bad_cache {
+                                    ""
+                                } else {
+                                    " Refresh the cache with --source nmap, or delete it with --prune-bad-cache."
+                                }
+                            ),
+                        );
+                        Ok(system_services_or_warn(cli, reporter, names, frequencies))
+                    }
+                    Err(_) => {
+                        reporter.warn_with(WarningCode::SourceFallback, format!("Nmap services cache file not found or unreadable at {}. Falling back to system services.", nmap_cache_path().display()));
+                        Ok(system_services_or_warn(cli, reporter, names, frequencies))
+                    }
+                }
+            }
+            Provider::Nmap => {
+                reporter.info(format!(
+                    "Source 'nmap': Attempting to fetch, cache, and parse Nmap services list from {}...",
+                    cli.nmap_url
+                ));
+                let nmap_content = refresh_nmap_cache(cli, reporter, deadline)
+                    .context("Failed to fetch remote Nmap services for source 'nmap'.")?;
+                parse_nmap_into(
+                    cli,
+                    &nmap_content,
+                    "fetched Nmap services list",
+                    reporter,
+                    names,
+                    frequencies,
+                )
+                .context("Failed to parse fetched Nmap services content.")
+            }
+            Provider::Local => scan_local_ports(cli, reporter, deadline, None),
+        }
+    }
+}
+
+/// Parses the Nmap services cache's content.
+fn parse_nmap_cache(
+    cli: &Cli,
+    content: &str,
+    reporter: &Reporter,
+    names: &mut ServiceNames,
+    frequencies: &mut Frequencies,
+) -> Result<HashSet<u16>> {
+    parse_nmap_into(
+        cli,
+        content,
+        "cached Nmap services list",
+        reporter,
+        names,
+        frequencies,
+    )
+    .with_context(|| {
+        format!(
+            "Failed to parse cached Nmap services content from {}.",
+            nmap_cache_path().display()
+        )
+    })
+}
+
+/// A providers.d hook run with the command line's settings. Like a failed scan, a failed
+/// hook is an error unless --force is set, in which case it warns and forbids nothing.
+struct CliHook<'a> {
+    hook: ProviderHook,
+    force: bool,
+    reporter: &'a Arc<Reporter>,
+}
+
+impl PortSource for CliHook<'_> {
+    fn name(&self) -> &str {
+        self.hook.name()
+    }
+
+    fn rejection_reason(&self) -> RejectionReason {
+        self.hook.rejection_reason()
+    }
+
+    fn plan(&self) -> PlannedSource {
+        self.hook.plan()
+    }
+
+    fn collect(&mut self, names: &mut ServiceNames) -> Result<HashSet<u16>> {
+        match self.hook.collect(names) {
+            Ok(ports) => Ok(ports),
+            Err(e) if self.force => {
+                self.reporter.warn_with(
+                    WarningCode::ProviderFailed,
+                    format!(
+                        "Provider '{}' failed: {:#}. Proceeding with --force without its ports.",
+                        self.hook.name(),
+                        e
+                    ),
+                );
+                Ok(HashSet::new())
+            }
+            Err(e) => Err(e.context(format!(
+                "Provider '{}' failed. Use --force to carry on without it, or --no-providers to skip provider hooks.",
+                self.hook.name()
+            ))),
+        }
+    }
+}
+
+/// The hooks in $PORTPICK_PROVIDERS_DIR or providers.d in the config directory, set up for
+/// this run.
+fn provider_hooks<'a>(
+    cli: &Cli,
+    within: &[(u16, u16)],
+    reporter: &'a Arc<Reporter>,
+) -> Result<Vec<CliHook<'a>>> {
+    let dir = match std::env::var_os(PROVIDERS_DIR_ENV) {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match default_providers_dir() {
+            Some(dir) => dir,
+            None => return Ok(Vec::new()),
+        },
+    };
+    let hooks = discover_hooks(&dir)?;
+    if !hooks.is_empty() {
+        reporter.info(format!(
+            "Found {} provider hook(s) in {}.",
+            hooks.len(),
+            dir.display()
+        ));
+    }
+    let address = cli.address.as_deref().unwrap_or("127.0.0.1");
+    Ok(hooks
+        .into_iter()
+        .map(|path| CliHook {
+            hook: ProviderHook::new(path)
+                .with_context("tcp", within, address)
+                .with_timeout(cli.provider_timeout),
+            force: cli.force,
+            reporter,
+        })
+        .collect())
+}
+
+/// Finds the ports in use on --address (or --netns), only looking at `ports` if given.
+///
+/// A failed scan is an error unless --force is set or Ctrl-C was pressed, in which case it
+/// warns and counts nothing as in use.
+fn scan_local_ports(
+    cli: &Cli,
+    reporter: &Arc<Reporter>,
+    deadline: Option<Instant>,
+    ports: Option<Vec<u16>>,
+) -> Result<HashSet<u16>> {
+    let mut scan_target =
+        ScanTarget::new(cli.address.as_deref().unwrap_or("127.0.0.1")).with_deadline(deadline);
+    match ports {
+        Some(ports) => scan_target = scan_target.with_ports(ports),
+        None => {
+            if let Some(relevant) = pick_relevant_ports(cli) {
+                scan_target = scan_target.with_relevant(relevant);
+            }
+        }
+    }
+    let container = cli.netns.as_deref().and_then(netns_container);
+    let scanner = with_scan_cache(
+        cli,
+        select_scanner(cli, reporter, container),
+        &scan_target,
+        reporter,
+    );
+    let progress = spinner(show_progress(cli), "Scanning for locally used ports");
+    let scanned = scanner.scan(&scan_target);
+    progress.finish_and_clear();
+    match scanned {
+        Ok(mut local_ports) => {
+            // Under WSL2 Windows can hold ports that look free from Linux.
+            if container.is_none() && running_under_wsl() {
+                match WindowsHostScanner::new(Arc::clone(reporter)).scan(&scan_target) {
+                    Ok(windows_ports) => local_ports.extend(windows_ports),
+                    Err(e) => reporter.warn_with(WarningCode::WindowsPortsUnavailable, format!("WSL detected but Windows-side ports could not be read: {}. Ports used by Windows processes may be suggested.", e)),
+                }
+            }
+            Ok(local_ports)
+        }
+        Err(e) if is_interrupted() => {
+            reporter.warn_with(WarningCode::Interrupted, format!("Local port scan interrupted: {}. Proceeding as with --force, but suggestions may be inaccurate.", e));
+            Ok(HashSet::new())
+        }
+        Err(e) if cli.force => {
+            reporter.warn_with(WarningCode::ScannerFailed, format!("Failed to get locally used ports: {}. Proceeding with --force, but suggestions may be inaccurate.", e));
+            // Proceed with an empty set of local ports, relying only on service data
+            Ok(HashSet::new())
+        }
+        // If the scan fails and --force is not used, it's safer to error out.
+        Err(e) => Err(e.context("Failed to get locally used ports. Cannot reliably find an available port. Use --force to attempt suggestion anyway.")),
+    }
+}
+
+/// The ports a pick looks at: the search ranges, plus the port either side of them for the
+/// rejected-neighbor notes. `None` when the run reports on every port, so the scan must be
+/// complete.
+fn pick_relevant_ports(cli: &Cli) -> Option<PortSet> {
+    if cli.count_only
+        || !cli.check.is_empty()
+        || !matches!(
+            cli.command,
+            None | Some(Command::Render(_) | Command::ComposeOverride(_))
+        )
+    {
+        return None;
+    }
+    let within = pick_options(cli).ranges().ok()?;
+    Some(PortSet::from(within).expand(1))
+}
+
+/// Wraps `scanner` with the short-lived scan cache in the state directory, unless disabled.
+///
+/// Results are only shared between runs that would scan the same thing the same way.
+fn with_scan_cache(
+    cli: &Cli,
+    scanner: Box<dyn PortScanner>,
+    target: &ScanTarget,
+    reporter: &Arc<Reporter>,
+) -> Box<dyn PortScanner> {
+    let Some(dir) = state_dir().filter(|_| cli.scan_cache_ttl > 0) else {
+        return scanner;
+    };
+    let key = format!(
+        "target={};netns={};bind={};rustscan={};scanner={:?}",
+        target.address,
+        cli.netns.as_deref().unwrap_or(""),
+        cli.bind_address
+            .map(|ip| ip.to_string())
+            .unwrap_or_default(),
+        std::env::var(RUSTSCAN_PROGRAM_ENV).unwrap_or_default(),
+        cli.scanner
+    );
+    let cache = ScanCache::new(dir, Duration::from_secs(cli.scan_cache_ttl));
+    Box::new(CachedScanner::new(
+        scanner,
+        cache,
+        key,
+        Arc::clone(reporter),
+    ))
+}
+
+/// The CI variable that is set, unless detection is off or the scanner was chosen explicitly
+/// (`$PORTPICK_RUSTSCAN`, --scanner, or --netns).
+fn ci_environment(cli: &Cli) -> Option<&'static str> {
+    if cli.no_ci_detection
+        || cli.netns.is_some()
+        || cli.scanner != ScannerChoice::Auto
+        || std::env::var_os(RUSTSCAN_PROGRAM_ENV).is_some()
+    {
+        return None;
+    }
+    detect_ci(|name| std::env::var(name).ok())
+}
+
+/// Skips the local scan of a run that can't see the ports it would scan (CI, containers) and
+/// bind-checks the suggestions instead, returning what it did for the notice. --random picks
+/// aren't checked, and neither is a block placed by an explicit --strategy, which clap keeps
+/// from being combined with --verify.
+fn verify_instead_of_scanning(cli: &mut Cli, matches: &ArgMatches) -> &'static str {
+    cli.skip_local_scan = true;
+    let strategy = matches.value_source("strategy") == Some(ValueSource::CommandLine);
+    cli.verify = !cli.random && !strategy;
+    match (cli.verify, strategy) {
+        (true, _) => "bind-checking the suggestions instead of scanning",
+        (false, true) => {
+            "skipping the local port scan without bind-checking the suggestions, which --strategy rules out"
+        }
+        (false, false) => "skipping the local port scan",
+    }
+}
+
+/// The container portpick runs in, unless detection is off.
+fn container_environment(cli: &Cli) -> Option<ContainerInfo> {
+    if cli.no_container_detection {
+        return None;
+    }
+    detect_container()
+}
+
+fn running_under_wsl() -> bool {
+    is_wsl(
+        fs::read_to_string("/proc/version").ok().as_deref(),
+        std::env::var("WSL_DISTRO_NAME").ok().as_deref(),
+    )
+}
+
+/// The container named by a `container:<name|id>` --netns spec.
+fn netns_container(spec: &str) -> Option<&str> {
+    spec.strip_prefix("container:")
+        .filter(|container| !container.is_empty())
+}
+
+/// The run the command line asks for, as the library's [`PickOptions`].
+fn pick_options(cli: &Cli) -> PickOptions {
+    let source = normalize_source(&cli.source, &Reporter::silent());
+    let sources = Provider::ALL
+        .into_iter()
+        .filter(|provider| provider.is_enabled(cli, source))
+        .map(|provider| match provider {
+            Provider::System => SourceKind::System,
+            Provider::Nmap => SourceKind::Nmap,
+            Provider::Local => SourceKind::Local,
+        })
+        .collect();
+    PickOptions {
+        sources,
+        services_file: cli.services_file.clone(),
+        nmap_services_file: Some(nmap_cache_path()),
+        address: cli.address.clone(),
+        bind_address: cli.bind_address,
+        scanner: cli.scanner,
+        protocol: Protocol::Tcp,
+        within: cli.within.clone(),
+        allow_privileged: cli.allow_privileged,
+        unprivileged_port_start: unprivileged_port_start(),
+        exclude: cli.exclude.clone(),
+        count: cli.number_of_ports,
+        names: cli.names.clone(),
+        spec: cli.spec.clone(),
+        continuous: cli.continuous,
+        strategy: cli.strategy,
+        random: cli.random,
+        verify: cli.verify,
+        seed: cli.seed,
+        sort: cli.sort,
+        avoid_family: cli.avoid_family,
+        alternates: cli.alternates,
+        include_unknown: cli.include_unknown,
+        profiles: cli.profile.clone(),
+    }
+}
+
+/// Lowercases --source, mapping unknown values to "system".
+fn normalize_source(source: &str, reporter: &Reporter) -> &'static str {
+    match source.to_lowercase().as_str() {
+        "nmap" => "nmap",
+        "cache" => "cache",
+        "system" => "system",
+        "all" => "all",
+        _ => {
+            reporter.verbose_warn(format!(
+                "Warning: Unknown source '{}'. Defaulting to 'system' services.",
+                source
+            ));
+            "system"
+        }
+    }
+}
+
+/// Reads the system services file.
+fn system_services(
+    cli: &Cli,
+    reporter: &Reporter,
+    names: &mut ServiceNames,
+    frequencies: &mut Frequencies,
+) -> Result<HashSet<u16>> {
+    reporter.info(format!(
+        "Source 'system': Attempting to use system services file: {}",
+        cli.services_file.display()
+    ));
+    read_system_services_ports(
+        &cli.services_file,
+        reporter,
+        cli.include_unknown,
+        names,
+        frequencies,
+    )
+    .with_context(|| {
+        format!(
+            "Could not read or parse system services file ({})",
+            cli.services_file.display()
+        )
+    })
+}
+
+/// [`system_services`], warning and returning nothing if the file can't be used; whether
+/// that leaves enough known services is for collect_forbidden to judge.
+fn system_services_or_warn(
+    cli: &Cli,
+    reporter: &Reporter,
+    names: &mut ServiceNames,
+    frequencies: &mut Frequencies,
+) -> HashSet<u16> {
+    system_services(cli, reporter, names, frequencies).unwrap_or_else(|e| {
+        reporter.warn_with(WarningCode::SourceUnavailable, format!("{:#}.", e));
+        HashSet::new()
+    })
+}
+
+/// The stderr reporter, also logging to --log-file (or $PORTPICK_LOG). A log that can't be
+/// opened is warned about and skipped.
+fn reporter_for(cli: &Cli) -> Reporter {
+    let reporter = Reporter::stderr(cli.verbose > 0);
+    let Some(path) = cli.log_file.clone().or_else(|| {
+        std::env::var_os(LOG_FILE_ENV)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    }) else {
+        return reporter;
+    };
+    let mut options = fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let reporter = match options.open(&path) {
+        Ok(file) => reporter.with_log(Box::new(file), cli.log_format),
+        Err(e) => {
+            reporter.warn(format!(
+                "Warning: Could not open log file '{}': {}. Continuing without it.",
+                path.display(),
+                e
+            ));
+            return reporter;
+        }
+    };
+    reporter.detail(format!(
+        "portpick {} started: {}",
+        env!("CARGO_PKG_VERSION"),
+        sanitized_command_line(&std::env::args().collect::<Vec<_>>())
+    ));
+    reporter
+}
+
+/// The scanner [`select_scanner`] would pick and the address it would scan, e.g.
+/// `rustscan on 127.0.0.1`.
+fn planned_scan(cli: &Cli) -> String {
+    let address = cli.address.as_deref().unwrap_or("127.0.0.1");
+    if let Some(container) = cli.netns.as_deref().and_then(netns_container) {
+        return format!("ss in container {}", container);
+    }
+    let scanner = match std::env::var(RUSTSCAN_PROGRAM_ENV) {
+        Ok(program)
+            if !program.is_empty()
+                && matches!(cli.scanner, ScannerChoice::Auto | ScannerChoice::Rustscan)
+                && cli.bind_address.is_none() =>
+        {
+            format!("rustscan ({})", program)
+        }
+        _ => cli
+            .scanner
+            .planned(cli.bind_address, std::env::var_os("PATH").as_deref()),
+    };
+    format!("{} on {}", scanner, address)
+}
+
+/// Picks the port scanner backend used to detect locally used ports.
+///
+/// rustscan only sees whether a port answers on --address, so --bind-address switches local
+/// detection to `ss`, which reports each listener's bind address, unless --scanner says
+/// otherwise. Without either, rustscan is used, or nmap or masscan when it isn't installed.
+/// --netns always reads the container's socket table.
+fn select_scanner(
+    cli: &Cli,
+    reporter: &Arc<Reporter>,
+    container: Option<&str>,
+) -> Box<dyn PortScanner> {
+    let rustscan = || -> Box<dyn PortScanner> {
+        let scanner = RustScanScanner::new(Arc::clone(reporter));
+        match std::env::var(RUSTSCAN_PROGRAM_ENV) {
+            Ok(program) if !program.is_empty() => Box::new(scanner.with_program(program)),
+            _ => Box::new(scanner),
+        }
+    };
+    let nmap = || -> Box<dyn PortScanner> {
+        let scanner = NmapScanner::new(Arc::clone(reporter));
+        Box::new(match cli.scan_timeout {
+            Some(timeout) => scanner.with_host_timeout(timeout),
+            None => scanner,
+        })
+    };
+    let masscan = || -> Box<dyn PortScanner> {
+        Box::new(MasscanScanner::new(Arc::clone(reporter)).with_rate(cli.masscan_rate))
+    };
+    match (container, cli.scanner, cli.bind_address) {
+        (Some(container), _, bind) => {
+            let scanner = ContainerScanner::new(container, Arc::clone(reporter));
+            Box::new(match bind {
+                Some(bind) => scanner.with_bind_address(bind),
+                None => scanner,
+            })
+        }
+        (None, ScannerChoice::Masscan, _) => masscan(),
+        (None, ScannerChoice::Nmap, _) => nmap(),
+        (None, ScannerChoice::Ss, bind) | (None, ScannerChoice::Auto, bind @ Some(_)) => {
+            let scanner = SsScanner::new(Arc::clone(reporter));
+            Box::new(match bind {
+                Some(bind) => scanner.with_bind_address(bind),
+                None => scanner,
+            })
+        }
+        (None, ScannerChoice::Rustscan, _) => rustscan(),
+        (None, ScannerChoice::Auto, _) => Box::new(
+            FallbackScanner::new(Arc::clone(reporter))
+                .then("rustscan", rustscan())
+                .then("nmap", nmap())
+                .then("masscan", masscan()),
+        ),
+    }
+}
+
+// find_available_ports moved to lib.rs
+
+/// Prints a --dry-run plan: JSON or YAML with those outputs, text otherwise.
+fn print_plan(cli: &Cli, plan: &Plan) -> Result<()> {
+    match cli.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(plan)?),
+        OutputFormat::Jsonl => println!("{}", serde_json::to_string(plan)?),
+        OutputFormat::Yaml => print!("{}", serde_yaml::to_string(plan)?),
+        _ => print!("{}", plan.render()),
+    }
+    Ok(())
+}
+
+/// Reads `path` to edit it in place; a file that doesn't exist yet reads as empty.
+fn read_for_update(path: &Path, what: &str) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(anyhow::Error::new(e).context(format!(
+            "Failed to read {} '{}'",
+            what,
+            path.display()
+        ))),
+    }
+}
+
+/// Updates (or creates) a .env file with NAME=PORT assignments for the suggestions.
+fn write_env_file(path: &Path, suggestions: &[Suggestion], naming: &EnvNaming) -> Result<()> {
+    let existing = read_for_update(path, "env file")?;
+    let assignments: Vec<(String, u16)> = env_var_names(suggestions, naming)
+        .into_iter()
+        .zip(suggestions.iter().map(|s| s.port))
+        .collect();
+    let updated = update_env_file(&existing, &assignments)
+        .with_context(|| format!("Failed to update env file '{}'", path.display()))?;
+    write_atomically(path, &updated)
+}
+
+/// Writes `export NAME=PORT` lines into the managed block of a direnv .envrc, creating it if
+/// need be. The rest of the file is left as it was.
+fn write_envrc(path: &Path, suggestions: &[Suggestion], naming: &EnvNaming) -> Result<()> {
+    let existing = read_for_update(path, ".envrc")?;
+    let exports: Vec<String> = render_env(suggestions, naming, true)
+        .lines()
+        .map(String::from)
+        .collect();
+    let updated = update_managed_block(&existing, &exports)
+        .with_context(|| format!("Failed to update '{}'", path.display()))?;
+    write_atomically(path, &updated)
+}
+
+/// The devcontainer.json at `path` with the suggestions forwarded and labeled by name.
+fn updated_devcontainer(
+    path: &Path,
+    suggestions: &[Suggestion],
+    overwrite: bool,
+) -> Result<String> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read '{}'", path.display()))?;
+    let ports: Vec<(u16, Option<String>)> = suggestions
+        .iter()
+        .map(|suggestion| (suggestion.port, suggestion.name.clone()))
+        .collect();
+    update_devcontainer(&content, &ports, overwrite)
+        .with_context(|| format!("Failed to update '{}'", path.display()))
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it into place, so readers
+/// never see a half-written file.
+fn write_atomically(path: &Path, contents: &str) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("'{}' is not a file path", path.display()))?;
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    fs::write(&temp_path, contents)
+        .with_context(|| format!("Failed to write '{}'", temp_path.display()))?;
+    fs::rename(&temp_path, path).map_err(|e| {
+        let _ = fs::remove_file(&temp_path);
+        anyhow::Error::new(e).context(format!("Failed to replace '{}'", path.display()))
+    })
+}
+
+/// Prints the systemd socket units, or writes them into the --output-file directory.
+fn emit_systemd_units(cli: &Cli, suggestions: &[Suggestion]) -> Result<()> {
+    let units = render_systemd_units(suggestions, cli.one_unit);
+    match &cli.output_file {
+        Some(dir) => {
+            let dir = match dir {
+                Some(dir) => dir.clone(),
+                None => systemd_user_unit_dir()?,
+            };
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create unit directory '{}'", dir.display()))?;
+            for unit in units {
+                let path = dir.join(&unit.file_name);
+                fs::write(&path, &unit.contents).with_context(|| {
+                    format!("Failed to write socket unit to '{}'", path.display())
+                })?;
+                println!("{}", path.display());
+            }
+        }
+        None => {
+            let rendered: Vec<String> = units
+                .iter()
+                .map(|unit| format!("# {}\n{}", unit.file_name, unit.contents))
+                .collect();
+            print!("{}", rendered.join("\n"));
+        }
+    }
+    Ok(())
+}
+
+/// Applies the config file under the flags: --profile is resolved against it (built-in
+/// profiles stay in `cli.profile` for their ports, profile ranges fill in --within unless it
+/// was given, and profile exclusions join --exclude), and its `[http]` settings come before
+/// --user-agent and --http-header.
+fn apply_config(cli: &mut Cli) -> Result<()> {
+    let (path, required) = match (&cli.config, std::env::var_os(CONFIG_FILE_ENV)) {
+        (Some(path), _) => (Some(path.clone()), true),
+        (None, Some(path)) if !path.is_empty() => (Some(PathBuf::from(path)), true),
+        _ => (default_config_path(), false),
+    };
+    let config = match path {
+        Some(path) => Config::load(&path, required)?,
+        None => Config::default(),
+    };
+    if cli.user_agent.is_none() {
+        cli.user_agent = config.http.user_agent.clone();
+    }
+    // Flags come last, so they replace a configured header of the same name.
+    cli.http_header = config
+        .http
+        .headers
+        .iter()
+        .cloned()
+        .chain(std::mem::take(&mut cli.http_header))
+        .collect();
+    if cli.profile.is_empty() {
+        return Ok(());
+    }
+    let resolved = config
+        .resolve_profiles(&cli.profile)
+        .context("Invalid --profile.")?;
+    cli.profile = resolved.builtin;
+    if cli.within.is_none() {
+        cli.within = resolved.within;
+    }
+    cli.exclude = match (cli.exclude.take(), resolved.exclude) {
+        (Some(flag), Some(profiles)) => Some(format!("{},{}", flag, profiles)),
+        (flag, profiles) => flag.or(profiles),
+    };
+    Ok(())
+}
+
+/// `$XDG_CACHE_HOME/portpick`, falling back to `~/.cache/portpick`, or to the temporary
+/// directory with neither set, so the Nmap cache is never written relative to the working
+/// directory.
+fn cache_dir() -> PathBuf {
+    let cache_home = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".cache"),
+            None => std::env::temp_dir(),
+        },
+    };
+    cache_home.join("portpick")
+}
+
+/// The Nmap services cache, `nmap-services.cache` in [`cache_dir`]; its lock sits beside it.
+fn nmap_cache_path() -> PathBuf {
+    cache_dir().join("nmap-services.cache")
+}
+
+/// `$XDG_STATE_HOME/portpick`, falling back to `~/.local/state/portpick`.
+fn state_dir() -> Option<PathBuf> {
+    let state_home = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?)
+            .join(".local")
+            .join("state"),
+    };
+    Some(state_home.join("portpick"))
+}
+
+/// `$XDG_CONFIG_HOME/systemd/user`, falling back to `~/.config/systemd/user`.
+fn systemd_user_unit_dir() -> Result<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".config"))
+            .context("Cannot locate the systemd user unit directory: HOME is not set.")?,
+    };
+    Ok(config_home.join("systemd").join("user"))
+}
+
+/// Prints how many ports and blocks are free within the ranges, failing below --at-least.
+fn report_free_count(
+    cli: &Cli,
+    forbidden_ports: &HashSet<u16>,
+    within: &[(u16, u16)],
+    block_size: u16,
+) -> Result<()> {
+    let count = count_free(forbidden_ports, within, block_size);
+    match cli.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&count)?),
+        OutputFormat::Jsonl => println!("{}", serde_json::to_string(&count)?),
+        OutputFormat::Yaml => println!("{}", serde_yaml::to_string(&count)?.trim_end()),
+        _ => {
+            let ranges: Vec<String> = within
+                .iter()
+                .map(|&(start, end)| format!("{}-{}", start, end))
+                .collect();
+            println!(
+                "{} free port(s), {} free continuous block(s) of {} in {}",
+                count.free_ports,
+                count.free_blocks,
+                block_size,
+                ranges.join(",")
+            );
+        }
+    }
+    match cli.at_least {
+        Some(at_least) if count.free_blocks < at_least => Err(anyhow::anyhow!(
+            "Only {} free block(s) of {} port(s), fewer than --at-least {}.",
+            count.free_blocks,
+            block_size,
+            at_least
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// What one services source says about a port for one protocol.
+#[derive(Debug, Serialize)]
+struct ServiceListing {
+    source: &'static str,
+    protocol: Protocol,
+    names: Vec<String>,
+}
+
+/// Everything `whatis` found out about a port.
+#[derive(Debug, Serialize)]
+struct PortDescription {
+    port: u16,
+    services: Vec<ServiceListing>,
+    /// `None` when the local scan was skipped or failed.
+    listening: Option<bool>,
+}
+
+/// Reads the content of each enabled services source, for lookups that need names rather
+/// than just port numbers.
+fn services_sources(cli: &Cli, source: &str, reporter: &Reporter) -> Vec<(&'static str, String)> {
+    let mut sources = Vec::new();
+    if Provider::System.is_enabled(cli, source) {
+        match read_services_file(&cli.services_file, reporter) {
+            Ok(content) => sources.push(("system", content)),
+            Err(e) => reporter.warn(format!(
+                "Warning: Could not read system services file ({}): {}",
+                cli.services_file.display(),
+                e
+            )),
+        }
+    }
+    if Provider::Nmap.is_enabled(cli, source) {
+        let content = if source == "cache" {
+            read_nmap_cache(cli, reporter, None).map_err(anyhow::Error::from)
+        } else {
+            fetch_remote_nmap_services(cli, reporter, None, &mut std::io::sink())
+                .map(|bytes| decode_or_warn(&bytes, &cli.nmap_url, reporter))
+        };
+        match content {
+            Ok(content) => sources.push(("nmap", content)),
+            Err(e) => reporter.warn(format!(
+                "Warning: Could not read the Nmap services list: {}",
+                e
+            )),
+        }
+    }
+    sources
+}
+
+/// Prints what the services sources call each port and whether it is listening locally.
+fn report_whatis(
+    cli: &Cli,
+    args: &WhatisArgs,
+    source: &str,
+    reporter: &Arc<Reporter>,
+    deadline: Option<Instant>,
+) -> Result<()> {
+    let mut maps = Vec::new();
+    for (name, content) in services_sources(cli, source, reporter) {
+        for protocol in [Protocol::Tcp, Protocol::Udp] {
+            maps.push((
+                name,
+                protocol,
+                parse_services_map(&content, protocol, cli.include_unknown)?,
+            ));
+        }
+    }
+    let listening = if Provider::Local.is_enabled(cli, source) {
+        match Provider::Local.collect(
+            cli,
+            source,
+            reporter,
+            deadline,
+            &mut ServiceNames::new(),
+            &mut Frequencies::new(),
+        ) {
+            Ok(ports) => Some(ports),
+            Err(e) => {
+                reporter.warn(format!("Warning: Local port scan failed: {:#}", e));
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let descriptions: Vec<PortDescription> = args
+        .ports
+        .iter()
+        .map(|&port| PortDescription {
+            port,
+            services: maps
+                .iter()
+                .filter_map(|(source, protocol, map)| {
+                    map.get(&port).map(|names| ServiceListing {
+                        source,
+                        protocol: *protocol,
+                        names: names.clone(),
+                    })
+                })
+                .collect(),
+            listening: listening.as_ref().map(|ports| ports.contains(&port)),
+        })
+        .collect();
+
+    match cli.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&descriptions)?),
+        OutputFormat::Yaml => println!("{}", serde_yaml::to_string(&descriptions)?.trim_end()),
+        OutputFormat::Jsonl => {
+            for description in &descriptions {
+                println!("{}", serde_json::to_string(description)?);
+            }
+        }
+        _ => {
+            for description in &descriptions {
+                println!("{}", description.port);
+                if description.services.is_empty() {
+                    println!("  no known service");
+                }
+                for listing in &description.services {
+                    let protocol = match listing.protocol {
+                        Protocol::Tcp => "tcp",
+                        Protocol::Udp => "udp",
+                    };
+                    println!(
+                        "  {}: {} ({})",
+                        protocol,
+                        listing.names.join(", "),
+                        listing.source
+                    );
+                }
+                let listening = match description.listening {
+                    Some(true) => "yes",
+                    Some(false) => "no",
+                    None => "unknown",
+                };
+                println!("  listening locally: {}", listening);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints `--version`: one line, or with --verbose or a machine-readable --output, the build and
+/// cache provenance for bug reports.
+fn report_version(cli: &Cli) -> Result<()> {
+    #[derive(Serialize)]
+    struct VersionReport {
+        #[serde(flatten)]
+        build: BuildInfo,
+        nmap_cache: CacheInfo,
+    }
+    let report = VersionReport {
+        build: BuildInfo::current(),
+        nmap_cache: CacheInfo::inspect(&nmap_cache_path(), SystemTime::now()),
+    };
+    match cli.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        OutputFormat::Yaml => println!("{}", serde_yaml::to_string(&report)?.trim_end()),
+        OutputFormat::Jsonl => println!("{}", serde_json::to_string(&report)?),
+        _ if cli.verbose == 0 => println!(
+            "portpick {} ({} {})",
+            report.build.version,
+            report.build.short_commit(),
+            report.build.build_date
+        ),
+        _ => {
+            let build = &report.build;
+            let cache = &report.nmap_cache;
+            println!("portpick {}", build.version);
+            println!("commit: {}", build.commit);
+            println!("build date: {}", build.build_date);
+            if build.features.is_empty() {
+                println!("features: none");
+            } else {
+                println!("features: {}", build.features.join(", "));
+            }
+            match (cache.present, cache.age_secs, cache.ports) {
+                (false, _, _) => println!("nmap cache: {} (not present)", cache.path),
+                (true, age, ports) => println!(
+                    "nmap cache: {} ({}, {})",
+                    cache.path,
+                    age.map(|secs| format!("{} days old", secs / (24 * 60 * 60)))
+                        .unwrap_or_else(|| "age unknown".to_string()),
+                    ports
+                        .map(|ports| format!("{} TCP ports", ports))
+                        .unwrap_or_else(|| "unparsable".to_string())
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints portpick.1, or writes it and the subcommand pages to --out-dir.
+fn render_man_pages(args: &ManArgs) -> Result<()> {
+    let command = Cli::command();
+    match &args.out_dir {
+        Some(dir) => {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create '{}'", dir.display()))?;
+            clap_mangen::generate_to(command, dir)
+                .with_context(|| format!("Failed to write man pages to '{}'", dir.display()))
+        }
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            clap_mangen::Man::new(command).render(&mut stdout)?;
+            Ok(())
+        }
+    }
+}
+
+/// Prints the JSON Schema of the `version` layout of [`PickOutcome`].
+#[cfg(feature = "schema")]
+fn print_schema(version: u32) -> Result<()> {
+    // Only one layout exists so far; older ones get their own types once there are more.
+    check_schema_version(version)?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&PickOutcome::json_schema())?
+    );
+    Ok(())
+}
+
+#[cfg(not(feature = "schema"))]
+fn print_schema(_version: u32) -> Result<()> {
+    anyhow::bail!(
+        "This portpick was built without the `schema` feature; rebuild with --features schema"
+    )
+}
+
+/// Runs every `doctor` check and prints the results, exiting with 1 if any failed.
+fn report_doctor(cli: &Cli, args: &DoctorArgs) -> Result<()> {
+    let path = std::env::var_os("PATH");
+    let rustscan = std::env::var(RUSTSCAN_PROGRAM_ENV)
+        .ok()
+        .filter(|program| !program.is_empty())
+        .unwrap_or_else(|| "rustscan".to_string());
+    let mut checks = vec![
+        check_tool(
+            &rustscan,
+            &["--version"],
+            path.as_deref(),
+            true,
+            "install RustScan (`cargo install rustscan`), or run with --skip-local-scan",
+        ),
+        check_tool(
+            "ss",
+            &["-V"],
+            path.as_deref(),
+            false,
+            "install iproute2; --bind-address needs ss",
+        ),
+        check_tool(
+            "lsof",
+            &["-v"],
+            path.as_deref(),
+            false,
+            "install lsof to see which process holds a port",
+        ),
+        check_tool(
+            "netstat",
+            &["--version"],
+            path.as_deref(),
+            false,
+            "install net-tools to see which process holds a port",
+        ),
+        check_services_file(&cli.services_file),
+        if args.offline {
+            DoctorCheck::pass("nmap url", "skipped (--offline)")
+        } else {
+            check_url("nmap url", &cli.nmap_url, Duration::from_secs(10))
+        },
+        check_services_cache(&nmap_cache_path(), SystemTime::now()),
+    ];
+    checks.push(check_writable_dir("cache directory", &cache_dir()));
+    checks.push(match state_dir() {
+        Some(dir) => check_writable_dir("state directory", &dir),
+        None => DoctorCheck::warn(
+            "state directory",
+            "neither $XDG_STATE_HOME nor $HOME is set",
+            "set $XDG_STATE_HOME so scan results can be cached",
+        ),
+    });
+
+    #[derive(Serialize)]
+    struct DoctorReport {
+        ok: bool,
+        checks: Vec<DoctorCheck>,
+    }
+    let report = DoctorReport {
+        ok: checks.iter().all(|check| check.status != CheckStatus::Fail),
+        checks,
+    };
+    match cli.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        OutputFormat::Yaml => println!("{}", serde_yaml::to_string(&report)?.trim_end()),
+        OutputFormat::Jsonl => {
+            for check in &report.checks {
+                println!("{}", serde_json::to_string(check)?);
+            }
+        }
+        _ => {
+            for check in &report.checks {
+                let status = match check.status {
+                    CheckStatus::Pass => "pass".green(),
+                    CheckStatus::Warn => "warn".yellow(),
+                    CheckStatus::Fail => "FAIL".red(),
+                };
+                println!("[{}] {}: {}", status, check.name, check.detail);
+                if let Some(hint) = &check.hint {
+                    println!("       {}", hint);
+                }
+            }
+        }
+    }
+    if !report.ok {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Resolves `project` into the --env-file, or with --check-only, checks the ports already
+/// there against `ctx` (whose ranges are replaced by each entry's own).
+fn apply_project(
+    cli: &Cli,
+    args: &ApplyArgs,
+    project: &ProjectSpec,
+    forbidden_ports: &HashSet<u16>,
+    ctx: ForbiddenContext,
+    reporter: &Reporter,
+) -> Result<()> {
+    let naming = EnvNaming {
+        prefix: cli.env_prefix.clone(),
+        case: cli.env_case,
+        template: cli.env_template.clone(),
+    };
+    if !args.check_only {
+        let resolved = project.resolve(forbidden_ports)?;
+        let assignments = project.env_assignments(&resolved, &naming);
+        let existing = read_for_update(&args.env_file, "env file")?;
+        let updated = update_env_file(&existing, &assignments)
+            .with_context(|| format!("Failed to update env file '{}'", args.env_file.display()))?;
+        write_atomically(&args.env_file, &updated)?;
+        reporter.success(format!(
+            "Wrote {} port assignment(s) to {}",
+            assignments.len(),
+            args.env_file.display()
+        ));
+        match cli.output {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&resolved)?),
+            OutputFormat::Yaml => println!("{}", serde_yaml::to_string(&resolved)?.trim_end()),
+            OutputFormat::Jsonl => {
+                for entry in &resolved {
+                    println!("{}", serde_json::to_string(entry)?);
+                }
+            }
+            _ => {
+                for (name, port) in &assignments {
+                    println!("{}={}", name, port);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    let content = fs::read_to_string(&args.env_file).with_context(|| {
+        format!(
+            "Failed to read env file '{}'; run `portpick apply` first",
+            args.env_file.display()
+        )
+    })?;
+    let recorded = parse_env_assignments(&content);
+    #[derive(Serialize)]
+    struct RecheckResult {
+        variable: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        port: Option<u16>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        status: Option<PortStatus>,
+    }
+    let mut results = Vec::new();
+    for (entry, names) in project.ports.iter().zip(project.port_var_names(&naming)) {
+        let entry_ctx = ForbiddenContext {
+            ranges: project.ranges_for(entry)?,
+            ..ctx.clone()
+        };
+        for variable in names {
+            let port = recorded
+                .iter()
+                .find(|(name, _)| *name == variable)
+                .map(|&(_, port)| port);
+            results.push(RecheckResult {
+                status: port.map(|port| check_port(port, &entry_ctx)),
+                variable,
+                port,
+            });
+        }
+    }
+    let ok = results
+        .iter()
+        .all(|result| result.status.as_ref().is_some_and(PortStatus::is_free));
+    match cli.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&results)?),
+        OutputFormat::Yaml => println!("{}", serde_yaml::to_string(&results)?.trim_end()),
+        OutputFormat::Jsonl => {
+            for result in &results {
+                println!("{}", serde_json::to_string(result)?);
+            }
+        }
+        _ => {
+            for result in &results {
+                match (&result.port, &result.status) {
+                    (Some(port), Some(PortStatus::Free)) => {
+                        println!("{}={} free", result.variable, port)
+                    }
+                    (Some(port), Some(PortStatus::Blocked(reasons))) => {
+                        let reasons: Vec<String> = reasons.iter().map(describe_block).collect();
+                        println!(
+                            "{}={} blocked: {}",
+                            result.variable,
+                            port,
+                            reasons.join(", ")
+                        );
+                    }
+                    _ => println!("{} missing", result.variable),
+                }
+            }
+        }
+    }
+    if !ok {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Reads the `batch` requests from stdin, numbered by line. Malformed lines are kept as
+/// errors, or with --strict fail the whole batch.
+fn read_batch_requests(
+    cli: &Cli,
+    args: &BatchArgs,
+) -> Result<Vec<(usize, Result<BatchRequest, String>)>> {
+    if !matches!(
+        cli.output,
+        OutputFormat::Text | OutputFormat::Json | OutputFormat::Jsonl | OutputFormat::Yaml
+    ) {
+        return Err(anyhow::anyhow!(
+            "batch only supports --output text, json, jsonl, or yaml."
+        ));
+    }
+    let mut input = String::new();
+    std::io::stdin()
+        .read_to_string(&mut input)
+        .context("Failed to read batch requests from stdin")?;
+    let mut requests = Vec::new();
+    for (index, line) in input.lines().enumerate() {
+        match BatchRequest::parse_line(line) {
+            Ok(None) => {}
+            Ok(Some(request)) => requests.push((index + 1, Ok(request))),
+            Err(e) if args.strict => {
+                return Err(e.context(format!("Invalid batch request on line {}", index + 1)));
+            }
+            Err(e) => requests.push((index + 1, Err(format!("{:#}", e)))),
+        }
+    }
+    Ok(requests)
+}
+
+/// Resolves the batch against the collected forbidden ports and prints one result per request,
+/// exiting with 1 if any failed.
+fn report_batch(
+    cli: &Cli,
+    requests: &[(usize, Result<BatchRequest, String>)],
+    forbidden_ports: &HashSet<u16>,
+    within: &[(u16, u16)],
+    session: Option<&PortSet>,
+) -> Result<()> {
+    let results = resolve_batch(requests, forbidden_ports, within);
+    let session_export = session_export(
+        cli,
+        session,
+        results
+            .iter()
+            .flat_map(|result| result.ports.iter().copied()),
+    );
+    match cli.output {
+        OutputFormat::Yaml => println!("{}", serde_yaml::to_string(&results)?.trim_end()),
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            for result in &results {
+                println!("{}", serde_json::to_string(result)?);
+            }
+        }
+        _ => {
+            for result in &results {
+                let label = result
+                    .name
+                    .as_ref()
+                    .map(|name| format!("{}: ", name))
+                    .unwrap_or_default();
+                match &result.error {
+                    Some(error) => println!("{}error on line {}: {}", label, result.line, error),
+                    None => {
+                        let ports: Vec<String> = result.ports.iter().map(u16::to_string).collect();
+                        println!("{}{}", label, ports.join(" "));
+                    }
+                }
+            }
+            if let Some(line) = session_export {
+                println!("{}", line);
+            }
+        }
+    }
+    if results.iter().any(|result| result.error.is_some()) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// A finished run for --notify to announce.
+struct Completion {
+    ports: Vec<u16>,
+    elapsed: Duration,
+    notify_after: Duration,
+    reporter: Arc<Reporter>,
+}
+
+/// --notify: tells the desktop, or failing that the terminal bell, that a run has picked its
+/// ports.
+fn announce_completion(completion: &Completion) {
+    let Completion {
+        ports,
+        elapsed,
+        notify_after,
+        reporter,
+    } = completion;
+    // The bell only means something on a terminal; elsewhere it is a stray byte in a log.
+    let mut bell: Box<dyn Write> = match std::io::stderr().is_terminal() {
+        true => Box::new(std::io::stderr()),
+        false => Box::new(std::io::sink()),
+    };
+    match notify_completion(&DesktopNotifier, &mut bell, ports, *elapsed, *notify_after) {
+        Announced::NotNeeded => reporter.info(format!(
+            "--notify: the run took {:.1}s, less than --notify-after; not announcing it.",
+            elapsed.as_secs_f64()
+        )),
+        Announced::Notification => reporter.info("--notify: sent a desktop notification."),
+        Announced::Bell(reason) => reporter.info(format!(
+            "--notify: could not show a desktop notification ({}); rang the terminal bell instead.",
+            reason
+        )),
+    }
+}
+
+/// The `export PORTPICK_SESSION_FORBID=...` line to print on stdout with --emit-session.
+/// When the variable is set without the flag, the line goes to stderr here instead.
+fn session_export(
+    cli: &Cli,
+    session: Option<&PortSet>,
+    picked: impl IntoIterator<Item = u16>,
+) -> Option<String> {
+    if !cli.emit_session && session.is_none() {
+        return None;
+    }
+    let line = session_export_line(&session_forbid_value(
+        session.unwrap_or(&PortSet::new()),
+        picked,
+    ));
+    if cli.emit_session {
+        return Some(line);
+    }
+    eprintln!("{}", line);
+    None
+}
+
+/// One row of the check report.
+#[derive(Serialize)]
+struct CheckResult {
+    port: u16,
+    status: PortStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    process: Option<ProcessHint>,
+}
+
+/// Prints whether each checked port is free and why not, with the listening process where
+/// `ss` or `lsof` can name it. `portpick check` prints a table and exits with 4 if any port is
+/// blocked; --check prints one line per port and exits with 1.
+fn report_check(
+    cli: &Cli,
+    ports: &[u16],
+    ctx: &ForbiddenContext,
+    reporter: &Reporter,
+) -> Result<()> {
+    let mut results: Vec<CheckResult> = ports
+        .iter()
+        .map(|&port| CheckResult {
+            port,
+            status: check_port(port, ctx),
+            process: None,
+        })
+        .collect();
+    let in_use_locally = |result: &CheckResult| match &result.status {
+        PortStatus::Blocked(reasons) => reasons.contains(&BlockReason::LocallyInUse),
+        PortStatus::Free => false,
+    };
+    // Only this machine's sockets can be attributed, so remote and container scans get no hints.
+    let scans_this_host = cli.netns.is_none()
+        && cli.address.as_deref().is_none_or(|address| {
+            address
+                .parse()
+                .is_ok_and(|ip| classify_address(ip) == AddressScope::Loopback)
+        });
+    if scans_this_host && results.iter().any(in_use_locally) {
+        match listening_processes(&SystemCommandRunner, None) {
+            Ok(mut processes) => {
+                for result in results.iter_mut().filter(|result| in_use_locally(result)) {
+                    result.process = processes.remove(&result.port);
+                }
+            }
+            Err(e) => reporter.verbose_warn(format!("Warning: {:#}", e)),
+        }
+    }
+
+    let subcommand = matches!(cli.command, Some(Command::Check(_)));
+    match cli.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&results)?),
+        OutputFormat::Yaml => println!("{}", serde_yaml::to_string(&results)?.trim_end()),
+        OutputFormat::Jsonl => {
+            for result in &results {
+                println!("{}", serde_json::to_string(result)?);
+            }
+        }
+        _ if subcommand => print_check_table(&results),
+        _ => {
+            for result in &results {
+                match &result.status {
+                    PortStatus::Free => println!("{} free", result.port),
+                    PortStatus::Blocked(reasons) => {
+                        let reasons: Vec<String> = reasons.iter().map(describe_block).collect();
+                        println!("{} blocked: {}", result.port, reasons.join(", "));
+                    }
+                }
+            }
+        }
+    }
+    if results.iter().any(|result| !result.status.is_free()) {
+        std::process::exit(if subcommand { 4 } else { 1 });
+    }
+    Ok(())
+}
+
+/// Prints check results as aligned PORT / STATUS / REASONS / PROCESS columns.
+fn print_check_table(results: &[CheckResult]) {
+    let rows: Vec<[String; 4]> = results
+        .iter()
+        .map(|result| {
+            let (status, reasons) = match &result.status {
+                PortStatus::Free => ("free", "-".to_string()),
+                PortStatus::Blocked(reasons) => (
+                    "blocked",
+                    reasons
+                        .iter()
+                        .map(describe_block)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ),
+            };
+            let process = match &result.process {
+                Some(ProcessHint {
+                    program,
+                    pid: Some(pid),
+                }) => format!("{} (pid {})", program, pid),
+                Some(ProcessHint { program, pid: None }) => program.clone(),
+                None => "-".to_string(),
+            };
+            [
+                result.port.to_string(),
+                status.to_string(),
+                reasons,
+                process,
+            ]
+        })
+        .collect();
+    let header = ["PORT", "STATUS", "REASONS", "PROCESS"].map(String::from);
+    let mut widths = [0; 3];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        println!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2]
+        );
+    }
+}
+
+/// Polls the --port ports until they are free (or in use with --until used), exiting with
+/// code 2 if --timeout is reached.
+fn wait_for_port_state(cli: &Cli, args: &WaitArgs, reporter: &Arc<Reporter>) -> Result<()> {
+    let quorum = if args.any {
+        WaitQuorum::Any
+    } else {
+        WaitQuorum::All
+    };
+    let probe_kind = args.probe.unwrap_or(match args.until {
+        WaitUntil::Free => WaitProbe::Bind,
+        WaitUntil::Used => WaitProbe::Connect,
+    });
+    let bind_address = cli
+        .bind_address
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    let host = cli.address.as_deref().unwrap_or("127.0.0.1");
+    let connect_address = match probe_kind {
+        WaitProbe::Connect => Some(resolve_host(host).context("Invalid --address")?.primary()),
+        _ => None,
+    };
+    let scanner = select_scanner(
+        cli,
+        reporter,
+        cli.netns.as_deref().and_then(netns_container),
+    );
+    let target = ScanTarget::new(host);
+    let mut scan_failed = false;
+    let mut in_use = |ports: &[u16]| -> Option<HashSet<u16>> {
+        let used = match probe_kind {
+            WaitProbe::Bind => ports
+                .iter()
+                .copied()
+                .filter(|&port| !bind_succeeds(bind_address, port))
+                .collect(),
+            WaitProbe::Connect => {
+                let address = connect_address?;
+                ports
+                    .iter()
+                    .copied()
+                    .filter(|&port| connect_succeeds(address, port, args.connect_timeout))
+                    .collect()
+            }
+            WaitProbe::Scanner => match scanner.scan(&target) {
+                Ok(used) => used,
+                Err(e) => {
+                    // A broken scanner won't fix itself; report it once and keep waiting.
+                    if !scan_failed {
+                        reporter.warn_with(
+                            WarningCode::ScannerFailed,
+                            format!("Scan failed, counting no port as ready: {:#}", e),
+                        );
+                    }
+                    scan_failed = true;
+                    return None;
+                }
+            },
+        };
+        Some(used)
+    };
+    let probe = |ports: &[u16]| -> HashSet<u16> {
+        let Some(used) = in_use(ports) else {
+            return HashSet::new();
+        };
+        ports
+            .iter()
+            .copied()
+            .filter(|port| used.contains(port) == (args.until == WaitUntil::Used))
+            .collect()
+    };
+    let dots = !args.quiet;
+    let outcome = wait_for_ports(
+        &args.ports,
+        quorum,
+        cli.timeout,
+        args.interval,
+        &SystemClock,
+        probe,
+        |_| {
+            if dots {
+                eprint!(".");
+                let _ = std::io::stderr().flush();
+            }
+        },
+    );
+    if dots && outcome.polls > 1 {
+        eprintln!();
+    }
+    let ports = |ports: &[u16]| {
+        ports
+            .iter()
+            .map(u16::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let (ready, pending) = match args.until {
+        WaitUntil::Free => ("Free", "still in use"),
+        WaitUntil::Used => ("Listening", "not listening yet"),
+    };
+    if !outcome.ready {
+        reporter.error(format!(
+            "Error: --timeout reached after {:.1}s; {}: {}.",
+            outcome.elapsed.as_secs_f64(),
+            pending,
+            ports(&outcome.pending)
+        ));
+        std::process::exit(2);
+    }
+    reporter.success(format!(
+        "{} after {:.1}s ({} poll(s)): {}",
+        ready,
+        outcome.elapsed.as_secs_f64(),
+        outcome.polls,
+        ports(&outcome.ready_ports)
+    ));
+    Ok(())
+}
+
+/// Saves `snapshot` for --save and prints how it differs from `earlier` for --diff.
+fn report_snapshot(
+    cli: &Cli,
+    args: &SnapshotArgs,
+    snapshot: &Snapshot,
+    earlier: Option<&Snapshot>,
+    reporter: &Reporter,
+) -> Result<()> {
+    if let Some(path) = &args.save {
+        write_atomically(path, &format!("{}\n", serde_json::to_string(snapshot)?))?;
+        reporter.success(format!(
+            "Saved {} forbidden ports to {}",
+            snapshot.forbidden.len(),
+            path.display()
+        ));
+    }
+    let Some(earlier) = earlier else {
+        return Ok(());
+    };
+    let diff = earlier.diff(snapshot);
+    match cli.output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&diff)?),
+        OutputFormat::Yaml => println!("{}", serde_yaml::to_string(&diff)?.trim_end()),
+        OutputFormat::Jsonl => {
+            for (kind, changes) in [
+                ("became_used", &diff.became_used),
+                ("became_free", &diff.became_free),
+                ("changed", &diff.changed),
+            ] {
+                for change in changes {
+                    let mut line = serde_json::to_value(change)?;
+                    line["type"] = kind.into();
+                    println!("{}", serde_json::to_string(&line)?);
+                }
+            }
+        }
+        _ => {
+            reporter.info(format!(
+                "Comparing with the snapshot taken on {} at {} (Unix time).",
+                earlier.host, earlier.taken_at
+            ));
+            if diff.is_empty() {
+                println!("No changes.");
+            }
+            for change in &diff.became_used {
+                println!(
+                    "+ {} now used: {}",
+                    change.port,
+                    describe_reasons(&change.after)
+                );
+            }
+            for change in &diff.became_free {
+                println!(
+                    "- {} now free, was: {}",
+                    change.port,
+                    describe_reasons(&change.before)
+                );
+            }
+            for change in &diff.changed {
+                println!(
+                    "~ {} {} -> {}",
+                    change.port,
+                    describe_reasons(&change.before),
+                    describe_reasons(&change.after)
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn describe_reasons(reasons: &[RejectionReason]) -> String {
+    if reasons.is_empty() {
+        return "unknown source".to_string();
+    }
+    reasons
+        .iter()
+        .map(|reason| match reason {
+            RejectionReason::KnownService => "known service",
+            RejectionReason::LocallyInUse => "in use locally",
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn describe_block(reason: &BlockReason) -> String {
+    match reason {
+        BlockReason::KnownService { names } if names.is_empty() => "known service".to_string(),
+        BlockReason::KnownService { names } => format!("known service ({})", names.join("/")),
+        BlockReason::LocallyInUse => "in use locally".to_string(),
+        BlockReason::Excluded => "excluded".to_string(),
+        BlockReason::OutOfRange => "outside the search ranges".to_string(),
+    }
+}
+
+/// Prints the forbidden-port summary for `stats`, optionally broken down into bands.
+fn report_stats(cli: &Cli, args: &StatsArgs, forbidden_ports: &HashSet<u16>) -> Result<()> {
+    let within = [(DEFAULT_PORT_RANGES[0].0, DEFAULT_PORT_RANGES[1].1)];
+    let searchable = within
+        .iter()
+        .map(|&(start, end)| usize::from(end - start) + 1)
+        .sum::<usize>();
+    let forbidden = searchable - count_free(forbidden_ports, &within, 1).free_ports as usize;
+    let bands = if args.bands {
+        occupancy_bands(forbidden_ports)
+    } else {
+        Vec::new()
+    };
+
+    if matches!(
+        cli.output,
+        OutputFormat::Json | OutputFormat::Yaml | OutputFormat::Jsonl
+    ) {
+        let mut summary = serde_json::json!({
+            "range": [within[0].0, within[0].1],
+            "searchable_ports": searchable,
+            "forbidden_ports": forbidden,
+        });
+        if args.bands {
+            summary["bands"] = bands
+                .iter()
+                .map(|(band, count)| {
+                    serde_json::json!({"start": band.start(), "end": band.end(), "forbidden": count})
+                })
+                .collect();
+        }
+        let rendered = match cli.output {
+            OutputFormat::Json => serde_json::to_string_pretty(&summary)?,
+            OutputFormat::Jsonl => serde_json::to_string(&summary)?,
+            _ => serde_yaml::to_string(&summary)?,
+        };
+        println!("{}", rendered.trim_end());
+        return Ok(());
+    }
+
+    println!(
+        "{} of {} ports forbidden in {}-{}",
+        forbidden, searchable, within[0].0, within[0].1
+    );
+    if !bands.is_empty() {
+        print_bands(&bands, terminal_width());
+    }
+    Ok(())
+}
+
+/// Width of the terminal on stdout, or 80 columns when it isn't one.
+fn terminal_width() -> usize {
+    terminal_size::terminal_size()
+        .map(|(terminal_size::Width(width), _)| usize::from(width))
+        .unwrap_or(80)
+}
+
+/// Prints one `START-END  COUNT  BAR` line per band, scaling bars to the busiest band so the
+/// lines fit in `width` columns.
+fn print_bands(bands: &[(RangeInclusive<u16>, usize)], width: usize) {
+    const LABEL_WIDTH: usize = 11; // "65000-65535"
+    const COUNT_WIDTH: usize = 5;
+    let bar_width = width.saturating_sub(LABEL_WIDTH + COUNT_WIDTH + 4).max(1);
+    let busiest = bands.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    for (band, count) in bands {
+        let label = format!("{}-{}", band.start(), band.end());
+        // Round up so every band with at least one forbidden port shows a mark.
+        let length = (count * bar_width).div_ceil(busiest.max(1));
+        println!(
+            "{:<LABEL_WIDTH$}  {:>COUNT_WIDTH$}  {}",
+            label,
+            count,
+            "#".repeat(length).yellow()
+        );
+    }
+}
+
+/// How `--output text` lays out the suggested ports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextLayout<'a> {
+    /// Header sentence, `- PORT (name)` lines, and color, for people at a terminal.
+    Pretty,
+    /// One `PORT` (or `PORT NAME`) line per suggestion and nothing else, for pipes and files.
+    Plain,
+    /// All ports on one line joined by the separator.
+    Inline(&'a str),
+    /// Each port terminated by a NUL byte.
+    Print0,
+}
+
+impl<'a> TextLayout<'a> {
+    /// Explicit layout flags win; otherwise decorations are dropped when stdout isn't a
+    /// terminal, unless --pretty asks to keep them.
+    fn for_cli(cli: &'a Cli) -> Self {
+        if cli.print0 {
+            TextLayout::Print0
+        } else if cli.inline {
+            TextLayout::Inline(&cli.joiner)
+        } else if cli.pretty || std::io::stdout().is_terminal() {
+            TextLayout::Pretty
+        } else {
+            TextLayout::Plain
+        }
+    }
+}
+
+/// Prints the result of `--output text`. Every text layout goes through here so shortfalls are
+/// reported the same way: in the header when pretty, on stderr otherwise.
+fn print_text_results(
+    cli: &Cli,
+    suggestions: &[Suggestion],
+    number_of_ports: u16,
+    reporter: &Reporter,
+) -> Result<()> {
+    let layout = TextLayout::for_cli(cli);
+    if suggestions.is_empty() {
+        reporter.error(format!(
+            "Could not find {} {}available port(s) in the checked ranges.",
+            number_of_ports,
+            if cli.continuous { "continuous " } else { "" }
+        ));
+        return Ok(());
+    }
+    let shortfall = suggestions.len() < usize::from(number_of_ports);
+    if shortfall && layout != TextLayout::Pretty {
+        reporter.warn(format!(
+            "Warning: Found {} out of {} requested available port(s).",
+            suggestions.len(),
+            number_of_ports
+        ));
+    }
+
+    let docker_lines = if cli.docker_format {
+        let ports: Vec<u16> = suggestions.iter().map(|s| s.port).collect();
+        Some(render_docker_mappings(
+            &ports,
+            cli.container_port_base,
+            cli.range_syntax,
+        )?)
+    } else {
+        None
+    };
+
+    let mut stdout = std::io::stdout().lock();
+    match layout {
+        TextLayout::Print0 => {
+            for suggestion in suggestions {
+                write!(stdout, "{}\0", suggestion.port)?;
+            }
+        }
+        TextLayout::Inline(joiner) => {
+            let ports: Vec<String> = suggestions.iter().map(|s| s.port.to_string()).collect();
+            writeln!(stdout, "{}", ports.join(joiner))?;
+        }
+        TextLayout::Plain => match &docker_lines {
+            Some(lines) => {
+                for line in lines {
+                    writeln!(stdout, "{}", line)?;
+                }
+            }
+            None => {
+                for suggestion in suggestions {
+                    match &suggestion.name {
+                        Some(name) => writeln!(stdout, "{} {}", suggestion.port, name)?,
+                        None => writeln!(stdout, "{}", suggestion.port)?,
+                    }
+                }
+            }
+        },
+        TextLayout::Pretty => {
+            let header = if cli.continuous && shortfall {
+                format!("\nCould not find a continuous block of {} ports. Found {} available port(s) instead:", number_of_ports, suggestions.len()).yellow()
+            } else if shortfall {
+                format!(
+                    "\nFound {} out of {} requested available port(s):",
+                    suggestions.len(),
+                    number_of_ports
+                )
+                .yellow()
+            } else {
+                "\nSuggested available port(s):".green()
+            };
+            writeln!(stdout, "{}", header)?;
+
+            const PORT_COLORS: [Color; 6] = [
+                Color::Red,
+                Color::Yellow,
+                Color::Green,
+                Color::Cyan,
+                Color::Blue,
+                Color::Magenta,
+            ];
+            let mut rng = rand::rng();
+            let port_color = *PORT_COLORS.choose(&mut rng).unwrap_or(&Color::White); // Default to white if selection fails
+            match &docker_lines {
+                Some(lines) => {
+                    for line in lines {
+                        writeln!(stdout, "{}", line.color(port_color))?;
+                    }
+                }
+                None => {
+                    for suggestion in suggestions {
+                        let colored_port = suggestion.port.to_string().color(port_color);
+                        let name = match &suggestion.name {
+                            Some(name) => format!(" ({})", name),
+                            None => String::new(),
+                        };
+                        writeln!(
+                            stdout,
+                            "- {}{}{}",
+                            colored_port,
+                            name,
+                            annotation(cli, suggestion)
+                        )?;
+                        print_alternates(&mut stdout, "  ", suggestion)?;
+                    }
+                }
+            }
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Prints the -vv map of the --neighborhood ports around each suggestion. Output meant for
+/// machines, and the one-line layouts, go without it.
+fn print_neighborhoods(
+    cli: &Cli,
+    suggestions: &[Suggestion],
+    forbidden_ports: &HashSet<u16>,
+    provenance: &Provenance,
+    reporter: &Reporter,
+) {
+    let machine = cli.output != OutputFormat::Text || cli.print0 || cli.inline;
+    if machine || suggestions.is_empty() {
+        return;
+    }
+    let suggested: HashSet<u16> = suggestions.iter().map(|s| s.port).collect();
+    reporter.detail("Neighborhood:");
+    for suggestion in suggestions {
+        reporter.detail(format!(
+            "  {}",
+            render_neighborhood(
+                suggestion.port,
+                cli.neighborhood,
+                &suggested,
+                forbidden_ports,
+                provenance
+            )
+        ));
+    }
+    reporter.detail(format!("  {}", NEIGHBORHOOD_KEY));
+}
 
-    /// Enable verbose output
-    #[clap(short, long)]
-    verbose: bool,
+/// Lists a suggestion's --alternates under it in the pretty layout, one `- PORT (alternate)`
+/// line each, indented by `indent`.
+fn print_alternates(out: &mut impl Write, indent: &str, suggestion: &Suggestion) -> Result<()> {
+    for alternate in &suggestion.alternates {
+        writeln!(out, "{}- {} {}", indent, alternate, "(alternate)".dimmed())?;
+    }
+    Ok(())
+}
 
-    /// Force port suggestion even if local port checking (e.g., lsof) fails.
-    /// This may result in less accurate suggestions.
-    #[clap(short, long)]
-    force: bool,
+/// The dimmed `  [registered, within 40000-40009]` after a port in the pretty layout, or
+/// nothing with --no-annotations.
+fn annotation(cli: &Cli, suggestion: &Suggestion) -> String {
+    match cli.no_annotations {
+        true => String::new(),
+        false => format!("  {}", format!("[{}]", suggestion.annotation()).dimmed()),
+    }
 }
 
-// parse_services_content moved to lib.rs
+/// Prints `--output text` for --spec: each group's label followed by its ports. Inline and
+/// NUL-terminated layouts have no room for labels and print the ports alone.
+fn print_group_results(cli: &Cli, allocations: &[GroupAllocation]) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    match TextLayout::for_cli(cli) {
+        TextLayout::Print0 => {
+            for suggestion in allocations.iter().flat_map(|a| &a.suggestions) {
+                write!(stdout, "{}\0", suggestion.port)?;
+            }
+        }
+        TextLayout::Inline(joiner) => {
+            let ports: Vec<String> = allocations
+                .iter()
+                .flat_map(|a| &a.suggestions)
+                .map(|s| s.port.to_string())
+                .collect();
+            writeln!(stdout, "{}", ports.join(joiner))?;
+        }
+        TextLayout::Plain => {
+            for allocation in allocations {
+                let ports: Vec<String> = allocation
+                    .suggestions
+                    .iter()
+                    .map(|s| s.port.to_string())
+                    .collect();
+                writeln!(stdout, "{}: {}", allocation.group.name, ports.join(" "))?;
+            }
+        }
+        TextLayout::Pretty => {
+            writeln!(stdout, "{}", "\nSuggested port groups:".green())?;
+            for allocation in allocations {
+                let group = &allocation.group;
+                let label = format!(
+                    "{} ({} {}port(s))",
+                    group.name,
+                    group.count,
+                    if group.continuous { "continuous " } else { "" }
+                );
+                if allocation.suggestions.len() < usize::from(group.count) {
+                    writeln!(
+                        stdout,
+                        "{}",
+                        format!("{}: found {}", label, allocation.suggestions.len()).yellow()
+                    )?;
+                } else {
+                    writeln!(stdout, "{}:", label)?;
+                }
+                for suggestion in &allocation.suggestions {
+                    writeln!(
+                        stdout,
+                        "  - {}{}",
+                        suggestion.port.to_string().cyan(),
+                        annotation(cli, suggestion)
+                    )?;
+                    print_alternates(&mut stdout, "    ", suggestion)?;
+                }
+            }
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
 
-fn read_system_services_ports(verbose: bool) -> Result<HashSet<u16>> {
-    if verbose {
-        println!(
-            "{}",
+fn main() -> Result<()> {
+    let result = run();
+    // After the results, so a slow notification daemon doesn't hold them up.
+    let pending = PENDING_ANNOUNCEMENT
+        .lock()
+        .ok()
+        .and_then(|mut pending| pending.take());
+    if let Some(completion) = pending.filter(|_| result.is_ok()) {
+        let _ = std::io::stdout().flush();
+        announce_completion(&completion);
+    }
+    // The results are out by now, so the next run gets the fresh copy without this one waiting.
+    if BACKGROUND_REFRESH_DUE.load(Ordering::SeqCst)
+        && let Err(e) = spawn_background_refresh()
+    {
+        Reporter::stderr(false).warn_with(
+            WarningCode::CacheStale,
             format!(
-                "Reading port data from system services file: {}",
-                SYSTEM_SERVICES_PATH
-            )
-            .cyan()
+                "Could not start refreshing the Nmap services cache in the background: {}",
+                e
+            ),
         );
     }
-    let file_content = fs::read_to_string(SYSTEM_SERVICES_PATH).with_context(|| {
-        format!(
-            "Failed to read system services file at '{}'",
-            SYSTEM_SERVICES_PATH
-        )
-    })?;
-    parse_services_content(&file_content, "system services file", verbose)
+    result
 }
 
-fn save_nmap_cache(content: &str, verbose: bool) -> Result<()> {
-    if verbose {
-        println!(
-            "{}",
-            format!("Caching Nmap services data to: {}", LOCAL_NMAP_CACHE_PATH).cyan()
-        );
+fn run() -> Result<()> {
+    let started = Instant::now();
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches)?;
+    match cli.color {
+        ColorChoice::Auto => {}
+        ColorChoice::Always => colored::control::set_override(true),
+        ColorChoice::Never => colored::control::set_override(false),
     }
-    fs::write(LOCAL_NMAP_CACHE_PATH, content).with_context(|| {
-        format!(
-            "Failed to write Nmap services cache to '{}'",
-            LOCAL_NMAP_CACHE_PATH
-        )
-    })
-}
+    if cli.version {
+        return report_version(&cli);
+    }
+    // All diagnostics go to stderr; stdout carries only the results.
+    let reporter = Arc::new(reporter_for(&cli));
+    install_interrupt_handler()?;
+    let deadline = cli.timeout.map(|timeout| Instant::now() + timeout);
 
-fn fetch_remote_nmap_services(verbose: bool) -> Result<String> {
-    if verbose {
-        println!(
-            "{}",
-            format!(
-                "Fetching Nmap services data from: {}",
-                REMOTE_NMAP_SERVICES_URL
-            )
-            .cyan()
-        );
+    apply_config(&mut cli)?;
+
+    if cli.refresh_nmap_cache {
+        return refresh_nmap_cache(&cli, &reporter, deadline).map(drop);
+    }
+
+    // Subcommands that pick nothing run before the pick's settings and $PORTPICK_SESSION_FORBID
+    // are checked, so neither -n 0 nor a stale session stops them.
+    if let Some(Command::Whatis(args)) = &cli.command {
+        let source = normalize_source(&cli.source, &reporter);
+        return report_whatis(&cli, args, source, &reporter, deadline);
+    }
+
+    if let Some(Command::Man(args)) = &cli.command {
+        return render_man_pages(args);
+    }
+
+    if let Some(Command::Schema) = &cli.command {
+        return print_schema(cli.schema_version);
+    }
+
+    if let Some(Command::Wait(args)) = &cli.command {
+        return wait_for_port_state(&cli, args, &reporter);
+    }
+
+    if let Some(Command::Doctor(args)) = &cli.command {
+        return report_doctor(&cli, args);
     }
 
-    let client = reqwest::blocking::Client::builder()
-        .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36")
-        .build()
-        .context("Failed to build reqwest client")?;
+    // compose-override picks one host port per container port.
+    if let Some(Command::ComposeOverride(args)) = &cli.command {
+        let wanted = u16::try_from(args.container_port.len()).unwrap_or(u16::MAX);
+        if cli.spec.is_some() || !cli.names.is_empty() {
+            return Err(anyhow::anyhow!(
+                "compose-override names its ports after --service; --names and --spec don't apply."
+            ));
+        }
+        if cli.number_of_ports.is_some_and(|count| count != wanted) {
+            return Err(anyhow::anyhow!(
+                "compose-override picks one port per --container-port ({}), not -n {}.",
+                wanted,
+                cli.number_of_ports.unwrap_or_default()
+            ));
+        }
+        cli.number_of_ports = Some(wanted);
+        cli.names = vec![args.service.clone(); args.container_port.len()];
+    }
 
-    let response = client
-        .get(REMOTE_NMAP_SERVICES_URL)
-        .send()
-        .context("Failed to send request to nmap-services URL")?;
+    let options = pick_options(&cli);
+    let groups = options.groups().context("Invalid --spec.")?;
+    let number_of_ports = options.requested()?;
 
-    if !response.status().is_success() {
+    if cli.output == OutputFormat::Compose && cli.names.is_empty() && groups.is_empty() {
         return Err(anyhow::anyhow!(
-            "Failed to download nmap-services file. Status: {}",
-            response.status()
+            "--output compose requires --names or --spec."
         ));
     }
-    response
-        .text()
-        .context("Failed to read response text from nmap-services URL")
-}
 
-fn get_locally_used_ports(cli: &Cli) -> Result<HashSet<u16>> {
-    if cli.verbose {
-        println!(
-            "{}",
-            "Scanning for locally used TCP ports using RustScan...".cyan()
-        );
+    if cli.output_file.is_some() && cli.output != OutputFormat::Systemd {
+        return Err(anyhow::anyhow!(
+            "--output-file is only supported with --output systemd."
+        ));
     }
-    // Consider making port range, batch size, and timeout configurable if needed.
-    let target_address = cli.address.as_deref().unwrap_or("127.0.0.1");
-    let rustscan_args = [
-        "-a", target_address, // Target address from --address flag or default
-        "--range",
-        "1-65535",      // Scan all standard port ranges
-        "--accessible", // Output only open ports, one port per line
-        "-b",
-        "1000", // Batch size for scanning
-        "-t",
-        "1500",      // Timeout per port in milliseconds
-        "--",        // Separator: arguments after this are for the command
-        "/bin/true", // Command to run instead of Nmap (does nothing)
-    ];
 
-    if cli.verbose {
-        println!(
-            "{}",
-            format!("Executing: rustscan {}", rustscan_args.join(" ")).dimmed()
-        );
+    if cli.seed.is_some() && !cli.random && cli.strategy != BlockStrategy::Random {
+        return Err(anyhow::anyhow!(
+            "--seed only applies to --random and --strategy random."
+        ));
     }
 
-    let output = Command::new("rustscan")
-        .args(&rustscan_args)
-        .output()
-        .context(
-            "Failed to execute rustscan command. Make sure rustscan is installed and in PATH.",
-        )?;
+    if cli.emit_session && !matches!(cli.output, OutputFormat::Text | OutputFormat::Shell) {
+        return Err(anyhow::anyhow!(
+            "--emit-session only works with --output text or shell, not --output {}.",
+            cli.output
+                .to_possible_value()
+                .map(|value| value.get_name().to_string())
+                .unwrap_or_default()
+        ));
+    }
+    if cli.print0 && cli.output != OutputFormat::Text {
+        return Err(anyhow::anyhow!(
+            "--print0 only works with --output text, not --output {}.",
+            cli.output
+                .to_possible_value()
+                .map(|value| value.get_name().to_string())
+                .unwrap_or_default()
+        ));
+    }
+
+    let within = options.ranges().context("Invalid --within range.")?;
+    if cli.within.is_none() {
+        let (start, reason) = options.default_search_start();
+        reporter.info(format!("Searching from port {}: {}.", start, reason));
+    }
+    let mut excluded = options.excluded().context("Invalid --exclude.")?;
+    let session = match std::env::var(SESSION_FORBID_ENV) {
+        Ok(value) => Some(
+            parse_session_forbid(&value)
+                .with_context(|| format!("Invalid ${}.", SESSION_FORBID_ENV))?,
+        ),
+        Err(_) => None,
+    };
+    if let Some(session) = &session {
+        reporter.info(format!(
+            "Avoiding {} port(s) picked earlier in this session (${}).",
+            session.len(),
+            SESSION_FORBID_ENV
+        ));
+        excluded = excluded.union(session);
+    }
+    let profiles = ProfileSource::new(&cli.profile).context("Invalid --profile.")?;
 
-    if !output.status.success() {
-        // RustScan might provide partial results or specific error info.
-        // For now, we treat any non-zero exit status as a failure.
+    if let Some(base) = cli.container_port_base
+        && u32::from(base) + u32::from(number_of_ports.max(1)) - 1 > u32::from(u16::MAX)
+    {
         return Err(anyhow::anyhow!(
-            "rustscan command failed with status: {}.\nStdout: {}\nStderr: {}",
-            output.status,
-            String::from_utf8_lossy(&output.stdout),
-            String::from_utf8_lossy(&output.stderr)
+            "--container-port-base {} leaves no room for {} container ports below 65536.",
+            base,
+            number_of_ports
         ));
     }
 
-    let output_str = String::from_utf8_lossy(&output.stdout);
-    let mut ports = HashSet::new();
+    if number_of_ports == 0 {
+        reporter.warn("Number of ports requested is 0. No ports to find.");
+        return Ok(());
+    }
 
-    for line in output_str.lines() {
-        let trimmed_line = line.trim();
-        if trimmed_line.is_empty() {
-            continue;
+    if let Some(spec) = &cli.netns
+        && netns_container(spec).is_none()
+    {
+        return Err(anyhow::anyhow!(
+            "Unsupported --netns '{}': expected container:<name|id>.",
+            spec
+        ));
+    }
+
+    let privileges = Privileges::detect();
+    reporter.info(format!("Privileges: {}", privileges));
+
+    // Like a broken project file, a malformed batch fails before anything is scanned, with
+    // --strict.
+    let batch = match &cli.command {
+        Some(Command::Batch(args)) => Some(read_batch_requests(&cli, args)?),
+        _ => None,
+    };
+
+    // A broken project file fails before anything is scanned.
+    let project = match &cli.command {
+        Some(Command::Apply(args)) => {
+            let content = fs::read_to_string(&args.file)
+                .with_context(|| format!("Failed to read '{}'", args.file.display()))?;
+            Some(
+                ProjectSpec::from_yaml(&content)
+                    .with_context(|| format!("Invalid project file '{}'", args.file.display()))?,
+            )
         }
+        _ => None,
+    };
 
-        // Attempt to parse as a plain port number first
-        if let Ok(port) = u16::from_str(trimmed_line) {
-            ports.insert(port);
-            continue;
+    let template = match &cli.command {
+        Some(Command::Render(args)) => {
+            if let Some(out) = &args.out
+                && !args.overwrite
+                && out.exists()
+            {
+                return Err(anyhow::anyhow!(
+                    "'{}' already exists; pass --overwrite to replace it.",
+                    out.display()
+                ));
+            }
+            Some(fs::read_to_string(&args.template).with_context(|| {
+                format!("Failed to read template '{}'", args.template.display())
+            })?)
         }
+        _ => None,
+    };
 
-        // Attempt to parse "Open <ip>:<port>" format
-        if trimmed_line.starts_with("Open ") {
-            if let Some(ip_port_part) = trimmed_line.split_whitespace().nth(1) {
-                if let Some(port_str) = ip_port_part.split(':').last() {
-                    if let Ok(port) = u16::from_str(port_str) {
-                        ports.insert(port);
-                        if cli.verbose { // Optionally log successful parsing of this format
-                            println!("{}", format!("Parsed port {} from rustscan line: '{}'", port, trimmed_line).dimmed());
-                        }
-                        continue;
+    // Like a template, the compose file and any override to merge into are checked before
+    // anything is scanned, so a conflict fails fast.
+    let existing_override = match &cli.command {
+        Some(Command::ComposeOverride(args)) => {
+            let content = fs::read_to_string(&args.compose_file)
+                .with_context(|| format!("Failed to read '{}'", args.compose_file.display()))?;
+            let services = compose_service_names(&content).with_context(|| {
+                format!("Invalid compose file '{}'", args.compose_file.display())
+            })?;
+            if !services.contains(&args.service) {
+                return Err(anyhow::anyhow!(
+                    "'{}' has no service '{}' (it defines {}).",
+                    args.compose_file.display(),
+                    args.service,
+                    if services.is_empty() {
+                        "none".to_string()
+                    } else {
+                        services.join(", ")
+                    }
+                ));
+            }
+            match &args.out {
+                Some(out) if out.exists() => {
+                    let existing = fs::read_to_string(out)
+                        .with_context(|| format!("Failed to read '{}'", out.display()))?;
+                    let conflicts =
+                        override_conflicts(&existing, &args.service, &args.container_port)
+                            .with_context(|| {
+                                format!("Invalid override file '{}'", out.display())
+                            })?;
+                    if !conflicts.is_empty() && !args.overwrite {
+                        return Err(anyhow::anyhow!(
+                            "'{}' already publishes {} for service '{}'; pass --overwrite to replace {}.",
+                            out.display(),
+                            conflicts.join(", "),
+                            args.service,
+                            if conflicts.len() == 1 { "it" } else { "them" }
+                        ));
                     }
+                    Some(existing)
                 }
+                _ => None,
             }
         }
+        _ => None,
+    };
 
-        // If verbose, log unparsed lines unless they are known informational messages
-        if cli.verbose {
-            let known_info_patterns = [
-                "File limit higher than batch size",
-                "Starting Script(s)",
-                "Running script",
-                "Depending on the complexity",
-                "Starting Nmap",
-                "Initiating Ping Scan",
-                "Scanning ", // Catches "Scanning 127.0.0.1..." and "Scanning localhost..."
-                "Completed Ping Scan",
-                "Initiating Connect Scan",
-                "Discovered open port ", // Catches "Discovered open port 22/tcp on 127.0.0.1"
-                "Completed Connect Scan",
-                "Nmap scan report for",
-                "Host is up",
-                "Scanned at",
-                "PORT ", // Catches "PORT STATE SERVICE REASON" header
-                "Read data files from",
-                "Nmap done",
-                // Lines that are valid Nmap output for open/closed/filtered ports but not just the number
-                "/tcp ", "/udp ", // Catches lines like "22/tcp open ssh"
-            ];
+    let earlier_snapshot = match &cli.command {
+        Some(Command::Snapshot(SnapshotArgs {
+            diff: Some(path), ..
+        })) => {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read snapshot '{}'", path.display()))?;
+            Some(
+                serde_json::from_str::<Snapshot>(&content)
+                    .with_context(|| format!("Invalid snapshot '{}'", path.display()))?,
+            )
+        }
+        _ => None,
+    };
 
-            let is_known_info = known_info_patterns
-                .iter()
-                .any(|pattern| trimmed_line.contains(pattern));
+    let check_ports = match &cli.command {
+        Some(Command::Check(args)) => args.ports.clone(),
+        _ => cli.check.clone(),
+    };
 
-            if !is_known_info {
-                eprintln!(
-                    "{}",
-                    format!(
-                        "Warning: Could not parse rustscan output line as a direct port: '{}'",
-                        trimmed_line
-                    )
-                    .yellow()
-                );
-            }
-        }
+    // Impossible requests fail before any fetching or scanning. Counting and stats report
+    // on capacity instead, so they have nothing to reject.
+    if cli.command.is_none()
+        && !cli.count_only
+        && check_ports.is_empty()
+        && groups.is_empty()
+        && let Err(e) = validate_request(number_of_ports, cli.continuous, &within)
+    {
+        reporter.error(format!("Error: {}", e));
+        std::process::exit(2);
     }
 
-    if cli.verbose {
-        println!(
-            "{}",
-            format!("RustScan found {} locally open TCP ports.", ports.len()).cyan()
-        );
+    if cli.fast
+        && (cli.count_only
+            || !check_ports.is_empty()
+            || !matches!(
+                cli.command,
+                None | Some(Command::Render(_) | Command::ComposeOverride(_))
+            ))
+    {
+        return Err(anyhow::anyhow!(
+            "--fast only applies when picking ports; counting, checking, and subcommands that report on every port need the full scan."
+        ));
     }
-    Ok(ports)
-}
 
-// find_available_ports moved to lib.rs
-
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let mut forbidden_ports = HashSet::new();
-
-    if cli.number_of_ports == 0 {
-        println!(
-            "{}",
-            "\nNumber of ports requested is 0. No ports to find.".yellow()
+    // CI runners rarely have a scanner installed; binding each suggestion checks the same host.
+    if !cli.skip_local_scan
+        && let Some(variable) = ci_environment(&cli)
+    {
+        let instead = verify_instead_of_scanning(&mut cli, &matches);
+        reporter.warn_with(
+            WarningCode::CiDetected,
+            format!(
+                "CI environment detected (${}); {}. Pass --no-ci-detection to scan anyway.",
+                variable, instead
+            ),
         );
-        return Ok(());
     }
-
-    // Determine the source of service port information
-    match cli.source.to_lowercase().as_str() {
-        "nmap" => {
-            if cli.verbose {
-                println!("{}", format!("Source 'nmap': Attempting to fetch, cache, and parse Nmap services list from {}...", REMOTE_NMAP_SERVICES_URL).cyan());
-            }
-            match fetch_remote_nmap_services(cli.verbose) {
-                Ok(nmap_content) => {
-                    if let Err(e) = save_nmap_cache(&nmap_content, cli.verbose) {
-                        eprintln!(
-                            "{}",
-                            format!(
-                                "Warning: Failed to save fetched Nmap services to cache at {}: {}",
-                                LOCAL_NMAP_CACHE_PATH, e
-                            )
-                            .yellow()
-                        );
-                    } else if cli.verbose {
-                        println!(
-                            "{}",
-                            format!(
-                                "Successfully cached Nmap services to {}",
-                                LOCAL_NMAP_CACHE_PATH
-                            )
-                            .green()
-                        );
-                    }
-                    match parse_services_content(
-                        &nmap_content,
-                        "fetched Nmap services list",
-                        cli.verbose,
-                    ) {
-                        Ok(nmap_ports) => forbidden_ports.extend(nmap_ports),
-                        Err(e) => {
-                            return Err(e.context("Failed to parse fetched Nmap services content."));
-                        }
-                    }
-                }
-                Err(e) => {
-                    return Err(e.context("Failed to fetch remote Nmap services for source 'nmap'."));
-                }
-            }
+    // Inside a container /etc/services is often a stub and localhost is the container's own
+    // network namespace, so neither says much about the host.
+    let mut builtin_services = false;
+    if let Some(container) = container_environment(&cli) {
+        let mut changes = Vec::new();
+        if matches.value_source("source") != Some(ValueSource::CommandLine)
+            && matches.value_source("services_file") != Some(ValueSource::CommandLine)
+            && cli.source_order.is_none()
+            && Provider::System.is_enabled(&cli, normalize_source(&cli.source, &Reporter::silent()))
+            && !cli.system
+        {
+            builtin_services = true;
+            changes.push("using the built-in services list instead of /etc/services");
         }
-        "cache" => {
-            if cli.verbose {
-                println!(
-                    "{}",
-                    format!(
-                        "Source 'cache': Attempting to use cached Nmap services from {}...",
-                        LOCAL_NMAP_CACHE_PATH
-                    )
-                    .cyan()
-                );
-            }
-            match fs::read_to_string(LOCAL_NMAP_CACHE_PATH) {
-                Ok(cached_content) => {
-                    match parse_services_content(
-                        &cached_content,
-                        "cached Nmap services list",
-                        cli.verbose,
-                    ) {
-                        Ok(cached_ports) => forbidden_ports.extend(cached_ports),
-                        Err(e) => {
-                            return Err(e.context(format!(
-                                "Failed to parse cached Nmap services content from {}.",
-                                LOCAL_NMAP_CACHE_PATH
-                            )));
-                        }
-                    }
-                }
-                Err(_) => {
-                    eprintln!("{}", format!("Warning: Nmap services cache file not found or unreadable at {}. Falling back to system services.", LOCAL_NMAP_CACHE_PATH).yellow());
-                    // Fallback to system services
-                    if cli.verbose {
-                        println!(
-                            "{}",
-                            format!(
-                                "Source 'system': Attempting to use system services file: {}",
-                                SYSTEM_SERVICES_PATH
-                            )
-                            .cyan()
-                        );
-                    }
-                    match read_system_services_ports(cli.verbose) {
-                        Ok(system_ports) => forbidden_ports.extend(system_ports),
-                        Err(e_sys) => eprintln!("{}", format!("Warning: Could not read or parse system services file ({}): {}. Proceeding with minimal forbidden ports.", SYSTEM_SERVICES_PATH, e_sys).yellow()),
-                    }
-                }
-            }
+        if !cli.skip_local_scan
+            && cli.address.is_none()
+            && cli.netns.is_none()
+            && cli.scanner == ScannerChoice::Auto
+            && std::env::var_os(RUSTSCAN_PROGRAM_ENV).is_none()
+        {
+            changes.push(verify_instead_of_scanning(&mut cli, &matches));
         }
-        "system" | _ => {
-            // Default to "system" if an unknown value is provided or if it's explicitly "system"
-            if cli.source.to_lowercase() != "system" && cli.verbose {
-                // Warn if it's an unknown value
-                eprintln!(
-                    "{}",
-                    format!(
-                        "Warning: Unknown source '{}'. Defaulting to 'system' services.",
-                        cli.source
-                    )
-                    .yellow()
-                );
-            }
-            if cli.verbose {
-                println!(
-                    "{}",
+        if !changes.is_empty() {
+            let host = fs::read_to_string("/proc/net/route")
+                .ok()
+                .and_then(|route| default_gateway(&route))
+                .map(|gateway| {
                     format!(
-                        "Source 'system': Attempting to use system services file: {}",
-                        SYSTEM_SERVICES_PATH
+                        "--address {} (the default gateway, usually the host)",
+                        gateway
                     )
-                    .cyan()
-                );
-            }
-            match read_system_services_ports(cli.verbose) {
-                Ok(system_ports) => forbidden_ports.extend(system_ports),
-                Err(e_sys) => {
-                    eprintln!("{}", format!("Warning: Could not read or parse system services file ({}): {}. Proceeding with minimal forbidden ports.", SYSTEM_SERVICES_PATH, e_sys).yellow());
-                }
-            }
+                })
+                .unwrap_or_else(|| "--address with the host's address".to_string());
+            reporter.warn_with(WarningCode::ContainerDetected, format!(
+                "Running inside {} ({}), where localhost is the container's own network; {}. Host ports aren't visible unless you pass {}. Pass --no-container-detection to scan as usual.",
+                container.kind,
+                container.evidence,
+                changes.join(" and "),
+                host
+            ));
         }
     }
+    // A mistyped host fails here rather than deep inside rustscan, which then scans the literal
+    // address so its own resolution can't pick a different one.
+    if !cli.skip_local_scan
+        && cli.netns.is_none()
+        && let Some(host) = &cli.address
+    {
+        let resolved = resolve_host(host).context("Invalid --address")?;
+        reporter.info(format!(
+            "Resolved --address '{}' to {} ({}); scanning {}.",
+            host,
+            resolved
+                .addresses
+                .iter()
+                .map(IpAddr::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+            resolved.scope(),
+            resolved.primary()
+        ));
+        cli.address = Some(resolved.primary().to_string());
+    }
+    // Rebuilt, since CI detection may have switched to bind-checking.
+    let options = pick_options(&cli);
 
-    // Pass the Cli struct to get_locally_used_ports to access cli.address and cli.verbose
-    match get_locally_used_ports(&cli) {
-        Ok(local_ports) => {
-            forbidden_ports.extend(local_ports);
+    if cli.local_only {
+        if matches.value_source("source") == Some(ValueSource::CommandLine) {
+            reporter.warn(format!(
+                "Warning: --source {} is ignored with --local-only.",
+                cli.source
+            ));
         }
-        Err(e) => {
-            if cli.force {
-                eprintln!("{}", format!("Warning: Failed to get locally used ports: {}. Proceeding with --force, but suggestions may be inaccurate.", e).yellow());
-                // Proceed with an empty set of local ports, relying only on service data
-            } else {
-                // If lsof fails and --force is not used, it's safer to error out.
-                return Err(e.context("Failed to get locally used ports. Cannot reliably find an available port. Use --force to attempt suggestion anyway."));
+        reporter.info("Local-only mode: not reading any services list.");
+    } else if cli.source_order.is_some()
+        && matches.value_source("source") == Some(ValueSource::CommandLine)
+    {
+        reporter.warn(format!(
+            "Warning: --source {} is ignored with --source-order.",
+            cli.source
+        ));
+    }
+
+    let source = normalize_source(&cli.source, &reporter);
+    let frequencies = Mutex::new(Frequencies::new());
+    let mut sources: Vec<Box<dyn PortSource>> = Vec::new();
+    // With --fast the local scan waits until there are candidates to check.
+    let fast_local = cli.fast && Provider::Local.is_enabled(&cli, source);
+    let nmap_source = match source {
+        "all" if Provider::Nmap.is_enabled(&cli, source) => {
+            let nmap_source = freshest_nmap_source(&cli);
+            reporter.info(format!(
+                "Source 'all': reading the Nmap list from {}.",
+                match nmap_source {
+                    "cache" => format!("the cache at {}", nmap_cache_path().display()),
+                    _ => cli.nmap_url.clone(),
+                }
+            ));
+            nmap_source
+        }
+        _ => source,
+    };
+    let source_order = cli.source_order.as_ref().filter(|_| !cli.local_only);
+    if let Some(SourceOrder(order)) = source_order {
+        let links = order
+            .iter()
+            .map(|&link| -> Box<dyn PortSource> {
+                let (provider, source) = match link {
+                    OrderedSource::Builtin => return Box::new(BuiltinServicesSource),
+                    OrderedSource::Cache => (Provider::Nmap, "cache"),
+                    OrderedSource::Nmap => (Provider::Nmap, "nmap"),
+                    OrderedSource::System => (Provider::System, "system"),
+                };
+                Box::new(CliSource {
+                    provider,
+                    name: link.name(),
+                    cli: &cli,
+                    source,
+                    chained: true,
+                    reporter: &reporter,
+                    deadline,
+                    frequencies: &frequencies,
+                })
+            })
+            .collect();
+        let chain = SourceChain::new(links, Arc::clone(&reporter));
+        reporter.info(format!(
+            "Source order: trying {} until one gives ports.",
+            chain.order().join(", then ")
+        ));
+        sources.push(Box::new(chain));
+    }
+    for provider in Provider::ALL {
+        if source_order.is_some() && provider != Provider::Local {
+            continue;
+        }
+        if builtin_services && provider == Provider::System {
+            reporter.info("Provider 'system': replaced by the built-in services list.");
+            sources.push(Box::new(BuiltinServicesSource));
+            continue;
+        }
+        if fast_local && provider == Provider::Local {
+            reporter.info("Provider 'local': deferred; --fast checks candidate ports only.");
+            continue;
+        }
+        if !provider.is_enabled(&cli, source) {
+            match provider {
+                Provider::Local => reporter.info(
+                    "Provider 'local': disabled, local port scan skipped by request (--skip-local-scan).",
+                ),
+                _ => reporter.info(format!("Provider '{}': disabled", provider.name())),
             }
+            continue;
+        }
+        sources.push(Box::new(CliSource {
+            provider,
+            name: provider.name(),
+            cli: &cli,
+            source: match provider {
+                Provider::Nmap => nmap_source,
+                _ => source,
+            },
+            chained: false,
+            reporter: &reporter,
+            deadline,
+            frequencies: &frequencies,
+        }));
+    }
+    if source == "all" && source_order.is_none() && !cli.local_only && !builtin_services {
+        sources.push(Box::new(BuiltinServicesSource));
+    }
+    if cli.no_providers {
+        reporter.info("Provider hooks: disabled (--no-providers).");
+    } else {
+        for hook in provider_hooks(&cli, &within, &reporter)? {
+            sources.push(Box::new(hook));
+        }
+    }
+    if !profiles.is_empty() {
+        sources.push(Box::new(profiles));
+    }
+    // A failed download falls back to the last one cached, and --allow-empty-sources
+    // settles for the built-in list after that.
+    let checks_services = source_order.is_some()
+        || Provider::System.is_enabled(&cli, source)
+        || Provider::Nmap.is_enabled(&cli, source);
+    let mut fallbacks: Vec<Box<dyn PortSource>> = Vec::new();
+    if source_order.is_none() && nmap_source != "cache" && Provider::Nmap.is_enabled(&cli, source) {
+        fallbacks.push(Box::new(CliSource {
+            provider: Provider::Nmap,
+            name: "cache",
+            cli: &cli,
+            source: "cache",
+            chained: false,
+            reporter: &reporter,
+            deadline,
+            frequencies: &frequencies,
+        }));
+    }
+    if cli.allow_empty_sources {
+        fallbacks.push(Box::new(BuiltinServicesSource));
+    }
+    if cli.dry_run {
+        let mut plan = options.plan(&sources)?;
+        if fast_local {
+            plan.sources.push(PlannedSource::new(
+                Provider::Local.name(),
+                format!("{}, candidate ports only (--fast)", planned_scan(&cli)),
+            ));
         }
+        plan.fallbacks = fallbacks.iter().map(|fallback| fallback.plan()).collect();
+        return print_plan(&cli, &plan);
+    }
+    let mut collected = ForbiddenContext::default();
+    let (forbidden_ports, breakdown) = collect_forbidden(
+        CollectOptions {
+            sources,
+            avoid_family: cli.avoid_family,
+            deadline,
+            fallbacks,
+            // Nothing to require when every services list is turned off.
+            min_known_ports: match checks_services {
+                true => MIN_KNOWN_SERVICE_PORTS,
+                false => 0,
+            },
+        },
+        &mut collected,
+        &reporter,
+    )
+    .map_err(|e| match e.downcast_ref::<TooFewKnownPorts>() {
+        Some(_) => e.context(
+            "Refusing to pick ports without knowing which services to avoid. Fix the sources above, or pass --allow-empty-sources to fall back to a small built-in list",
+        ),
+        None => e,
+    })?;
+    let ForbiddenContext {
+        provenance,
+        service_names,
+        ..
+    } = collected;
+    let frequencies = frequencies.into_inner().unwrap_or_default();
+    let timed_out = breakdown.timed_out_during.is_some();
+    if let Some(phase) = &breakdown.timed_out_during {
+        on_timeout(&cli, &reporter, phase);
     }
 
-    if cli.verbose {
-        println!(
-            "{}",
-            format!("Total {} forbidden ports collected.", forbidden_ports.len()).cyan()
+    if is_interrupted() {
+        reporter.warn_with(WarningCode::Interrupted,
+            "Interrupted; forbidden port data is incomplete and suggestions are best-effort (as with --force). Press Ctrl-C again to exit immediately.",
         );
     }
-
-    // Calculate total number of ports in the search ranges to check against requested number of continuous ports.
-    // (1024..=49151) -> 49151 - 1024 + 1 = 48128 ports
-    // (49152..=65535) -> 65535 - 49152 + 1 = 16384 ports
-    // Total = 48128 + 16384 = 64512 ports. This fits in u16.
-    const TOTAL_SEARCHABLE_PORTS: u16 = (49151u16 - 1024u16 + 1u16) + (65535u16 - 49152u16 + 1u16);
-    if cli.continuous && cli.number_of_ports > 1 && TOTAL_SEARCHABLE_PORTS < cli.number_of_ports {
-        // Basic check if requested number of continuous ports can even exist in the searched ranges
-        println!("{}", format!("\nWarning: Requested number of continuous ports ({}) is very large and might not be possible to find as it exceeds the total number of searchable ports ({}).", cli.number_of_ports, TOTAL_SEARCHABLE_PORTS).yellow());
+    reporter.info(format!(
+        "Forbidden ports by source: {}",
+        breakdown.summary()
+    ));
+    for shared in &breakdown.shared {
+        reporter.info(format!("Overlap: {}.", shared));
     }
+    reporter.info(format!(
+        "Total {} forbidden ports collected.",
+        forbidden_ports.len()
+    ));
+    // The selection API still takes a HashSet; convert once at the boundary.
+    let forbidden_ports = forbidden_ports.union(&excluded).to_hash_set();
 
-    let available_ports =
-        find_available_ports(&forbidden_ports, cli.number_of_ports, cli.continuous);
+    if !timed_out && deadline_passed(deadline) {
+        on_timeout(&cli, &reporter, "selection");
+    }
 
-    const PORT_COLORS: [Color; 6] = [
-        Color::Red,
-        Color::Yellow,
-        Color::Green,
-        Color::Cyan,
-        Color::Blue,
-        Color::Magenta,
-    ];
-    let mut rng = rand::rng();
-    let selected_port_color = PORT_COLORS.choose(&mut rng).unwrap_or(&Color::White); // Default to white if selection fails
+    if let Some(Command::Stats(args)) = &cli.command {
+        return report_stats(&cli, args, &forbidden_ports);
+    }
 
-    if available_ports.is_empty() {
-        println!(
-            "{}",
-            format!(
-                "\nCould not find {} {}available port(s) in the checked ranges.",
-                cli.number_of_ports,
-                if cli.continuous { "continuous " } else { "" }
-            )
-            .red()
+    if let Some(Command::Snapshot(args)) = &cli.command {
+        let address = breakdown
+            .sources
+            .iter()
+            .any(|source| source.name == Provider::Local.name())
+            .then(|| {
+                cli.address
+                    .clone()
+                    .unwrap_or_else(|| "127.0.0.1".to_string())
+            });
+        let taken_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or(0);
+        let snapshot = Snapshot::new(
+            &forbidden_ports,
+            &provenance,
+            local_hostname(),
+            address,
+            breakdown
+                .sources
+                .iter()
+                .map(|source| source.name.clone())
+                .collect(),
+            taken_at,
         );
-    } else if cli.continuous && available_ports.len() < cli.number_of_ports as usize {
-        println!("{}", format!("\nCould not find a continuous block of {} ports. Found {} available port(s) instead:", cli.number_of_ports, available_ports.len()).yellow());
-        for port in available_ports {
-            let port_str = format!("{}", port);
-            let colored_port = port_str.color(*selected_port_color);
-            if cli.docker_format {
-                println!("{}:", colored_port);
-            } else {
-                println!("- {}", colored_port);
+        return report_snapshot(&cli, args, &snapshot, earlier_snapshot.as_ref(), &reporter);
+    }
+
+    if let (Some(Command::Apply(args)), Some(project)) = (&cli.command, &project) {
+        let ctx = ForbiddenContext {
+            provenance: provenance.clone(),
+            service_names: service_names.clone(),
+            ..ForbiddenContext::default()
+        };
+        return apply_project(&cli, args, project, &forbidden_ports, ctx, &reporter);
+    }
+
+    if let Some(requests) = &batch {
+        return report_batch(&cli, requests, &forbidden_ports, &within, session.as_ref());
+    }
+
+    if !check_ports.is_empty() {
+        let ctx = ForbiddenContext {
+            provenance: provenance.clone(),
+            service_names: service_names.clone(),
+            excluded: excluded.clone(),
+            ..ForbiddenContext::new(&within)
+        };
+        return report_check(&cli, &check_ports, &ctx, &reporter);
+    }
+
+    if cli.count_only {
+        return report_free_count(&cli, &forbidden_ports, &within, number_of_ports);
+    }
+
+    let (suggestions, allocations) = if fast_local {
+        let (suggestions, allocations, stats) = options.pick_fast(
+            &forbidden_ports,
+            &provenance,
+            &frequencies,
+            &reporter,
+            &mut |ports| scan_local_ports(&cli, &reporter, deadline, Some(ports.to_vec())),
+        )?;
+        reporter.info(format!(
+            "--fast: checked {} candidate port(s) in {} round(s); {} in use.",
+            stats.checked, stats.rounds, stats.busy
+        ));
+        (suggestions, allocations)
+    } else {
+        options.pick(&forbidden_ports, &provenance, &frequencies, &reporter)?
+    };
+
+    reporter.info(format!(
+        "Selected {} port(s): {}",
+        suggestions.len(),
+        suggestions
+            .iter()
+            .map(|suggestion| suggestion.port.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    ));
+    if cli.verbose >= 2 {
+        if fast_local {
+            reporter.detail("No neighborhood map with --fast: it only scans near its picks.");
+        } else {
+            print_neighborhoods(&cli, &suggestions, &forbidden_ports, &provenance, &reporter);
+        }
+    }
+    reporter.info(format!("Timings: {}", timing_summary(&reporter.timings())));
+    warn_unbindable(&suggestions, &privileges, &reporter);
+    let session_export = session_export(
+        &cli,
+        session.as_ref(),
+        suggestions.iter().map(|suggestion| suggestion.port),
+    );
+    if cli.notify
+        && let Ok(mut pending) = PENDING_ANNOUNCEMENT.lock()
+    {
+        *pending = Some(Completion {
+            ports: suggestions
+                .iter()
+                .map(|suggestion| suggestion.port)
+                .collect(),
+            elapsed: started.elapsed(),
+            notify_after: cli.notify_after,
+            reporter: Arc::clone(&reporter),
+        });
+    }
+
+    let env_naming = EnvNaming {
+        prefix: cli.env_prefix.clone(),
+        case: cli.env_case,
+        template: cli.env_template.clone(),
+    };
+    // Checked before anything is written, so a label conflict leaves every file as it was.
+    let devcontainer = match &cli.write_devcontainer {
+        Some(path) => {
+            let path = path
+                .as_deref()
+                .unwrap_or(Path::new(DEFAULT_DEVCONTAINER_PATH));
+            Some((
+                path,
+                updated_devcontainer(path, &suggestions, cli.overwrite)?,
+            ))
+        }
+        None => None,
+    };
+    if let Some(path) = &cli.write_env {
+        write_env_file(path, &suggestions, &env_naming)?;
+        reporter.success(format!("Wrote port assignments to {}", path.display()));
+    }
+    if let Some(path) = &cli.write_envrc {
+        let path = path.as_deref().unwrap_or(Path::new(".envrc"));
+        write_envrc(path, &suggestions, &env_naming)?;
+        reporter.warn(format!(
+            "Note: Wrote port exports to {}; run `direnv allow` to load them.",
+            path.display()
+        ));
+    }
+    if let Some((path, updated)) = devcontainer {
+        write_atomically(path, &updated)?;
+        reporter.success(format!("Forwarded the ports in {}", path.display()));
+    }
+
+    if let (Some(Command::Render(args)), Some(template)) = (&cli.command, &template) {
+        let rendered = render_template(
+            template,
+            &template_placeholders(&suggestions),
+            args.keep_unknown,
+        )
+        .with_context(|| format!("Failed to render '{}'", args.template.display()))?;
+        match &args.out {
+            Some(out) => {
+                write_atomically(out, &rendered)?;
+                reporter.success(format!("Wrote {}", out.display()));
             }
+            None => print!("{}", rendered),
         }
-    } else if !cli.continuous && available_ports.len() < cli.number_of_ports as usize {
-        println!(
-            "{}",
-            format!(
-                "\nFound {} out of {} requested available port(s):",
-                available_ports.len(),
-                cli.number_of_ports
-            )
-            .yellow()
-        );
-        for port in available_ports {
-            let port_str = format!("{}", port);
-            let colored_port = port_str.color(*selected_port_color);
-            if cli.docker_format {
-                println!("{}:", colored_port);
-            } else {
-                println!("- {}", colored_port);
+        return Ok(());
+    }
+
+    if let Some(Command::ComposeOverride(args)) = &cli.command {
+        if suggestions.len() < args.container_port.len() {
+            return Err(anyhow::anyhow!(
+                "Found only {} of the {} ports service '{}' needs.",
+                suggestions.len(),
+                args.container_port.len(),
+                args.service
+            ));
+        }
+        let mappings: Vec<(u16, u16)> = suggestions
+            .iter()
+            .map(|suggestion| suggestion.port)
+            .zip(args.container_port.iter().copied())
+            .collect();
+        let rendered = merge_compose_override(
+            existing_override.as_deref(),
+            &args.service,
+            &mappings,
+            args.overwrite,
+        )?;
+        match &args.out {
+            Some(out) => {
+                write_atomically(out, &rendered)?;
+                reporter.success(format!("Wrote {}", out.display()));
             }
+            None => print!("{}", rendered),
         }
-    } else {
-        // Found all requested ports
-        println!("{}", "\nSuggested available port(s):".green());
-        for port in available_ports {
-            let port_str = format!("{}", port);
-            let colored_port = port_str.color(*selected_port_color);
-            if cli.docker_format {
-                println!("{}:", colored_port);
-            } else {
-                println!("- {}", colored_port);
+        return Ok(());
+    }
+
+    match cli.output {
+        OutputFormat::Text => {}
+        OutputFormat::Json | OutputFormat::Yaml | OutputFormat::Jsonl => {
+            let outcome = PickOutcome {
+                schema_version: cli.schema_version,
+                requested: number_of_ports,
+                continuous: cli.continuous,
+                suggestions,
+                interrupted: is_interrupted(),
+                warnings: reporter.warnings(),
+                groups: allocations.iter().map(GroupAllocation::summary).collect(),
+                sources: Some(breakdown),
+                timings: reporter.timings(),
+            };
+            if cli.output == OutputFormat::Jsonl {
+                // Flush per line so streaming consumers see each record as it is written.
+                let mut stdout = std::io::stdout().lock();
+                for line in outcome.json_lines() {
+                    writeln!(stdout, "{}", serde_json::to_string(&line)?)?;
+                    stdout.flush()?;
+                }
+                return Ok(());
+            }
+            let rendered = match cli.output {
+                OutputFormat::Json => serde_json::to_string_pretty(&outcome)?,
+                _ => serde_yaml::to_string(&outcome)?,
+            };
+            println!("{}", rendered.trim_end());
+            return Ok(());
+        }
+        OutputFormat::Compose => {
+            print!("{}", render_compose(&suggestions, &cli.container_port));
+            return Ok(());
+        }
+        OutputFormat::Systemd => return emit_systemd_units(&cli, &suggestions),
+        OutputFormat::Nginx => {
+            print!("{}", render_nginx(&suggestions, &cli.server_name));
+            return Ok(());
+        }
+        OutputFormat::Caddy => {
+            print!("{}", render_caddy(&suggestions, &cli.server_name));
+            return Ok(());
+        }
+        OutputFormat::Env | OutputFormat::Shell => {
+            let export = cli.output == OutputFormat::Shell;
+            print!("{}", render_env(&suggestions, &env_naming, export));
+            if let Some(line) = session_export {
+                println!("{}", line);
             }
+            return Ok(());
+        }
+        OutputFormat::Grep => {
+            let sources: Vec<String> = breakdown
+                .sources
+                .iter()
+                .map(|source| source.name.clone())
+                .filter(|name| name != "avoid-family")
+                .collect();
+            print!(
+                "{}",
+                render_grep(
+                    cli.address.as_deref().unwrap_or("127.0.0.1"),
+                    &suggestions,
+                    &sources,
+                    forbidden_ports.len()
+                )
+            );
+            return Ok(());
+        }
+        OutputFormat::Markdown => {
+            print!(
+                "{}",
+                render_markdown(
+                    &suggestions,
+                    &cli.container_port,
+                    number_of_ports,
+                    cli.continuous
+                )
+            );
+            return Ok(());
         }
     }
 
+    if !allocations.is_empty() {
+        print_group_results(&cli, &allocations)?;
+    } else {
+        print_text_results(&cli, &suggestions, number_of_ports, &reporter)?;
+    }
+    if let Some(line) = session_export {
+        println!("{}", line);
+    }
     Ok(())
 }
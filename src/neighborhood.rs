@@ -0,0 +1,75 @@
+use crate::{Provenance, RejectionReason};
+use std::collections::HashSet;
+
+/// Ports shown on each side of a suggestion by the `-vv` neighborhood map.
+pub const DEFAULT_NEIGHBORHOOD: u16 = 10;
+
+/// What the letters in a neighborhood map stand for.
+pub const NEIGHBORHOOD_KEY: &str = "Key: * suggested, + also suggested, . free, S service, L in use locally, X excluded or otherwise forbidden";
+
+/// The letter `port` stands as in a neighborhood map. A port both listed and in use reads as
+/// `L`, since its being busy right now is what matters; a forbidden port with neither reason,
+/// such as one from --exclude or a profile, reads as `X`.
+pub fn neighbor_code(
+    port: u16,
+    suggested: &HashSet<u16>,
+    forbidden_ports: &HashSet<u16>,
+    provenance: &Provenance,
+) -> char {
+    let reasons = provenance.reasons(port);
+    if suggested.contains(&port) {
+        '+'
+    } else if reasons.contains(&RejectionReason::LocallyInUse) {
+        'L'
+    } else if reasons.contains(&RejectionReason::KnownService) {
+        'S'
+    } else if forbidden_ports.contains(&port) {
+        'X'
+    } else {
+        '.'
+    }
+}
+
+/// Maps the `radius` ports on each side of `port`, one letter each (see [`NEIGHBORHOOD_KEY`]),
+/// and names the free run `port` sits in, e.g.
+/// `40003  39993 ..S...L...*+.....XL.. 40013  free 40000-40003 (4 port(s))`.
+///
+/// The window stops at ports 0 and 65535. The free run stops at its edges, and at forbidden
+/// ports and the other suggestions, which are taken as well.
+pub fn render_neighborhood(
+    port: u16,
+    radius: u16,
+    suggested: &HashSet<u16>,
+    forbidden_ports: &HashSet<u16>,
+    provenance: &Provenance,
+) -> String {
+    let first = port.saturating_sub(radius);
+    let last = port.saturating_add(radius);
+    let cells: String = (first..=last)
+        .map(|neighbor| match neighbor == port {
+            true => '*',
+            false => neighbor_code(neighbor, suggested, forbidden_ports, provenance),
+        })
+        .collect();
+    let free =
+        |neighbor: &u16| !forbidden_ports.contains(neighbor) && !suggested.contains(neighbor);
+    let start = (first..port).rev().take_while(free).last().unwrap_or(port);
+    let end = (port..=last)
+        .skip(1)
+        .take_while(free)
+        .last()
+        .unwrap_or(port);
+    let run = match start == end {
+        true => start.to_string(),
+        false => format!("{}-{}", start, end),
+    };
+    format!(
+        "{}  {} {} {}  free {} ({} port(s))",
+        port,
+        first,
+        cells,
+        last,
+        run,
+        end - start + 1
+    )
+}
@@ -0,0 +1,402 @@
+use crate::{Reporter, validate_services_content};
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long to wait for another process refreshing the cache before giving up.
+pub const DEFAULT_CACHE_LOCK_WAIT: Duration = Duration::from_secs(30);
+
+/// The filesystem steps of a cache write. Tests inject failures through this.
+pub trait CacheFs {
+    /// Creates `path` (readable by everyone, writable by its owner), writes `contents`, and
+    /// syncs it to disk.
+    fn write_synced(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+}
+
+/// The real filesystem.
+pub struct SystemFs;
+
+impl CacheFs for SystemFs {
+    fn write_synced(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut file = create_cache_file(path)?;
+        file.write_all(contents)?;
+        file.sync_all()
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+}
+
+/// What [`NmapCache::refresh`] came back with.
+#[derive(Debug)]
+pub enum Refresh {
+    /// This process downloaded the content; `saved` tells whether it made it into the cache.
+    Fetched { content: Vec<u8>, saved: Result<()> },
+    /// Another process refreshed the cache while this one waited, so its copy was used.
+    Reused(Vec<u8>),
+}
+
+impl Refresh {
+    pub fn content(&self) -> &[u8] {
+        match self {
+            Refresh::Fetched { content, .. } | Refresh::Reused(content) => content,
+        }
+    }
+}
+
+/// The downloaded Nmap services list, kept for `--source cache`.
+///
+/// A save writes a temporary file beside the cache, syncs it, and renames it into place, so an
+/// interrupted save leaves the previous cache as it was. A refresh streams the download into
+/// that temporary file as it arrives. Content that doesn't pass [`validate_services_content`]
+/// is never saved.
+///
+/// Each save records the content's SHA-256 in a `.sha256` file beside the cache, and a load
+/// whose content doesn't match it is an `InvalidData` error. A cache saved before hashes were
+/// recorded has none and is read as it is.
+///
+/// Refreshing takes an exclusive lock on a `.lock` file beside the cache and reading a shared
+/// one, so concurrent runs neither interleave writes nor download the list twice.
+#[derive(Debug, Clone)]
+pub struct NmapCache {
+    path: PathBuf,
+}
+
+impl NmapCache {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        NmapCache { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// How long ago the cache was last written, or `None` if it doesn't exist.
+    pub fn age(&self, now: SystemTime) -> Option<Duration> {
+        let modified = modified(&self.path)?;
+        Some(now.duration_since(modified).unwrap_or_default())
+    }
+
+    /// Whether the cache exists and is older than `max_age`.
+    pub fn is_stale(&self, max_age: Duration, now: SystemTime) -> bool {
+        self.age(now).is_some_and(|age| age > max_age)
+    }
+
+    /// The file recording the SHA-256 of the cache's content.
+    pub fn digest_path(&self) -> PathBuf {
+        self.sibling("sha256")
+    }
+
+    fn lock_path(&self) -> PathBuf {
+        self.sibling("lock")
+    }
+
+    fn sibling(&self, extension: &str) -> PathBuf {
+        let file_name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.path
+            .with_file_name(format!("{}.{}", file_name, extension))
+    }
+
+    /// Reads the cache under a shared lock, waiting up to `max_wait` for a refresh in
+    /// progress to finish, and checks it against its recorded SHA-256.
+    pub fn load(&self, max_wait: Duration, reporter: &Reporter) -> io::Result<Vec<u8>> {
+        // A cache in a directory we can't create files in is still worth reading unlocked.
+        if let Ok(lock) = &File::open(self.lock_path()) {
+            self.wait_for_lock(lock, true, max_wait, reporter)?;
+        }
+        self.read_verified()
+    }
+
+    /// Deletes the cache and its recorded SHA-256.
+    pub fn remove(&self) -> io::Result<()> {
+        fs::remove_file(&self.path)?;
+        match fs::remove_file(self.digest_path()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn read_verified(&self) -> io::Result<Vec<u8>> {
+        let content = fs::read(&self.path)?;
+        let recorded = match fs::read_to_string(self.digest_path()) {
+            Ok(recorded) => recorded,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(content),
+            Err(e) => return Err(e),
+        };
+        let actual = sha256_hex(&content);
+        if recorded.trim() != actual {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "its SHA-256 is {}, not the {} recorded when it was saved",
+                    actual,
+                    recorded.trim()
+                ),
+            ));
+        }
+        Ok(content)
+    }
+
+    /// Downloads the list with `fetch` and saves it, holding the lock throughout. If another
+    /// process is already refreshing the cache, waits up to `max_wait` for it and uses its
+    /// copy instead of downloading again.
+    ///
+    /// `fetch` writes the content to the writer it is given as it downloads, and returns it.
+    /// Only a failed `fetch` or a timed-out wait is an error; a failed save is reported in
+    /// [`Refresh::Fetched`].
+    pub fn refresh(
+        &self,
+        max_wait: Duration,
+        reporter: &Reporter,
+        fetch: impl FnOnce(&mut dyn Write) -> Result<Vec<u8>>,
+    ) -> Result<Refresh> {
+        let lock = self
+            .path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map_or(Ok(()), create_private_dir)
+            .and_then(|()| {
+                OpenOptions::new()
+                    .create(true)
+                    .truncate(false)
+                    .write(true)
+                    .open(self.lock_path())
+            });
+        let lock = match lock {
+            Ok(lock) => lock,
+            // Without a lock file the cache can't be written either; let the save say why.
+            Err(_) => return self.fetch_into_cache(fetch),
+        };
+
+        let before = modified(&self.path);
+        let waited = self
+            .wait_for_lock(&lock, false, max_wait, reporter)
+            .with_context(|| {
+                format!(
+                    "Failed to lock the Nmap services cache '{}'",
+                    self.path.display()
+                )
+            })?;
+        if waited && modified(&self.path) != before {
+            match self.read_verified() {
+                Ok(content) if validate_services_content(&content).is_ok() => {
+                    reporter
+                        .info("Another portpick refreshed the cache meanwhile; using its copy.");
+                    return Ok(Refresh::Reused(content));
+                }
+                _ => {}
+            }
+        }
+        self.fetch_into_cache(fetch)
+    }
+
+    /// Runs `fetch` against a new temporary file and moves that into place. A failed `fetch`
+    /// leaves no temporary file behind.
+    fn fetch_into_cache(
+        &self,
+        fetch: impl FnOnce(&mut dyn Write) -> Result<Vec<u8>>,
+    ) -> Result<Refresh> {
+        let (temp_path, mut file) = match self.temp_path().and_then(|temp_path| {
+            create_cache_file(&temp_path)
+                .with_context(|| format!("Failed to write '{}'", temp_path.display()))
+                .map(|file| (temp_path, file))
+        }) {
+            Ok(temp) => temp,
+            Err(e) => {
+                let content = fetch(&mut io::sink())?;
+                return Ok(Refresh::Fetched {
+                    content,
+                    saved: Err(e),
+                });
+            }
+        };
+        let content = match fetch(&mut file) {
+            Ok(content) => content,
+            Err(e) => {
+                drop(file);
+                let _ = fs::remove_file(&temp_path);
+                return Err(e);
+            }
+        };
+        let saved = self
+            .check_content(&content)
+            .and_then(|()| {
+                file.sync_all()
+                    .with_context(|| format!("Failed to write '{}'", temp_path.display()))
+            })
+            .and_then(|()| {
+                drop(file);
+                self.commit(&SystemFs, &temp_path, &content)
+            });
+        if saved.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        Ok(Refresh::Fetched { content, saved })
+    }
+
+    /// Takes the lock, polling until `max_wait` passes. Returns whether it had to wait.
+    fn wait_for_lock(
+        &self,
+        lock: &File,
+        shared: bool,
+        max_wait: Duration,
+        reporter: &Reporter,
+    ) -> io::Result<bool> {
+        let try_lock = || match shared {
+            true => lock.try_lock_shared(),
+            false => lock.try_lock(),
+        };
+        let deadline = Instant::now() + max_wait;
+        let mut waited = false;
+        loop {
+            match try_lock() {
+                Ok(()) => return Ok(waited),
+                Err(TryLockError::Error(e)) => return Err(e),
+                Err(TryLockError::WouldBlock) => {}
+            }
+            if !waited {
+                reporter.warn(format!(
+                    "Another portpick is refreshing the cache at {}; waiting up to {}s...",
+                    self.path.display(),
+                    max_wait.as_secs()
+                ));
+                waited = true;
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "another portpick is still refreshing the cache after {}s",
+                        max_wait.as_secs()
+                    ),
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    /// Replaces the cache with `content`.
+    pub fn save(&self, content: &[u8]) -> Result<()> {
+        self.save_with(&SystemFs, content)
+    }
+
+    /// [`NmapCache::save`] through `filesystem`.
+    pub fn save_with(&self, filesystem: &dyn CacheFs, content: &[u8]) -> Result<()> {
+        self.check_content(content)?;
+        let temp_path = self.temp_path()?;
+        let written = filesystem
+            .write_synced(&temp_path, content)
+            .with_context(|| format!("Failed to write '{}'", temp_path.display()))
+            .and_then(|()| self.commit(filesystem, &temp_path, content));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        written
+    }
+
+    fn check_content(&self, content: &[u8]) -> Result<()> {
+        validate_services_content(content).with_context(|| {
+            format!(
+                "Refusing to replace the Nmap services cache '{}'",
+                self.path.display()
+            )
+        })?;
+        Ok(())
+    }
+
+    fn dir(&self) -> &Path {
+        match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        }
+    }
+
+    /// Where a save writes before renaming, creating the cache directory if need be.
+    fn temp_path(&self) -> Result<PathBuf> {
+        let dir = self.dir();
+        create_private_dir(dir)
+            .with_context(|| format!("Failed to create cache directory '{}'", dir.display()))?;
+        let file_name = self
+            .path
+            .file_name()
+            .with_context(|| format!("'{}' is not a file path", self.path.display()))?;
+        Ok(self.path.with_file_name(format!(
+            ".{}.{}.tmp",
+            file_name.to_string_lossy(),
+            std::process::id()
+        )))
+    }
+
+    /// Renames the synced `temp_path`, holding `content`, over the cache and records its
+    /// SHA-256.
+    fn commit(&self, filesystem: &dyn CacheFs, temp_path: &Path, content: &[u8]) -> Result<()> {
+        filesystem
+            .rename(temp_path, &self.path)
+            .with_context(|| format!("Failed to replace '{}'", self.path.display()))?;
+        let digest_path = self.digest_path();
+        filesystem
+            .write_synced(&digest_path, sha256_hex(content).as_bytes())
+            .with_context(|| format!("Failed to write '{}'", digest_path.display()))?;
+        // Make the rename itself durable; not every platform can open a directory.
+        if let Ok(dir) = File::open(self.dir()) {
+            let _ = dir.sync_all();
+        }
+        Ok(())
+    }
+}
+
+/// An age in the largest whole unit, e.g. `3 days`, `1 hour`, or `12 minutes`.
+pub fn describe_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    let (count, unit) = match secs {
+        0..3_600 => (secs / 60, "minute"),
+        3_600..86_400 => (secs / 3_600, "hour"),
+        _ => (secs / 86_400, "day"),
+    };
+    match count {
+        1 => format!("1 {}", unit),
+        _ => format!("{} {}s", count, unit),
+    }
+}
+
+/// The SHA-256 of `content`, in lowercase hex.
+pub fn sha256_hex(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Creates (or truncates) `path`, readable by everyone and writable by its owner.
+fn create_cache_file(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o644);
+    options.open(path)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Creates `dir` and its missing parents, readable only by their owner.
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)
+}
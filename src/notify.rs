@@ -0,0 +1,110 @@
+use anyhow::Result;
+use std::io::Write;
+use std::time::Duration;
+
+/// How long a run takes before `--notify` announces that it finished.
+pub const DEFAULT_NOTIFY_AFTER: Duration = Duration::from_secs(10);
+
+/// Shows a desktop notification. Tests swap in a recorder.
+pub trait Notifier {
+    /// Shows `summary` with `body` below it; an error means nothing was shown.
+    fn notify(&self, summary: &str, body: &str) -> Result<()>;
+}
+
+/// The desktop's notification daemon, through notify-rust on Linux and macOS. Built without
+/// the `notify` feature, every notification fails, so callers fall back to the bell.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DesktopNotifier;
+
+impl Notifier for DesktopNotifier {
+    #[cfg(feature = "notify")]
+    fn notify(&self, summary: &str, body: &str) -> Result<()> {
+        notify_rust::Notification::new()
+            .appname("portpick")
+            .summary(summary)
+            .body(body)
+            .show()?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "notify"))]
+    fn notify(&self, _summary: &str, _body: &str) -> Result<()> {
+        anyhow::bail!("built without desktop notifications (the `notify` feature)")
+    }
+}
+
+/// What a finished run's notification says: its length in the summary, e.g. `portpick
+/// finished in 14s`, and the suggested ports in the body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletionMessage {
+    pub summary: String,
+    pub body: String,
+}
+
+impl CompletionMessage {
+    pub fn new(ports: &[u16], elapsed: Duration) -> Self {
+        let body = match ports {
+            [] => "No free ports found.".to_string(),
+            [port] => format!("Suggested port: {}", port),
+            ports => format!(
+                "Suggested ports: {}",
+                ports
+                    .iter()
+                    .map(u16::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        CompletionMessage {
+            summary: format!("portpick finished in {}", describe_elapsed(elapsed)),
+            body,
+        }
+    }
+}
+
+/// `14s`, or `2m 5s` from a minute on.
+fn describe_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    match secs {
+        0..60 => format!("{}s", secs),
+        _ => format!("{}m {}s", secs / 60, secs % 60),
+    }
+}
+
+/// Whether a run that took `elapsed` is worth announcing, with `threshold` from
+/// `--notify-after`.
+pub fn should_notify(elapsed: Duration, threshold: Duration) -> bool {
+    elapsed >= threshold
+}
+
+/// How [`notify_completion`] announced the run, if it did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Announced {
+    /// The run was quicker than the threshold.
+    NotNeeded,
+    Notification,
+    /// The notification failed for this reason, so the terminal bell rang instead.
+    Bell(String),
+}
+
+/// Announces a finished run that took at least `threshold` through `notifier`, ringing the
+/// bell on `bell` if that fails. Neither failing is an error: the run's results matter more.
+pub fn notify_completion(
+    notifier: &dyn Notifier,
+    bell: &mut dyn Write,
+    ports: &[u16],
+    elapsed: Duration,
+    threshold: Duration,
+) -> Announced {
+    if !should_notify(elapsed, threshold) {
+        return Announced::NotNeeded;
+    }
+    let message = CompletionMessage::new(ports, elapsed);
+    match notifier.notify(&message.summary, &message.body) {
+        Ok(()) => Announced::Notification,
+        Err(e) => {
+            let _ = bell.write_all(b"\x07").and_then(|()| bell.flush());
+            Announced::Bell(format!("{:#}", e))
+        }
+    }
+}
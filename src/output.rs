@@ -0,0 +1,731 @@
+use crate::{RangeCategory, Suggestion, VerificationStatus};
+use anyhow::{Result, bail};
+
+/// Groups suggestions sharing a name, in first-seen order. Unnamed suggestions each stay alone.
+fn group_by_name(suggestions: &[Suggestion]) -> Vec<Vec<&Suggestion>> {
+    let mut groups: Vec<Vec<&Suggestion>> = Vec::new();
+    for suggestion in suggestions {
+        let existing = suggestion.name.as_ref().and_then(|name| {
+            groups
+                .iter_mut()
+                .find(|group| group[0].name.as_ref() == Some(name))
+        });
+        match existing {
+            Some(group) => group.push(suggestion),
+            None => groups.push(vec![suggestion]),
+        }
+    }
+    groups
+}
+
+/// Renders suggestions as a docker-compose `services:` fragment.
+///
+/// Suggestions sharing a name are grouped under one service, in first-seen order. Unnamed
+/// suggestions become `service1`, `service2`, ... by position. `container_ports` pairs up with
+/// the suggestions by index; missing entries map to the same port as the host side. Port
+/// mappings are always quoted so YAML 1.1 parsers can't read `"22:22"` as a base-60 number.
+pub fn render_compose(suggestions: &[Suggestion], container_ports: &[u16]) -> String {
+    let mut services: Vec<(String, Vec<String>)> = Vec::new();
+    for (index, suggestion) in suggestions.iter().enumerate() {
+        let name = suggestion
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("service{}", index + 1));
+        let container_port = container_ports
+            .get(index)
+            .copied()
+            .unwrap_or(suggestion.port);
+        let mapping = format!("\"{}:{}\"", suggestion.port, container_port);
+        match services.iter_mut().find(|(existing, _)| *existing == name) {
+            Some((_, mappings)) => mappings.push(mapping),
+            None => services.push((name, vec![mapping])),
+        }
+    }
+
+    let mut rendered = String::from("services:\n");
+    for (name, mappings) in services {
+        rendered.push_str(&format!("  {}:\n    ports:\n", yaml_key(&name)));
+        for mapping in mappings {
+            rendered.push_str(&format!("      - {}\n", mapping));
+        }
+    }
+    rendered
+}
+
+/// Renders `--docker-format` lines: each host port followed by a colon, and with
+/// `container_port_base` the container port it is paired with. Container ports count up from
+/// the base in the order of `ports`, whether or not the host ports are consecutive.
+///
+/// With `range_syntax`, host ports that are ascending and consecutive collapse to compose's
+/// single `"START-END:BASE-LAST"` line; others are still listed one per line.
+pub fn render_docker_mappings(
+    ports: &[u16],
+    container_port_base: Option<u16>,
+    range_syntax: bool,
+) -> Result<Vec<String>> {
+    let Some(base) = container_port_base else {
+        return Ok(ports.iter().map(|port| format!("{}:", port)).collect());
+    };
+    let last_offset = ports.len().saturating_sub(1);
+    let Some(last_container) = u16::try_from(last_offset)
+        .ok()
+        .and_then(|offset| base.checked_add(offset))
+    else {
+        bail!(
+            "Container ports from {} for {} host ports would pass 65535.",
+            base,
+            ports.len()
+        );
+    };
+    let consecutive = ports
+        .windows(2)
+        .all(|pair| pair[0].checked_add(1) == Some(pair[1]));
+    if range_syntax && ports.len() > 1 && consecutive {
+        return Ok(vec![format!(
+            "{}-{}:{}-{}",
+            ports[0], ports[last_offset], base, last_container
+        )]);
+    }
+    Ok(ports
+        .iter()
+        .zip(base..=last_container)
+        .map(|(port, container)| format!("{}:{}", port, container))
+        .collect())
+}
+
+/// Plain scalars that YAML 1.1 would read as booleans or null.
+const YAML_RESERVED_WORDS: [&str; 9] =
+    ["true", "false", "yes", "no", "on", "off", "y", "n", "null"];
+
+/// Quotes a YAML mapping key unless it is a plain compose-style identifier.
+fn yaml_key(key: &str) -> String {
+    let is_plain = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        && key.chars().any(|c| c.is_ascii_alphabetic())
+        && !YAML_RESERVED_WORDS.contains(&key.to_lowercase().as_str());
+    if is_plain {
+        key.to_string()
+    } else {
+        format!("\"{}\"", key.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+/// A rendered systemd unit, ready to be written as `file_name`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemdUnit {
+    pub file_name: String,
+    pub contents: String,
+}
+
+/// Renders suggestions as systemd `.socket` units with one `ListenStream=` per port.
+///
+/// By default every name gets its own unit listing all of its ports (unnamed suggestions get
+/// one `portpick-<port>` unit each). With `one_unit`, all ports are listed in a single unit
+/// named after the first suggestion. Names from the caller are escaped like `systemd-escape`
+/// does.
+pub fn render_systemd_units(suggestions: &[Suggestion], one_unit: bool) -> Vec<SystemdUnit> {
+    // Returns the human-readable name and the escaped unit file stem.
+    let unit_name = |suggestion: &Suggestion| match &suggestion.name {
+        Some(name) => (name.clone(), systemd_escape(name)),
+        None => {
+            let name = format!("portpick-{}", suggestion.port);
+            (name.clone(), name)
+        }
+    };
+    let render = |(name, stem): (String, String), ports: &[&Suggestion]| {
+        let mut contents = format!(
+            "[Unit]\nDescription=portpick socket for {}\n\n[Socket]\n",
+            name.replace(|c: char| c.is_control(), " ")
+        );
+        for suggestion in ports {
+            contents.push_str(&format!("ListenStream={}\n", suggestion.port));
+        }
+        contents.push_str("\n[Install]\nWantedBy=sockets.target\n");
+        SystemdUnit {
+            file_name: format!("{}.socket", stem),
+            contents,
+        }
+    };
+
+    if one_unit {
+        match suggestions.first() {
+            Some(first) => vec![render(
+                unit_name(first),
+                &suggestions.iter().collect::<Vec<_>>(),
+            )],
+            None => Vec::new(),
+        }
+    } else {
+        group_by_name(suggestions)
+            .iter()
+            .map(|group| render(unit_name(group[0]), group))
+            .collect()
+    }
+}
+
+/// Escapes a string for use in a unit name, following `systemd-escape` (without `--path`).
+pub fn systemd_escape(name: &str) -> String {
+    let mut escaped = String::new();
+    for (index, byte) in name.bytes().enumerate() {
+        match byte {
+            b'/' => escaped.push('-'),
+            b'.' if index == 0 => escaped.push_str("\\x2e"),
+            b if b.is_ascii_alphanumeric() || matches!(b, b':' | b'_' | b'.') => {
+                escaped.push(b as char)
+            }
+            b => escaped.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    escaped
+}
+
+/// Virtual host for a suggestion: the server name itself, or `<name>.<server_name>` when
+/// several named suggestions would otherwise share it.
+fn virtual_host(suggestion: &Suggestion, server_name: &str, shared: bool) -> String {
+    match &suggestion.name {
+        Some(name) if shared => format!("{}.{}", dns_label(name), server_name),
+        _ => server_name.to_string(),
+    }
+}
+
+/// Lowercases a name and replaces anything that isn't valid in a DNS label with `-`.
+fn dns_label(name: &str) -> String {
+    let label: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    label.trim_matches('-').to_string()
+}
+
+/// Renders `--output grep`: one nmap-style line such as
+/// `Host: 127.0.0.1 Ports: 8080,8081,8082 Continuous: yes Source: system Forbidden: 1342`.
+///
+/// The labels and their order are fixed so awk and grep one-liners keep working across
+/// releases. `Continuous` says whether the ports form one unbroken ascending run, and an empty
+/// list of ports or sources reads as `-`.
+pub fn render_grep(
+    host: &str,
+    suggestions: &[Suggestion],
+    sources: &[String],
+    forbidden: usize,
+) -> String {
+    let ports: Vec<String> = suggestions.iter().map(|s| s.port.to_string()).collect();
+    let continuous = !suggestions.is_empty()
+        && suggestions
+            .windows(2)
+            .all(|pair| u32::from(pair[1].port) == u32::from(pair[0].port) + 1);
+    let or_dash = |list: String| match list.is_empty() {
+        true => "-".to_string(),
+        false => list,
+    };
+    format!(
+        "Host: {} Ports: {} Continuous: {} Source: {} Forbidden: {}\n",
+        host,
+        or_dash(ports.join(",")),
+        if continuous { "yes" } else { "no" },
+        or_dash(sources.join(",")),
+        forbidden
+    )
+}
+
+/// Renders suggestions as a GitHub-flavored Markdown table, after a one-line summary.
+///
+/// The columns are Name, Port, Container Port (only when `container_ports` is given, paired by
+/// index as for [`render_compose`]), and Notes. With `continuous`, each row of a consecutive
+/// run notes the block it belongs to. Cells are padded to their column's width so the raw text lines up too.
+pub fn render_markdown(
+    suggestions: &[Suggestion],
+    container_ports: &[u16],
+    requested: u16,
+    continuous: bool,
+) -> String {
+    let summary = match (suggestions.len(), usize::from(requested)) {
+        (0, requested) => format!("No available ports found ({} requested).", requested),
+        (found, requested) if found < requested => format!(
+            "Found {} of {} requested available ports.",
+            found, requested
+        ),
+        (1, _) => "Suggested 1 available port.".to_string(),
+        (found, _) => format!("Suggested {} available ports.", found),
+    };
+    if suggestions.is_empty() {
+        return format!("{}\n", summary);
+    }
+
+    // A continuous run that fell short of a block is just a list of ports.
+    let consecutive = suggestions
+        .windows(2)
+        .all(|pair| u32::from(pair[1].port) == u32::from(pair[0].port) + 1);
+    let block = match (suggestions.first(), suggestions.last()) {
+        (Some(first), Some(last)) if continuous && consecutive && suggestions.len() > 1 => Some(
+            format!("continuous block {}\u{2013}{}", first.port, last.port),
+        ),
+        _ => None,
+    };
+    let mut header = vec!["Name", "Port"];
+    if !container_ports.is_empty() {
+        header.push("Container Port");
+    }
+    header.push("Notes");
+    let rows: Vec<Vec<String>> = suggestions
+        .iter()
+        .enumerate()
+        .map(|(index, suggestion)| {
+            let mut row = vec![
+                markdown_cell(suggestion.name.as_deref().unwrap_or_default()),
+                suggestion.port.to_string(),
+            ];
+            if !container_ports.is_empty() {
+                let container_port = container_ports
+                    .get(index)
+                    .copied()
+                    .unwrap_or(suggestion.port);
+                row.push(container_port.to_string());
+            }
+            let mut notes = Vec::new();
+            if let Some(block) = &block {
+                notes.push(block.clone());
+            }
+            if suggestion.range == RangeCategory::System {
+                notes.push("needs privileges to bind".to_string());
+            }
+            match suggestion.verification {
+                VerificationStatus::Verified => notes.push("bind-checked".to_string()),
+                VerificationStatus::Failed => notes.push("bind check failed".to_string()),
+                VerificationStatus::Unverified => {}
+            }
+            row.push(notes.join("; "));
+            row
+        })
+        .collect();
+
+    // Port columns are numbers and align right; GFM needs at least three dashes per column.
+    let numeric = |column: usize| column > 0 && column + 1 < header.len();
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .chain([header[column].len(), 3])
+                .max()
+                .unwrap_or(3)
+        })
+        .collect();
+    let render_row = |cells: &[String]| -> String {
+        let cells: Vec<String> = cells
+            .iter()
+            .enumerate()
+            .map(|(column, cell)| match numeric(column) {
+                true => format!("{:>width$}", cell, width = widths[column]),
+                false => format!("{:<width$}", cell, width = widths[column]),
+            })
+            .collect();
+        format!("| {} |\n", cells.join(" | "))
+    };
+
+    let mut rendered = format!("{}\n\n", summary);
+    rendered.push_str(&render_row(
+        &header
+            .iter()
+            .map(|cell| cell.to_string())
+            .collect::<Vec<_>>(),
+    ));
+    let rule: Vec<String> = widths
+        .iter()
+        .enumerate()
+        .map(|(column, &width)| match numeric(column) {
+            true => format!("{}:", "-".repeat(width - 1)),
+            false => "-".repeat(width),
+        })
+        .collect();
+    rendered.push_str(&format!("| {} |\n", rule.join(" | ")));
+    for row in &rows {
+        rendered.push_str(&render_row(row));
+    }
+    rendered
+}
+
+/// Escapes the characters that would end a table cell or start inline markup.
+fn markdown_cell(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| match c {
+            '|' | '\\' | '`' | '*' | '_' => vec!['\\', c],
+            _ => vec![c],
+        })
+        .collect()
+}
+
+/// Renders an nginx `upstream` plus `server` block proxying to each suggested port.
+///
+/// Ports sharing a name are balanced by one upstream. With more than one block, each named one
+/// gets its own `<name>.<server_name>` host.
+pub fn render_nginx(suggestions: &[Suggestion], server_name: &str) -> String {
+    let groups = group_by_name(suggestions);
+    let shared = groups.len() > 1;
+    groups
+        .iter()
+        .map(|group| {
+            let first = group[0];
+            let upstream = match &first.name {
+                Some(name) => dns_label(name).replace('-', "_"),
+                None => format!("portpick_{}", first.port),
+            };
+            let servers: String = group
+                .iter()
+                .map(|suggestion| format!("    server 127.0.0.1:{};\n", suggestion.port))
+                .collect();
+            format!(
+                "upstream {upstream} {{\n{servers}}}\n\nserver {{\n    listen 80;\n    server_name {host};\n\n    location / {{\n        proxy_pass http://{upstream};\n    }}\n}}\n",
+                upstream = upstream,
+                servers = servers,
+                host = virtual_host(first, server_name, shared),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Renders a Caddyfile site block with `reverse_proxy` for each suggested port.
+///
+/// Ports sharing a name are balanced by one `reverse_proxy`. With more than one block, each
+/// named one gets its own `<name>.<server_name>` host.
+pub fn render_caddy(suggestions: &[Suggestion], server_name: &str) -> String {
+    let groups = group_by_name(suggestions);
+    let shared = groups.len() > 1;
+    groups
+        .iter()
+        .map(|group| {
+            let upstreams: Vec<String> = group
+                .iter()
+                .map(|suggestion| format!(":{}", suggestion.port))
+                .collect();
+            format!(
+                "{} {{\n    reverse_proxy {}\n}}\n",
+                virtual_host(group[0], server_name, shared),
+                upstreams.join(" ")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Default template for environment variable names.
+pub const DEFAULT_ENV_TEMPLATE: &str = "{PREFIX}{NAME}_PORT";
+
+/// Letter case applied to derived environment variable names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum EnvCase {
+    #[default]
+    Upper,
+    Lower,
+    Preserve,
+}
+
+/// How environment variable names are derived from suggestion names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvNaming {
+    pub prefix: String,
+    pub case: EnvCase,
+    /// Template with `{PREFIX}` and `{NAME}` placeholders.
+    pub template: String,
+}
+
+impl Default for EnvNaming {
+    fn default() -> Self {
+        EnvNaming {
+            prefix: String::new(),
+            case: EnvCase::Upper,
+            template: DEFAULT_ENV_TEMPLATE.to_string(),
+        }
+    }
+}
+
+/// Derives the environment variable name for a port called `name`.
+///
+/// Characters that aren't valid in a shell variable name (dashes, dots, spaces...) become `_`.
+/// An empty name drops the `{NAME}` placeholder together with one adjacent `_`, so the default
+/// template yields `PORT`. A result starting with a digit gets a leading `_`.
+pub fn env_var_name(name: &str, naming: &EnvNaming) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let template = if sanitized.is_empty() {
+        ["{NAME}_", "_{NAME}", "{NAME}"]
+            .iter()
+            .find(|placeholder| naming.template.contains(*placeholder))
+            .map(|placeholder| naming.template.replacen(placeholder, "", 1))
+            .unwrap_or_else(|| naming.template.clone())
+    } else {
+        naming.template.clone()
+    };
+    let derived = template
+        .replace("{PREFIX}", &naming.prefix)
+        .replace("{NAME}", &sanitized);
+    let mut cased = match naming.case {
+        EnvCase::Upper => derived.to_uppercase(),
+        EnvCase::Lower => derived.to_lowercase(),
+        EnvCase::Preserve => derived,
+    };
+    if cased.is_empty() || cased.starts_with(|c: char| c.is_ascii_digit()) {
+        cased.insert(0, '_');
+    }
+    cased
+}
+
+/// Derives a variable name for each suggestion.
+///
+/// Unnamed suggestions use an empty name; when there are several, they are told apart by
+/// appending their 1-based position (`PORT_1`, `PORT_2`, ...). Likewise, a name shared by
+/// several suggestions is numbered within that name (`WORKERS_PORT_1`, `WORKERS_PORT_2`, ...).
+pub fn env_var_names(suggestions: &[Suggestion], naming: &EnvNaming) -> Vec<String> {
+    let unnamed = suggestions.iter().filter(|s| s.name.is_none()).count();
+    let sharing = |name: &String| {
+        suggestions
+            .iter()
+            .filter(|s| s.name.as_ref() == Some(name))
+            .count()
+    };
+    suggestions
+        .iter()
+        .enumerate()
+        .map(|(index, suggestion)| match &suggestion.name {
+            Some(name) if sharing(name) > 1 => {
+                let position = suggestions[..index]
+                    .iter()
+                    .filter(|s| s.name.as_ref() == Some(name))
+                    .count();
+                format!("{}_{}", env_var_name(name, naming), position + 1)
+            }
+            Some(name) => env_var_name(name, naming),
+            None if unnamed > 1 => format!("{}_{}", env_var_name("", naming), index + 1),
+            None => env_var_name("", naming),
+        })
+        .collect()
+}
+
+/// Renders `NAME=PORT` lines, prefixed with `export ` for shell sourcing when `export` is set.
+pub fn render_env(suggestions: &[Suggestion], naming: &EnvNaming, export: bool) -> String {
+    env_var_names(suggestions, naming)
+        .iter()
+        .zip(suggestions)
+        .map(|(name, suggestion)| {
+            format!(
+                "{}{}={}\n",
+                if export { "export " } else { "" },
+                name,
+                suggestion.port
+            )
+        })
+        .collect()
+}
+
+/// First line of the block of a file that portpick manages.
+pub const MANAGED_BLOCK_BEGIN: &str = "# portpick begin";
+/// Last line of the block of a file that portpick manages.
+pub const MANAGED_BLOCK_END: &str = "# portpick end";
+
+/// Where the managed block sits in a file's lines: the indices of its marker lines.
+fn find_managed_block(lines: &[&str]) -> Result<Option<(usize, usize)>> {
+    let mut block = None;
+    let mut begin = None;
+    for (index, line) in lines.iter().enumerate() {
+        match line.trim() {
+            MANAGED_BLOCK_BEGIN => {
+                if let Some(open) = begin {
+                    bail!(
+                        "'{}' on line {} is inside the block opened on line {}; remove one of them",
+                        MANAGED_BLOCK_BEGIN,
+                        index + 1,
+                        open + 1
+                    );
+                }
+                if block.is_some() {
+                    bail!(
+                        "A second '{}' block starts on line {}; merge them into one",
+                        MANAGED_BLOCK_BEGIN,
+                        index + 1
+                    );
+                }
+                begin = Some(index);
+            }
+            MANAGED_BLOCK_END => match begin.take() {
+                Some(open) => block = Some((open, index)),
+                None => bail!(
+                    "'{}' on line {} has no '{}' before it",
+                    MANAGED_BLOCK_END,
+                    index + 1,
+                    MANAGED_BLOCK_BEGIN
+                ),
+            },
+            _ => {}
+        }
+    }
+    if let Some(open) = begin {
+        bail!(
+            "'{}' on line {} has no matching '{}'; add it where the block ends",
+            MANAGED_BLOCK_BEGIN,
+            open + 1,
+            MANAGED_BLOCK_END
+        );
+    }
+    Ok(block)
+}
+
+/// The line ending `content` uses, judged by its first line.
+fn line_ending(content: &str) -> &'static str {
+    match content.split_once('\n') {
+        Some((first, _)) if first.ends_with('\r') => "\r\n",
+        _ => "\n",
+    }
+}
+
+/// Replaces the lines between [`MANAGED_BLOCK_BEGIN`] and [`MANAGED_BLOCK_END`] in `content`
+/// with `body_lines`, or appends a new block if there is none. Everything outside the block is
+/// kept byte for byte, and the block's lines end the way the file's do (`\r\n` or `\n`).
+///
+/// Nested or repeated blocks and a marker without its partner are errors, so a hand-edited
+/// file is never guessed at.
+pub fn update_managed_block(content: &str, body_lines: &[String]) -> Result<String> {
+    let eol = line_ending(content);
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let mut block = format!("{}{}", MANAGED_BLOCK_BEGIN, eol);
+    for line in body_lines {
+        block.push_str(line);
+        block.push_str(eol);
+    }
+    block.push_str(MANAGED_BLOCK_END);
+    block.push_str(eol);
+
+    let mut updated = String::with_capacity(content.len() + block.len());
+    match find_managed_block(&lines)? {
+        Some((begin, end)) => {
+            updated.extend(lines[..begin].iter().copied());
+            updated.push_str(&block);
+            updated.extend(lines[end + 1..].iter().copied());
+        }
+        None => {
+            updated.push_str(content);
+            if !content.is_empty() && !content.ends_with('\n') {
+                updated.push_str(eol);
+            }
+            updated.push_str(&block);
+        }
+    }
+    Ok(updated)
+}
+
+/// Updates `.env`-style `content` with `assignments`. `NAME=` lines outside the managed block
+/// are replaced in place, so an existing entry never shadows a new one; the rest go into the
+/// block, through [`update_managed_block`]. Every other line is preserved as-is.
+pub fn update_env_file(content: &str, assignments: &[(String, u16)]) -> Result<String> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let block = find_managed_block(&lines)?;
+    let in_block = |index: usize| block.is_some_and(|(begin, end)| (begin..=end).contains(&index));
+    let mut pending: Vec<&(String, u16)> = assignments.iter().collect();
+    let mut updated = String::with_capacity(content.len());
+    for (index, line) in lines.iter().enumerate() {
+        let key = line
+            .trim_start()
+            .trim_start_matches("export ")
+            .split('=')
+            .next()
+            .unwrap_or("")
+            .trim();
+        match pending.iter().position(|(name, _)| name == key) {
+            Some(position) if line.contains('=') && !in_block(index) => {
+                let (name, port) = pending.remove(position);
+                let export = if line.trim_start().starts_with("export ") {
+                    "export "
+                } else {
+                    ""
+                };
+                let eol = &line[line.trim_end_matches(['\r', '\n']).len()..];
+                updated.push_str(&format!("{}{}={}{}", export, name, port, eol));
+            }
+            _ => updated.push_str(line),
+        }
+    }
+    if pending.is_empty() && block.is_none() {
+        return Ok(updated);
+    }
+    let body: Vec<String> = pending
+        .iter()
+        .map(|(name, port)| format!("{}={}", name, port))
+        .collect();
+    update_managed_block(&updated, &body)
+}
+
+/// Placeholder names (`PORT_WEB`, `PORT_WORKERS_1`, ...) for each suggestion, as used by
+/// [`render_template`].
+pub fn template_placeholders(suggestions: &[Suggestion]) -> Vec<(String, u16)> {
+    let naming = EnvNaming {
+        template: "PORT_{NAME}".to_string(),
+        ..EnvNaming::default()
+    };
+    env_var_names(suggestions, &naming)
+        .into_iter()
+        .zip(suggestions.iter().map(|s| s.port))
+        .collect()
+}
+
+/// Replaces every `{{NAME}}` in `template` with the port `values` gives it. Spaces inside the
+/// braces are allowed; braces around anything other than a variable name are left alone.
+///
+/// Placeholders missing from `values` are an error listing all of them, unless `keep_unknown`
+/// is set, in which case they are kept as they are.
+pub fn render_template(
+    template: &str,
+    values: &[(String, u16)],
+    keep_unknown: bool,
+) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut unknown: Vec<&str> = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        let name = after[..end].trim();
+        let is_name =
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        match values.iter().find(|(key, _)| is_name && key == name) {
+            Some((_, port)) => rendered.push_str(&port.to_string()),
+            None => {
+                if is_name && !keep_unknown && !unknown.contains(&name) {
+                    unknown.push(name);
+                }
+                rendered.push_str(&rest[start..start + 2 + end + 2]);
+            }
+        }
+        rest = &after[end + 2..];
+    }
+    rendered.push_str(rest);
+    if !unknown.is_empty() {
+        let known: Vec<&str> = values.iter().map(|(key, _)| key.as_str()).collect();
+        bail!(
+            "Unknown placeholder(s) {} (known: {})",
+            unknown
+                .iter()
+                .map(|name| format!("{{{{{}}}}}", name))
+                .collect::<Vec<_>>()
+                .join(", "),
+            if known.is_empty() {
+                "none".to_string()
+            } else {
+                known.join(", ")
+            }
+        );
+    }
+    Ok(rendered)
+}
@@ -0,0 +1,662 @@
+use crate::{
+    BlockStrategy, CollectOptions, DEFAULT_UNPRIVILEGED_PORT_START, FallbackScanner,
+    ForbiddenContext, GroupAllocation, MasscanScanner, NmapScanner, PickOutcome, PlannedSource,
+    PortGroup, PortScanner, PortSet, PortSource, Privileges, ProfileSource, Protocol, Provenance,
+    RejectionReason, Reporter, RustScanScanner, SCHEMA_VERSION, ScanTarget, SortOrder, SsScanner,
+    Suggestion, VerificationStatus, allocate_alternates, categorize_suggestions, collect_forbidden,
+    default_port_ranges_from, describe_ports, is_interrupted, parse_group_spec, parse_port_spec,
+    parse_service_frequencies, parse_services_map, place_block, random_available_ports_in,
+    sort_suggestions_with, suggest_groups_in, suggest_ports_in, verified_ports_in, warn_unbindable,
+};
+use anyhow::{Context, Result, bail};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The services file read by the `system` source unless told otherwise.
+pub const SYSTEM_SERVICES_PATH: &str = "/etc/services";
+
+/// A source of forbidden ports [`PickOptions::resolve`] can run by itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceKind {
+    /// The system services file, `services_file`
+    System,
+    /// A copy of the Nmap services list, `nmap_services_file`
+    Nmap,
+    /// Ports found listening by the local scan
+    Local,
+}
+
+/// Which backend the local scan uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ScannerChoice {
+    /// `ss` when `bind_address` is set, since only it reports bind addresses; otherwise the
+    /// first of rustscan, nmap, and masscan that can run
+    #[default]
+    Auto,
+    Rustscan,
+    Ss,
+    /// Connect scan; slower than rustscan but needs no privileges
+    Nmap,
+    /// Raw SYN scan at a modest rate; needs root or CAP_NET_RAW
+    Masscan,
+}
+
+/// Everything a pick run needs, mirroring the command line, so other tools can drive portpick
+/// from their own config files. The CLI builds one of these from its arguments.
+///
+/// Every field has a default; an empty document asks for one TCP port from the system services
+/// file and the local scan.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PickOptions {
+    pub sources: Vec<SourceKind>,
+    pub services_file: PathBuf,
+    /// Nmap services list read by the `nmap` source; required when that source is listed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nmap_services_file: Option<PathBuf>,
+    /// Host the local scan looks at [default: 127.0.0.1].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Only ports that can't be bound on this address count as in use; also where `verify`
+    /// binds [default: all interfaces].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bind_address: Option<IpAddr>,
+    pub scanner: ScannerChoice,
+    /// Which protocol's services-list entries are forbidden.
+    pub protocol: Protocol,
+    /// Port spec to search [default: 1024-65535].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub within: Option<String>,
+    /// Search from port 1 when `within` isn't given.
+    pub allow_privileged: bool,
+    /// `net.ipv4.ip_unprivileged_port_start`, where the default search starts without
+    /// `allow_privileged` [default: 1024]. The CLI reads it from `/proc/sys`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unprivileged_port_start: Option<u16>,
+    /// Port spec never suggested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<String>,
+    /// Number of ports [default: one per name, the total of `spec`, or 1].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<u16>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub names: Vec<String>,
+    /// Labeled groups such as `web:1,workers:3c`, allocated one after another.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spec: Option<String>,
+    pub continuous: bool,
+    pub strategy: BlockStrategy,
+    pub random: bool,
+    pub verify: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    pub sort: SortOrder,
+    pub avoid_family: u16,
+    /// Backups picked for each suggestion (or block), first fit and not bind-checked.
+    pub alternates: u16,
+    pub include_unknown: bool,
+    /// Built-in profiles whose ports are forbidden too, such as `devtools`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<String>,
+}
+
+impl Default for PickOptions {
+    fn default() -> Self {
+        PickOptions {
+            sources: vec![SourceKind::System, SourceKind::Local],
+            services_file: PathBuf::from(SYSTEM_SERVICES_PATH),
+            nmap_services_file: None,
+            address: None,
+            bind_address: None,
+            scanner: ScannerChoice::Auto,
+            protocol: Protocol::Tcp,
+            within: None,
+            allow_privileged: false,
+            unprivileged_port_start: None,
+            exclude: None,
+            count: None,
+            names: Vec::new(),
+            spec: None,
+            continuous: false,
+            strategy: BlockStrategy::First,
+            random: false,
+            verify: false,
+            seed: None,
+            sort: SortOrder::Asc,
+            avoid_family: 0,
+            alternates: 0,
+            include_unknown: false,
+            profiles: Vec::new(),
+        }
+    }
+}
+
+impl PickOptions {
+    /// The `spec` groups, in order; empty without a spec.
+    pub fn groups(&self) -> Result<Vec<PortGroup>> {
+        match &self.spec {
+            Some(spec) => parse_group_spec(spec),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// How many ports the run asks for.
+    pub fn requested(&self) -> Result<u16> {
+        match self.count {
+            Some(n) if !self.names.is_empty() && usize::from(n) != self.names.len() => bail!(
+                "--number-of-ports ({}) does not match the number of --names ({}).",
+                n,
+                self.names.len()
+            ),
+            Some(n) => Ok(n),
+            None if !self.names.is_empty() => {
+                u16::try_from(self.names.len()).context("Too many --names given.")
+            }
+            None => {
+                let groups = self.groups()?;
+                if groups.is_empty() {
+                    return Ok(1);
+                }
+                groups
+                    .iter()
+                    .try_fold(0u16, |total, group| total.checked_add(group.count))
+                    .context("--spec asks for more than 65535 ports.")
+            }
+        }
+    }
+
+    /// The search ranges: `within`, or the defaults from [`PickOptions::default_search_start`].
+    pub fn ranges(&self) -> Result<Vec<(u16, u16)>> {
+        match &self.within {
+            Some(spec) => parse_port_spec(spec),
+            None => Ok(default_port_ranges_from(self.default_search_start().0)),
+        }
+    }
+
+    /// Where the default ranges start, and why: port 1 with `allow_privileged`, otherwise
+    /// `unprivileged_port_start`, so ports anyone may bind are fair game.
+    pub fn default_search_start(&self) -> (u16, String) {
+        match self.unprivileged_port_start {
+            _ if self.allow_privileged => (1, "--allow-privileged".to_string()),
+            Some(start) => (
+                start.max(1),
+                format!("net.ipv4.ip_unprivileged_port_start is {}", start),
+            ),
+            None => (
+                DEFAULT_UNPRIVILEGED_PORT_START,
+                "ports below it need privileges to bind".to_string(),
+            ),
+        }
+    }
+
+    /// The `exclude` ports.
+    pub fn excluded(&self) -> Result<PortSet> {
+        match &self.exclude {
+            Some(spec) => Ok(parse_port_spec(spec)?.into()),
+            None => Ok(PortSet::new()),
+        }
+    }
+
+    /// Builds the listed sources.
+    pub fn sources(&self, reporter: &Arc<Reporter>) -> Result<Vec<Box<dyn PortSource>>> {
+        let mut sources: Vec<Box<dyn PortSource>> = Vec::new();
+        for kind in &self.sources {
+            sources.push(match kind {
+                SourceKind::System => Box::new(ServicesFileSource {
+                    name: "system",
+                    path: self.services_file.clone(),
+                    protocol: self.protocol,
+                    include_unknown: self.include_unknown,
+                }),
+                SourceKind::Nmap => Box::new(ServicesFileSource {
+                    name: "nmap",
+                    path: self
+                        .nmap_services_file
+                        .clone()
+                        .context("The nmap source needs nmap_services_file")?,
+                    protocol: self.protocol,
+                    include_unknown: self.include_unknown,
+                }),
+                SourceKind::Local => {
+                    if self.protocol != Protocol::Tcp {
+                        bail!("The local scan only sees TCP listeners; leave out the local source for UDP.");
+                    }
+                    let scanner: Box<dyn PortScanner> = match (self.scanner, self.bind_address)
+                    {
+                        (ScannerChoice::Rustscan, _) => {
+                            Box::new(RustScanScanner::new(Arc::clone(reporter)))
+                        }
+                        (ScannerChoice::Nmap, _) => {
+                            Box::new(NmapScanner::new(Arc::clone(reporter)))
+                        }
+                        (ScannerChoice::Masscan, _) => {
+                            Box::new(MasscanScanner::new(Arc::clone(reporter)))
+                        }
+                        (ScannerChoice::Auto, None) => Box::new(
+                            FallbackScanner::new(Arc::clone(reporter))
+                                .then(
+                                    "rustscan",
+                                    Box::new(RustScanScanner::new(Arc::clone(reporter))),
+                                )
+                                .then("nmap", Box::new(NmapScanner::new(Arc::clone(reporter))))
+                                .then(
+                                    "masscan",
+                                    Box::new(MasscanScanner::new(Arc::clone(reporter))),
+                                ),
+                        ),
+                        (_, bind) => {
+                            let scanner = SsScanner::new(Arc::clone(reporter));
+                            Box::new(match bind {
+                                Some(bind) => scanner.with_bind_address(bind),
+                                None => scanner,
+                            })
+                        }
+                    };
+                    Box::new(ScannerSource {
+                        scanner,
+                        target: ScanTarget::new(
+                            self.address.as_deref().unwrap_or("127.0.0.1"),
+                        ),
+                        planned: self.scanner.planned(
+                            self.bind_address,
+                            std::env::var_os("PATH").as_deref(),
+                        ),
+                    })
+                }
+            });
+        }
+        if !self.profiles.is_empty() {
+            sources.push(Box::new(ProfileSource::new(&self.profiles)?));
+        }
+        Ok(sources)
+    }
+
+    /// Runs the whole pipeline with the listed sources.
+    pub fn resolve(&self) -> Result<PickOutcome> {
+        let reporter = Arc::new(Reporter::silent());
+        let sources = self.sources(&reporter)?;
+        self.resolve_with(sources, &reporter)
+    }
+
+    /// Runs the whole pipeline with `sources` in place of the listed ones: collects the
+    /// forbidden ports, adds `exclude`, and picks.
+    ///
+    /// Open frequencies for `sort: frequency` come from the listed services files.
+    pub fn resolve_with(
+        &self,
+        sources: Vec<Box<dyn PortSource + '_>>,
+        reporter: &Reporter,
+    ) -> Result<PickOutcome> {
+        let requested = self.requested()?;
+        let mut ctx = ForbiddenContext::new(&self.ranges()?);
+        let (forbidden, breakdown) = collect_forbidden(
+            CollectOptions {
+                sources,
+                avoid_family: self.avoid_family,
+                ..Default::default()
+            },
+            &mut ctx,
+            reporter,
+        )?;
+        let mut forbidden_ports = forbidden.to_hash_set();
+        forbidden_ports.extend(self.excluded()?.iter());
+        let frequencies = self.listed_frequencies();
+        let (suggestions, allocations) =
+            self.pick(&forbidden_ports, &ctx.provenance, &frequencies, reporter)?;
+        warn_unbindable(&suggestions, &Privileges::detect(), reporter);
+        Ok(PickOutcome {
+            schema_version: SCHEMA_VERSION,
+            requested,
+            continuous: self.continuous,
+            suggestions,
+            interrupted: is_interrupted(),
+            warnings: reporter.warnings(),
+            groups: allocations.iter().map(GroupAllocation::summary).collect(),
+            sources: Some(breakdown),
+            timings: reporter.timings(),
+        })
+    }
+
+    /// Open frequencies from the listed services files that carry them.
+    fn listed_frequencies(&self) -> HashMap<u16, f64> {
+        let mut frequencies = HashMap::new();
+        for kind in &self.sources {
+            let path = match kind {
+                SourceKind::System => Some(&self.services_file),
+                SourceKind::Nmap => self.nmap_services_file.as_ref(),
+                SourceKind::Local => None,
+            };
+            let Some(content) = path.and_then(|path| std::fs::read_to_string(path).ok()) else {
+                continue;
+            };
+            for (port, frequency) in parse_service_frequencies(&content, self.protocol) {
+                let known = frequencies.entry(port).or_insert(frequency);
+                *known = f64::max(*known, frequency);
+            }
+        }
+        frequencies
+    }
+
+    /// Picks ports avoiding `forbidden_ports`: per `spec` group, at random, bind-verified, as a
+    /// placed block, or first fit, then names and sorts them. `provenance` explains skipped
+    /// neighbors and `frequencies` feeds `sort: frequency`. Also returns the group
+    /// allocations, empty without a spec.
+    pub fn pick(
+        &self,
+        forbidden_ports: &HashSet<u16>,
+        provenance: &Provenance,
+        frequencies: &HashMap<u16, f64>,
+        reporter: &Reporter,
+    ) -> Result<(Vec<Suggestion>, Vec<GroupAllocation>)> {
+        let groups = self.groups()?;
+        let requested = self.requested()?;
+        let within = self.ranges()?;
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        };
+        if self.sort == SortOrder::Frequency && frequencies.is_empty() {
+            bail!(
+                "--sort frequency needs open frequencies from the Nmap services list, and no enabled source had any. Use --source nmap or --source cache, add --nmap, or point --services-file at an nmap-services file."
+            );
+        }
+
+        let started = Instant::now();
+        let mut allocations = Vec::new();
+        let mut suggestions = if !groups.is_empty() {
+            allocations = suggest_groups_in(forbidden_ports, &groups, &within, provenance)?;
+            for allocation in &mut allocations {
+                sort_suggestions_with(&mut allocation.suggestions, self.sort, frequencies);
+                let found = allocation.suggestions.len();
+                if found < usize::from(allocation.group.count) {
+                    reporter.warn(format!(
+                        "Warning: Group '{}' got {} of {} requested {}port(s).",
+                        allocation.group.name,
+                        found,
+                        allocation.group.count,
+                        if allocation.group.continuous {
+                            "continuous "
+                        } else {
+                            ""
+                        }
+                    ));
+                }
+            }
+            allocations
+                .iter()
+                .flat_map(|allocation| allocation.suggestions.clone())
+                .collect()
+        } else if self.random {
+            let ports = random_available_ports_in(
+                forbidden_ports,
+                requested,
+                self.continuous,
+                &within,
+                &mut rng,
+            )?;
+            describe_ports(&ports, forbidden_ports, provenance)
+        } else if self.verify {
+            let address = self
+                .bind_address
+                .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+            let ports = verified_ports_in(
+                forbidden_ports,
+                requested,
+                self.continuous,
+                &within,
+                address,
+            )?;
+            let mut suggestions = describe_ports(&ports, forbidden_ports, provenance);
+            for suggestion in &mut suggestions {
+                suggestion.verification = VerificationStatus::Verified;
+            }
+            suggestions
+        } else if self.continuous && self.strategy != BlockStrategy::First {
+            let ports = place_block(forbidden_ports, requested, &within, self.strategy, &mut rng)?;
+            describe_ports(&ports, forbidden_ports, provenance)
+        } else {
+            suggest_ports_in(
+                forbidden_ports,
+                requested,
+                self.continuous,
+                &within,
+                provenance,
+            )?
+        };
+        // Names follow selection order; sorting afterwards keeps them attached to their ports.
+        for (suggestion, name) in suggestions.iter_mut().zip(&self.names) {
+            suggestion.name = Some(name.clone());
+        }
+        // Groups were sorted one by one, keeping each group's ports together.
+        if allocations.is_empty() {
+            sort_suggestions_with(&mut suggestions, self.sort, frequencies);
+        }
+        let user_ranges = self.within.is_some().then_some(within.as_slice());
+        categorize_suggestions(&mut suggestions, user_ranges);
+        for allocation in &mut allocations {
+            categorize_suggestions(&mut allocation.suggestions, user_ranges);
+        }
+        if self.alternates > 0 {
+            self.pick_alternates(forbidden_ports, &mut suggestions, &mut allocations, &within);
+            let short: Vec<String> = suggestions
+                .iter()
+                .filter(|suggestion| suggestion.alternates.len() < usize::from(self.alternates))
+                .map(|suggestion| suggestion.port.to_string())
+                .collect();
+            if !short.is_empty() {
+                reporter.warn(format!(
+                    "Warning: The search ranges ran out before {} got {} alternate(s) each.",
+                    short.join(", "),
+                    self.alternates
+                ));
+            }
+        }
+        // Bind-checking is most of a verified pick.
+        let phase = match self.verify && groups.is_empty() && !self.random {
+            true => "verification",
+            false => "selection",
+        };
+        reporter.record_timing(phase, started.elapsed());
+        Ok((suggestions, allocations))
+    }
+}
+
+impl PickOptions {
+    /// Gives every suggestion its `alternates`, group by group with a spec, avoiding all the
+    /// suggestions and everything in `forbidden_ports`.
+    fn pick_alternates(
+        &self,
+        forbidden_ports: &HashSet<u16>,
+        suggestions: &mut [Suggestion],
+        allocations: &mut [GroupAllocation],
+        within: &[(u16, u16)],
+    ) {
+        let mut taken = forbidden_ports.clone();
+        taken.extend(suggestions.iter().map(|suggestion| suggestion.port));
+        if allocations.is_empty() {
+            allocate_alternates(
+                &mut taken,
+                suggestions,
+                self.alternates,
+                self.continuous,
+                within,
+            );
+            return;
+        }
+        for allocation in allocations.iter_mut() {
+            allocate_alternates(
+                &mut taken,
+                &mut allocation.suggestions,
+                self.alternates,
+                allocation.group.continuous,
+                within,
+            );
+        }
+        // The suggestions are the groups' ports in group order.
+        let grouped = allocations
+            .iter()
+            .flat_map(|allocation| &allocation.suggestions);
+        for (suggestion, grouped) in suggestions.iter_mut().zip(grouped) {
+            suggestion.alternates = grouped.alternates.clone();
+        }
+    }
+}
+
+/// What a [`PickOptions::pick_fast`] run looked at.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FastPickStats {
+    /// Ports handed to the check, over all rounds.
+    pub checked: usize,
+    /// Checked ports that were in use.
+    pub busy: usize,
+    pub rounds: usize,
+}
+
+/// Candidates checked in the first round of a fast pick, at least; doubles every round.
+const FAST_INITIAL_WINDOW: usize = 16;
+
+impl PickOptions {
+    /// Like [`pick`](Self::pick), but without knowing the locally used ports up front: each
+    /// round picks avoiding what is known, hands the picks that haven't been checked yet, topped
+    /// up with the next free ports in search order, to `check`, and picks again if any of them
+    /// are in use. So a request for one port checks a handful instead of all 65535.
+    ///
+    /// `check` returns which of the ports it was given are in use; they are recorded as
+    /// [`RejectionReason::LocallyInUse`]. Only the final round reports warnings.
+    pub fn pick_fast(
+        &self,
+        forbidden_ports: &HashSet<u16>,
+        provenance: &Provenance,
+        frequencies: &HashMap<u16, f64>,
+        reporter: &Reporter,
+        check: &mut dyn FnMut(&[u16]) -> Result<HashSet<u16>>,
+    ) -> Result<(Vec<Suggestion>, Vec<GroupAllocation>, FastPickStats)> {
+        // Repeating the last round must give the same picks, so random choices need a seed.
+        let mut options = self.clone();
+        if options.seed.is_none() && (options.random || options.strategy == BlockStrategy::Random) {
+            options.seed = Some(rand::random());
+        }
+        let within = options.ranges()?;
+        let mut forbidden = forbidden_ports.clone();
+        let mut provenance = provenance.clone();
+        let mut checked = HashSet::new();
+        let mut stats = FastPickStats::default();
+        let mut scanning = Duration::ZERO;
+        let mut window = FAST_INITIAL_WINDOW.max(2 * usize::from(options.requested()?));
+        loop {
+            stats.rounds += 1;
+            let (suggestions, _) =
+                options.pick(&forbidden, &provenance, frequencies, &Reporter::silent())?;
+            let mut candidates: Vec<u16> = suggestions
+                .iter()
+                .flat_map(|suggestion| {
+                    std::iter::once(suggestion.port).chain(suggestion.alternates.iter().copied())
+                })
+                .filter(|port| !checked.contains(port))
+                .collect();
+            if candidates.is_empty() {
+                reporter.record_timing("scan", scanning);
+                let (suggestions, allocations) =
+                    options.pick(&forbidden, &provenance, frequencies, reporter)?;
+                return Ok((suggestions, allocations, stats));
+            }
+            // Checking ahead saves a round for every busy pick.
+            let known: PortSet = forbidden.iter().chain(&checked).copied().collect();
+            let ahead = known
+                .iter_free_within(&within)
+                .flatten()
+                .filter(|port| !candidates.contains(port))
+                .take(window.saturating_sub(candidates.len()))
+                .collect::<Vec<_>>();
+            candidates.extend(ahead);
+            let started = Instant::now();
+            let busy = check(&candidates)?;
+            scanning += started.elapsed();
+            stats.checked += candidates.len();
+            stats.busy += busy.len();
+            provenance.record_all(&busy, RejectionReason::LocallyInUse);
+            forbidden.extend(&busy);
+            checked.extend(candidates);
+            window = window.saturating_mul(2);
+        }
+    }
+}
+
+/// A services file whose entries for one protocol are forbidden.
+struct ServicesFileSource {
+    name: &'static str,
+    path: PathBuf,
+    protocol: Protocol,
+    include_unknown: bool,
+}
+
+impl PortSource for ServicesFileSource {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn rejection_reason(&self) -> RejectionReason {
+        RejectionReason::KnownService
+    }
+
+    fn collect(&mut self, names: &mut HashMap<u16, Vec<String>>) -> Result<HashSet<u16>> {
+        let content = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read '{}'", self.path.display()))?;
+        let services = parse_services_map(&content, self.protocol, self.include_unknown)?;
+        let ports = services.keys().copied().collect();
+        for (port, port_names) in services {
+            let known = names.entry(port).or_default();
+            for name in port_names {
+                if !known.contains(&name) {
+                    known.push(name);
+                }
+            }
+        }
+        Ok(ports)
+    }
+
+    fn lists_services(&self) -> bool {
+        true
+    }
+
+    fn plan(&self) -> PlannedSource {
+        PlannedSource::new(self.name, self.path.display().to_string())
+    }
+}
+
+/// The local scan.
+struct ScannerSource {
+    scanner: Box<dyn PortScanner>,
+    target: ScanTarget,
+    /// Which scanner, for [`PortSource::plan`].
+    planned: String,
+}
+
+impl PortSource for ScannerSource {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    fn rejection_reason(&self) -> RejectionReason {
+        RejectionReason::LocallyInUse
+    }
+
+    fn collect(&mut self, _names: &mut HashMap<u16, Vec<String>>) -> Result<HashSet<u16>> {
+        self.scanner.scan(&self.target)
+    }
+
+    fn plan(&self) -> PlannedSource {
+        PlannedSource::new(
+            self.name(),
+            format!("{} on {}", self.planned, self.target.address),
+        )
+    }
+}
@@ -0,0 +1,191 @@
+use crate::{
+    CacheInfo, PickOptions, PortSet, PortSource, ProfileSource, ScannerChoice, describe_age,
+    find_program,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// A source a run would read, and where from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedSource {
+    pub name: String,
+    /// A path, a URL, or the scanner and address, e.g. `rustscan on 127.0.0.1`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// The cached copy this source reads or refreshes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheInfo>,
+}
+
+impl PlannedSource {
+    pub fn new(name: impl Into<String>, location: impl Into<String>) -> Self {
+        PlannedSource {
+            name: name.into(),
+            location: Some(location.into()),
+            cache: None,
+        }
+    }
+
+    pub fn with_cache(mut self, cache: CacheInfo) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+}
+
+/// What a run would do, worked out without reading, fetching, or scanning anything, as
+/// printed by `--dry-run`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plan {
+    pub sources: Vec<PlannedSource>,
+    /// Tried in order when the services lists come up short.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallbacks: Vec<PlannedSource>,
+    /// The search ranges with `exclude` and profile ports taken out, as a port spec.
+    pub ranges: String,
+    /// How many ports [`Plan::ranges`] holds.
+    pub searchable: usize,
+    pub requested: u16,
+    /// How ports are chosen and ordered, e.g. `first fit, sorted asc`.
+    pub selection: String,
+}
+
+impl Plan {
+    /// The plan as lines of text.
+    pub fn render(&self) -> String {
+        let describe = |source: &PlannedSource| {
+            let mut line = format!("  {:<10}", source.name);
+            if let Some(location) = &source.location {
+                line.push_str(location);
+            }
+            if let Some(cache) = &source.cache {
+                line.push_str(&format!(
+                    " (cache {}: {})",
+                    cache.path,
+                    cache_freshness(cache)
+                ));
+            }
+            line.trim_end().to_string()
+        };
+        let mut out = String::from("Sources:\n");
+        if self.sources.is_empty() {
+            out.push_str("  none\n");
+        }
+        for source in &self.sources {
+            out.push_str(&describe(source));
+            out.push('\n');
+        }
+        if !self.fallbacks.is_empty() {
+            out.push_str("Fallbacks:\n");
+            for source in &self.fallbacks {
+                out.push_str(&describe(source));
+                out.push('\n');
+            }
+        }
+        out.push_str(&format!(
+            "Search: {} ({} ports)\nSelection: {} port(s), {}\n",
+            if self.ranges.is_empty() {
+                "nothing"
+            } else {
+                &self.ranges
+            },
+            self.searchable,
+            self.requested,
+            self.selection
+        ));
+        out
+    }
+}
+
+/// How old a cache is, e.g. `3 days old, 27000 ports`, or `not created yet`.
+fn cache_freshness(cache: &CacheInfo) -> String {
+    if !cache.present {
+        return "not created yet".to_string();
+    }
+    let age = match cache.age_secs {
+        Some(secs) => format!("{} old", describe_age(Duration::from_secs(secs))),
+        None => "age unknown".to_string(),
+    };
+    match cache.ports {
+        Some(ports) => format!("{}, {} ports", age, ports),
+        None => format!("{}, unparseable", age),
+    }
+}
+
+impl ScannerChoice {
+    /// The scanner this choice would run, looking for `auto`'s candidates on `path`: `ss`
+    /// with a bind address, otherwise the first of rustscan, nmap, and masscan installed.
+    pub fn planned(self, bind_address: Option<IpAddr>, path: Option<&OsStr>) -> String {
+        match (self, bind_address) {
+            (ScannerChoice::Rustscan, _) => "rustscan".to_string(),
+            (ScannerChoice::Nmap, _) => "nmap".to_string(),
+            (ScannerChoice::Masscan, _) => "masscan".to_string(),
+            (ScannerChoice::Ss, None) => "ss".to_string(),
+            (_, Some(bind)) => format!("ss, bind address {}", bind),
+            (ScannerChoice::Auto, None) => ["rustscan", "nmap", "masscan"]
+                .into_iter()
+                .find(|program| find_program(program, path).is_some())
+                .map(String::from)
+                .unwrap_or_else(|| "none (rustscan, nmap, and masscan aren't installed)".into()),
+        }
+    }
+}
+
+impl PickOptions {
+    /// What [`PickOptions::resolve_with`] would do with `sources`, without running them.
+    pub fn plan(&self, sources: &[Box<dyn PortSource + '_>]) -> Result<Plan> {
+        let mut searchable = PortSet::from(self.ranges()?).difference(&self.excluded()?);
+        if !self.profiles.is_empty() {
+            let profiles: PortSet = ProfileSource::new(&self.profiles)?.ports().collect();
+            searchable = searchable.difference(&profiles);
+        }
+        Ok(Plan {
+            sources: sources.iter().map(|source| source.plan()).collect(),
+            fallbacks: Vec::new(),
+            ranges: searchable.to_spec(),
+            searchable: searchable.len(),
+            requested: self.requested()?,
+            selection: self.selection(),
+        })
+    }
+
+    /// How ports are chosen and ordered, e.g. `continuous block, best fit, sorted asc`.
+    fn selection(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(spec) = &self.spec {
+            parts.push(format!("per group of '{}'", spec));
+        } else if self.random {
+            parts.push("at random".to_string());
+        } else if self.verify {
+            parts.push("first fit, bind-checked".to_string());
+        } else if self.continuous {
+            parts.push(format!("continuous block, {} fit", name_of(self.strategy)));
+        } else {
+            parts.push("first fit".to_string());
+        }
+        if self.continuous && (self.random || self.verify) {
+            parts.push("continuous".to_string());
+        }
+        if let Some(seed) = self.seed {
+            parts.push(format!("seed {}", seed));
+        }
+        if self.avoid_family > 0 {
+            parts.push(format!("avoiding {} around services", self.avoid_family));
+        }
+        if self.alternates > 0 {
+            parts.push(format!("{} alternate(s) each", self.alternates));
+        }
+        parts.push(format!("sorted {}", name_of(self.sort)));
+        parts.join(", ")
+    }
+}
+
+/// The name a snake_case enum serializes as, such as `asc`.
+fn name_of(value: impl Serialize) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(String::from))
+        .unwrap_or_default()
+}
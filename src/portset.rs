@@ -0,0 +1,287 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
+/// A set of ports stored as sorted, coalesced inclusive ranges.
+///
+/// Overlapping and adjacent ranges are merged on insert, so "everything from 32768 to 60999"
+/// costs one entry instead of 28232. It serializes the same way, as `[start, end]` pairs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Vec<(u16, u16)>", into = "Vec<(u16, u16)>")]
+pub struct PortSet {
+    // Invariant: sorted by start, and each range ends at least two below the next one's start.
+    ranges: Vec<(u16, u16)>,
+}
+
+impl PortSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, port: u16) {
+        self.insert_range(port..=port);
+    }
+
+    /// Inserts every port in `range`. An empty range (start after end) is ignored.
+    pub fn insert_range(&mut self, range: RangeInclusive<u16>) {
+        let (start, end) = (*range.start(), *range.end());
+        if start > end {
+            return;
+        }
+        // Widen to u32 so that touching ranges at u16::MAX don't overflow.
+        let first = self
+            .ranges
+            .partition_point(|&(_, e)| u32::from(e) + 1 < u32::from(start));
+        let last = self
+            .ranges
+            .partition_point(|&(s, _)| u32::from(s) <= u32::from(end) + 1);
+        if first == last {
+            self.ranges.insert(first, (start, end));
+        } else {
+            let merged = (
+                start.min(self.ranges[first].0),
+                end.max(self.ranges[last - 1].1),
+            );
+            self.ranges.splice(first..last, [merged]);
+        }
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        let index = self.ranges.partition_point(|&(_, end)| end < port);
+        self.ranges
+            .get(index)
+            .is_some_and(|&(start, _)| start <= port)
+    }
+
+    /// Ports in either set.
+    pub fn union(&self, other: &PortSet) -> PortSet {
+        // Merge the two sorted lists, coalescing as we go.
+        let mut merged: Vec<(u16, u16)> =
+            Vec::with_capacity(self.ranges.len() + other.ranges.len());
+        let (mut i, mut j) = (0, 0);
+        loop {
+            let next = match (self.ranges.get(i), other.ranges.get(j)) {
+                (Some(&a), Some(&b)) if b.0 < a.0 => {
+                    j += 1;
+                    b
+                }
+                (Some(&a), _) => {
+                    i += 1;
+                    a
+                }
+                (None, Some(&b)) => {
+                    j += 1;
+                    b
+                }
+                (None, None) => break,
+            };
+            match merged.last_mut() {
+                Some((_, end)) if u32::from(next.0) <= u32::from(*end) + 1 => {
+                    *end = (*end).max(next.1);
+                }
+                _ => merged.push(next),
+            }
+        }
+        PortSet { ranges: merged }
+    }
+
+    /// Ports in both sets.
+    pub fn intersection(&self, other: &PortSet) -> PortSet {
+        let mut common = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while let (Some(&(a_start, a_end)), Some(&(b_start, b_end))) =
+            (self.ranges.get(i), other.ranges.get(j))
+        {
+            let (start, end) = (a_start.max(b_start), a_end.min(b_end));
+            if start <= end {
+                common.push((start, end));
+            }
+            if a_end < b_end {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        PortSet { ranges: common }
+    }
+
+    /// How many ports this set shares with `other`, without building the intersection.
+    pub fn overlap(&self, other: &PortSet) -> usize {
+        let mut shared = 0;
+        let (mut i, mut j) = (0, 0);
+        while let (Some(&(a_start, a_end)), Some(&(b_start, b_end))) =
+            (self.ranges.get(i), other.ranges.get(j))
+        {
+            let (start, end) = (a_start.max(b_start), a_end.min(b_end));
+            if start <= end {
+                shared += usize::from(end - start) + 1;
+            }
+            if a_end < b_end {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        shared
+    }
+
+    /// Ports in this set but not in `other`.
+    pub fn difference(&self, other: &PortSet) -> PortSet {
+        PortSet {
+            ranges: self
+                .ranges
+                .iter()
+                .flat_map(|&(start, end)| other.free_between(start, end))
+                .map(|run| (*run.start(), *run.end()))
+                .collect(),
+        }
+    }
+
+    /// Ports of `range` not in the set.
+    pub fn complement_within(&self, range: RangeInclusive<u16>) -> PortSet {
+        PortSet {
+            ranges: self
+                .free_between(*range.start(), *range.end())
+                .into_iter()
+                .map(|run| (*run.start(), *run.end()))
+                .collect(),
+        }
+    }
+
+    /// Every port within `radius` of a port in the set, clamped to the u16 range.
+    pub fn expand(&self, radius: u16) -> PortSet {
+        let mut expanded = PortSet::new();
+        for &(start, end) in &self.ranges {
+            expanded.insert_range(start.saturating_sub(radius)..=end.saturating_add(radius));
+        }
+        expanded
+    }
+
+    /// Number of ports in the set.
+    pub fn len(&self) -> usize {
+        self.ranges
+            .iter()
+            .map(|&(start, end)| usize::from(end - start) + 1)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The coalesced ranges, in ascending order.
+    pub fn ranges(&self) -> impl Iterator<Item = RangeInclusive<u16>> + '_ {
+        self.ranges.iter().map(|&(start, end)| start..=end)
+    }
+
+    /// The set as a port spec such as `80,8000-8010`, as [`crate::parse_port_spec`] reads it;
+    /// empty for an empty set.
+    pub fn to_spec(&self) -> String {
+        self.ranges()
+            .map(|range| match range.start() == range.end() {
+                true => range.start().to_string(),
+                false => format!("{}-{}", range.start(), range.end()),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Every port in the set, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        self.ranges().flatten()
+    }
+
+    /// Maximal runs of ports not in the set, within each of `within` in the order given.
+    ///
+    /// Runs never span two entries of `within`, even if they are adjacent.
+    pub fn iter_free_within<'a>(
+        &'a self,
+        within: &'a [(u16, u16)],
+    ) -> impl Iterator<Item = RangeInclusive<u16>> + 'a {
+        within
+            .iter()
+            .flat_map(move |&(start, end)| self.free_between(start, end))
+    }
+
+    fn free_between(&self, start: u16, end: u16) -> Vec<RangeInclusive<u16>> {
+        let mut free = Vec::new();
+        if start > end {
+            return free;
+        }
+        let mut cursor = u32::from(start);
+        let first = self.ranges.partition_point(|&(_, e)| e < start);
+        for &(taken_start, taken_end) in &self.ranges[first..] {
+            if taken_start > end {
+                break;
+            }
+            if u32::from(taken_start) > cursor {
+                free.push(cursor as u16..=taken_start - 1);
+            }
+            cursor = u32::from(taken_end) + 1;
+        }
+        if cursor <= u32::from(end) {
+            free.push(cursor as u16..=end);
+        }
+        free
+    }
+
+    pub fn to_hash_set(&self) -> HashSet<u16> {
+        self.iter().collect()
+    }
+}
+
+impl From<HashSet<u16>> for PortSet {
+    fn from(ports: HashSet<u16>) -> Self {
+        ports.into_iter().collect()
+    }
+}
+
+impl From<RangeInclusive<u16>> for PortSet {
+    fn from(range: RangeInclusive<u16>) -> Self {
+        let mut set = PortSet::new();
+        set.insert_range(range);
+        set
+    }
+}
+
+impl From<&HashSet<u16>> for PortSet {
+    fn from(ports: &HashSet<u16>) -> Self {
+        ports.iter().copied().collect()
+    }
+}
+
+impl FromIterator<u16> for PortSet {
+    fn from_iter<I: IntoIterator<Item = u16>>(iter: I) -> Self {
+        let mut set = PortSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl Extend<u16> for PortSet {
+    fn extend<I: IntoIterator<Item = u16>>(&mut self, iter: I) {
+        // Sorting first turns a pile of single ports into cheap appends to the last range.
+        let mut ports: Vec<u16> = iter.into_iter().collect();
+        ports.sort_unstable();
+        for port in ports {
+            self.insert(port);
+        }
+    }
+}
+
+impl From<Vec<(u16, u16)>> for PortSet {
+    /// Accepts ranges in any order, overlapping or reversed, restoring the invariant.
+    fn from(ranges: Vec<(u16, u16)>) -> Self {
+        let mut set = PortSet::new();
+        for (start, end) in ranges {
+            set.insert_range(start.min(end)..=start.max(end));
+        }
+        set
+    }
+}
+
+impl From<PortSet> for Vec<(u16, u16)> {
+    fn from(set: PortSet) -> Self {
+        set.ranges
+    }
+}
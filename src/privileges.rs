@@ -0,0 +1,161 @@
+use crate::{Reporter, Suggestion, WarningCode};
+use std::fmt;
+
+/// Linux capability number allowing privileged ports (below 1024 by default) to be bound.
+pub const CAP_NET_BIND_SERVICE: u32 = 10;
+/// Linux capability number allowing raw sockets, as masscan sends.
+pub const CAP_NET_RAW: u32 = 13;
+
+/// Where binding stops needing privileges unless `net.ipv4.ip_unprivileged_port_start` says
+/// otherwise.
+pub const DEFAULT_UNPRIVILEGED_PORT_START: u16 = 1024;
+
+/// What this process may do, as far as it can tell: its effective user ID and, on Linux, its
+/// effective capabilities (`CapEff` in `/proc/self/status`) and the lowest port anyone may bind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Privileges {
+    /// `None` where there are no user IDs, as on Windows.
+    pub euid: Option<u32>,
+    /// `None` where `/proc/self/status` can't be read or has no `CapEff` line.
+    pub cap_eff: Option<u64>,
+    /// `net.ipv4.ip_unprivileged_port_start`; `None` where it can't be read, which means
+    /// [`DEFAULT_UNPRIVILEGED_PORT_START`].
+    pub unprivileged_port_start: Option<u16>,
+}
+
+impl Privileges {
+    /// Reads this process's privileges.
+    pub fn detect() -> Self {
+        #[cfg(unix)]
+        // SAFETY: geteuid has no preconditions and can't fail.
+        let euid = Some(unsafe { libc::geteuid() });
+        #[cfg(not(unix))]
+        let euid = None;
+        let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+        Privileges {
+            unprivileged_port_start: unprivileged_port_start(),
+            ..Privileges::from_status(euid, &status)
+        }
+    }
+
+    /// The privileges described by `euid` and `/proc/self/status` content.
+    pub fn from_status(euid: Option<u32>, status: &str) -> Self {
+        Privileges {
+            euid,
+            cap_eff: parse_cap_eff(status),
+            unprivileged_port_start: None,
+        }
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.euid == Some(0)
+    }
+
+    /// Whether the effective set holds `capability`, or `None` when it is unknown.
+    pub fn has_capability(&self, capability: u32) -> Option<bool> {
+        self.cap_eff.map(|mask| mask >> capability & 1 == 1)
+    }
+
+    /// Whether `capability`, or failing a capability set, being root allows what it covers.
+    /// Unknown on both counts counts as allowed, so portpick doesn't refuse what it can't judge.
+    fn allows(&self, capability: u32) -> bool {
+        self.has_capability(capability)
+            .or(self.euid.map(|euid| euid == 0))
+            .unwrap_or(true)
+    }
+
+    /// The lowest port binding needs no privileges for.
+    pub fn privileged_below(&self) -> u16 {
+        self.unprivileged_port_start
+            .unwrap_or(DEFAULT_UNPRIVILEGED_PORT_START)
+    }
+
+    /// Whether ports below [`Privileges::privileged_below`] can be bound.
+    pub fn can_bind_privileged_ports(&self) -> bool {
+        self.allows(CAP_NET_BIND_SERVICE)
+    }
+
+    /// Whether `port` can be bound, privileged or not.
+    pub fn can_bind(&self, port: u16) -> bool {
+        port >= self.privileged_below() || self.can_bind_privileged_ports()
+    }
+
+    /// Whether raw sockets can be opened, as masscan needs.
+    pub fn can_send_raw_packets(&self) -> bool {
+        self.allows(CAP_NET_RAW)
+    }
+}
+
+/// One line for `-v`, such as `euid 1000, no CAP_NET_BIND_SERVICE, no CAP_NET_RAW`, followed
+/// by the unprivileged port start when it isn't 1024.
+impl fmt::Display for Privileges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.euid {
+            Some(euid) => write!(f, "euid {}", euid)?,
+            None => write!(f, "euid unknown")?,
+        }
+        if let Some(start) = self
+            .unprivileged_port_start
+            .filter(|&start| start != DEFAULT_UNPRIVILEGED_PORT_START)
+        {
+            write!(f, ", unprivileged ports from {}", start)?;
+        }
+        if self.cap_eff.is_none() {
+            return write!(f, ", capabilities unknown");
+        }
+        for (capability, name) in [
+            (CAP_NET_BIND_SERVICE, "CAP_NET_BIND_SERVICE"),
+            (CAP_NET_RAW, "CAP_NET_RAW"),
+        ] {
+            match self.has_capability(capability) {
+                Some(true) => write!(f, ", {}", name)?,
+                _ => write!(f, ", no {}", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The effective capability mask from `/proc/self/status` content: the hex number on its
+/// `CapEff:` line.
+pub fn parse_cap_eff(status: &str) -> Option<u64> {
+    status.lines().find_map(|line| {
+        let mask = line.strip_prefix("CapEff:")?.trim();
+        u64::from_str_radix(mask, 16).ok()
+    })
+}
+
+/// `net.ipv4.ip_unprivileged_port_start` from `/proc/sys` content: the lowest port anyone may
+/// bind. Rootless containers often lower it to 0.
+pub fn parse_unprivileged_port_start(content: &str) -> Option<u16> {
+    content.trim().parse().ok()
+}
+
+/// This machine's `net.ipv4.ip_unprivileged_port_start`, where it can be read.
+pub fn unprivileged_port_start() -> Option<u16> {
+    std::fs::read_to_string("/proc/sys/net/ipv4/ip_unprivileged_port_start")
+        .ok()
+        .and_then(|content| parse_unprivileged_port_start(&content))
+}
+
+/// Warns about suggestions `privileges` can't bind, so they'd need root or
+/// `CAP_NET_BIND_SERVICE` to use.
+pub fn warn_unbindable(suggestions: &[Suggestion], privileges: &Privileges, reporter: &Reporter) {
+    let ports: Vec<String> = suggestions
+        .iter()
+        .filter(|suggestion| !privileges.can_bind(suggestion.port))
+        .map(|suggestion| suggestion.port.to_string())
+        .collect();
+    if ports.is_empty() {
+        return;
+    }
+    reporter.warn_with(
+        WarningCode::PrivilegedPort,
+        format!(
+            "Binding {} will fail: ports below {} need root or CAP_NET_BIND_SERVICE, and this process has neither ({}).",
+            ports.join(", "),
+            privileges.privileged_below(),
+            privileges
+        ),
+    );
+}
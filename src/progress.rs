@@ -0,0 +1,36 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use std::time::Duration;
+
+/// How often spinners redraw while the work they track gives no updates.
+const SPINNER_TICK: Duration = Duration::from_millis(100);
+
+/// A spinner with elapsed time on stderr, or a hidden bar when progress is off.
+///
+/// Call `finish_and_clear` when the work is done so the line disappears.
+pub fn spinner(enabled: bool, message: impl Into<String>) -> ProgressBar {
+    if !enabled {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::new_spinner().with_message(message.into());
+    bar.set_style(
+        ProgressStyle::with_template("{spinner} {msg} ({elapsed})")
+            .expect("spinner template is valid"),
+    );
+    bar.enable_steady_tick(SPINNER_TICK);
+    bar
+}
+
+/// A byte counter on stderr, or a hidden bar when progress is off. Becomes a real bar once
+/// the total is known via `set_length`.
+pub fn byte_bar(enabled: bool, message: impl Into<String>) -> ProgressBar {
+    if !enabled {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::no_length().with_message(message.into());
+    bar.set_style(
+        ProgressStyle::with_template("{msg} [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec})")
+            .expect("byte bar template is valid")
+            .progress_chars("=> "),
+    );
+    bar
+}
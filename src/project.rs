@@ -0,0 +1,244 @@
+use crate::{
+    DEFAULT_PORT_RANGES, EnvNaming, PortSet, Suggestion, env_var_name, env_var_names,
+    find_available_ports_in, parse_port_spec, validate_request,
+};
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Default name of the project file read by `portpick apply`.
+pub const PROJECT_FILE: &str = "portpick.yaml";
+
+/// Everything a project needs, as checked into its `portpick.yaml`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectSpec {
+    /// Port spec searched by entries without their own `within` [default: 1024-65535].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub within: Option<String>,
+    pub ports: Vec<PortRequest>,
+}
+
+/// One named entry of a [`ProjectSpec`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortRequest {
+    pub name: String,
+    #[serde(default = "one_port")]
+    pub count: u16,
+    #[serde(default)]
+    pub continuous: bool,
+    /// Prefer the free ports closest to this one instead of the lowest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub near: Option<u16>,
+    /// Port spec for this entry, replacing the file-wide `within`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub within: Option<String>,
+    /// Port inside the container, written as `<NAME>_CONTAINER_PORT`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_port: Option<u16>,
+}
+
+fn one_port() -> u16 {
+    1
+}
+
+/// The ports chosen for one [`PortRequest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolvedEntry {
+    pub name: String,
+    pub ports: Vec<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_port: Option<u16>,
+}
+
+impl ProjectSpec {
+    /// Parses and validates a `portpick.yaml`.
+    pub fn from_yaml(content: &str) -> Result<Self> {
+        let spec: ProjectSpec =
+            serde_yaml::from_str(content).context("Failed to parse the project file")?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    /// The search ranges of `entry`: its own `within`, else the file's, else the defaults.
+    pub fn ranges_for(&self, entry: &PortRequest) -> Result<Vec<(u16, u16)>> {
+        match entry.within.as_ref().or(self.within.as_ref()) {
+            Some(spec) => parse_port_spec(spec)
+                .with_context(|| format!("Invalid within for '{}'", entry.name)),
+            None => Ok(DEFAULT_PORT_RANGES.to_vec()),
+        }
+    }
+
+    /// Rejects files no set of free ports could satisfy: duplicate names, entries that don't
+    /// fit their own ranges, and entries whose overlapping ranges together hold fewer ports
+    /// than they ask for.
+    pub fn validate(&self) -> Result<()> {
+        let mut windows: Vec<(Vec<usize>, PortSet, usize)> = Vec::new();
+        for (index, entry) in self.ports.iter().enumerate() {
+            if entry.name.trim().is_empty() {
+                bail!("Entry {} has an empty name", index + 1);
+            }
+            if self.ports[..index].iter().any(|e| e.name == entry.name) {
+                bail!("Entry '{}' appears twice", entry.name);
+            }
+            if entry.count == 0 {
+                bail!("Entry '{}' asks for 0 ports", entry.name);
+            }
+            let ranges = self.ranges_for(entry)?;
+            validate_request(entry.count, entry.continuous, &ranges)
+                .with_context(|| format!("Entry '{}' can never be satisfied", entry.name))?;
+
+            // Merge this entry's window with every window it overlaps.
+            let mut window = PortSet::new();
+            for &(start, end) in &ranges {
+                window.insert_range(start..=end);
+            }
+            let mut members = vec![index];
+            let mut requested = usize::from(entry.count);
+            let mut kept = Vec::new();
+            for (other_members, other_window, other_requested) in windows {
+                if other_window.ranges().any(|range| {
+                    window
+                        .ranges()
+                        .any(|own| own.start() <= range.end() && range.start() <= own.end())
+                }) {
+                    members.extend(other_members);
+                    window = window.union(&other_window);
+                    requested += other_requested;
+                } else {
+                    kept.push((other_members, other_window, other_requested));
+                }
+            }
+            kept.push((members, window, requested));
+            windows = kept;
+        }
+        for (mut members, window, requested) in windows {
+            if requested > window.len() {
+                members.sort_unstable();
+                let names: Vec<&str> = members
+                    .iter()
+                    .map(|&index| self.ports[index].name.as_str())
+                    .collect();
+                bail!(
+                    "Entries {} need {} ports in total, but their overlapping ranges only hold {}",
+                    names.join(", "),
+                    requested,
+                    window.len()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Picks ports for every entry in file order, each avoiding `forbidden_ports` and the ports
+    /// given to earlier entries. The same inputs always give the same result.
+    pub fn resolve(&self, forbidden_ports: &HashSet<u16>) -> Result<Vec<ResolvedEntry>> {
+        let mut forbidden = forbidden_ports.clone();
+        let mut resolved = Vec::with_capacity(self.ports.len());
+        for entry in &self.ports {
+            let ranges = self.ranges_for(entry)?;
+            let ports = match entry.near {
+                Some(anchor) => {
+                    ports_near(&forbidden, entry.count, entry.continuous, &ranges, anchor)
+                }
+                None => {
+                    find_available_ports_in(&forbidden, entry.count, entry.continuous, &ranges)?
+                }
+            };
+            if ports.len() < usize::from(entry.count) {
+                bail!(
+                    "Could not find {} {}port(s) for '{}'",
+                    entry.count,
+                    if entry.continuous { "continuous " } else { "" },
+                    entry.name
+                );
+            }
+            forbidden.extend(&ports);
+            resolved.push(ResolvedEntry {
+                name: entry.name.clone(),
+                ports,
+                container_port: entry.container_port,
+            });
+        }
+        Ok(resolved)
+    }
+
+    /// The variable names each entry's ports are written under, numbered within an entry that
+    /// has several (`WORKERS_PORT_1`, ...).
+    pub fn port_var_names(&self, naming: &EnvNaming) -> Vec<Vec<String>> {
+        self.ports
+            .iter()
+            .map(|entry| {
+                let placeholders: Vec<Suggestion> = (0..entry.count)
+                    .map(|_| Suggestion {
+                        name: Some(entry.name.clone()),
+                        ..Suggestion::new(0)
+                    })
+                    .collect();
+                env_var_names(&placeholders, naming)
+            })
+            .collect()
+    }
+
+    /// `NAME=PORT` assignments for `resolved`, plus `<NAME>_CONTAINER_PORT` where set.
+    pub fn env_assignments(
+        &self,
+        resolved: &[ResolvedEntry],
+        naming: &EnvNaming,
+    ) -> Vec<(String, u16)> {
+        let mut assignments = Vec::new();
+        for (names, entry) in self.port_var_names(naming).into_iter().zip(resolved) {
+            assignments.extend(names.into_iter().zip(entry.ports.iter().copied()));
+            if let Some(container_port) = entry.container_port {
+                let name = env_var_name(&format!("{}_container", entry.name), naming);
+                assignments.push((name, container_port));
+            }
+        }
+        assignments
+    }
+}
+
+/// The `count` free ports nearest `anchor` (ties go to the lower port), or for a continuous
+/// request the fitting block whose start is nearest `anchor`.
+pub(crate) fn ports_near(
+    forbidden_ports: &HashSet<u16>,
+    count: u16,
+    continuous: bool,
+    ranges: &[(u16, u16)],
+    anchor: u16,
+) -> Vec<u16> {
+    let forbidden = PortSet::from(forbidden_ports);
+    let distance = |port: u16| port.abs_diff(anchor);
+    if continuous {
+        forbidden
+            .iter_free_within(ranges)
+            .filter(|run| run.len() >= usize::from(count))
+            .map(|run| anchor.clamp(*run.start(), *run.end() - (count - 1)))
+            .min_by_key(|&start| (distance(start), start))
+            .map(|start| (start..=start + (count - 1)).collect())
+            .unwrap_or_default()
+    } else {
+        let mut free: Vec<u16> = forbidden.iter_free_within(ranges).flatten().collect();
+        free.sort_by_key(|&port| (distance(port), port));
+        free.truncate(usize::from(count));
+        free.sort_unstable();
+        free
+    }
+}
+
+/// Reads `NAME=PORT` lines from `.env`-style content, skipping comments, `export ` prefixes,
+/// and values that aren't ports.
+pub fn parse_env_assignments(content: &str) -> Vec<(String, u16)> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (name, value) = line.split_once('=')?;
+            let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+            Some((name.trim().to_string(), value.parse().ok()?))
+        })
+        .collect()
+}
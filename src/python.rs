@@ -0,0 +1,199 @@
+//! Python bindings, built with the `python` feature. `pyproject.toml` has maturin build the
+//! `portpick` extension module from this file:
+//!
+//! ```sh
+//! maturin develop --release
+//! ```
+//!
+//! ```python
+//! import portpick
+//! portpick.find_available({8080, 8081}, 2)
+//! portpick.PortPicker().within("8000-8100").count(3).continuous(True).resolve()
+//! ```
+
+use crate::{
+    DEFAULT_PORT_RANGES, PickOptions, Provenance, Reporter, SortOrder, SourceKind,
+    find_available_ports_in, parse_services_content,
+};
+use clap::ValueEnum;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+fn value_error(error: anyhow::Error) -> PyErr {
+    PyValueError::new_err(format!("{error:#}"))
+}
+
+/// The TCP ports named in services-file text (`/etc/services` or Nmap format), skipping
+/// entries named `unknown`.
+#[pyfunction]
+fn parse_services(content: &str) -> PyResult<HashSet<u16>> {
+    parse_services_content(content, "services text", &Reporter::silent(), false)
+        .map_err(value_error)
+}
+
+/// `n` free ports in the default ranges avoiding `forbidden`, ascending; fewer if there
+/// aren't enough.
+#[pyfunction]
+#[pyo3(signature = (forbidden, n, continuous = false))]
+fn find_available(forbidden: HashSet<u16>, n: u16, continuous: bool) -> PyResult<Vec<u16>> {
+    find_available_ports_in(&forbidden, n, continuous, &DEFAULT_PORT_RANGES).map_err(value_error)
+}
+
+/// A pick run built up one setting at a time, backed by [`PickOptions`]; each setter returns
+/// the picker so calls chain.
+#[pyclass(name = "PortPicker", module = "portpick")]
+#[derive(Debug, Clone, Default)]
+pub struct PortPicker {
+    options: PickOptions,
+}
+
+#[pymethods]
+impl PortPicker {
+    #[new]
+    fn new() -> Self {
+        PortPicker::default()
+    }
+
+    /// Sources of forbidden ports `resolve` consults: "system", "nmap", or "local".
+    fn sources(mut slf: PyRefMut<'_, Self>, sources: Vec<String>) -> PyResult<PyRefMut<'_, Self>> {
+        slf.options.sources = sources
+            .iter()
+            .map(|source| match source.as_str() {
+                "system" => Ok(SourceKind::System),
+                "nmap" => Ok(SourceKind::Nmap),
+                "local" => Ok(SourceKind::Local),
+                other => Err(PyValueError::new_err(format!(
+                    "Unknown source '{other}' (expected system, nmap, or local)"
+                ))),
+            })
+            .collect::<PyResult<_>>()?;
+        Ok(slf)
+    }
+
+    fn services_file(mut slf: PyRefMut<'_, Self>, path: PathBuf) -> PyRefMut<'_, Self> {
+        slf.options.services_file = path;
+        slf
+    }
+
+    fn nmap_services_file(mut slf: PyRefMut<'_, Self>, path: PathBuf) -> PyRefMut<'_, Self> {
+        slf.options.nmap_services_file = Some(path);
+        slf
+    }
+
+    fn address(mut slf: PyRefMut<'_, Self>, address: String) -> PyRefMut<'_, Self> {
+        slf.options.address = Some(address);
+        slf
+    }
+
+    /// Search ranges, as for `--within`: "8000-8100,9000".
+    fn within(mut slf: PyRefMut<'_, Self>, spec: String) -> PyRefMut<'_, Self> {
+        slf.options.within = Some(spec);
+        slf
+    }
+
+    /// Ports never to pick, as for `--exclude`.
+    fn exclude(mut slf: PyRefMut<'_, Self>, spec: String) -> PyRefMut<'_, Self> {
+        slf.options.exclude = Some(spec);
+        slf
+    }
+
+    fn count(mut slf: PyRefMut<'_, Self>, count: u16) -> PyRefMut<'_, Self> {
+        slf.options.count = Some(count);
+        slf
+    }
+
+    fn names(mut slf: PyRefMut<'_, Self>, names: Vec<String>) -> PyRefMut<'_, Self> {
+        slf.options.names = names;
+        slf
+    }
+
+    /// Named groups, as for `--spec`: "web:3c,db:1".
+    fn spec(mut slf: PyRefMut<'_, Self>, spec: String) -> PyRefMut<'_, Self> {
+        slf.options.spec = Some(spec);
+        slf
+    }
+
+    #[pyo3(signature = (continuous = true))]
+    fn continuous(mut slf: PyRefMut<'_, Self>, continuous: bool) -> PyRefMut<'_, Self> {
+        slf.options.continuous = continuous;
+        slf
+    }
+
+    #[pyo3(signature = (random = true))]
+    fn random(mut slf: PyRefMut<'_, Self>, random: bool) -> PyRefMut<'_, Self> {
+        slf.options.random = random;
+        slf
+    }
+
+    #[pyo3(signature = (verify = true))]
+    fn verify(mut slf: PyRefMut<'_, Self>, verify: bool) -> PyRefMut<'_, Self> {
+        slf.options.verify = verify;
+        slf
+    }
+
+    fn seed(mut slf: PyRefMut<'_, Self>, seed: u64) -> PyRefMut<'_, Self> {
+        slf.options.seed = Some(seed);
+        slf
+    }
+
+    /// Order of the result: "asc", "desc", "random", or "frequency".
+    fn sort(mut slf: PyRefMut<'_, Self>, order: String) -> PyResult<PyRefMut<'_, Self>> {
+        slf.options.sort = SortOrder::from_str(&order, true).map_err(PyValueError::new_err)?;
+        Ok(slf)
+    }
+
+    #[pyo3(signature = (include_unknown = true))]
+    fn include_unknown(mut slf: PyRefMut<'_, Self>, include_unknown: bool) -> PyRefMut<'_, Self> {
+        slf.options.include_unknown = include_unknown;
+        slf
+    }
+
+    /// Picks ports avoiding `forbidden` (plus the excluded ports) without consulting any
+    /// source.
+    #[pyo3(signature = (forbidden = None))]
+    fn pick(&self, forbidden: Option<HashSet<u16>>) -> PyResult<Vec<u16>> {
+        let mut forbidden = forbidden.unwrap_or_default();
+        forbidden.extend(self.options.excluded().map_err(value_error)?.iter());
+        let (suggestions, _) = self
+            .options
+            .pick(
+                &forbidden,
+                &Provenance::default(),
+                &HashMap::new(),
+                &Reporter::silent(),
+            )
+            .map_err(value_error)?;
+        Ok(suggestions
+            .iter()
+            .map(|suggestion| suggestion.port)
+            .collect())
+    }
+
+    /// Runs the whole pipeline: reads the sources, scans if "local" is among them, and picks.
+    fn resolve(&self, py: Python<'_>) -> PyResult<Vec<u16>> {
+        let outcome = py
+            .allow_threads(|| self.options.resolve())
+            .map_err(value_error)?;
+        Ok(outcome
+            .suggestions
+            .iter()
+            .map(|suggestion| suggestion.port)
+            .collect())
+    }
+
+    fn __repr__(&self) -> String {
+        format!("PortPicker({:?})", self.options)
+    }
+}
+
+/// The `portpick` Python module.
+#[pymodule]
+pub fn portpick(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(parse_services, module)?)?;
+    module.add_function(wrap_pyfunction!(find_available, module)?)?;
+    module.add_class::<PortPicker>()?;
+    module.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use crate::PortSet;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// Parses a port spec such as `8080`, `20000-29999`, or `80,443,8000-8100` into inclusive ranges.
+///
+/// Ranges are returned in the order given. A range whose start exceeds its end is an error.
+pub fn parse_port_spec(spec: &str) -> Result<Vec<(u16, u16)>> {
+    let mut ranges = Vec::new();
+    for part in spec.split(',').map(str::trim) {
+        if part.is_empty() {
+            bail!("Empty entry in port spec '{}'", spec);
+        }
+        let (start, end) = match part.split_once('-') {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => (part, part),
+        };
+        let start = u16::from_str(start)
+            .with_context(|| format!("Invalid port '{}' in port spec '{}'", start, spec))?;
+        let end = u16::from_str(end)
+            .with_context(|| format!("Invalid port '{}' in port spec '{}'", end, spec))?;
+        if start > end {
+            bail!(
+                "Invalid range '{}' in port spec '{}': start is greater than end",
+                part,
+                spec
+            );
+        }
+        ranges.push((start, end));
+    }
+    Ok(ranges)
+}
+
+/// Checks that every range has its start at or before its end.
+pub fn validate_ranges(ranges: &[(u16, u16)]) -> Result<()> {
+    match ranges.iter().find(|(start, end)| start > end) {
+        Some((start, end)) => bail!(
+            "Invalid port range {}-{}: start is greater than end",
+            start,
+            end
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Why a request can never be satisfied, whatever ports happen to be in use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestError {
+    /// A search range has its start after its end.
+    InvalidRange { start: u16, end: u16 },
+    /// A continuous block is larger than the largest search range.
+    BlockTooLarge { requested: u16, max_block: usize },
+    /// More ports are requested than the search ranges contain.
+    TooManyPorts { requested: u16, searchable: usize },
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::InvalidRange { start, end } => write!(
+                f,
+                "Invalid port range {}-{}: start is greater than end",
+                start, end
+            ),
+            RequestError::BlockTooLarge {
+                requested,
+                max_block,
+            } => write!(
+                f,
+                "Cannot find a continuous block of {} ports: the largest block the search ranges allow is {} port(s).",
+                requested, max_block
+            ),
+            RequestError::TooManyPorts {
+                requested,
+                searchable,
+            } => write!(
+                f,
+                "Cannot find {} ports: the search ranges only contain {} port(s).",
+                requested, searchable
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+/// Rejects requests that no set of free ports could satisfy within `ranges`.
+///
+/// Continuous blocks never span two ranges, so their limit is the largest single range;
+/// otherwise it is the number of distinct ports across all ranges.
+pub fn validate_request(
+    num_ports: u16,
+    continuous: bool,
+    ranges: &[(u16, u16)],
+) -> Result<(), RequestError> {
+    if let Some(&(start, end)) = ranges.iter().find(|(start, end)| start > end) {
+        return Err(RequestError::InvalidRange { start, end });
+    }
+    if continuous {
+        let max_block = ranges
+            .iter()
+            .map(|&(start, end)| usize::from(end - start) + 1)
+            .max()
+            .unwrap_or(0);
+        if usize::from(num_ports) > max_block {
+            return Err(RequestError::BlockTooLarge {
+                requested: num_ports,
+                max_block,
+            });
+        }
+    } else {
+        let mut searchable = PortSet::new();
+        for &(start, end) in ranges {
+            searchable.insert_range(start..=end);
+        }
+        if usize::from(num_ports) > searchable.len() {
+            return Err(RequestError::TooManyPorts {
+                requested: num_ports,
+                searchable: searchable.len(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The ports of `within` not in `forbidden`, as sorted, non-overlapping, maximal ranges.
+///
+/// `within` may be in any order and may overlap or touch; it is coalesced first, so a free run
+/// is never split where two of its entries meet. Reversed entries are ignored.
+pub fn free_intervals(forbidden: &PortSet, within: &[(u16, u16)]) -> Vec<RangeInclusive<u16>> {
+    let mut search = PortSet::new();
+    for &(start, end) in within {
+        search.insert_range(start..=end);
+    }
+    let search: Vec<(u16, u16)> = search.into();
+    forbidden.iter_free_within(&search).collect()
+}
+
+/// How much free capacity a set of ranges has left.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FreeCount {
+    pub ranges: Vec<(u16, u16)>,
+    pub free_ports: u32,
+    pub block_size: u16,
+    /// Number of non-overlapping continuous blocks of `block_size` free ports.
+    pub free_blocks: u32,
+}
+
+/// Counts free ports and free non-overlapping blocks of `block_size` ports within `ranges`.
+pub fn count_free(
+    forbidden_ports: &HashSet<u16>,
+    ranges: &[(u16, u16)],
+    block_size: u16,
+) -> FreeCount {
+    let mut free_ports = 0;
+    let mut free_blocks = 0;
+    for interval in PortSet::from(forbidden_ports).iter_free_within(ranges) {
+        let length = u32::from(*interval.end()) - u32::from(*interval.start()) + 1;
+        free_ports += length;
+        if block_size > 0 {
+            free_blocks += length / u32::from(block_size);
+        }
+    }
+    FreeCount {
+        ranges: ranges.to_vec(),
+        free_ports,
+        block_size,
+        free_blocks,
+    }
+}
@@ -0,0 +1,208 @@
+use crate::{PortScanner, Reporter, ScanTarget};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default lifetime of a cached scan.
+pub const DEFAULT_SCAN_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// On-disk form of a cached scan.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    key: String,
+    scanned_at: u64,
+    ports: Vec<u16>,
+}
+
+/// A scan result read back from the cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedScan {
+    pub ports: HashSet<u16>,
+    pub age: Duration,
+}
+
+/// Short-lived scan results stored as one JSON file per key in a state directory.
+///
+/// Readers and writers take a lock on a sibling `.lock` file, and entries are written to a
+/// temporary file and renamed into place, so concurrent runs never see a torn entry.
+#[derive(Debug, Clone)]
+pub struct ScanCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl ScanCache {
+    /// A zero `ttl` disables the cache: nothing is read or written.
+    pub fn new(dir: impl Into<PathBuf>, ttl: Duration) -> Self {
+        ScanCache {
+            dir: dir.into(),
+            ttl,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let file_stem: String = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        self.dir.join(format!("scan-{}.json", file_stem))
+    }
+
+    fn lock_file(path: &Path) -> Result<File> {
+        let lock_path = path.with_extension("lock");
+        OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("Failed to open scan cache lock '{}'", lock_path.display()))
+    }
+
+    /// Returns the entry for `key` if it exists and is younger than the TTL at `now`.
+    ///
+    /// Entries written under a different key (a file name collision) or from the future
+    /// (clock changes) are treated as missing.
+    pub fn load(&self, key: &str, now: SystemTime) -> Result<Option<CachedScan>> {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+        let path = self.path(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let lock = Self::lock_file(&path)?;
+        lock.lock_shared()
+            .context("Failed to lock the scan cache for reading")?;
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(anyhow::Error::new(e)
+                    .context(format!("Failed to read scan cache '{}'", path.display())));
+            }
+        };
+        drop(lock);
+
+        let entry: CacheEntry = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse scan cache '{}'", path.display()))?;
+        if entry.key != key {
+            return Ok(None);
+        }
+        let scanned_at = UNIX_EPOCH + Duration::from_secs(entry.scanned_at);
+        match now.duration_since(scanned_at) {
+            Ok(age) if age <= self.ttl => Ok(Some(CachedScan {
+                ports: entry.ports.into_iter().collect(),
+                age,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    /// Records `ports` as the result of scanning `key` at `now`.
+    pub fn store(&self, key: &str, ports: &HashSet<u16>, now: SystemTime) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir).with_context(|| {
+            format!(
+                "Failed to create scan cache directory '{}'",
+                self.dir.display()
+            )
+        })?;
+        let mut ports: Vec<u16> = ports.iter().copied().collect();
+        ports.sort_unstable();
+        let entry = CacheEntry {
+            key: key.to_string(),
+            scanned_at: now
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or(0),
+            ports,
+        };
+
+        let path = self.path(key);
+        let lock = Self::lock_file(&path)?;
+        lock.lock()
+            .context("Failed to lock the scan cache for writing")?;
+        let temp_path = path.with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&temp_path, serde_json::to_string(&entry)?)
+            .with_context(|| format!("Failed to write scan cache '{}'", temp_path.display()))?;
+        fs::rename(&temp_path, &path)
+            .with_context(|| format!("Failed to replace scan cache '{}'", path.display()))
+    }
+}
+
+/// Wraps a scanner, answering from a [`ScanCache`] while its entry is fresh.
+pub struct CachedScanner {
+    inner: Box<dyn PortScanner>,
+    cache: ScanCache,
+    key: String,
+    reporter: Arc<Reporter>,
+}
+
+impl CachedScanner {
+    /// `key` identifies the backend and what it scans; results are only shared within a key.
+    pub fn new(
+        inner: Box<dyn PortScanner>,
+        cache: ScanCache,
+        key: impl Into<String>,
+        reporter: Arc<Reporter>,
+    ) -> Self {
+        CachedScanner {
+            inner,
+            cache,
+            key: key.into(),
+            reporter,
+        }
+    }
+}
+
+impl PortScanner for CachedScanner {
+    fn scan(&self, target: &ScanTarget) -> Result<HashSet<u16>> {
+        // A scan of some ports says nothing about the others, and one that stopped after the
+        // relevant ports may have missed the rest, so each gets an entry of its own. The lists
+        // can be long; their hashes keep the file name short.
+        let mut key = self.key.clone();
+        if let Some(ports) = &target.ports {
+            let mut hasher = DefaultHasher::new();
+            ports.hash(&mut hasher);
+            key.push_str(&format!(";ports={:016x}", hasher.finish()));
+        }
+        if let Some(relevant) = &target.relevant {
+            let mut hasher = DefaultHasher::new();
+            relevant.ranges().for_each(|range| range.hash(&mut hasher));
+            key.push_str(&format!(";relevant={:016x}", hasher.finish()));
+        }
+        match self.cache.load(&key, SystemTime::now()) {
+            Ok(Some(cached)) => {
+                self.reporter.info(format!(
+                    "Using cached scan from {}s ago ({} ports).",
+                    cached.age.as_secs(),
+                    cached.ports.len()
+                ));
+                return Ok(cached.ports);
+            }
+            Ok(None) => {}
+            Err(e) => self
+                .reporter
+                .verbose_warn(format!("Warning: Ignoring unreadable scan cache: {}", e)),
+        }
+
+        let ports = self.inner.scan(target)?;
+        if let Err(e) = self.cache.store(&key, &ports, SystemTime::now()) {
+            self.reporter
+                .verbose_warn(format!("Warning: Could not cache scan results: {}", e));
+        }
+        Ok(ports)
+    }
+}
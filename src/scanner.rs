@@ -0,0 +1,1390 @@
+use crate::interrupt;
+use crate::listener::{
+    Listener, ListenerProcess, ProcessHint, parse_lsof_listeners, parse_proc_net_listeners,
+    parse_ss_listener_processes, parse_ss_listeners, ports_conflicting_with,
+};
+use crate::{
+    PortSet, Privileges, RUSTSCAN_LATEST_KNOWN, Reporter, RustScanArgSet, ToolVersion, WarningCode,
+    parse_tool_version,
+};
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::net::IpAddr;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// The host whose listening ports a scanner should report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanTarget {
+    pub address: String,
+    /// The only ports to look at, or `None` for all of them.
+    pub ports: Option<Vec<u16>>,
+    /// The ports whose state the caller needs, or `None` for all of the scanned ones. A
+    /// scanner that finds ports progressively may stop once these are all accounted for.
+    pub relevant: Option<PortSet>,
+    /// Commands still running at this point are killed and the scan fails.
+    pub deadline: Option<Instant>,
+}
+
+impl ScanTarget {
+    pub fn new(address: impl Into<String>) -> Self {
+        ScanTarget {
+            address: address.into(),
+            ports: None,
+            relevant: None,
+            deadline: None,
+        }
+    }
+
+    /// Only look at `ports`; scanners that probe port by port skip the rest.
+    pub fn with_ports(mut self, ports: Vec<u16>) -> Self {
+        self.ports = Some(ports);
+        self
+    }
+
+    /// Only `relevant` ports need an answer; what is found outside them may be incomplete.
+    pub fn with_relevant(mut self, relevant: PortSet) -> Self {
+        self.relevant = Some(relevant);
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Drops `found` ports outside [`ScanTarget::ports`], for backends that read a whole table
+    /// regardless.
+    pub fn restrict(&self, mut found: HashSet<u16>) -> HashSet<u16> {
+        if let Some(ports) = &self.ports {
+            found.retain(|port| ports.contains(port));
+        }
+        found
+    }
+}
+
+impl Default for ScanTarget {
+    fn default() -> Self {
+        ScanTarget::new("127.0.0.1")
+    }
+}
+
+/// A backend that discovers which TCP ports are currently in use on a target.
+pub trait PortScanner: Send {
+    fn scan(&self, target: &ScanTarget) -> Result<HashSet<u16>>;
+}
+
+/// Captured result of running an external command.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandOutput {
+    /// Exit code, or `None` if the process was terminated by a signal.
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    /// Describes the exit status the same way `std::process::ExitStatus` does.
+    pub fn status_description(&self) -> String {
+        match self.code {
+            Some(code) => format!("exit status: {}", code),
+            None => "terminated by signal".to_string(),
+        }
+    }
+}
+
+/// Runs external programs on behalf of a scanner. Tests inject canned output through this.
+pub trait CommandRunner: Send {
+    fn run(&self, program: &str, args: &[String]) -> std::io::Result<CommandOutput>;
+
+    /// Like `run`, but gives up with a `TimedOut` error once `deadline` passes.
+    ///
+    /// Runners that can't stop a command early just run it.
+    fn run_until(
+        &self,
+        program: &str,
+        args: &[String],
+        _deadline: Option<Instant>,
+    ) -> std::io::Result<CommandOutput> {
+        self.run(program, args)
+    }
+
+    /// Like `run_until`, but hands each line of stdout to `on_line` as it arrives. Once
+    /// `on_line` returns a time to stop at and that time comes, the command is killed and the
+    /// output so far is returned as [`StreamedOutput::stopped`].
+    ///
+    /// Runners that can't stream run the command to completion and replay its lines.
+    fn run_streaming(
+        &self,
+        program: &str,
+        args: &[String],
+        deadline: Option<Instant>,
+        on_line: &mut dyn FnMut(&str) -> Option<Instant>,
+    ) -> std::io::Result<StreamedOutput> {
+        let output = self.run_until(program, args, deadline)?;
+        let stopped = output.stdout.lines().any(|line| on_line(line).is_some());
+        Ok(StreamedOutput { output, stopped })
+    }
+}
+
+/// Result of [`CommandRunner::run_streaming`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StreamedOutput {
+    pub output: CommandOutput,
+    /// The command was killed because the caller had seen enough, so it has no exit code.
+    pub stopped: bool,
+}
+
+/// Runs commands for real via `std::process::Command`.
+///
+/// The command runs in its own process group, so a Ctrl-C in the terminal reaches only
+/// portpick. Once Ctrl-C is pressed the whole group is terminated and reaped, and the output
+/// captured so far is returned with no exit code.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemCommandRunner;
+
+/// How often a running command is checked for exit or Ctrl-C.
+const CHILD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+impl CommandRunner for SystemCommandRunner {
+    fn run(&self, program: &str, args: &[String]) -> std::io::Result<CommandOutput> {
+        self.run_until(program, args, None)
+    }
+
+    fn run_until(
+        &self,
+        program: &str,
+        args: &[String],
+        deadline: Option<Instant>,
+    ) -> std::io::Result<CommandOutput> {
+        self.run_streaming(program, args, deadline, &mut |_| None)
+            .map(|streamed| streamed.output)
+    }
+
+    fn run_streaming(
+        &self,
+        program: &str,
+        args: &[String],
+        deadline: Option<Instant>,
+        on_line: &mut dyn FnMut(&str) -> Option<Instant>,
+    ) -> std::io::Result<StreamedOutput> {
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = command.spawn()?;
+        interrupt::register_child(&child);
+        let lines = read_lines_in_background(child.stdout.take());
+        let stderr = read_to_end_in_background(child.stderr.take());
+
+        let mut stdout = Vec::new();
+        let mut stdout_open = true;
+        let mut stop_at: Option<Instant> = None;
+        let mut stopped = false;
+        let status = loop {
+            // Lines are read until stdout closes, so none queued at exit are lost.
+            if stdout_open {
+                match lines.recv_timeout(CHILD_POLL_INTERVAL) {
+                    Ok(line) => {
+                        if let Some(at) = on_line(String::from_utf8_lossy(&line).trim_end()) {
+                            stop_at = Some(stop_at.map_or(at, |earlier| earlier.min(at)));
+                        }
+                        stdout.extend(line);
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => stdout_open = false,
+                }
+            } else if let Some(status) = child.try_wait()? {
+                break status;
+            } else {
+                std::thread::sleep(CHILD_POLL_INTERVAL);
+            }
+            if stop_at.is_some_and(|at| Instant::now() >= at) {
+                stopped = true;
+                interrupt::kill_process_group(child.id());
+                let _ = child.kill();
+                break child.wait()?;
+            }
+            if interrupt::is_interrupted() {
+                interrupt::kill_process_group(child.id());
+                // Fall back to killing just the child if the group signal didn't land.
+                let _ = child.kill();
+                break child.wait()?;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                interrupt::kill_process_group(child.id());
+                let _ = child.kill();
+                child.wait()?;
+                interrupt::clear_child();
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("{} was still running at the --timeout deadline", program),
+                ));
+            }
+        };
+        interrupt::clear_child();
+
+        Ok(StreamedOutput {
+            output: CommandOutput {
+                code: status.code(),
+                stdout: String::from_utf8_lossy(&stdout).into_owned(),
+                stderr: stderr.join().unwrap_or_default(),
+            },
+            stopped,
+        })
+    }
+}
+
+/// Sends each line of `pipe`, newline included, until it closes.
+fn read_lines_in_background<R: Read + Send + 'static>(pipe: Option<R>) -> mpsc::Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let Some(pipe) = pipe else {
+            return;
+        };
+        let mut pipe = BufReader::new(pipe);
+        loop {
+            let mut line = Vec::new();
+            match pipe.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) if sender.send(line).is_err() => break,
+                Ok(_) => {}
+            }
+        }
+    });
+    receiver
+}
+
+fn read_to_end_in_background<R: Read + Send + 'static>(
+    pipe: Option<R>,
+) -> std::thread::JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    })
+}
+
+/// Per-port connect timeout passed to rustscan.
+const RUSTSCAN_TIMEOUT: Duration = Duration::from_millis(1500);
+
+impl RustScanArgSet {
+    /// Arguments passed to rustscan for the given target.
+    // Consider making batch size and timeout configurable if needed.
+    pub fn args(&self, target: &ScanTarget) -> Vec<String> {
+        // Every port, unless the target names the ones that matter.
+        let ports = match &target.ports {
+            Some(ports) => [
+                "-p".to_string(),
+                ports
+                    .iter()
+                    .map(u16::to_string)
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ],
+            None => ["--range".to_string(), "1-65535".to_string()],
+        };
+        let mut args = vec!["-a".to_string(), target.address.clone()]; // Target address from --address flag or default
+        args.extend(ports);
+        args.push(match self.accessible {
+            true => "--accessible".to_string(), // Output only open ports, one port per line
+            false => "-g".to_string(),          // Output open ports on one summary line
+        });
+        if self.scan_order {
+            // Probe in the order given, which early stopping relies on
+            args.extend(["--scan-order".to_string(), "serial".to_string()]);
+        }
+        let timeout = RUSTSCAN_TIMEOUT.as_millis().to_string();
+        args.extend(
+            [
+                "-b",
+                "1000", // Batch size for scanning
+                self.timeout_flag,
+                &timeout,    // Timeout per port in milliseconds
+                "--",        // Separator: arguments after this are for the command
+                "/bin/true", // Command to run instead of Nmap (does nothing)
+            ]
+            .iter()
+            .map(|arg| arg.to_string()),
+        );
+        args
+    }
+}
+
+/// Scans for open ports by shelling out to `rustscan`.
+///
+/// Rustscan probes ports in the order given and prints each open one as soon as it answers.
+/// So once it reports a port past every [`ScanTarget::relevant`] one, those have all been
+/// probed, and within one connect timeout they have all answered; the scan stops there
+/// instead of running through the rest of the range.
+///
+/// Before its first scan it runs `rustscan --version` and picks the flags that release
+/// accepts from [`RUSTSCAN_ARG_SETS`](crate::RUSTSCAN_ARG_SETS), remembering the answer for
+/// later scans.
+pub struct RustScanScanner {
+    program: String,
+    runner: Box<dyn CommandRunner>,
+    reporter: Arc<Reporter>,
+    /// The detected version, or `None` inside if rustscan didn't print one.
+    version: OnceLock<Option<ToolVersion>>,
+}
+
+impl RustScanScanner {
+    pub fn new(reporter: Arc<Reporter>) -> Self {
+        Self::with_runner(Box::new(SystemCommandRunner), reporter)
+    }
+
+    pub fn with_runner(runner: Box<dyn CommandRunner>, reporter: Arc<Reporter>) -> Self {
+        RustScanScanner {
+            program: "rustscan".to_string(),
+            runner,
+            reporter,
+            version: OnceLock::new(),
+        }
+    }
+
+    /// Runs `program` instead of looking up `rustscan` in PATH.
+    pub fn with_program(mut self, program: impl Into<String>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    /// Arguments passed to the newest known rustscan for the given target.
+    pub fn args(target: &ScanTarget) -> Vec<String> {
+        RustScanArgSet::latest().args(target)
+    }
+
+    /// The installed rustscan's version, asked for on the first call only. `Ok(None)` if it
+    /// ran but didn't print one.
+    pub fn version(&self, deadline: Option<Instant>) -> Result<Option<ToolVersion>> {
+        if let Some(version) = self.version.get() {
+            return Ok(*version);
+        }
+        self.reporter
+            .detail(format!("Executing: {} --version", self.program));
+        let output = self
+            .runner
+            .run_until(&self.program, &["--version".to_string()], deadline)
+            .context(
+                "Failed to execute rustscan command. Make sure rustscan is installed and in PATH.",
+            )?;
+        let version = match output.success() {
+            true => parse_tool_version(&output.stdout),
+            false => None,
+        };
+        match version {
+            Some(version) if RustScanArgSet::for_version(version).is_some() => {
+                self.reporter
+                    .detail(format!("Detected rustscan {}.", version));
+            }
+            Some(version) => self.reporter.warn(format!(
+                "Warning: rustscan {} is not a release portpick knows (latest known: {}); using the arguments for {}.",
+                version, RUSTSCAN_LATEST_KNOWN, RUSTSCAN_LATEST_KNOWN
+            )),
+            None => self.reporter.warn(format!(
+                "Warning: Could not tell which rustscan version '{}' is; using the arguments for {}.",
+                self.program, RUSTSCAN_LATEST_KNOWN
+            )),
+        }
+        Ok(*self.version.get_or_init(|| version))
+    }
+
+    /// Where `port` comes in the order rustscan probes `target`'s ports.
+    fn probe_position(target: &ScanTarget, port: u16) -> Option<usize> {
+        match &target.ports {
+            Some(ports) => ports.iter().position(|&p| p == port),
+            None => Some(usize::from(port)),
+        }
+    }
+
+    /// Position of the last relevant port, or `None` if the whole scan is needed.
+    fn last_relevant_position(target: &ScanTarget) -> Option<usize> {
+        let relevant = target.relevant.as_ref()?;
+        match &target.ports {
+            Some(ports) => ports.iter().rposition(|&port| relevant.contains(port)),
+            None => relevant
+                .ranges()
+                .last()
+                .map(|range| usize::from(*range.end())),
+        }
+    }
+}
+
+impl PortScanner for RustScanScanner {
+    fn scan(&self, target: &ScanTarget) -> Result<HashSet<u16>> {
+        self.reporter
+            .info("Scanning for locally used TCP ports using RustScan...");
+        let version = self.version(target.deadline)?;
+        let arg_set = version
+            .and_then(RustScanArgSet::for_version)
+            .unwrap_or_else(RustScanArgSet::latest);
+        let rustscan_args = arg_set.args(target);
+
+        self.reporter.detail(format!(
+            "Executing: {} {}",
+            self.program,
+            rustscan_args.join(" ")
+        ));
+
+        let last_relevant = Self::last_relevant_position(target);
+        let mut found = HashSet::new();
+        let mut covered_by = None;
+        let streamed = self
+            .runner
+            .run_streaming(
+                &self.program,
+                &rustscan_args,
+                target.deadline,
+                &mut |line| {
+                    let ports = parse_rustscan_output(line, &self.reporter);
+                    found.extend(&ports);
+                    let past_relevant = ports.iter().find(|&&port| {
+                        Self::probe_position(target, port)
+                            .zip(last_relevant)
+                            .is_some_and(|(position, last)| position > last)
+                    });
+                    match (past_relevant, covered_by) {
+                        (Some(&port), None) => {
+                            covered_by = Some(port);
+                            Some(Instant::now() + RUSTSCAN_TIMEOUT)
+                        }
+                        _ => None,
+                    }
+                },
+            )
+            .context(
+                "Failed to execute rustscan command. Make sure rustscan is installed and in PATH.",
+            )?;
+        let output = streamed.output;
+
+        if streamed.stopped {
+            let ports = target.restrict(found);
+            self.reporter.info(format!(
+                "RustScan stopped early: port {} is past every port needed, so the {} open ports found so far cover them.",
+                covered_by.unwrap_or_default(),
+                ports.len()
+            ));
+            return Ok(ports);
+        }
+        if !output.success() && interrupt::is_interrupted() {
+            let ports = target.restrict(found);
+            self.reporter.warn_with(WarningCode::Interrupted, format!(
+                "RustScan was interrupted; using the {} open ports found so far. Local port data is incomplete.",
+                ports.len()
+            ));
+            return Ok(ports);
+        }
+        match judge_rustscan_run(&output) {
+            RustScanRun::Complete => {}
+            RustScanRun::Partial { reported } => {
+                self.reporter.warn_with(
+                    WarningCode::PartialScan,
+                    format!(
+                        "RustScan failed with {} after reporting {} open ports; using them, but local port data may be incomplete.{}",
+                        output.status_description(),
+                        reported,
+                        match output.stderr.trim() {
+                            "" => String::new(),
+                            stderr => format!(" Stderr: {}", stderr),
+                        }
+                    ),
+                );
+            }
+            RustScanRun::RejectedArguments => {
+                return Err(anyhow::anyhow!(
+                    "rustscan {} rejected the arguments portpick passed for releases since {}: {}",
+                    version.map_or_else(|| "(version unknown)".to_string(), |v| v.to_string()),
+                    arg_set.since,
+                    output.stderr.trim()
+                ));
+            }
+            RustScanRun::Failed => {
+                return Err(anyhow::anyhow!(
+                    "rustscan command failed with status: {}.\nStdout: {}\nStderr: {}",
+                    output.status_description(),
+                    output.stdout,
+                    output.stderr
+                ));
+            }
+        }
+
+        let ports = target.restrict(found);
+
+        self.reporter.info(format!(
+            "RustScan found {} locally open TCP ports.",
+            ports.len()
+        ));
+        Ok(ports)
+    }
+}
+
+/// What a finished rustscan run amounts to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RustScanRun {
+    Complete,
+    /// Exited with an error after printing `reported` open ports, which still count.
+    Partial {
+        reported: usize,
+    },
+    /// rustscan's parser refused a flag portpick passed.
+    RejectedArguments,
+    /// Exited with an error and printed no port.
+    Failed,
+}
+
+/// Judges a rustscan run by its exit code, stdout, and stderr. rustscan sometimes exits
+/// non-zero after listing perfectly good open ports, e.g. when one interface errors, so a
+/// failure only counts as one when stdout has no port lines.
+pub fn judge_rustscan_run(output: &CommandOutput) -> RustScanRun {
+    if output.success() {
+        return RustScanRun::Complete;
+    }
+    if rejected_arguments(&output.stderr) {
+        return RustScanRun::RejectedArguments;
+    }
+    match parse_rustscan_output(&output.stdout, &Reporter::silent()).len() {
+        0 => RustScanRun::Failed,
+        reported => RustScanRun::Partial { reported },
+    }
+}
+
+/// Whether rustscan's command-line parser refused a flag, as it does for one its release
+/// doesn't have.
+fn rejected_arguments(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    [
+        "unexpected argument",
+        "wasn't expected",
+        "unrecognized option",
+    ]
+    .iter()
+    .any(|pattern| stderr.contains(pattern))
+}
+
+/// Packets per second masscan sends unless told otherwise; far below its default so a full
+/// scan doesn't flood a shared network or drop replies on a busy host.
+pub const DEFAULT_MASSCAN_RATE: u32 = 1000;
+
+/// A scanner that can't run with this process's privileges, such as masscan without root or
+/// `CAP_NET_RAW`. Distinct from other scan failures, so a caller trying several scanners can
+/// move on to the next.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannerNeedsPrivileges {
+    pub scanner: String,
+    /// What the scanner said.
+    pub detail: String,
+}
+
+impl fmt::Display for ScannerNeedsPrivileges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} needs root or the CAP_NET_RAW capability to send raw packets",
+            self.scanner
+        )?;
+        if !self.detail.is_empty() {
+            write!(f, ": {}", self.detail)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ScannerNeedsPrivileges {}
+
+/// Scans for open ports by shelling out to `masscan`, reading its list output (`-oL -`).
+///
+/// masscan sends raw SYN packets, so it needs root or `CAP_NET_RAW`; without them the scan
+/// fails with [`ScannerNeedsPrivileges`].
+pub struct MasscanScanner {
+    program: String,
+    rate: u32,
+    privileges: Option<Privileges>,
+    runner: Box<dyn CommandRunner>,
+    reporter: Arc<Reporter>,
+}
+
+impl MasscanScanner {
+    /// A scanner that checks this process's privileges before running masscan.
+    pub fn new(reporter: Arc<Reporter>) -> Self {
+        Self::with_runner(Box::new(SystemCommandRunner), reporter)
+            .with_privileges(Privileges::detect())
+    }
+
+    pub fn with_runner(runner: Box<dyn CommandRunner>, reporter: Arc<Reporter>) -> Self {
+        MasscanScanner {
+            program: "masscan".to_string(),
+            rate: DEFAULT_MASSCAN_RATE,
+            privileges: None,
+            runner,
+            reporter,
+        }
+    }
+
+    /// Fails with [`ScannerNeedsPrivileges`] without running masscan when `privileges` can't
+    /// open raw sockets.
+    pub fn with_privileges(mut self, privileges: Privileges) -> Self {
+        self.privileges = Some(privileges);
+        self
+    }
+
+    /// Sends `rate` packets per second instead of [`DEFAULT_MASSCAN_RATE`].
+    pub fn with_rate(mut self, rate: u32) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    /// Arguments passed to masscan for the given target.
+    pub fn args(&self, target: &ScanTarget) -> Vec<String> {
+        let ports = match &target.ports {
+            Some(ports) => ports
+                .iter()
+                .map(u16::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+            None => "1-65535".to_string(),
+        };
+        vec![
+            format!("-p{}", ports),
+            target.address.clone(),
+            "--rate".to_string(),
+            self.rate.to_string(),
+            "-oL".to_string(),
+            "-".to_string(),
+        ]
+    }
+}
+
+/// Whether masscan's complaint is about privileges rather than anything else.
+fn lacks_privileges(stderr: &str) -> bool {
+    let stderr = stderr.to_ascii_lowercase();
+    [
+        "permission denied",
+        "operation not permitted",
+        "run as root",
+    ]
+    .iter()
+    .any(|hint| stderr.contains(hint))
+}
+
+impl PortScanner for MasscanScanner {
+    fn scan(&self, target: &ScanTarget) -> Result<HashSet<u16>> {
+        if let Some(privileges) = self.privileges
+            && !privileges.can_send_raw_packets()
+        {
+            return Err(ScannerNeedsPrivileges {
+                scanner: "masscan".to_string(),
+                detail: format!("this process has neither ({})", privileges),
+            }
+            .into());
+        }
+        self.reporter.info(format!(
+            "Scanning for locally used TCP ports using masscan at {} packets/s...",
+            self.rate
+        ));
+        let args = self.args(target);
+        self.reporter
+            .detail(format!("Executing: {} {}", self.program, args.join(" ")));
+
+        let output = self
+            .runner
+            .run_until(&self.program, &args, target.deadline)
+            .context(
+                "Failed to execute masscan command. Make sure masscan is installed and in PATH.",
+            )?;
+        if !output.success() && interrupt::is_interrupted() {
+            let ports = target.restrict(parse_masscan_output(&output.stdout));
+            self.reporter.warn_with(WarningCode::Interrupted, format!(
+                "masscan was interrupted; using the {} open ports found so far. Local port data is incomplete.",
+                ports.len()
+            ));
+            return Ok(ports);
+        }
+        if !output.success() {
+            if lacks_privileges(&output.stderr) {
+                return Err(ScannerNeedsPrivileges {
+                    scanner: "masscan".to_string(),
+                    detail: output.stderr.trim().to_string(),
+                }
+                .into());
+            }
+            return Err(anyhow::anyhow!(
+                "masscan command failed with status: {}.\nStderr: {}",
+                output.status_description(),
+                output.stderr
+            ));
+        }
+
+        let ports = target.restrict(parse_masscan_output(&output.stdout));
+        self.reporter
+            .info(format!("masscan found {} open TCP ports.", ports.len()));
+        Ok(ports)
+    }
+}
+
+/// Scans for open ports with `nmap -sT`, a connect scan that needs no privileges, reading its
+/// grepable output (`-oG -`).
+pub struct NmapScanner {
+    program: String,
+    host_timeout: Option<Duration>,
+    runner: Box<dyn CommandRunner>,
+    reporter: Arc<Reporter>,
+}
+
+impl NmapScanner {
+    pub fn new(reporter: Arc<Reporter>) -> Self {
+        Self::with_runner(Box::new(SystemCommandRunner), reporter)
+    }
+
+    pub fn with_runner(runner: Box<dyn CommandRunner>, reporter: Arc<Reporter>) -> Self {
+        NmapScanner {
+            program: "nmap".to_string(),
+            host_timeout: None,
+            runner,
+            reporter,
+        }
+    }
+
+    /// Has nmap give up on the host after `timeout` (`--host-timeout`).
+    pub fn with_host_timeout(mut self, timeout: Duration) -> Self {
+        self.host_timeout = Some(timeout);
+        self
+    }
+
+    /// Arguments passed to nmap for the given target.
+    pub fn args(&self, target: &ScanTarget) -> Vec<String> {
+        let ports = match &target.ports {
+            Some(ports) => format!(
+                "-p{}",
+                ports
+                    .iter()
+                    .map(u16::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            None => "-p-".to_string(),
+        };
+        let mut args: Vec<String> = [ports.as_str(), "-sT", "--open", "-oG", "-"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        if let Some(timeout) = self.host_timeout {
+            args.push("--host-timeout".to_string());
+            args.push(format!("{}ms", timeout.as_millis()));
+        }
+        args.push(target.address.clone());
+        args
+    }
+}
+
+impl PortScanner for NmapScanner {
+    fn scan(&self, target: &ScanTarget) -> Result<HashSet<u16>> {
+        self.reporter
+            .info("Scanning for locally used TCP ports using nmap...");
+        let args = self.args(target);
+        self.reporter
+            .detail(format!("Executing: {} {}", self.program, args.join(" ")));
+
+        let output = self
+            .runner
+            .run_until(&self.program, &args, target.deadline)
+            .context("Failed to execute nmap command. Make sure nmap is installed and in PATH.")?;
+        if !output.success() && interrupt::is_interrupted() {
+            let ports = target.restrict(parse_nmap_grepable(&output.stdout));
+            self.reporter.warn_with(WarningCode::Interrupted, format!(
+                "nmap was interrupted; using the {} open ports found so far. Local port data is incomplete.",
+                ports.len()
+            ));
+            return Ok(ports);
+        }
+        if !output.success() {
+            return Err(anyhow::anyhow!(
+                "nmap command failed with status: {}.\nStderr: {}",
+                output.status_description(),
+                output.stderr
+            ));
+        }
+        // A host nmap gave up on reports no ports at all, which must not read as none open.
+        if output.stdout.contains("Status: Timeout") {
+            return Err(anyhow::anyhow!(
+                "nmap gave up on {} after the host timeout of {:.1}s",
+                target.address,
+                self.host_timeout.unwrap_or_default().as_secs_f64()
+            ));
+        }
+
+        let ports = target.restrict(parse_nmap_grepable(&output.stdout));
+        self.reporter
+            .info(format!("nmap found {} open TCP ports.", ports.len()));
+        Ok(ports)
+    }
+}
+
+/// Whether a scan failed because the scanner can't run here at all: it isn't installed, or it
+/// needs privileges this process lacks ([`ScannerNeedsPrivileges`]).
+pub fn scanner_unavailable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.is::<ScannerNeedsPrivileges>()
+            || cause
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+    })
+}
+
+/// Tries scanners in order until one runs, moving on from those that are
+/// [`scanner_unavailable`]. Any other failure is the chain's.
+pub struct FallbackScanner {
+    scanners: Vec<(String, Box<dyn PortScanner>)>,
+    reporter: Arc<Reporter>,
+}
+
+impl FallbackScanner {
+    pub fn new(reporter: Arc<Reporter>) -> Self {
+        FallbackScanner {
+            scanners: Vec::new(),
+            reporter,
+        }
+    }
+
+    /// Adds `scanner`, called `name` in messages, after the ones already in the chain.
+    pub fn then(mut self, name: impl Into<String>, scanner: Box<dyn PortScanner>) -> Self {
+        self.scanners.push((name.into(), scanner));
+        self
+    }
+}
+
+impl PortScanner for FallbackScanner {
+    fn scan(&self, target: &ScanTarget) -> Result<HashSet<u16>> {
+        let mut unavailable = Vec::new();
+        for (name, scanner) in &self.scanners {
+            match scanner.scan(target) {
+                Ok(ports) => return Ok(ports),
+                Err(e) if scanner_unavailable(&e) => {
+                    self.reporter
+                        .info(format!("Scanner '{}' unavailable: {:#}", name, e));
+                    unavailable.push(name.as_str());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        anyhow::bail!(
+            "No port scanner could run (tried {}); install one of them",
+            unavailable.join(", ")
+        )
+    }
+}
+
+/// Reads listening ports from inside a Docker container's network namespace.
+///
+/// Runs `docker exec <container> cat /proc/net/tcp /proc/net/tcp6`, so ports bound by services
+/// sharing the container's network (`--network container:<name>`) are seen even when the host
+/// loopback scan can't reach them. The scan target address is not used.
+pub struct ContainerScanner {
+    container: String,
+    bind_address: Option<IpAddr>,
+    runner: Box<dyn CommandRunner>,
+    reporter: Arc<Reporter>,
+}
+
+impl ContainerScanner {
+    pub fn new(container: impl Into<String>, reporter: Arc<Reporter>) -> Self {
+        Self::with_runner(container, Box::new(SystemCommandRunner), reporter)
+    }
+
+    pub fn with_runner(
+        container: impl Into<String>,
+        runner: Box<dyn CommandRunner>,
+        reporter: Arc<Reporter>,
+    ) -> Self {
+        ContainerScanner {
+            container: container.into(),
+            bind_address: None,
+            runner,
+            reporter,
+        }
+    }
+
+    /// Only report ports whose listeners conflict with binding on `address`.
+    pub fn with_bind_address(mut self, address: IpAddr) -> Self {
+        self.bind_address = Some(address);
+        self
+    }
+
+    /// Arguments passed to `docker`.
+    pub fn args(&self) -> Vec<String> {
+        [
+            "exec",
+            &self.container,
+            "cat",
+            "/proc/net/tcp",
+            "/proc/net/tcp6",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect()
+    }
+}
+
+impl PortScanner for ContainerScanner {
+    fn scan(&self, target: &ScanTarget) -> Result<HashSet<u16>> {
+        self.reporter.info(format!(
+            "Reading listening TCP ports inside container '{}'...",
+            self.container
+        ));
+        let args = self.args();
+        self.reporter
+            .detail(format!("Executing: docker {}", args.join(" ")));
+
+        let output = self
+            .runner
+            .run_until("docker", &args, target.deadline)
+            .context(
+                "Failed to execute docker command. Make sure docker is installed and in PATH.",
+            )?;
+
+        // /proc/net/tcp6 is missing when IPv6 is disabled; the IPv4 table alone still counts.
+        if !output.success() {
+            if output.stdout.contains("local_address") {
+                self.reporter.verbose_warn(format!(
+                    "Warning: docker exec reported {} but returned a socket table; continuing. Stderr: {}",
+                    output.status_description(),
+                    output.stderr.trim()
+                ));
+            } else {
+                return Err(anyhow::anyhow!(
+                    "docker exec into container '{}' failed with status: {}.\nStderr: {}",
+                    self.container,
+                    output.status_description(),
+                    output.stderr
+                ));
+            }
+        }
+
+        let listeners = parse_proc_net_listeners(&output.stdout);
+        let ports = target.restrict(blocked_ports(&listeners, self.bind_address));
+        self.reporter.info(format!(
+            "Found {} listening TCP ports inside container '{}'.",
+            ports.len(),
+            self.container
+        ));
+        Ok(ports)
+    }
+}
+
+/// Extracts listening ports from `/proc/net/tcp` or `/proc/net/tcp6` content.
+///
+/// See [`parse_proc_net_listeners`] for the bind addresses as well.
+pub fn parse_proc_net_tcp(content: &str) -> HashSet<u16> {
+    parse_proc_net_listeners(content)
+        .into_iter()
+        .map(|listener| listener.port)
+        .collect()
+}
+
+/// Narrows listeners to the ports that block `bind_address`, or keeps all of them without one.
+fn blocked_ports(listeners: &[Listener], bind_address: Option<IpAddr>) -> HashSet<u16> {
+    match bind_address {
+        Some(bind) => ports_conflicting_with(listeners, bind),
+        None => listeners.iter().map(|listener| listener.port).collect(),
+    }
+}
+
+/// Lists local listeners with `ss -Hlnt`, which reports each socket's bind address.
+pub struct SsScanner {
+    bind_address: Option<IpAddr>,
+    runner: Box<dyn CommandRunner>,
+    reporter: Arc<Reporter>,
+}
+
+impl SsScanner {
+    pub fn new(reporter: Arc<Reporter>) -> Self {
+        Self::with_runner(Box::new(SystemCommandRunner), reporter)
+    }
+
+    pub fn with_runner(runner: Box<dyn CommandRunner>, reporter: Arc<Reporter>) -> Self {
+        SsScanner {
+            bind_address: None,
+            runner,
+            reporter,
+        }
+    }
+
+    /// Only report ports whose listeners conflict with binding on `address`.
+    pub fn with_bind_address(mut self, address: IpAddr) -> Self {
+        self.bind_address = Some(address);
+        self
+    }
+}
+
+impl PortScanner for SsScanner {
+    fn scan(&self, target: &ScanTarget) -> Result<HashSet<u16>> {
+        self.reporter
+            .info("Listing local listening TCP sockets using ss...");
+        let args = vec!["-Hlnt".to_string()];
+        self.reporter
+            .detail(format!("Executing: ss {}", args.join(" ")));
+
+        let output = self
+            .runner
+            .run_until("ss", &args, target.deadline)
+            .context(
+                "Failed to execute ss command. Make sure iproute2 is installed and in PATH.",
+            )?;
+        if !output.success() {
+            return Err(anyhow::anyhow!(
+                "ss command failed with status: {}.\nStderr: {}",
+                output.status_description(),
+                output.stderr
+            ));
+        }
+
+        let listeners = parse_ss_listeners(&output.stdout);
+        let ports = target.restrict(blocked_ports(&listeners, self.bind_address));
+        self.reporter.info(format!(
+            "ss found {} listening sockets; {} ports are unavailable{}.",
+            listeners.len(),
+            ports.len(),
+            self.bind_address
+                .map(|bind| format!(" on {}", bind))
+                .unwrap_or_default()
+        ));
+        Ok(ports)
+    }
+}
+
+/// Which process listens on each local TCP port, asking `ss -Hlntp` and falling back to `lsof`.
+///
+/// Without root, both only see the current user's processes; other ports are left out. Errs
+/// only if neither tool could be run.
+pub fn listening_processes(
+    runner: &dyn CommandRunner,
+    deadline: Option<Instant>,
+) -> Result<HashMap<u16, ProcessHint>> {
+    type Parser = fn(&str) -> Vec<ListenerProcess>;
+    let attempts: [(&str, &[&str], Parser); 2] = [
+        ("ss", &["-Hlntp"], parse_ss_listener_processes),
+        (
+            "lsof",
+            &["-nP", "-iTCP", "-sTCP:LISTEN"],
+            parse_lsof_listeners,
+        ),
+    ];
+    let mut failures = Vec::new();
+    for (program, args, parse) in attempts {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        // lsof exits with 1 when it finds nothing, so its output is what counts.
+        match runner.run_until(program, &args, deadline) {
+            Ok(output) if output.success() || program == "lsof" => {
+                let mut processes = HashMap::new();
+                for entry in parse(&output.stdout) {
+                    if let Some(process) = entry.process {
+                        processes.entry(entry.listener.port).or_insert(process);
+                    }
+                }
+                return Ok(processes);
+            }
+            Ok(output) => failures.push(format!("{} {}", program, output.status_description())),
+            Err(e) => failures.push(format!("{}: {}", program, e)),
+        }
+    }
+    Err(anyhow::anyhow!(
+        "Could not list listening processes ({})",
+        failures.join("; ")
+    ))
+}
+
+/// Whether we are running under WSL, judging by `/proc/version` and `$WSL_DISTRO_NAME`.
+pub fn is_wsl(proc_version: Option<&str>, wsl_distro_name: Option<&str>) -> bool {
+    if wsl_distro_name.is_some_and(|name| !name.is_empty()) {
+        return true;
+    }
+    proc_version.is_some_and(|version| {
+        let version = version.to_lowercase();
+        version.contains("microsoft") || version.contains("wsl")
+    })
+}
+
+/// Environment variables that mark a CI run, and whether any value counts or only "true"/"1".
+const CI_VARIABLES: [(&str, bool); 4] = [
+    ("GITHUB_ACTIONS", false),
+    ("GITLAB_CI", true),
+    ("BUILDKITE", false),
+    ("CI", false),
+];
+
+/// The variable that shows we are running in a CI pipeline, looked up through `var`.
+///
+/// Empty values and values such as `false` don't count, except for variables that are only
+/// ever set in CI.
+pub fn detect_ci(var: impl Fn(&str) -> Option<String>) -> Option<&'static str> {
+    CI_VARIABLES.iter().find_map(|&(name, any_value)| {
+        let value = var(name)?;
+        let value = value.trim();
+        let set = if any_value {
+            !value.is_empty()
+        } else {
+            value.eq_ignore_ascii_case("true") || value == "1"
+        };
+        set.then_some(name)
+    })
+}
+
+/// Reads the Windows side of a WSL2 machine through interop.
+///
+/// Listening ports come from `netstat.exe -ano`. Ranges Windows reserves (Hyper-V, WinNAT) come
+/// from `netsh.exe` and are added when it is reachable; if it isn't, that part is skipped with a
+/// verbose warning.
+pub struct WindowsHostScanner {
+    runner: Box<dyn CommandRunner>,
+    reporter: Arc<Reporter>,
+}
+
+impl WindowsHostScanner {
+    pub fn new(reporter: Arc<Reporter>) -> Self {
+        Self::with_runner(Box::new(SystemCommandRunner), reporter)
+    }
+
+    pub fn with_runner(runner: Box<dyn CommandRunner>, reporter: Arc<Reporter>) -> Self {
+        WindowsHostScanner { runner, reporter }
+    }
+
+    fn run(
+        &self,
+        program: &str,
+        args: &[&str],
+        deadline: Option<Instant>,
+    ) -> Result<CommandOutput> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        self.reporter
+            .detail(format!("Executing: {} {}", program, args.join(" ")));
+        let output = self
+            .runner
+            .run_until(program, &args, deadline)
+            .with_context(|| format!("Failed to execute {} (is WSL interop enabled?)", program))?;
+        if !output.success() {
+            return Err(anyhow::anyhow!(
+                "{} failed with status: {}.\nStderr: {}",
+                program,
+                output.status_description(),
+                output.stderr
+            ));
+        }
+        Ok(output)
+    }
+}
+
+impl PortScanner for WindowsHostScanner {
+    fn scan(&self, target: &ScanTarget) -> Result<HashSet<u16>> {
+        self.reporter
+            .info("WSL detected: reading Windows-side listening ports via netstat.exe...");
+        let netstat = self.run("netstat.exe", &["-ano"], target.deadline)?;
+        let mut ports = parse_netstat_listening(&netstat.stdout);
+        self.reporter.info(format!(
+            "Windows reports {} listening TCP ports.",
+            ports.len()
+        ));
+
+        match self.run(
+            "netsh.exe",
+            &[
+                "interface",
+                "ipv4",
+                "show",
+                "excludedportrange",
+                "protocol=tcp",
+            ],
+            target.deadline,
+        ) {
+            Ok(netsh) => {
+                let ranges = parse_excluded_port_ranges(&netsh.stdout);
+                self.reporter.info(format!(
+                    "Windows excludes {} TCP port range(s).",
+                    ranges.len()
+                ));
+                for (start, end) in ranges {
+                    ports.extend(start..=end);
+                }
+            }
+            Err(e) => self.reporter.verbose_warn(format!(
+                "Warning: Could not read Windows excluded port ranges: {}",
+                e
+            )),
+        }
+        Ok(target.restrict(ports))
+    }
+}
+
+/// Extracts listening TCP ports from Windows `netstat -ano` output.
+///
+/// Rows look like `TCP    0.0.0.0:135    0.0.0.0:0    LISTENING    1234`; IPv6 local
+/// addresses such as `[::]:445` work the same way.
+pub fn parse_netstat_listening(output: &str) -> HashSet<u16> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4
+                || !fields[0].eq_ignore_ascii_case("TCP")
+                || !fields[3].eq_ignore_ascii_case("LISTENING")
+            {
+                return None;
+            }
+            u16::from_str(fields[1].rsplit_once(':')?.1).ok()
+        })
+        .collect()
+}
+
+/// Extracts `(start, end)` ranges from `netsh interface ipv4 show excludedportrange` output.
+pub fn parse_excluded_port_ranges(output: &str) -> Vec<(u16, u16)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let start = u16::from_str(fields.next()?).ok()?;
+            let end = u16::from_str(fields.next()?).ok()?;
+            (start <= end).then_some((start, end))
+        })
+        .collect()
+}
+
+/// Extracts open TCP ports from masscan's list output (`-oL`): lines such as
+/// `open tcp 8080 127.0.0.1 1700000000`. Comments, other states, and other protocols are
+/// skipped.
+pub fn parse_masscan_output(output: &str) -> HashSet<u16> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some("open"), Some("tcp"), Some(port)) => port.parse().ok(),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Extracts open TCP ports from nmap's grepable output (`-oG`).
+///
+/// Each host line's `Ports:` field lists comma-separated `port/state/protocol/owner/service/
+/// rpc/version/` tuples; only `open` TCP ones count. Hosts may span several lines (one with
+/// their status, one with their ports), and comments are skipped.
+pub fn parse_nmap_grepable(output: &str) -> HashSet<u16> {
+    let mut ports = HashSet::new();
+    for line in output.lines().filter(|line| line.starts_with("Host:")) {
+        let Some(field) = line
+            .split('\t')
+            .find_map(|field| field.trim().strip_prefix("Ports:"))
+        else {
+            continue;
+        };
+        for entry in field.split(',') {
+            let mut parts = entry.trim().split('/');
+            if let (Some(port), Some("open"), Some("tcp")) =
+                (parts.next(), parts.next(), parts.next())
+                && let Ok(port) = port.parse()
+            {
+                ports.insert(port);
+            }
+        }
+    }
+    ports
+}
+
+/// Extracts open ports from rustscan's `--accessible` or `-g` output.
+///
+/// Accepts bare port numbers, `Open <ip>:<port>` lines, and `<ip> -> [<port>,...]` lines;
+/// anything else is ignored.
+pub fn parse_rustscan_output(output: &str, reporter: &Reporter) -> HashSet<u16> {
+    let mut ports = HashSet::new();
+
+    for line in output.lines() {
+        let trimmed_line = line.trim();
+        if trimmed_line.is_empty() {
+            continue;
+        }
+
+        // Attempt to parse as a plain port number first
+        if let Ok(port) = u16::from_str(trimmed_line) {
+            ports.insert(port);
+            continue;
+        }
+
+        // Attempt to parse the "<ip> -> [<port>,<port>]" format of -g
+        if let Some((_, list)) = trimmed_line.split_once(" -> [")
+            && let Some(list) = list.strip_suffix(']')
+        {
+            ports.extend(
+                list.split(',')
+                    .filter_map(|port| u16::from_str(port.trim()).ok()),
+            );
+            continue;
+        }
+
+        // Attempt to parse "Open <ip>:<port>" format
+        if trimmed_line.starts_with("Open ")
+            && let Some(ip_port_part) = trimmed_line.split_whitespace().nth(1)
+            && let Some(port_str) = ip_port_part.split(':').next_back()
+            && let Ok(port) = u16::from_str(port_str)
+        {
+            ports.insert(port);
+            reporter.detail(format!(
+                "Parsed port {} from rustscan line: '{}'",
+                port, trimmed_line
+            ));
+            continue;
+        }
+
+        // If verbose, log unparsed lines unless they are known informational messages
+        if reporter.is_verbose() {
+            let known_info_patterns = [
+                "File limit higher than batch size",
+                "Starting Script(s)",
+                "Running script",
+                "Depending on the complexity",
+                "Starting Nmap",
+                "Initiating Ping Scan",
+                "Scanning ", // Catches "Scanning 127.0.0.1..." and "Scanning localhost..."
+                "Completed Ping Scan",
+                "Initiating Connect Scan",
+                "Discovered open port ", // Catches "Discovered open port 22/tcp on 127.0.0.1"
+                "Completed Connect Scan",
+                "Nmap scan report for",
+                "Host is up",
+                "Scanned at",
+                "PORT ", // Catches "PORT STATE SERVICE REASON" header
+                "Read data files from",
+                "Nmap done",
+                // Lines that are valid Nmap output for open/closed/filtered ports but not just the number
+                "/tcp ",
+                "/udp ", // Catches lines like "22/tcp open ssh"
+            ];
+
+            let is_known_info = known_info_patterns
+                .iter()
+                .any(|pattern| trimmed_line.contains(pattern));
+
+            if !is_known_info {
+                reporter.warn(format!(
+                    "Warning: Could not parse rustscan output line as a direct port: '{}'",
+                    trimmed_line
+                ));
+            }
+        }
+    }
+
+    ports
+}
@@ -0,0 +1,162 @@
+use crate::{
+    Protocol, Reporter, parse_service_frequencies, parse_services_content, parse_services_map,
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Everything portpick takes from a services file: the TCP ports it forbids, what it calls
+/// them, and how often they are seen open.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ParsedServices {
+    pub ports: HashSet<u16>,
+    /// Names of the forbidden ports, in file order.
+    pub names: HashMap<u16, Vec<String>>,
+    /// Open frequencies, for Nmap-format files that carry them.
+    pub frequencies: HashMap<u16, f64>,
+}
+
+impl ParsedServices {
+    /// Parses services-file `content`, as [`parse_services_content`] does. Names are recorded
+    /// for the forbidden ports only.
+    pub fn parse(
+        content: &str,
+        source_description: &str,
+        reporter: &Reporter,
+        include_unknown: bool,
+    ) -> Result<Self> {
+        let ports = parse_services_content(content, source_description, reporter, include_unknown)?;
+        let mut names = parse_services_map(content, Protocol::Tcp, false)?;
+        names.retain(|port, _| ports.contains(port));
+        Ok(ParsedServices {
+            ports,
+            names,
+            frequencies: parse_service_frequencies(content, Protocol::Tcp),
+        })
+    }
+}
+
+/// The size and modification time of a file, which change whenever it is edited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub size: u64,
+    /// Nanoseconds since the Unix epoch, or 0 where the platform has no modification times.
+    pub modified: u128,
+}
+
+impl FileStamp {
+    pub fn of(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path)
+            .with_context(|| format!("Failed to read metadata of '{}'", path.display()))?;
+        Ok(FileStamp {
+            size: metadata.len(),
+            modified: metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|since| since.as_nanos())
+                .unwrap_or(0),
+        })
+    }
+}
+
+/// On-disk form of a cached parse.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    path: PathBuf,
+    include_unknown: bool,
+    stamp: FileStamp,
+    parsed: ParsedServices,
+}
+
+/// Parsed services files stored as one JSON file per services file in a state directory, so
+/// a large `/etc/services` (or any `--services-file`) is only parsed again after it changes.
+///
+/// An entry is reused while the file's [`FileStamp`] is the one recorded with it. Entries are
+/// written to a temporary file and renamed into place, so concurrent runs never see a torn
+/// one.
+#[derive(Debug, Clone)]
+pub struct ServicesCache {
+    dir: PathBuf,
+}
+
+impl ServicesCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        ServicesCache { dir: dir.into() }
+    }
+
+    fn entry_path(&self, path: &Path, include_unknown: bool) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+        include_unknown.hash(&mut hasher);
+        self.dir
+            .join(format!("services-{:016x}.json", hasher.finish()))
+    }
+
+    /// Returns the parse of `path` stored while the file looked as it does now.
+    ///
+    /// A changed or missing file, or an entry for another path (a hash collision), counts as
+    /// missing; an entry that can't be parsed is an error.
+    pub fn load(&self, path: &Path, include_unknown: bool) -> Result<Option<ParsedServices>> {
+        let entry_path = self.entry_path(path, include_unknown);
+        let content = match fs::read_to_string(&entry_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(anyhow::Error::new(e).context(format!(
+                    "Failed to read services cache '{}'",
+                    entry_path.display()
+                )));
+            }
+        };
+        let entry: CacheEntry = serde_json::from_str(&content).with_context(|| {
+            format!("Failed to parse services cache '{}'", entry_path.display())
+        })?;
+        let current = FileStamp::of(path).ok();
+        if entry.path != path
+            || entry.include_unknown != include_unknown
+            || current != Some(entry.stamp)
+        {
+            return Ok(None);
+        }
+        Ok(Some(entry.parsed))
+    }
+
+    /// Records `parsed` as the parse of `path` when it had `stamp`, taken before reading it so
+    /// an edit made meanwhile isn't covered up.
+    pub fn store(
+        &self,
+        path: &Path,
+        include_unknown: bool,
+        stamp: FileStamp,
+        parsed: &ParsedServices,
+    ) -> Result<()> {
+        fs::create_dir_all(&self.dir).with_context(|| {
+            format!(
+                "Failed to create services cache directory '{}'",
+                self.dir.display()
+            )
+        })?;
+        let entry = CacheEntry {
+            path: path.to_path_buf(),
+            include_unknown,
+            stamp,
+            parsed: parsed.clone(),
+        };
+        let entry_path = self.entry_path(path, include_unknown);
+        let temp_path = entry_path.with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&temp_path, serde_json::to_string(&entry)?)
+            .with_context(|| format!("Failed to write services cache '{}'", temp_path.display()))?;
+        fs::rename(&temp_path, &entry_path).with_context(|| {
+            format!(
+                "Failed to replace services cache '{}'",
+                entry_path.display()
+            )
+        })
+    }
+}
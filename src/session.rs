@@ -0,0 +1,29 @@
+use crate::{PortSet, parse_port_spec};
+use anyhow::Result;
+
+/// Carries the ports earlier runs in a shell session or Makefile picked, as a port spec, so
+/// later runs avoid them without the persistent ledger.
+pub const SESSION_FORBID_ENV: &str = "PORTPICK_SESSION_FORBID";
+
+/// The ports a [`SESSION_FORBID_ENV`] value lists. Blank means none, so a session can start
+/// with the variable exported empty.
+pub fn parse_session_forbid(value: &str) -> Result<PortSet> {
+    match value.trim() {
+        "" => Ok(PortSet::new()),
+        spec => Ok(parse_port_spec(spec)?.into()),
+    }
+}
+
+/// The value to export after a run picked `picked`: the session's ports so far with these
+/// added, as a port spec with runs coalesced, e.g. `8000-8002,9000`.
+pub fn session_forbid_value(session: &PortSet, picked: impl IntoIterator<Item = u16>) -> String {
+    let mut updated = session.clone();
+    updated.extend(picked);
+    updated.to_spec()
+}
+
+/// The line `--emit-session` prints, `export PORTPICK_SESSION_FORBID=<value>`, for a shell to
+/// `eval`. The format is stable across releases.
+pub fn session_export_line(value: &str) -> String {
+    format!("export {}={}", SESSION_FORBID_ENV, value)
+}
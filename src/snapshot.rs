@@ -0,0 +1,120 @@
+use crate::{PortSet, Provenance, RejectionReason};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// The forbidden ports of one run, saved by `portpick snapshot --save` to compare against later.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Seconds since the Unix epoch.
+    pub taken_at: u64,
+    /// Machine the snapshot was taken on.
+    pub host: String,
+    /// Address whose listening ports were scanned, if any were.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Providers that contributed ports (`system`, `nmap`, `local`).
+    pub sources: Vec<String>,
+    pub forbidden: PortSet,
+    /// Ports listed by a services source.
+    pub known_service: PortSet,
+    /// Ports found listening by the local scan.
+    pub locally_in_use: PortSet,
+}
+
+impl Snapshot {
+    pub fn new(
+        forbidden_ports: &HashSet<u16>,
+        provenance: &Provenance,
+        host: String,
+        address: Option<String>,
+        sources: Vec<String>,
+        taken_at: u64,
+    ) -> Self {
+        let with_reason = |reason: RejectionReason| -> PortSet {
+            forbidden_ports
+                .iter()
+                .copied()
+                .filter(|&port| provenance.reasons(port).contains(&reason))
+                .collect()
+        };
+        Snapshot {
+            taken_at,
+            host,
+            address,
+            sources,
+            forbidden: PortSet::from(forbidden_ports),
+            known_service: with_reason(RejectionReason::KnownService),
+            locally_in_use: with_reason(RejectionReason::LocallyInUse),
+        }
+    }
+
+    /// Why `port` was forbidden when the snapshot was taken; empty if it wasn't.
+    pub fn reasons(&self, port: u16) -> Vec<RejectionReason> {
+        let mut reasons = Vec::new();
+        if self.known_service.contains(port) {
+            reasons.push(RejectionReason::KnownService);
+        }
+        if self.locally_in_use.contains(port) {
+            reasons.push(RejectionReason::LocallyInUse);
+        }
+        reasons
+    }
+
+    /// What changed between this snapshot and `later`.
+    pub fn diff(&self, later: &Snapshot) -> SnapshotDiff {
+        let mut diff = SnapshotDiff::default();
+        for port in self.forbidden.union(&later.forbidden).iter() {
+            let change = PortChange {
+                port,
+                before: self.reasons(port),
+                after: later.reasons(port),
+            };
+            match (
+                self.forbidden.contains(port),
+                later.forbidden.contains(port),
+            ) {
+                (false, true) => diff.became_used.push(change),
+                (true, false) => diff.became_free.push(change),
+                _ if change.before != change.after => diff.changed.push(change),
+                _ => {}
+            }
+        }
+        diff
+    }
+}
+
+/// One port that differs between two snapshots.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortChange {
+    pub port: u16,
+    pub before: Vec<RejectionReason>,
+    pub after: Vec<RejectionReason>,
+}
+
+/// Ports whose state differs between an earlier and a later [`Snapshot`], each list ascending.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotDiff {
+    /// Free before, forbidden now.
+    pub became_used: Vec<PortChange>,
+    /// Forbidden before, free now.
+    pub became_free: Vec<PortChange>,
+    /// Forbidden both times, for different reasons.
+    pub changed: Vec<PortChange>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.became_used.is_empty() && self.became_free.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// This machine's host name, or "unknown" if it can't be determined.
+pub fn local_hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|name| name.trim().to_string())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
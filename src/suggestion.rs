@@ -0,0 +1,335 @@
+use crate::{GroupSummary, PhaseTiming, SourceBreakdown, Warning};
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// IANA port range a port belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum RangeCategory {
+    /// 0-1023, normally requires elevated privileges to bind.
+    System,
+    /// 1024-49151, assigned by IANA on request.
+    Registered,
+    /// 49152-65535, also known as the ephemeral or private range.
+    Dynamic,
+}
+
+impl RangeCategory {
+    pub fn of(port: u16) -> Self {
+        match port {
+            0..=1023 => RangeCategory::System,
+            1024..=49151 => RangeCategory::Registered,
+            _ => RangeCategory::Dynamic,
+        }
+    }
+
+    /// How the human output names the range.
+    pub fn label(self) -> &'static str {
+        match self {
+            RangeCategory::System => "system",
+            RangeCategory::Registered => "registered",
+            RangeCategory::Dynamic => "dynamic",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+/// Whether a suggested port was confirmed free by actually binding it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationStatus {
+    #[default]
+    Unverified,
+    Verified,
+    Failed,
+}
+
+/// Why a port was not eligible for suggestion.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RejectionReason {
+    /// Listed by a services source (system services file, nmap-services).
+    KnownService,
+    /// Reported as listening by the local port scan.
+    LocallyInUse,
+}
+
+/// A forbidden port directly adjacent to a suggestion, with the reasons it was skipped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RejectedNeighbor {
+    pub port: u16,
+    pub reasons: Vec<RejectionReason>,
+}
+
+/// A single suggested port together with the context it was chosen in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Suggestion {
+    pub port: u16,
+    pub name: Option<String>,
+    pub range: RangeCategory,
+    /// The `--within` range the port was found in, as a port spec such as `40000-40009`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub within: Option<String>,
+    pub protocol: Protocol,
+    pub verification: VerificationStatus,
+    pub rejected_neighbors: Vec<RejectedNeighbor>,
+    /// Backups for when the port turns out to be unusable, in order of preference. They don't
+    /// overlap each other or any suggestion.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternates: Vec<u16>,
+}
+
+impl Suggestion {
+    pub fn new(port: u16) -> Self {
+        Suggestion {
+            port,
+            name: None,
+            range: RangeCategory::of(port),
+            within: None,
+            protocol: Protocol::Tcp,
+            verification: VerificationStatus::Unverified,
+            rejected_neighbors: Vec::new(),
+            alternates: Vec::new(),
+        }
+    }
+
+    /// The note the human output dims after the port, e.g. `registered, within 40000-40009`.
+    pub fn annotation(&self) -> String {
+        match &self.within {
+            Some(within) => format!("{}, within {}", self.range.label(), within),
+            None => self.range.label().to_string(),
+        }
+    }
+}
+
+/// Records which source(s) caused each port to be forbidden.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Provenance {
+    reasons: HashMap<u16, Vec<RejectionReason>>,
+}
+
+impl Provenance {
+    pub fn record(&mut self, port: u16, reason: RejectionReason) {
+        let reasons = self.reasons.entry(port).or_default();
+        if !reasons.contains(&reason) {
+            reasons.push(reason);
+        }
+    }
+
+    pub fn record_all<'a>(
+        &mut self,
+        ports: impl IntoIterator<Item = &'a u16>,
+        reason: RejectionReason,
+    ) {
+        for &port in ports {
+            self.record(port, reason.clone());
+        }
+    }
+
+    pub fn reasons(&self, port: u16) -> &[RejectionReason] {
+        self.reasons.get(&port).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
+
+/// Collects the forbidden ports immediately below and above `port`.
+pub(crate) fn rejected_neighbors(
+    port: u16,
+    forbidden_ports: &HashSet<u16>,
+    provenance: &Provenance,
+) -> Vec<RejectedNeighbor> {
+    [port.checked_sub(1), port.checked_add(1)]
+        .into_iter()
+        .flatten()
+        .filter(|port| forbidden_ports.contains(port))
+        .map(|port| RejectedNeighbor {
+            port,
+            reasons: provenance.reasons(port).to_vec(),
+        })
+        .collect()
+}
+
+/// Order in which suggestions are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    /// Ascending port number
+    #[default]
+    Asc,
+    /// Descending port number
+    Desc,
+    /// Selection order, as chosen by the search
+    None,
+    /// Least often seen open first, per the Nmap services list; ties by ascending port
+    Frequency,
+}
+
+/// Orders suggestions by port. Names and other metadata travel with their port.
+///
+/// Without frequency data, [`SortOrder::Frequency`] sorts ascending; see
+/// [`sort_suggestions_with`].
+pub fn sort_suggestions(suggestions: &mut [Suggestion], order: SortOrder) {
+    sort_suggestions_with(suggestions, order, &HashMap::new());
+}
+
+/// Like [`sort_suggestions`], with the open frequencies [`SortOrder::Frequency`] sorts by.
+/// Ports missing from `frequencies` count as never seen open.
+pub fn sort_suggestions_with(
+    suggestions: &mut [Suggestion],
+    order: SortOrder,
+    frequencies: &HashMap<u16, f64>,
+) {
+    match order {
+        SortOrder::Asc => suggestions.sort_by_key(|s| s.port),
+        SortOrder::Desc => suggestions.sort_by_key(|s| std::cmp::Reverse(s.port)),
+        SortOrder::None => {}
+        SortOrder::Frequency => {
+            let frequency = |port: u16| frequencies.get(&port).copied().unwrap_or(0.0);
+            suggestions.sort_by(|a, b| {
+                frequency(a.port)
+                    .total_cmp(&frequency(b.port))
+                    .then(a.port.cmp(&b.port))
+            });
+        }
+    }
+}
+
+/// The layout of [`PickOutcome`] in machine-readable output, emitted as its
+/// `schema_version`.
+///
+/// Adding a field or an enum variant keeps the version; consumers are expected to ignore what
+/// they don't know. Renaming, removing, or retyping a field is a breaking change and gets a new
+/// version, which is only emitted when asked for with `--schema-version`, so existing consumers
+/// keep getting the layout they were written against.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Every `schema_version` this build can emit, oldest first.
+pub const SUPPORTED_SCHEMA_VERSIONS: &[u32] = &[SCHEMA_VERSION];
+
+/// Checks that `version` is one of [`SUPPORTED_SCHEMA_VERSIONS`].
+pub fn check_schema_version(version: u32) -> Result<u32> {
+    if !SUPPORTED_SCHEMA_VERSIONS.contains(&version) {
+        bail!(
+            "Unknown schema version {} (supported: {})",
+            version,
+            SUPPORTED_SCHEMA_VERSIONS
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(version)
+}
+
+fn default_schema_version() -> u32 {
+    SCHEMA_VERSION
+}
+
+/// The result of a pick run, as emitted by the CLI's machine-readable output formats. This is
+/// version 1 of the layout; see [`SCHEMA_VERSION`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PickOutcome {
+    /// Which layout this is. Documents from before versioning are version 1.
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
+    pub requested: u16,
+    pub continuous: bool,
+    pub suggestions: Vec<Suggestion>,
+    /// Set when Ctrl-C cut data collection short, so the suggestions are best-effort.
+    #[serde(default)]
+    pub interrupted: bool,
+    /// Conditions that may have made the suggestions less accurate, in the order they arose.
+    #[serde(default)]
+    pub warnings: Vec<Warning>,
+    /// The --spec groups, when ports were allocated per group. Suggestions carry their group's
+    /// name.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<GroupSummary>,
+    /// How many forbidden ports each source contributed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sources: Option<SourceBreakdown>,
+    /// How long each phase took, in the order they finished.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub timings: Vec<PhaseTiming>,
+}
+
+/// One line of `--output jsonl`: a suggestion, then a closing summary.
+///
+/// Every line is a complete JSON object tagged with `"type"`. The lines are written and flushed
+/// one by one, but only after the whole pick: sorting, naming, and alternates need every port.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonLine {
+    Suggestion {
+        /// Position in the reported order, starting at 0.
+        index: usize,
+        /// Whether the port was confirmed free by binding it.
+        verified: bool,
+        #[serde(flatten)]
+        suggestion: Suggestion,
+    },
+    Summary {
+        #[serde(default = "default_schema_version")]
+        schema_version: u32,
+        requested: u16,
+        continuous: bool,
+        found: usize,
+        interrupted: bool,
+        #[serde(default)]
+        warnings: Vec<Warning>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        groups: Vec<GroupSummary>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sources: Option<SourceBreakdown>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        timings: Vec<PhaseTiming>,
+    },
+}
+
+impl PickOutcome {
+    /// The JSON Lines records for this outcome: one per suggestion, then the summary.
+    pub fn json_lines(&self) -> Vec<JsonLine> {
+        let suggestions = self
+            .suggestions
+            .iter()
+            .enumerate()
+            .map(|(index, suggestion)| JsonLine::Suggestion {
+                index,
+                verified: suggestion.verification == VerificationStatus::Verified,
+                suggestion: suggestion.clone(),
+            });
+        let summary = JsonLine::Summary {
+            schema_version: self.schema_version,
+            requested: self.requested,
+            continuous: self.continuous,
+            found: self.suggestions.len(),
+            interrupted: self.interrupted,
+            warnings: self.warnings.clone(),
+            groups: self.groups.clone(),
+            sources: self.sources.clone(),
+            timings: self.timings.clone(),
+        };
+        suggestions.chain([summary]).collect()
+    }
+
+    /// The JSON Schema of [`PickOutcome`], as printed by `portpick schema`.
+    #[cfg(feature = "schema")]
+    pub fn json_schema() -> serde_json::Value {
+        schemars::schema_for!(PickOutcome).to_value()
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{PortSet, validate_ranges};
+use anyhow::Result;
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+
+/// Number of threads binding candidate ports at the same time.
+pub const VERIFY_THREADS: usize = 8;
+
+/// Whether a TCP listener can be bound on `address:port` right now. The listener is dropped
+/// immediately.
+pub fn bind_succeeds(address: IpAddr, port: u16) -> bool {
+    TcpListener::bind(SocketAddr::new(address, port)).is_ok()
+}
+
+/// Runs `probe` on every port concurrently, returning the results in the order of `ports`.
+pub fn probe_all<F>(ports: &[u16], probe: &F) -> Vec<bool>
+where
+    F: Fn(u16) -> bool + Sync,
+{
+    if ports.is_empty() {
+        return Vec::new();
+    }
+    let chunk_size = ports.len().div_ceil(VERIFY_THREADS);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = ports
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(|&port| probe(port)).collect()))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| -> Vec<bool> { worker.join().expect("probe thread panicked") })
+            .collect()
+    })
+}
+
+/// Takes candidates in order until `num_ports` of them pass `probe`.
+///
+/// Candidates are probed concurrently in batches, but the result keeps candidate order, so it
+/// is the same as checking them one by one.
+pub fn verify_candidates<I, F>(candidates: I, num_ports: usize, probe: F) -> Vec<u16>
+where
+    I: IntoIterator<Item = u16>,
+    F: Fn(u16) -> bool + Sync,
+{
+    let mut candidates = candidates.into_iter();
+    let mut verified = Vec::with_capacity(num_ports);
+    while verified.len() < num_ports {
+        // At least enough for the ports still missing, and enough to keep every thread busy.
+        let batch_size = (num_ports - verified.len()).max(VERIFY_THREADS);
+        let batch: Vec<u16> = candidates.by_ref().take(batch_size).collect();
+        if batch.is_empty() {
+            break;
+        }
+        let results = probe_all(&batch, &probe);
+        verified.extend(
+            batch
+                .iter()
+                .zip(results)
+                .filter(|(_, ok)| *ok)
+                .map(|(&port, _)| port)
+                .take(num_ports - verified.len()),
+        );
+    }
+    verified
+}
+
+/// Finds the first block of `num_ports` consecutive free ports within `ranges` whose members
+/// all pass `probe`.
+///
+/// Each block is probed in one concurrent pass. When a member fails, the search resumes just
+/// past the last failing port, since no block containing it can succeed.
+pub fn verify_block<F>(
+    forbidden_ports: &HashSet<u16>,
+    num_ports: u16,
+    ranges: &[(u16, u16)],
+    probe: F,
+) -> Option<Vec<u16>>
+where
+    F: Fn(u16) -> bool + Sync,
+{
+    if num_ports == 0 {
+        return Some(Vec::new());
+    }
+    let forbidden = PortSet::from(forbidden_ports);
+    for run in forbidden.iter_free_within(ranges) {
+        let (mut start, end) = (u32::from(*run.start()), u32::from(*run.end()));
+        while start + u32::from(num_ports) - 1 <= end {
+            let block: Vec<u16> = (start..start + u32::from(num_ports))
+                .map(|port| port as u16)
+                .collect();
+            let results = probe_all(&block, &probe);
+            match block.iter().zip(&results).rev().find(|(_, ok)| !**ok) {
+                Some((&failed, _)) => start = u32::from(failed) + 1,
+                None => return Some(block),
+            }
+        }
+    }
+    None
+}
+
+/// Like [`crate::find_available_ports_in`], but only returns ports that could actually be bound
+/// on `address`.
+pub fn verified_ports_in(
+    forbidden_ports: &HashSet<u16>,
+    num_ports: u16,
+    continuous: bool,
+    ranges: &[(u16, u16)],
+    address: IpAddr,
+) -> Result<Vec<u16>> {
+    validate_ranges(ranges)?;
+    let probe = |port| bind_succeeds(address, port);
+    if continuous {
+        return Ok(verify_block(forbidden_ports, num_ports, ranges, probe).unwrap_or_default());
+    }
+    let forbidden = PortSet::from(forbidden_ports);
+    let candidates = forbidden.iter_free_within(ranges).flatten();
+    Ok(verify_candidates(candidates, usize::from(num_ports), probe))
+}
@@ -0,0 +1,71 @@
+use crate::{Reporter, parse_services_content};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// What this binary was built from, as recorded by the build script.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub version: String,
+    /// Full git commit hash, or "unknown" when built outside a git checkout.
+    pub commit: String,
+    /// UTC build date as `YYYY-MM-DD`.
+    pub build_date: String,
+    pub features: Vec<String>,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            commit: env!("PORTPICK_GIT_COMMIT").to_string(),
+            build_date: env!("PORTPICK_BUILD_DATE").to_string(),
+            features: env!("PORTPICK_FEATURES")
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .map(String::from)
+                .collect(),
+        }
+    }
+
+    /// The first 7 characters of the commit, as git abbreviates it.
+    pub fn short_commit(&self) -> &str {
+        self.commit.get(..7).unwrap_or(&self.commit)
+    }
+}
+
+/// State of a cached services list, for bug reports.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheInfo {
+    pub path: String,
+    pub present: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub age_secs: Option<u64>,
+    /// TCP ports the cache lists, or `None` if it is missing or can't be parsed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ports: Option<usize>,
+}
+
+impl CacheInfo {
+    /// Looks at the cache at `path` without changing it.
+    pub fn inspect(path: &Path, now: SystemTime) -> Self {
+        let content = fs::read_to_string(path).ok();
+        let age_secs = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .map(|age| age.as_secs());
+        let ports = content.as_deref().and_then(|content| {
+            parse_services_content(content, "services cache", &Reporter::silent(), false)
+                .ok()
+                .map(|ports| ports.len())
+        });
+        CacheInfo {
+            path: path.display().to_string(),
+            present: content.is_some(),
+            age_secs: age_secs.filter(|_| content.is_some()),
+            ports,
+        }
+    }
+}
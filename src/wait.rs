@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+/// Source of time for [`wait_for_ports`], so tests can run the loop without sleeping.
+pub trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+/// The real clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// How many of the awaited ports have to be ready.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WaitQuorum {
+    #[default]
+    All,
+    Any,
+}
+
+/// How a wait ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WaitOutcome {
+    /// Whether the quorum was reached before the timeout.
+    pub ready: bool,
+    /// Ports that were ready on the last poll.
+    pub ready_ports: Vec<u16>,
+    /// Ports that were not.
+    pub pending: Vec<u16>,
+    pub polls: u32,
+    pub elapsed: Duration,
+}
+
+/// Polls `ports` every `interval` until `quorum` of them are ready or `timeout` elapses.
+///
+/// `probe` gets the ports still worth asking about and returns the ready ones, so a scanner
+/// can answer a whole poll in one pass. `on_poll` runs after every poll that wasn't enough.
+/// The last poll happens at the timeout at the latest; without a timeout the wait is endless.
+pub fn wait_for_ports<P, F>(
+    ports: &[u16],
+    quorum: WaitQuorum,
+    timeout: Option<Duration>,
+    interval: Duration,
+    clock: &dyn Clock,
+    mut probe: P,
+    mut on_poll: F,
+) -> WaitOutcome
+where
+    P: FnMut(&[u16]) -> HashSet<u16>,
+    F: FnMut(&WaitOutcome),
+{
+    let start = clock.now();
+    let mut polls = 0;
+    loop {
+        let ready = probe(ports);
+        polls += 1;
+        let (ready_ports, pending): (Vec<u16>, Vec<u16>) =
+            ports.iter().partition(|port| ready.contains(port));
+        let elapsed = clock.now().saturating_duration_since(start);
+        let done = match quorum {
+            WaitQuorum::All => pending.is_empty(),
+            WaitQuorum::Any => !ready_ports.is_empty(),
+        };
+        let outcome = WaitOutcome {
+            ready: done,
+            ready_ports,
+            pending,
+            polls,
+            elapsed,
+        };
+        let remaining = timeout.map(|timeout| timeout.saturating_sub(elapsed));
+        if done || remaining == Some(Duration::ZERO) {
+            return outcome;
+        }
+        on_poll(&outcome);
+        clock.sleep(remaining.map_or(interval, |remaining| remaining.min(interval)));
+    }
+}
+
+/// Whether something accepts a TCP connection on `address:port` within `timeout`.
+pub fn connect_succeeds(address: IpAddr, port: u16, timeout: Duration) -> bool {
+    TcpStream::connect_timeout(&SocketAddr::new(address, port), timeout).is_ok()
+}
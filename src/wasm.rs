@@ -0,0 +1,113 @@
+//! JavaScript bindings for the browser, built with the `wasm` feature and without `native`:
+//!
+//! ```sh
+//! wasm-pack build --target web --no-default-features --features wasm
+//! ```
+//!
+//! ```js
+//! const forbidden = new PortSet(parseServices(servicesText, false));
+//! findAvailable(forbidden.ports(), 3, true, "8000-8100");
+//! ```
+
+use crate::{
+    DEFAULT_PORT_RANGES, PortSet, Reporter, find_available_ports_in, parse_port_spec,
+    parse_services_content,
+};
+use wasm_bindgen::prelude::*;
+
+fn js_error(error: anyhow::Error) -> JsError {
+    JsError::new(&format!("{error:#}"))
+}
+
+/// The TCP ports named in services-file text (`/etc/services` or Nmap format), ascending.
+/// Entries named `unknown` are skipped unless `include_unknown` is set.
+#[wasm_bindgen(js_name = parseServices)]
+pub fn parse_services(content: &str, include_unknown: bool) -> Result<Vec<u16>, JsError> {
+    let ports = parse_services_content(
+        content,
+        "services text",
+        &Reporter::silent(),
+        include_unknown,
+    )
+    .map_err(js_error)?;
+    Ok(PortSet::from(ports).iter().collect())
+}
+
+/// `count` free ports avoiding `forbidden`, ascending, searched in `within` (a `--within` style
+/// spec such as `"8000-8100,9000"`) or the default ranges. Fewer if there aren't enough.
+#[wasm_bindgen(js_name = findAvailable)]
+pub fn find_available(
+    forbidden: &[u16],
+    count: u16,
+    continuous: bool,
+    within: Option<String>,
+) -> Result<Vec<u16>, JsError> {
+    let ranges = match within {
+        Some(spec) => parse_port_spec(&spec).map_err(js_error)?,
+        None => DEFAULT_PORT_RANGES.to_vec(),
+    };
+    let forbidden = forbidden.iter().copied().collect();
+    find_available_ports_in(&forbidden, count, continuous, &ranges).map_err(js_error)
+}
+
+/// [`PortSet`] for JavaScript, where it is called `PortSet`.
+#[wasm_bindgen(js_name = PortSet)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JsPortSet(PortSet);
+
+#[wasm_bindgen(js_class = PortSet)]
+impl JsPortSet {
+    #[wasm_bindgen(constructor)]
+    pub fn new(ports: &[u16]) -> JsPortSet {
+        JsPortSet(ports.iter().copied().collect())
+    }
+
+    /// The ports a `--within` style spec covers.
+    #[wasm_bindgen(js_name = fromSpec)]
+    pub fn from_spec(spec: &str) -> Result<JsPortSet, JsError> {
+        Ok(JsPortSet(PortSet::from(
+            parse_port_spec(spec).map_err(js_error)?,
+        )))
+    }
+
+    pub fn union(&self, other: &JsPortSet) -> JsPortSet {
+        JsPortSet(self.0.union(&other.0))
+    }
+
+    pub fn intersection(&self, other: &JsPortSet) -> JsPortSet {
+        JsPortSet(self.0.intersection(&other.0))
+    }
+
+    pub fn difference(&self, other: &JsPortSet) -> JsPortSet {
+        JsPortSet(self.0.difference(&other.0))
+    }
+
+    /// The ports from `start` to `end` that aren't in the set.
+    #[wasm_bindgen(js_name = complementWithin)]
+    pub fn complement_within(&self, start: u16, end: u16) -> JsPortSet {
+        JsPortSet(self.0.complement_within(start..=end))
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        self.0.contains(port)
+    }
+
+    /// Number of ports, like a JavaScript `Set`.
+    #[wasm_bindgen(getter)]
+    pub fn size(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Every port, ascending.
+    pub fn ports(&self) -> Vec<u16> {
+        self.0.iter().collect()
+    }
+
+    /// The coalesced ranges, flattened to `[start, end, start, end, ...]`.
+    pub fn ranges(&self) -> Vec<u16> {
+        self.0
+            .ranges()
+            .flat_map(|range| [*range.start(), *range.end()])
+            .collect()
+    }
+}
@@ -0,0 +1,67 @@
+use portpick::{AddressScope, classify_address, resolve_host};
+use std::net::IpAddr;
+
+fn scope(address: &str) -> AddressScope {
+    classify_address(address.parse::<IpAddr>().unwrap())
+}
+
+#[test]
+fn test_classify_address_v4() {
+    for loopback in ["127.0.0.1", "127.8.9.10", "0.0.0.0"] {
+        assert_eq!(scope(loopback), AddressScope::Loopback, "{loopback}");
+    }
+    for private in [
+        "10.1.2.3",
+        "172.16.0.1",
+        "172.31.255.255",
+        "192.168.1.10",
+        "169.254.0.5",
+        "100.64.0.1",
+        "100.127.255.254",
+    ] {
+        assert_eq!(scope(private), AddressScope::Private, "{private}");
+    }
+    for public in ["8.8.8.8", "172.32.0.1", "100.128.0.1", "192.169.0.1"] {
+        assert_eq!(scope(public), AddressScope::Public, "{public}");
+    }
+}
+
+#[test]
+fn test_classify_address_v6() {
+    assert_eq!(scope("::1"), AddressScope::Loopback);
+    assert_eq!(scope("::"), AddressScope::Loopback);
+    assert_eq!(scope("fd12:3456::1"), AddressScope::Private);
+    assert_eq!(scope("fe80::1"), AddressScope::Private);
+    assert_eq!(scope("2001:4860:4860::8888"), AddressScope::Public);
+    // IPv4-mapped addresses are judged by the IPv4 address.
+    assert_eq!(scope("::ffff:127.0.0.1"), AddressScope::Loopback);
+    assert_eq!(scope("::ffff:192.168.0.1"), AddressScope::Private);
+}
+
+#[test]
+fn test_resolve_host_takes_literals_as_is() {
+    let resolved = resolve_host("192.168.1.7").unwrap();
+    assert_eq!(
+        resolved.addresses,
+        vec!["192.168.1.7".parse::<IpAddr>().unwrap()]
+    );
+    assert_eq!(resolved.scope(), AddressScope::Private);
+    let resolved = resolve_host("[::1]").unwrap();
+    assert_eq!(resolved.primary(), "::1".parse::<IpAddr>().unwrap());
+}
+
+#[test]
+fn test_resolve_host_prefers_ipv4_for_localhost() {
+    let resolved = resolve_host("localhost").unwrap();
+    assert_eq!(resolved.scope(), AddressScope::Loopback);
+    assert!(resolved.primary().is_ipv4() || resolved.addresses.iter().all(IpAddr::is_ipv6));
+}
+
+#[test]
+fn test_resolve_host_names_the_failing_host() {
+    let error = resolve_host("nonexistent.invalid").unwrap_err();
+    assert!(
+        format!("{error:#}").contains("'nonexistent.invalid'"),
+        "{error:#}"
+    );
+}
@@ -0,0 +1,101 @@
+use portpick::{BatchRequest, BatchResult, resolve_batch};
+use std::collections::HashSet;
+
+#[test]
+fn test_parse_line_mini_syntax_and_json() {
+    assert_eq!(
+        BatchRequest::parse_line("3c within=9000-9999 name=cluster").unwrap(),
+        Some(BatchRequest {
+            count: 3,
+            continuous: true,
+            within: Some("9000-9999".to_string()),
+            name: Some("cluster".to_string()),
+            ..BatchRequest::default()
+        })
+    );
+    assert_eq!(
+        BatchRequest::parse_line("name=web near=8080").unwrap(),
+        Some(BatchRequest {
+            near: Some(8080),
+            name: Some("web".to_string()),
+            ..BatchRequest::default()
+        })
+    );
+    assert_eq!(
+        BatchRequest::parse_line(r#"{"count": 2, "within": "9000-9099"}"#).unwrap(),
+        BatchRequest::parse_line("2 within=9000-9099").unwrap()
+    );
+    assert_eq!(BatchRequest::parse_line("   ").unwrap(), None);
+    assert_eq!(
+        BatchRequest::parse_line("# reserved for later").unwrap(),
+        None
+    );
+}
+
+#[test]
+fn test_parse_line_rejects_malformed_requests() {
+    for line in [
+        "x3",
+        "3 4",
+        "0",
+        "2 colour=red",
+        "2 within=9000-",
+        "3c within=9000-9001",
+        r#"{"count": 2, "colour": "red"}"#,
+        "{not json",
+    ] {
+        assert!(BatchRequest::parse_line(line).is_err(), "{}", line);
+    }
+}
+
+#[test]
+fn test_resolve_batch_never_reuses_ports() {
+    let lines = [
+        "3c within=9000-9009 name=cluster",
+        "2 within=9000-9009",
+        "nonsense",
+        "2c within=9000-9009",
+        "4 within=9000-9009",
+    ];
+    let requests: Vec<(usize, Result<BatchRequest, String>)> = lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| {
+            let request = BatchRequest::parse_line(line)
+                .map(Option::unwrap)
+                .map_err(|e| e.to_string());
+            (index + 1, request)
+        })
+        .collect();
+    let forbidden: HashSet<u16> = [9001].into();
+    let results = resolve_batch(&requests, &forbidden, &[(1024, 65535)]);
+
+    let ports = |line: usize| results[line - 1].ports.clone();
+    assert_eq!(ports(1), vec![9002, 9003, 9004]);
+    assert_eq!(ports(2), vec![9000, 9005]);
+    assert!(results[2].error.as_deref().unwrap().contains("nonsense"));
+    assert_eq!(ports(4), vec![9006, 9007]);
+    // Only 9008 and 9009 are left.
+    assert_eq!(
+        results[4],
+        BatchResult::failed(5, None, "Could not find 4 port(s)")
+    );
+
+    let mut seen = forbidden.clone();
+    for result in &results {
+        for &port in &result.ports {
+            assert!(seen.insert(port), "port {} given twice", port);
+        }
+    }
+}
+
+#[test]
+fn test_resolve_batch_uses_default_ranges() {
+    let requests = vec![
+        (1, Ok(BatchRequest::default())),
+        (2, Ok(BatchRequest::parse_line("2c").unwrap().unwrap())),
+    ];
+    let results = resolve_batch(&requests, &HashSet::new(), &[(40000, 40002)]);
+    assert_eq!(results[0].ports, vec![40000]);
+    assert_eq!(results[1].ports, vec![40001, 40002]);
+}
@@ -0,0 +1,67 @@
+use portpick::{
+    BlockReason, ForbiddenContext, PortSet, PortStatus, Provenance, RejectionReason, check_port,
+};
+
+fn context() -> ForbiddenContext {
+    let mut provenance = Provenance::default();
+    provenance.record(8080, RejectionReason::KnownService);
+    provenance.record(8080, RejectionReason::LocallyInUse);
+    provenance.record(9000, RejectionReason::LocallyInUse);
+    let mut ctx = ForbiddenContext {
+        provenance,
+        ..ForbiddenContext::new(&[(1024, 49151)])
+    };
+    ctx.service_names
+        .insert(8080, vec!["http-alt".to_string(), "webcache".to_string()]);
+    ctx.excluded = [8080, 8081].into_iter().collect::<PortSet>();
+    ctx
+}
+
+#[test]
+fn test_check_port_free() {
+    assert_eq!(check_port(8082, &context()), PortStatus::Free);
+    assert!(check_port(8082, &context()).is_free());
+}
+
+#[test]
+fn test_check_port_reports_every_simultaneous_reason() {
+    assert_eq!(
+        check_port(8080, &context()),
+        PortStatus::Blocked(vec![
+            BlockReason::KnownService {
+                names: vec!["http-alt".to_string(), "webcache".to_string()],
+            },
+            BlockReason::LocallyInUse,
+            BlockReason::Excluded,
+        ])
+    );
+    assert_eq!(
+        check_port(9000, &context()),
+        PortStatus::Blocked(vec![BlockReason::LocallyInUse])
+    );
+}
+
+#[test]
+fn test_check_port_outside_searchable_ranges() {
+    assert_eq!(
+        check_port(80, &context()),
+        PortStatus::Blocked(vec![BlockReason::OutOfRange])
+    );
+    assert_eq!(
+        check_port(49152, &context()),
+        PortStatus::Blocked(vec![BlockReason::OutOfRange])
+    );
+    assert_eq!(
+        check_port(49152, &ForbiddenContext::default()),
+        PortStatus::Free
+    );
+}
+
+#[test]
+fn test_port_status_json_shape() {
+    let status = check_port(8080, &context());
+    let json = serde_json::to_value(&status).unwrap();
+    assert_eq!(json["blocked"][0]["kind"], "known_service");
+    assert_eq!(json["blocked"][1]["kind"], "locally_in_use");
+    assert_eq!(serde_json::to_value(PortStatus::Free).unwrap(), "free");
+}
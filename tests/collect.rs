@@ -0,0 +1,690 @@
+use anyhow::{Result, bail};
+use indicatif::ProgressBar;
+use portpick::{
+    BUILTIN_SERVICE_PORTS, BuiltinServicesSource, CollectOptions, CommandOutput, CommandRunner,
+    DEVTOOLS_PORTS, ForbiddenContext, PortScanner, PortSource, ProfileSource, RejectionReason,
+    Reporter, RustScanScanner, ScanTarget, SourceBreakdown, SourceChain, SourceCount,
+    SystemCommandRunner, TooFewKnownPorts, client_builder, collect_forbidden, download_text,
+    parse_services_content,
+};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A source answering with a fixed set of ports, optionally naming them.
+struct Fixed {
+    name: &'static str,
+    reason: RejectionReason,
+    ports: Vec<u16>,
+    named: bool,
+}
+
+impl Fixed {
+    fn services(name: &'static str, ports: &[u16]) -> Self {
+        Fixed {
+            name,
+            reason: RejectionReason::KnownService,
+            ports: ports.to_vec(),
+            named: true,
+        }
+    }
+
+    fn scan(ports: &[u16]) -> Self {
+        Fixed {
+            name: "local",
+            reason: RejectionReason::LocallyInUse,
+            ports: ports.to_vec(),
+            named: false,
+        }
+    }
+}
+
+impl PortSource for Fixed {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn rejection_reason(&self) -> RejectionReason {
+        self.reason.clone()
+    }
+
+    fn collect(&mut self, names: &mut HashMap<u16, Vec<String>>) -> Result<HashSet<u16>> {
+        if self.named {
+            for &port in &self.ports {
+                names
+                    .entry(port)
+                    .or_default()
+                    .push(format!("{}-{}", self.name, port));
+            }
+        }
+        Ok(self.ports.iter().copied().collect())
+    }
+
+    fn lists_services(&self) -> bool {
+        self.named
+    }
+}
+
+/// A services list that can't be read.
+struct Unavailable(&'static str);
+
+impl PortSource for Unavailable {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn rejection_reason(&self) -> RejectionReason {
+        RejectionReason::KnownService
+    }
+
+    fn collect(&mut self, _names: &mut HashMap<u16, Vec<String>>) -> Result<HashSet<u16>> {
+        bail!("{} is unreachable", self.0)
+    }
+
+    fn lists_services(&self) -> bool {
+        true
+    }
+}
+
+/// A source that always fails.
+struct Failing;
+
+impl PortSource for Failing {
+    fn name(&self) -> &str {
+        "broken"
+    }
+
+    fn rejection_reason(&self) -> RejectionReason {
+        RejectionReason::LocallyInUse
+    }
+
+    fn collect(&mut self, _names: &mut HashMap<u16, Vec<String>>) -> Result<HashSet<u16>> {
+        bail!("scanner exploded")
+    }
+}
+
+/// A services list that counts how often it is read.
+struct Counted(Fixed, Arc<Mutex<usize>>);
+
+impl PortSource for Counted {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn rejection_reason(&self) -> RejectionReason {
+        self.0.rejection_reason()
+    }
+
+    fn collect(&mut self, names: &mut HashMap<u16, Vec<String>>) -> Result<HashSet<u16>> {
+        *self.1.lock().unwrap() += 1;
+        self.0.collect(names)
+    }
+
+    fn lists_services(&self) -> bool {
+        true
+    }
+}
+
+fn count(name: &str, ports: usize) -> SourceCount {
+    SourceCount {
+        name: name.to_string(),
+        ports,
+        passed_over: Vec::new(),
+    }
+}
+
+#[test]
+fn test_counts_per_source_and_overlap() {
+    let mut ctx = ForbiddenContext::default();
+    let (forbidden, breakdown) = collect_forbidden(
+        CollectOptions {
+            sources: vec![
+                Box::new(Fixed::services("system", &[22, 80, 443])),
+                Box::new(Fixed::services("nmap", &[80, 443, 8080, 9000])),
+                Box::new(Fixed::scan(&[443, 3000])),
+            ],
+            ..CollectOptions::default()
+        },
+        &mut ctx,
+        &Reporter::silent(),
+    )
+    .unwrap();
+    assert_eq!(
+        forbidden.iter().collect::<Vec<_>>(),
+        vec![22, 80, 443, 3000, 8080, 9000]
+    );
+    assert_eq!(
+        breakdown,
+        SourceBreakdown {
+            sources: vec![count("system", 3), count("nmap", 4), count("local", 2)],
+            // 80 twice, 443 three times: 9 contributions for 6 ports.
+            overlap: 3,
+            total: 6,
+            shared: breakdown.shared.clone(),
+            ..SourceBreakdown::default()
+        }
+    );
+    assert_eq!(
+        breakdown.summary(),
+        "system 3, nmap 4, local 2; overlap 3; total 6"
+    );
+    let shared: Vec<String> = breakdown.shared.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        shared,
+        [
+            "system and nmap share 2 ports",
+            "system and local share 1 port",
+            "nmap and local share 1 port"
+        ]
+    );
+    assert_eq!(
+        ctx.provenance.reasons(443),
+        [RejectionReason::KnownService, RejectionReason::LocallyInUse]
+    );
+    assert_eq!(ctx.service_names[&80], vec!["system-80", "nmap-80"]);
+    assert!(!ctx.service_names.contains_key(&3000));
+}
+
+#[test]
+fn test_avoid_family_counts_as_a_source() {
+    let mut ctx = ForbiddenContext::default();
+    let (forbidden, breakdown) = collect_forbidden(
+        CollectOptions {
+            sources: vec![
+                Box::new(Fixed::services("system", &[5432])),
+                Box::new(Fixed::scan(&[5433, 6000])),
+            ],
+            avoid_family: 2,
+            ..CollectOptions::default()
+        },
+        &mut ctx,
+        &Reporter::silent(),
+    )
+    .unwrap();
+    assert_eq!(forbidden.len(), 6);
+    assert_eq!(
+        breakdown.sources,
+        vec![
+            count("system", 1),
+            count("local", 2),
+            count("avoid-family", 5)
+        ]
+    );
+    // 5432 and 5433 are also in the family.
+    assert_eq!(breakdown.overlap, 2);
+    assert_eq!(
+        ctx.provenance.reasons(5430),
+        [RejectionReason::KnownService]
+    );
+}
+
+#[test]
+fn test_no_sources() {
+    let mut ctx = ForbiddenContext::default();
+    let (forbidden, breakdown) =
+        collect_forbidden(CollectOptions::default(), &mut ctx, &Reporter::silent()).unwrap();
+    assert!(forbidden.is_empty());
+    assert_eq!(breakdown, SourceBreakdown::default());
+    assert_eq!(breakdown.summary(), "no sources; overlap 0; total 0");
+}
+
+#[test]
+fn test_source_error_is_returned() {
+    let mut ctx = ForbiddenContext::default();
+    let err = collect_forbidden(
+        CollectOptions {
+            sources: vec![
+                Box::new(Fixed::services("system", &[22])),
+                Box::new(Failing),
+            ],
+            ..CollectOptions::default()
+        },
+        &mut ctx,
+        &Reporter::silent(),
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "scanner exploded");
+}
+
+/// Collects as portpick does by default: the Nmap download and the system file, then the
+/// cache and the built-in list as fallbacks. `up` says which of the first three work; a
+/// working one that is `empty` finds nothing. Returns the sources that contributed, and the
+/// warnings.
+fn run_chain(up: [bool; 3], empty: bool) -> (Vec<String>, String) {
+    let list = |name: &'static str, ok: bool, ports: &[u16]| -> Box<dyn PortSource> {
+        match (ok, empty) {
+            (false, _) => Box::new(Unavailable(name)),
+            (true, true) => Box::new(Fixed::services(name, &[])),
+            (true, false) => Box::new(Fixed::services(name, ports)),
+        }
+    };
+    let out = Arc::new(Mutex::new(Vec::new()));
+    let reporter = Reporter::with_writer(false, Box::new(SharedWriter(Arc::clone(&out))));
+    let mut ctx = ForbiddenContext::default();
+    let (forbidden, breakdown) = collect_forbidden(
+        CollectOptions {
+            sources: vec![
+                list("nmap", up[0], &[80, 8080]),
+                list("system", up[1], &[22]),
+                Box::new(Fixed::scan(&[3000])),
+            ],
+            fallbacks: vec![
+                list("cache", up[2], &[80, 8080, 9000]),
+                Box::new(BuiltinServicesSource),
+            ],
+            min_known_ports: 1,
+            ..CollectOptions::default()
+        },
+        &mut ctx,
+        &reporter,
+    )
+    .unwrap();
+    assert!(forbidden.contains(3000));
+    let sources = breakdown
+        .sources
+        .into_iter()
+        .filter(|source| source.ports > 0)
+        .map(|source| source.name)
+        .collect();
+    let warnings = String::from_utf8(out.lock().unwrap().clone()).unwrap();
+    (sources, warnings)
+}
+
+#[test]
+fn test_fallback_chain_knocks_out_sources_one_at_a_time() {
+    let (sources, warnings) = run_chain([true, true, true], false);
+    assert_eq!(sources, ["nmap", "system", "local"]);
+    assert_eq!(warnings, "");
+
+    // The system file alone is enough.
+    let (sources, warnings) = run_chain([false, true, true], false);
+    assert_eq!(sources, ["system", "local"]);
+    assert_eq!(
+        warnings,
+        "Warning: Provider 'nmap' failed: nmap is unreachable\n"
+    );
+
+    let (sources, warnings) = run_chain([false, false, true], false);
+    assert_eq!(sources, ["local", "cache"]);
+    assert!(
+        warnings.contains("Warning: Provider 'system' failed: system is unreachable\n"),
+        "{}",
+        warnings
+    );
+    assert!(
+        warnings.contains(
+            "Warning: The services lists gave 0 known-service port(s); falling back to 'cache'.\n"
+        ),
+        "{}",
+        warnings
+    );
+    assert!(!warnings.contains("'builtin'"), "{}", warnings);
+
+    let (sources, warnings) = run_chain([false, false, false], false);
+    assert_eq!(sources, ["local", "builtin"]);
+    assert!(
+        warnings.contains("Warning: Provider 'cache' failed: cache is unreachable\n"),
+        "{}",
+        warnings
+    );
+    assert!(
+        warnings.contains("falling back to 'builtin'"),
+        "{}",
+        warnings
+    );
+
+    // Lists that work but find nothing fall through the same way.
+    let (sources, _) = run_chain([true, true, true], true);
+    assert_eq!(sources, ["local", "builtin"]);
+}
+
+#[test]
+fn test_source_chain_advances_in_declared_order() {
+    let out = Arc::new(Mutex::new(Vec::new()));
+    let reporter = Arc::new(Reporter::with_writer(
+        false,
+        Box::new(SharedWriter(Arc::clone(&out))),
+    ));
+    let reads = Arc::new(Mutex::new(0));
+    let chain = SourceChain::new(
+        vec![
+            Box::new(Unavailable("cache")),
+            Box::new(Fixed::services("nmap", &[])),
+            Box::new(Fixed::services("system", &[22, 80])),
+            Box::new(Counted(
+                Fixed::services("builtin", &[443]),
+                Arc::clone(&reads),
+            )),
+        ],
+        Arc::clone(&reporter),
+    );
+    assert_eq!(chain.order(), ["cache", "nmap", "system", "builtin"]);
+    assert_eq!(chain.name(), "source-order");
+
+    let mut ctx = ForbiddenContext::default();
+    let (forbidden, breakdown) = collect_forbidden(
+        CollectOptions {
+            sources: vec![Box::new(chain), Box::new(Fixed::scan(&[3000]))],
+            min_known_ports: 1,
+            ..CollectOptions::default()
+        },
+        &mut ctx,
+        &reporter,
+    )
+    .unwrap();
+    assert_eq!(forbidden.iter().collect::<Vec<_>>(), vec![22, 80, 3000]);
+    assert_eq!(
+        breakdown.sources,
+        vec![
+            SourceCount {
+                passed_over: vec!["cache".to_string(), "nmap".to_string()],
+                ..count("system", 2)
+            },
+            count("local", 1)
+        ]
+    );
+    assert_eq!(
+        breakdown.summary(),
+        "system 2 (after cache, nmap), local 1; overlap 0; total 3"
+    );
+    assert_eq!(ctx.service_names[&22], vec!["system-22"]);
+    // Nothing after the supplier runs.
+    assert_eq!(*reads.lock().unwrap(), 0);
+    assert_eq!(
+        String::from_utf8(out.lock().unwrap().clone()).unwrap(),
+        "Warning: Source 'cache' failed: cache is unreachable; trying 'nmap'.\n\
+         Warning: Source 'nmap' found no ports; trying 'system'.\n"
+    );
+}
+
+#[test]
+fn test_source_chain_with_nothing_to_give_is_a_services_problem() {
+    let mut ctx = ForbiddenContext::default();
+    let err = collect_forbidden(
+        CollectOptions {
+            sources: vec![Box::new(SourceChain::new(
+                vec![
+                    Box::new(Unavailable("nmap")),
+                    Box::new(Fixed::services("system", &[])),
+                ],
+                Arc::new(Reporter::silent()),
+            ))],
+            min_known_ports: 1,
+            ..CollectOptions::default()
+        },
+        &mut ctx,
+        &Reporter::silent(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<TooFewKnownPorts>().unwrap().problems,
+        [
+            "'source-order' failed: No source in the order nmap, system gave any ports ('nmap' failed: nmap is unreachable; 'system' found no ports)"
+        ]
+    );
+
+    // The built-in fallback still applies.
+    let (forbidden, breakdown) = collect_forbidden(
+        CollectOptions {
+            sources: vec![Box::new(SourceChain::new(
+                vec![Box::new(Unavailable("cache"))],
+                Arc::new(Reporter::silent()),
+            ))],
+            fallbacks: vec![Box::new(BuiltinServicesSource)],
+            min_known_ports: 1,
+            ..CollectOptions::default()
+        },
+        &mut ctx,
+        &Reporter::silent(),
+    )
+    .unwrap();
+    assert_eq!(forbidden.len(), BUILTIN_SERVICE_PORTS.len());
+    assert_eq!(
+        breakdown.sources,
+        vec![count("builtin", BUILTIN_SERVICE_PORTS.len())]
+    );
+}
+
+#[test]
+fn test_too_few_known_ports_names_each_source() {
+    let mut ctx = ForbiddenContext::default();
+    let err = collect_forbidden(
+        CollectOptions {
+            sources: vec![
+                Box::new(Unavailable("nmap")),
+                Box::new(Fixed::services("system", &[])),
+                Box::new(Fixed::scan(&[3000])),
+            ],
+            fallbacks: vec![Box::new(Unavailable("cache"))],
+            min_known_ports: 1,
+            ..CollectOptions::default()
+        },
+        &mut ctx,
+        &Reporter::silent(),
+    )
+    .unwrap_err();
+    let shortfall = err.downcast_ref::<TooFewKnownPorts>().unwrap();
+    assert_eq!(
+        shortfall.problems,
+        [
+            "'nmap' failed: nmap is unreachable",
+            "'system' found no ports",
+            "'cache' failed: cache is unreachable"
+        ]
+    );
+    assert_eq!(
+        err.to_string(),
+        "The services lists gave 0 known-service port(s), fewer than the 1 required ('nmap' failed: nmap is unreachable; 'system' found no ports; 'cache' failed: cache is unreachable)"
+    );
+
+    // Other sources still fail collection outright.
+    let err = collect_forbidden(
+        CollectOptions {
+            sources: vec![
+                Box::new(Fixed::services("system", &[22])),
+                Box::new(Failing),
+            ],
+            fallbacks: vec![Box::new(BuiltinServicesSource)],
+            min_known_ports: 1,
+            ..CollectOptions::default()
+        },
+        &mut ctx,
+        &Reporter::silent(),
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "scanner exploded");
+}
+
+#[test]
+fn test_builtin_services_are_named() {
+    let mut names = HashMap::new();
+    let ports = BuiltinServicesSource.collect(&mut names).unwrap();
+    assert_eq!(ports.len(), BUILTIN_SERVICE_PORTS.len());
+    assert_eq!(names[&5432], ["postgresql"]);
+    assert!(ports.contains(&22) && ports.contains(&6379));
+}
+
+#[test]
+fn test_passed_deadline_stops_collection() {
+    let mut ctx = ForbiddenContext::default();
+    let (forbidden, breakdown) = collect_forbidden(
+        CollectOptions {
+            sources: vec![Box::new(Failing), Box::new(Fixed::scan(&[3000]))],
+            deadline: Some(Instant::now() - Duration::from_secs(1)),
+            ..CollectOptions::default()
+        },
+        &mut ctx,
+        &Reporter::silent(),
+    )
+    .unwrap();
+    assert!(forbidden.is_empty());
+    assert_eq!(breakdown.timed_out_during.as_deref(), Some("broken"));
+}
+
+/// Downloads a services list, like the `nmap` source.
+struct Download {
+    url: String,
+}
+
+impl PortSource for Download {
+    fn name(&self) -> &str {
+        "nmap"
+    }
+
+    fn rejection_reason(&self) -> RejectionReason {
+        RejectionReason::KnownService
+    }
+
+    fn collect(&mut self, _names: &mut HashMap<u16, Vec<String>>) -> Result<HashSet<u16>> {
+        let client = client_builder().build()?;
+        let content = download_text(&client, &self.url, &ProgressBar::hidden())?;
+        parse_services_content(&content, "download", &Reporter::silent(), false)
+    }
+}
+
+/// Serves a services list after `delay`. Returns the URL to request.
+fn serve_slowly(delay: Duration) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/nmap-services", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            line.clear();
+        }
+        thread::sleep(delay);
+        let body = "http 80/tcp\n";
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+    });
+    url
+}
+
+/// Ignores rustscan's arguments and runs `script` instead.
+struct ScriptRunner {
+    script: &'static str,
+}
+
+impl CommandRunner for ScriptRunner {
+    fn run(&self, _program: &str, args: &[String]) -> std::io::Result<CommandOutput> {
+        if args == ["--version"] {
+            return SystemCommandRunner.run("echo", &["rustscan 2.4.1".to_string()]);
+        }
+        SystemCommandRunner.run("sh", &["-c".to_string(), self.script.to_string()])
+    }
+}
+
+/// The local scan, through a slow fake rustscan.
+struct SlowScan;
+
+impl PortSource for SlowScan {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    fn rejection_reason(&self) -> RejectionReason {
+        RejectionReason::LocallyInUse
+    }
+
+    fn collect(&mut self, _names: &mut HashMap<u16, Vec<String>>) -> Result<HashSet<u16>> {
+        let runner = ScriptRunner {
+            script: "sleep 1.5; echo 3000",
+        };
+        RustScanScanner::with_runner(Box::new(runner), Arc::new(Reporter::silent()))
+            .scan(&ScanTarget::default())
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_download_and_scan_run_concurrently() {
+    let mut ctx = ForbiddenContext::default();
+    let started = Instant::now();
+    let (forbidden, breakdown) = collect_forbidden(
+        CollectOptions {
+            sources: vec![
+                Box::new(Download {
+                    url: serve_slowly(Duration::from_millis(1500)),
+                }),
+                Box::new(SlowScan),
+            ],
+            ..CollectOptions::default()
+        },
+        &mut ctx,
+        &Reporter::silent(),
+    )
+    .unwrap();
+    // One after the other would take at least 3s.
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(1500), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(2700), "{:?}", elapsed);
+    assert_eq!(forbidden.iter().collect::<Vec<_>>(), vec![80, 3000]);
+    assert_eq!(breakdown.sources, vec![count("nmap", 1), count("local", 1)]);
+}
+
+#[test]
+fn test_devtools_profile_forbids_and_names_dev_ports() {
+    let mut ctx = ForbiddenContext::default();
+    let (forbidden, breakdown) = collect_forbidden(
+        CollectOptions {
+            sources: vec![
+                Box::new(Fixed::services("system", &[5432])),
+                Box::new(ProfileSource::new(&["devtools"]).unwrap()),
+            ],
+            ..CollectOptions::default()
+        },
+        &mut ctx,
+        &Reporter::silent(),
+    )
+    .unwrap();
+    assert!(forbidden.contains(5173) && forbidden.contains(9229) && forbidden.contains(35729));
+    assert_eq!(forbidden.len(), DEVTOOLS_PORTS.len());
+    assert_eq!(ctx.service_names[&5173], ["vite dev server"]);
+    // A port the services file knows keeps its name there first.
+    assert_eq!(ctx.service_names[&5432], ["system-5432", "postgres"]);
+    assert_eq!(
+        ctx.provenance.reasons(5173),
+        [RejectionReason::KnownService]
+    );
+    assert_eq!(breakdown.sources[1], count("profile", DEVTOOLS_PORTS.len()));
+}
+
+#[test]
+fn test_profiles_combine_once_each_and_reject_unknown_names() {
+    let mut source = ProfileSource::new(&["devtools", "devtools"]).unwrap();
+    let mut names = HashMap::new();
+    assert_eq!(
+        source.collect(&mut names).unwrap().len(),
+        DEVTOOLS_PORTS.len()
+    );
+    assert_eq!(names[&5173], ["vite dev server"]);
+    assert!(ProfileSource::new::<&str>(&[]).unwrap().is_empty());
+
+    let error = ProfileSource::new(&["devtools", "gpu"]).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Unknown profile 'gpu' (built-in profiles: devtools)"
+    );
+}
+
+struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use portpick::{compose_service_names, merge_compose_override, override_conflicts};
+
+const OVERRIDE: &str = include_str!("fixtures/docker-compose.override.yml");
+
+#[test]
+fn test_compose_service_names_in_file_order() {
+    let content = include_str!("fixtures/docker-compose.yml");
+    assert_eq!(compose_service_names(content).unwrap(), ["web", "db"]);
+    assert!(compose_service_names("version: '3'\n").is_err());
+}
+
+#[test]
+fn test_merge_compose_override_fresh() {
+    let rendered = merge_compose_override(None, "web", &[(40000, 80), (8022, 22)], false).unwrap();
+    // Mappings are quoted: YAML 1.1 would read 8022:22 as a base-60 number.
+    assert_eq!(
+        rendered,
+        "services:\n  web:\n    ports:\n    - \"40000:80\"\n    - \"8022:22\"\n"
+    );
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&rendered).unwrap();
+    assert_eq!(parsed["services"]["web"]["ports"][1], "8022:22");
+}
+
+#[test]
+fn test_merge_compose_override_keeps_unrelated_keys() {
+    let rendered = merge_compose_override(Some(OVERRIDE), "web", &[(40000, 80)], false).unwrap();
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&rendered).unwrap();
+    assert_eq!(parsed["version"], "3.9");
+    assert_eq!(parsed["x-portpick"], "keep");
+    assert_eq!(
+        parsed["services"]["web"]["environment"]["LOG_LEVEL"],
+        "debug"
+    );
+    assert_eq!(parsed["services"]["db"]["ports"][0], "15432:5432");
+    assert_eq!(
+        parsed["services"]["web"]["ports"],
+        serde_yaml::from_str::<serde_yaml::Value>("['9000:9000', '40000:80']").unwrap()
+    );
+
+    // A service the override doesn't mention yet is added to it.
+    let rendered =
+        merge_compose_override(Some(OVERRIDE), "cache", &[(40001, 6379)], false).unwrap();
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&rendered).unwrap();
+    assert_eq!(parsed["services"]["cache"]["ports"][0], "40001:6379");
+    assert_eq!(parsed["services"]["web"]["ports"][0], "9000:9000");
+}
+
+#[test]
+fn test_merge_compose_override_refuses_to_replace_a_published_port() {
+    assert_eq!(
+        override_conflicts(OVERRIDE, "db", &[5432, 80]).unwrap(),
+        ["15432:5432"]
+    );
+    assert!(
+        override_conflicts(OVERRIDE, "web", &[80])
+            .unwrap()
+            .is_empty()
+    );
+
+    let error = merge_compose_override(Some(OVERRIDE), "db", &[(40000, 5432)], false).unwrap_err();
+    assert!(error.to_string().contains("15432:5432"), "{error}");
+
+    let rendered = merge_compose_override(Some(OVERRIDE), "db", &[(40000, 5432)], true).unwrap();
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&rendered).unwrap();
+    assert_eq!(
+        parsed["services"]["db"]["ports"],
+        serde_yaml::from_str::<serde_yaml::Value>("['40000:5432']").unwrap()
+    );
+}
+
+#[test]
+fn test_override_conflicts_reads_every_port_syntax() {
+    let existing = "services:\n  web:\n    ports:\n      - 80\n      - \"127.0.0.1:8443:443/tcp\"\n      - target: 8080\n        published: 18080\n      - \"9000-9001:9000-9001\"\n";
+    assert_eq!(
+        override_conflicts(existing, "web", &[80, 443, 8080, 9000]).unwrap(),
+        [
+            "80",
+            "127.0.0.1:8443:443/tcp",
+            "target: 8080 published: 18080"
+        ]
+    );
+}
@@ -0,0 +1,111 @@
+use portpick::{Config, HttpConfig, ProfileConfig, ResolvedProfiles};
+use std::path::Path;
+
+const FIXTURE: &str = include_str!("fixtures/config.toml");
+
+fn resolve(names: &[&str]) -> anyhow::Result<ResolvedProfiles> {
+    Config::from_toml(FIXTURE)?.resolve_profiles(names)
+}
+
+#[test]
+fn test_config_parses_profiles() {
+    let config = Config::from_toml(FIXTURE).unwrap();
+    assert_eq!(
+        config.profiles["team-b"],
+        ProfileConfig {
+            extends: vec!["team-a".to_string(), "devtools".to_string()],
+            exclude: vec!["40004".to_string()],
+            within: Vec::new(),
+        }
+    );
+    assert_eq!(Config::from_toml("").unwrap(), Config::default());
+}
+
+#[test]
+fn test_builtin_profiles_pass_through() {
+    assert_eq!(
+        resolve(&["devtools"]).unwrap(),
+        ResolvedProfiles {
+            builtin: vec!["devtools".to_string()],
+            within: None,
+            exclude: None,
+        }
+    );
+}
+
+#[test]
+fn test_extends_apply_before_the_profile() {
+    assert_eq!(
+        resolve(&["team-b"]).unwrap(),
+        ResolvedProfiles {
+            builtin: vec!["devtools".to_string()],
+            within: Some("40000-40009".to_string()),
+            exclude: Some("40000-40001,40004".to_string()),
+        }
+    );
+}
+
+#[test]
+fn test_later_profiles_replace_within_and_add_exclusions() {
+    let resolved = resolve(&["team-a", "high"]).unwrap();
+    assert_eq!(resolved.within.as_deref(), Some("40006-40009,41000"));
+    assert_eq!(resolved.exclude.as_deref(), Some("40000-40001"));
+    let resolved = resolve(&["high", "team-a"]).unwrap();
+    assert_eq!(resolved.within.as_deref(), Some("40000-40009"));
+
+    // A profile reached twice is applied once, where it is first reached.
+    let resolved = resolve(&["team-a", "team-b", "team-a"]).unwrap();
+    assert_eq!(resolved.exclude.as_deref(), Some("40000-40001,40004"));
+}
+
+#[test]
+fn test_unknown_profiles_list_what_is_available() {
+    let error = resolve(&["team-c"]).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Unknown profile 'team-c' (available: devtools, high, team-a, team-b)"
+    );
+    let error = Config::from_toml("[profiles.a]\nextends = [\"missing\"]\n")
+        .unwrap()
+        .resolve_profiles(&["a"])
+        .unwrap_err();
+    assert!(error.to_string().contains("'missing'"), "{error}");
+}
+
+#[test]
+fn test_invalid_configs_are_rejected() {
+    let cycle =
+        Config::from_toml("[profiles.a]\nextends = [\"b\"]\n[profiles.b]\nextends = [\"a\"]\n")
+            .unwrap();
+    let error = cycle.resolve_profiles(&["a"]).unwrap_err();
+    assert_eq!(error.to_string(), "Profile 'a' extends itself: a -> b -> a");
+
+    let error = Config::from_toml("[profiles.devtools]\nexclude = [\"80\"]\n").unwrap_err();
+    assert!(error.to_string().contains("built in"), "{error}");
+    assert!(Config::from_toml("[profiles.a]\nwithin = [\"9000-8000\"]\n").is_err());
+    assert!(Config::from_toml("[profiles.a]\nincluded = [\"80\"]\n").is_err());
+}
+
+#[test]
+fn test_config_http_settings() {
+    let config = Config::from_toml(
+        "[http]\nuser_agent = \"corp-fetcher/2\"\nheaders = [\"Authorization: Bearer abc\"]\n",
+    )
+    .unwrap();
+    assert_eq!(
+        config.http,
+        HttpConfig {
+            user_agent: Some("corp-fetcher/2".to_string()),
+            headers: vec!["Authorization: Bearer abc".to_string()],
+        }
+    );
+    let error = Config::from_toml("[http]\nheaders = [\"no colon\"]\n").unwrap_err();
+    assert!(format!("{:#}", error).contains("Name: value"), "{error:#}");
+}
+
+#[test]
+fn test_missing_config_is_empty_unless_required() {
+    let missing = Path::new("/nonexistent/portpick/config.toml");
+    assert_eq!(Config::load(missing, false).unwrap(), Config::default());
+    assert!(Config::load(missing, true).is_err());
+}
@@ -0,0 +1,82 @@
+use portpick::{
+    ContainerInfo, ContainerKind, container_from_cgroup, container_from_env,
+    container_from_marker_files, default_gateway,
+};
+use std::net::Ipv4Addr;
+use std::path::Path;
+
+fn info(kind: ContainerKind, evidence: &str) -> Option<ContainerInfo> {
+    Some(ContainerInfo {
+        kind,
+        evidence: evidence.to_string(),
+    })
+}
+
+#[test]
+fn test_container_from_marker_files() {
+    assert_eq!(
+        container_from_marker_files(|path| path == Path::new("/.dockerenv")),
+        info(ContainerKind::Docker, "/.dockerenv")
+    );
+    assert_eq!(
+        container_from_marker_files(|path| path == Path::new("/run/.containerenv")),
+        info(ContainerKind::Podman, "/run/.containerenv")
+    );
+    assert_eq!(container_from_marker_files(|_| false), None);
+}
+
+#[test]
+fn test_container_from_env() {
+    assert_eq!(
+        container_from_env(Some("podman")),
+        info(ContainerKind::Podman, "$container=podman")
+    );
+    assert_eq!(
+        container_from_env(Some("lxc")),
+        info(ContainerKind::Lxc, "$container=lxc")
+    );
+    assert_eq!(
+        container_from_env(Some("oci")),
+        info(ContainerKind::Other, "$container=oci")
+    );
+    assert_eq!(container_from_env(Some(" ")), None);
+    assert_eq!(container_from_env(None), None);
+}
+
+#[test]
+fn test_container_from_cgroup() {
+    assert_eq!(
+        container_from_cgroup(include_str!("fixtures/cgroup_docker")),
+        info(
+            ContainerKind::Docker,
+            "/proc/1/cgroup (/docker/3f1c2e9a7b6d)"
+        )
+    );
+    assert_eq!(
+        container_from_cgroup(include_str!("fixtures/cgroup_kubepods"))
+            .unwrap()
+            .kind,
+        ContainerKind::Kubernetes
+    );
+    assert_eq!(
+        container_from_cgroup(include_str!("fixtures/cgroup_host")),
+        None
+    );
+    // cgroup v2 gives nothing away.
+    assert_eq!(container_from_cgroup("0::/\n"), None);
+}
+
+#[test]
+fn test_default_gateway() {
+    assert_eq!(
+        default_gateway(include_str!("fixtures/proc_net_route")),
+        Some(Ipv4Addr::new(172, 17, 0, 1))
+    );
+    // Only a header, or no default route.
+    assert_eq!(default_gateway("Iface\tDestination\tGateway\n"), None);
+    assert_eq!(
+        default_gateway("Iface\tDestination\tGateway\neth0\t000011AC\t00000000\n"),
+        None
+    );
+    assert_eq!(ContainerKind::Lxc.to_string(), "LXC");
+}
@@ -0,0 +1,115 @@
+use portpick::update_devcontainer;
+
+const DEVCONTAINER: &str = include_str!("fixtures/devcontainer.json");
+
+fn labeled(ports: &[(u16, &str)]) -> Vec<(u16, Option<String>)> {
+    ports
+        .iter()
+        .map(|&(port, name)| (port, (!name.is_empty()).then(|| name.to_string())))
+        .collect()
+}
+
+#[test]
+fn test_update_devcontainer_keeps_comments_and_trailing_commas() {
+    let updated = update_devcontainer(
+        DEVCONTAINER,
+        &labeled(&[(3000, "web"), (40001, "api"), (9229, "debugger")]),
+        false,
+    )
+    .unwrap();
+    assert_eq!(updated, include_str!("fixtures/devcontainer.updated.json"));
+    // Running again changes nothing.
+    assert_eq!(
+        update_devcontainer(
+            &updated,
+            &labeled(&[(3000, "web"), (40001, "api"), (9229, "debugger")]),
+            false
+        )
+        .unwrap(),
+        updated
+    );
+}
+
+#[test]
+fn test_update_devcontainer_adds_missing_keys() {
+    let existing =
+        "{\n  // Generated by the template\n  \"name\": \"web\",\n  \"image\": \"node:20\"\n}\n";
+    let updated =
+        update_devcontainer(existing, &labeled(&[(40000, "web"), (40001, "")]), false).unwrap();
+    assert_eq!(
+        updated,
+        "{\n  // Generated by the template\n  \"name\": \"web\",\n  \"image\": \"node:20\",\n  \"forwardPorts\": [40000, 40001],\n  \"portsAttributes\": { \"40000\": { \"label\": \"web\" } }\n}\n"
+    );
+    let parsed: serde_json::Value =
+        serde_json::from_str(&updated.replace("  // Generated by the template\n", "")).unwrap();
+    assert_eq!(parsed["forwardPorts"][1], 40001);
+    assert_eq!(parsed["portsAttributes"]["40000"]["label"], "web");
+}
+
+#[test]
+fn test_update_devcontainer_inline_and_crlf() {
+    assert_eq!(
+        update_devcontainer("{}", &labeled(&[(40000, "")]), false).unwrap(),
+        "{ \"forwardPorts\": [40000] }"
+    );
+    assert_eq!(
+        update_devcontainer(
+            "{\"forwardPorts\": [], \"name\": \"x\"}",
+            &labeled(&[(40000, ""), (40000, "")]),
+            false
+        )
+        .unwrap(),
+        "{\"forwardPorts\": [40000], \"name\": \"x\"}"
+    );
+    assert_eq!(
+        update_devcontainer(
+            "{\r\n\t\"forwardPorts\": [\r\n\t\t3000\r\n\t]\r\n}\r\n",
+            &labeled(&[(40000, "")]),
+            false
+        )
+        .unwrap(),
+        "{\r\n\t\"forwardPorts\": [\r\n\t\t3000,\r\n\t\t40000\r\n\t]\r\n}\r\n"
+    );
+}
+
+#[test]
+fn test_update_devcontainer_conflicting_label_needs_overwrite() {
+    let err =
+        update_devcontainer(DEVCONTAINER, &labeled(&[(3000, "frontend")]), false).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "'portsAttributes' already labels 3000 as \"web\"; pass --overwrite to relabel it"
+    );
+
+    let updated = update_devcontainer(DEVCONTAINER, &labeled(&[(3000, "frontend")]), true).unwrap();
+    assert_eq!(
+        updated,
+        DEVCONTAINER.replace(
+            "\"3000\": { \"label\": \"web\" }",
+            "\"3000\": { \"label\": \"frontend\" }"
+        )
+    );
+}
+
+#[test]
+fn test_update_devcontainer_rejects_malformed_files() {
+    for (content, expected) in [
+        ("{ /* open", "Unterminated comment on line 1"),
+        (
+            "{\n\"forwardPorts\": 3000\n}",
+            "'forwardPorts' is not a list",
+        ),
+        (
+            "{\n\"name\": \"x\"\n\"image\": \"y\"\n}",
+            "Expected ',' or '}' on line 3",
+        ),
+        ("[]", "The file is not a JSON object"),
+        (
+            "{\n\"forwardPorts\": [3000,,]\n}",
+            "Unexpected ',' on line 2",
+        ),
+    ] {
+        let err = update_devcontainer(content, &labeled(&[(40000, "web")]), false).unwrap_err();
+        assert_eq!(err.to_string(), expected);
+    }
+}
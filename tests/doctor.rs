@@ -0,0 +1,103 @@
+use portpick::{
+    CheckStatus, check_services_cache, check_services_file, check_tool, check_writable_dir,
+    find_program,
+};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const SERVICES_FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/services");
+const FAKE_RUSTSCAN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/fake-rustscan");
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("portpick-doctor-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_find_program_searches_path() {
+    let fixtures = OsString::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"));
+    assert_eq!(
+        find_program("fake-rustscan", Some(&fixtures)),
+        Some(PathBuf::from(FAKE_RUSTSCAN))
+    );
+    assert_eq!(find_program("fake-rustscan", None), None);
+    assert_eq!(find_program("no-such-tool", Some(&fixtures)), None);
+    // Paths are not looked up.
+    assert_eq!(
+        find_program(FAKE_RUSTSCAN, None),
+        Some(PathBuf::from(FAKE_RUSTSCAN))
+    );
+}
+
+#[test]
+fn test_check_tool_missing_required_fails_optional_warns() {
+    let empty = OsString::new();
+    let required = check_tool("rustscan", &["--version"], Some(&empty), true, "install it");
+    assert_eq!(required.status, CheckStatus::Fail);
+    assert_eq!(required.hint.as_deref(), Some("install it"));
+    let optional = check_tool("ss", &["-V"], Some(&empty), false, "install it");
+    assert_eq!(optional.status, CheckStatus::Warn);
+}
+
+#[test]
+fn test_check_tool_reports_first_output_line() {
+    let check = check_tool(FAKE_RUSTSCAN, &[], None, true, "");
+    assert_eq!(check.status, CheckStatus::Pass);
+    // The fake prints nothing without $FAKE_RUSTSCAN_OPEN.
+    assert!(
+        check.detail.starts_with("version unknown ("),
+        "{}",
+        check.detail
+    );
+}
+
+#[test]
+fn test_check_services_file() {
+    let check = check_services_file(Path::new(SERVICES_FIXTURE));
+    assert_eq!(check.status, CheckStatus::Pass);
+    assert!(check.detail.ends_with(": 2 TCP ports"), "{}", check.detail);
+
+    let missing = check_services_file(Path::new("/nonexistent/services"));
+    assert_eq!(missing.status, CheckStatus::Fail);
+    assert!(missing.hint.unwrap().contains("--services-file"));
+}
+
+#[test]
+fn test_check_services_cache() {
+    let dir = temp_dir("cache");
+    let cache = dir.join("nmap-services.cache");
+    assert_eq!(
+        check_services_cache(&cache, SystemTime::now()).status,
+        CheckStatus::Pass
+    );
+
+    std::fs::copy(SERVICES_FIXTURE, &cache).unwrap();
+    let now = SystemTime::now() + Duration::from_secs(3 * 24 * 60 * 60 + 60);
+    let valid = check_services_cache(&cache, now);
+    assert_eq!(valid.status, CheckStatus::Pass);
+    assert!(
+        valid.detail.ends_with("2 TCP ports, 3 days old"),
+        "{}",
+        valid.detail
+    );
+
+    std::fs::write(&cache, "<html>captive portal</html>\n").unwrap();
+    assert_eq!(check_services_cache(&cache, now).status, CheckStatus::Fail);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_check_writable_dir() {
+    let dir = temp_dir("writable");
+    let existing = check_writable_dir("state", &dir);
+    assert_eq!(existing.status, CheckStatus::Pass);
+    assert!(existing.detail.ends_with("is writable"));
+    let nested = check_writable_dir("state", &dir.join("a/b"));
+    assert_eq!(nested.status, CheckStatus::Pass);
+    assert!(nested.detail.ends_with("can be created"));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,432 @@
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use indicatif::ProgressBar;
+use portpick::{
+    DEFAULT_MAX_DOWNLOAD_SIZE, DEFAULT_USER_AGENT, MAX_REDIRECTS, Protocol, TLS_BACKEND,
+    check_redirect, client_builder, download_services, download_services_to, download_text,
+    header_map, masked_headers, parse_byte_size, parse_services_map, redirect_policy,
+};
+use reqwest::Url;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Serves one request with `head` followed by `chunks`, flushing and pausing between chunks
+/// so the client sees the body arrive in pieces. Returns the URL to request.
+fn serve_once(head: String, chunks: Vec<Vec<u8>>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/nmap-services", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            line.clear();
+        }
+        let mut stream = stream;
+        stream.write_all(head.as_bytes()).unwrap();
+        for chunk in chunks {
+            stream.write_all(&chunk).unwrap();
+            stream.flush().unwrap();
+            thread::sleep(Duration::from_millis(2));
+        }
+    });
+    url
+}
+
+/// Serves every request with `respond(path, base_url)`, one connection each. Returns the
+/// base URL.
+fn serve_routes(respond: impl Fn(&str, &str) -> String + Send + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let url = base.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let path = request_line.split(' ').nth(1).unwrap_or("/").to_string();
+            let mut stream = stream;
+            let _ = stream.write_all(respond(&path, &base).as_bytes());
+        }
+    });
+    url
+}
+
+fn redirect(location: &str) -> String {
+    format!(
+        "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        location
+    )
+}
+
+fn ok(content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+fn large_body() -> String {
+    (0..40_000)
+        .map(|port| format!("svc{} {}/tcp\n", port, port % 65536))
+        .collect()
+}
+
+#[test]
+fn test_download_text_streams_body_with_known_length() {
+    let body = large_body();
+    let chunks: Vec<Vec<u8>> = body.as_bytes().chunks(8192).map(<[u8]>::to_vec).collect();
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    let url = serve_once(head, chunks);
+
+    let progress = ProgressBar::hidden();
+    let text = download_text(&reqwest::blocking::Client::new(), &url, &progress).unwrap();
+    assert_eq!(text, body);
+    assert_eq!(progress.length(), Some(body.len() as u64));
+    assert_eq!(progress.position(), body.len() as u64);
+    assert!(progress.is_finished());
+}
+
+#[test]
+fn test_download_text_streams_chunked_body_without_length() {
+    let body = large_body();
+    let chunks: Vec<Vec<u8>> = body
+        .as_bytes()
+        .chunks(10_000)
+        .map(|piece| {
+            let mut chunk = format!("{:x}\r\n", piece.len()).into_bytes();
+            chunk.extend_from_slice(piece);
+            chunk.extend_from_slice(b"\r\n");
+            chunk
+        })
+        .chain([b"0\r\n\r\n".to_vec()])
+        .collect();
+    let head =
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n".to_string();
+    let url = serve_once(head, chunks);
+
+    let progress = ProgressBar::hidden();
+    let text = download_text(&reqwest::blocking::Client::new(), &url, &progress).unwrap();
+    assert_eq!(text, body);
+    assert_eq!(progress.length(), None);
+    assert_eq!(progress.position(), body.len() as u64);
+}
+
+#[test]
+fn test_download_text_rejects_error_status() {
+    let head = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    let url = serve_once(head.to_string(), Vec::new());
+
+    let err = download_text(
+        &reqwest::blocking::Client::new(),
+        &url,
+        &ProgressBar::hidden(),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("Status: 404"), "{}", err);
+}
+
+fn gzip(body: &str) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body.as_bytes()).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn test_download_services_decompresses_gzip_body() {
+    let body = large_body();
+    let compressed = gzip(&body);
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        compressed.len()
+    );
+    let url = serve_once(head, vec![compressed]);
+
+    let client = client_builder().build().unwrap();
+    let bytes = download_services(&client, &url, &ProgressBar::hidden()).unwrap();
+    // What gets cached is the plain text, which parses to every port.
+    let text = String::from_utf8(bytes).unwrap();
+    assert_eq!(text, body);
+    let tcp = parse_services_map(&text, Protocol::Tcp, true).unwrap();
+    assert_eq!(tcp.len(), 40_000);
+}
+
+#[test]
+fn test_download_services_rejects_undecoded_gzip() {
+    // Compressed, but without a Content-Encoding saying so.
+    let compressed = gzip(&large_body());
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        compressed.len()
+    );
+    let url = serve_once(head, vec![compressed]);
+
+    let client = client_builder().build().unwrap();
+    let err = download_services(&client, &url, &ProgressBar::hidden()).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("gzip-compressed"),
+        "{:#}",
+        err
+    );
+}
+
+#[test]
+fn test_download_services_follows_redirect_chain() {
+    let base = serve_routes(|path, base| match path {
+        "/old" => redirect(&format!("{}/moved", base)),
+        "/moved" => redirect("/nmap-services"),
+        _ => ok("text/plain", "http 80/tcp\nhttps 443/tcp\n"),
+    });
+    let hops = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&hops);
+    let client = client_builder()
+        .redirect(redirect_policy(move |from, to| {
+            seen.lock()
+                .unwrap()
+                .push((from.path().to_string(), to.path().to_string()));
+        }))
+        .build()
+        .unwrap();
+
+    let body =
+        download_services(&client, &format!("{}/old", base), &ProgressBar::hidden()).unwrap();
+    assert_eq!(body, b"http 80/tcp\nhttps 443/tcp\n");
+    assert_eq!(
+        *hops.lock().unwrap(),
+        vec![
+            ("/old".to_string(), "/moved".to_string()),
+            ("/moved".to_string(), "/nmap-services".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_download_services_stops_redirect_loops() {
+    let base = serve_routes(|path, _| match path {
+        "/a" => redirect("/b"),
+        _ => redirect("/a"),
+    });
+    let client = client_builder().build().unwrap();
+    let err =
+        download_services(&client, &format!("{}/a", base), &ProgressBar::hidden()).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("too many redirects"),
+        "{:#}",
+        err
+    );
+}
+
+#[test]
+fn test_check_redirect_refuses_downgrade() {
+    let https = Url::parse("https://svn.nmap.org/nmap/nmap-services").unwrap();
+    let http = Url::parse("http://portal.example/login").unwrap();
+    let err = check_redirect(&https, &http, 0).unwrap_err();
+    assert!(
+        err.contains("down to 'http://portal.example/login'"),
+        "{}",
+        err
+    );
+    assert!(check_redirect(&http, &https, 0).is_ok());
+    assert!(check_redirect(&https, &https, MAX_REDIRECTS - 1).is_ok());
+    assert!(check_redirect(&https, &https, MAX_REDIRECTS).is_err());
+}
+
+#[test]
+fn test_download_services_rejects_html_portal() {
+    let base = serve_routes(|path, _| match path {
+        "/nmap-services" => redirect("/login"),
+        _ => ok(
+            "text/html; charset=utf-8",
+            "<html><body>http 80/tcp Please sign in</body></html>",
+        ),
+    });
+    let client = client_builder().build().unwrap();
+    let err = download_services(
+        &client,
+        &format!("{}/nmap-services", base),
+        &ProgressBar::hidden(),
+    )
+    .unwrap_err();
+    assert!(format!("{:#}", err).contains("captive portal"), "{:#}", err);
+}
+
+/// Serves one request with a small services list, sending its header lines (lowercased) down
+/// the returned channel. Returns the URL to request.
+#[test]
+fn test_download_services_to_streams_and_hashes() {
+    let body = "http\t80/tcp\t0.484143\nssh\t22/tcp\t0.182286\n";
+    let url = serve_routes(move |_, _| ok("text/plain", body));
+    let mut sink = Vec::new();
+    let downloaded = download_services_to(
+        &client_builder().build().unwrap(),
+        &url,
+        &ProgressBar::hidden(),
+        DEFAULT_MAX_DOWNLOAD_SIZE,
+        &mut sink,
+    )
+    .unwrap();
+    assert_eq!(downloaded.content, body.as_bytes());
+    assert_eq!(sink, body.as_bytes());
+    assert_eq!(
+        downloaded.sha256,
+        "97a43da3ec2ff433d687863feaf4dd049781b00d271876c2d2bd137440519e22"
+    );
+}
+
+#[test]
+fn test_download_services_to_rejects_oversized_body() {
+    let body = large_body();
+    // Announced up front, and found only while reading a body of unknown length.
+    let url = serve_routes(move |path, _| match path {
+        "/announced" => ok("text/plain", &body),
+        _ => format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\n{}",
+            body
+        ),
+    });
+    for path in ["/announced", "/unannounced"] {
+        let mut sink = Vec::new();
+        let err = download_services_to(
+            &client_builder().build().unwrap(),
+            &format!("{}{}", url, path),
+            &ProgressBar::hidden(),
+            64 * 1024,
+            &mut sink,
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("is larger than the download limit of 64.00 KiB"),
+            "{}: {:#}",
+            path,
+            err
+        );
+        assert!(sink.len() <= 64 * 1024, "{}: {}", path, sink.len());
+    }
+}
+
+#[test]
+fn test_parse_byte_size() {
+    assert_eq!(parse_byte_size("1048576").unwrap(), 1 << 20);
+    assert_eq!(parse_byte_size("512K").unwrap(), 512 << 10);
+    assert_eq!(parse_byte_size("10MB").unwrap(), DEFAULT_MAX_DOWNLOAD_SIZE);
+    assert_eq!(parse_byte_size("2 GiB").unwrap(), 2 << 30);
+    for spec in ["", "ten", "5T", "-1M", "99999999999G"] {
+        assert!(parse_byte_size(spec).is_err(), "{}", spec);
+    }
+}
+
+fn serve_recording_headers() -> (String, std::sync::mpsc::Receiver<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/nmap-services", listener.local_addr().unwrap());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut headers = Vec::new();
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            headers.push(line.trim_end().to_ascii_lowercase());
+            line.clear();
+        }
+        sender.send(headers).unwrap();
+        let mut stream = stream;
+        stream
+            .write_all(ok("text/plain", "http 80/tcp\n").as_bytes())
+            .unwrap();
+    });
+    (url, receiver)
+}
+
+#[test]
+fn test_client_builder_sends_portpick_user_agent() {
+    let (url, headers) = serve_recording_headers();
+    let client = client_builder().build().unwrap();
+    download_services(&client, &url, &ProgressBar::hidden()).unwrap();
+
+    let expected = format!("user-agent: {}", DEFAULT_USER_AGENT.to_ascii_lowercase());
+    let headers = headers.recv().unwrap();
+    assert!(headers.contains(&expected), "{:?}", headers);
+    assert!(DEFAULT_USER_AGENT.starts_with("portpick/"));
+}
+
+#[test]
+fn test_custom_headers_and_user_agent_are_sent() {
+    let (url, headers) = serve_recording_headers();
+    let custom = header_map(&[
+        "X-Token: first",
+        "Authorization: Bearer s3cret",
+        "x-token: second",
+    ])
+    .unwrap();
+    let client = client_builder()
+        .user_agent("corp-fetcher/2")
+        .default_headers(custom)
+        .build()
+        .unwrap();
+    download_services(&client, &url, &ProgressBar::hidden()).unwrap();
+
+    let headers = headers.recv().unwrap();
+    for expected in [
+        "user-agent: corp-fetcher/2",
+        "authorization: bearer s3cret",
+        "x-token: second",
+    ] {
+        assert!(
+            headers.iter().any(|header| header == expected),
+            "{:?}",
+            headers
+        );
+    }
+    assert!(!headers.iter().any(|header| header == "x-token: first"));
+}
+
+#[test]
+fn test_masked_headers_hide_values() {
+    let headers = header_map(&["Authorization: Bearer s3cret"]).unwrap();
+    assert_eq!(masked_headers(&headers), vec!["authorization: ****"]);
+    assert!(header_map(&["Authorization"]).is_err());
+    assert!(header_map(&["Bad Name: x"]).is_err());
+}
+
+#[test]
+fn test_tls_backend_matches_enabled_feature() {
+    let expected = if cfg!(feature = "rustls") {
+        "rustls"
+    } else {
+        "native-tls"
+    };
+    assert_eq!(TLS_BACKEND, expected);
+}
+
+#[test]
+#[ignore] // Performs network I/O; run with `--features native,rustls` to check the rustls build
+fn test_client_builder_fetches_over_https() {
+    let client = client_builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .unwrap();
+    let body = download_text(
+        &client,
+        "https://svn.nmap.org/nmap/nmap-services",
+        &ProgressBar::hidden(),
+    )
+    .unwrap();
+    assert!(
+        body.contains("http\t80/tcp"),
+        "unexpected body from {TLS_BACKEND}"
+    );
+}
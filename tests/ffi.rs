@@ -0,0 +1,116 @@
+#![cfg(feature = "ffi")]
+
+use portpick::ffi::{PORTPICK_ERROR, portpick_find, portpick_parse_services};
+use portpick::{Reporter, find_available_ports, parse_services_content};
+use std::collections::HashSet;
+use std::fs;
+use std::ptr;
+
+#[test]
+fn test_ffi_find_matches_native_api() {
+    let forbidden = [1024, 1025, 1027];
+    let mut out = [0u16; 8];
+    let written = unsafe {
+        portpick_find(
+            forbidden.as_ptr(),
+            forbidden.len(),
+            3,
+            false,
+            out.as_mut_ptr(),
+            out.len(),
+        )
+    };
+    assert_eq!(written, 3);
+    let native = find_available_ports(&HashSet::from(forbidden), 3, false);
+    assert_eq!(&out[..3], native.as_slice());
+
+    let written = unsafe {
+        portpick_find(
+            forbidden.as_ptr(),
+            forbidden.len(),
+            3,
+            true,
+            out.as_mut_ptr(),
+            out.len(),
+        )
+    };
+    assert_eq!(written, 3);
+    assert_eq!(&out[..3], [1028, 1029, 1030]);
+}
+
+#[test]
+fn test_ffi_find_rejects_bad_arguments() {
+    let mut out = [0u16; 2];
+    // Not enough room for the requested count.
+    assert_eq!(
+        unsafe { portpick_find(ptr::null(), 0, 3, false, out.as_mut_ptr(), out.len()) },
+        PORTPICK_ERROR
+    );
+    // Null forbidden list with a non-zero length.
+    assert_eq!(
+        unsafe { portpick_find(ptr::null(), 4, 1, false, out.as_mut_ptr(), out.len()) },
+        PORTPICK_ERROR
+    );
+    assert_eq!(
+        unsafe { portpick_find(ptr::null(), 0, 1, false, ptr::null_mut(), 1) },
+        PORTPICK_ERROR
+    );
+    assert_eq!(
+        unsafe { portpick_find(ptr::null(), 0, 0, false, ptr::null_mut(), 0) },
+        0
+    );
+}
+
+#[test]
+fn test_ffi_parse_services_reports_total_and_truncates() {
+    let content = fs::read("tests/fixtures/services").unwrap();
+    let native: Vec<u16> = {
+        let text = String::from_utf8(content.clone()).unwrap();
+        let mut ports: Vec<u16> = parse_services_content(&text, "test", &Reporter::silent(), false)
+            .unwrap()
+            .into_iter()
+            .collect();
+        ports.sort_unstable();
+        ports
+    };
+    assert!(native.len() > 1);
+
+    let total =
+        unsafe { portpick_parse_services(content.as_ptr(), content.len(), ptr::null_mut(), 0) };
+    assert_eq!(total, native.len() as isize);
+
+    let mut out = vec![0u16; native.len()];
+    let total =
+        unsafe { portpick_parse_services(content.as_ptr(), content.len(), out.as_mut_ptr(), 1) };
+    assert_eq!(total, native.len() as isize);
+    assert_eq!(out[0], native[0]);
+    assert_eq!(out[1], 0, "only out_cap entries are written");
+
+    let total = unsafe {
+        portpick_parse_services(content.as_ptr(), content.len(), out.as_mut_ptr(), out.len())
+    };
+    assert_eq!(total, native.len() as isize);
+    assert_eq!(out, native);
+}
+
+#[test]
+fn test_ffi_parse_services_rejects_invalid_utf8() {
+    let content = [0xff, 0xfe, b'\n'];
+    let mut out = [0u16; 4];
+    assert_eq!(
+        unsafe { portpick_parse_services(content.as_ptr(), content.len(), out.as_mut_ptr(), 4) },
+        PORTPICK_ERROR
+    );
+}
+
+#[test]
+fn test_header_declares_every_exported_function() {
+    let header = fs::read_to_string("include/portpick.h").unwrap();
+    for declaration in [
+        "intptr_t portpick_find(const uint16_t *forbidden,",
+        "intptr_t portpick_parse_services(const uint8_t *content,",
+        "#define PORTPICK_ERROR -1",
+    ] {
+        assert!(header.contains(declaration), "missing {declaration}");
+    }
+}
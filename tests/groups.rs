@@ -0,0 +1,65 @@
+use portpick::{PortGroup, Provenance, parse_group_spec, suggest_groups_in};
+use std::collections::HashSet;
+
+fn group(name: &str, count: u16, continuous: bool) -> PortGroup {
+    PortGroup {
+        name: name.to_string(),
+        count,
+        continuous,
+    }
+}
+
+#[test]
+fn test_parse_group_spec() {
+    assert_eq!(
+        parse_group_spec("web:1, db ,workers:3c").unwrap(),
+        vec![
+            group("web", 1, false),
+            group("db", 1, false),
+            group("workers", 3, true)
+        ]
+    );
+    for bad in ["web:0", "web:x", ":2", "web:1,web:2", "web:-1c", ""] {
+        assert!(
+            parse_group_spec(bad).is_err(),
+            "{:?} should be rejected",
+            bad
+        );
+    }
+}
+
+#[test]
+fn test_suggest_groups_forbids_earlier_groups_ports() {
+    let forbidden: HashSet<u16> = [102].into_iter().collect();
+    let groups = parse_group_spec("web:1,db:1,workers:3c").unwrap();
+    let allocations =
+        suggest_groups_in(&forbidden, &groups, &[(100, 110)], &Provenance::default()).unwrap();
+    let ports: Vec<Vec<u16>> = allocations
+        .iter()
+        .map(|a| a.suggestions.iter().map(|s| s.port).collect())
+        .collect();
+    assert_eq!(ports, vec![vec![100], vec![101], vec![103, 104, 105]]);
+    for allocation in &allocations {
+        assert!(
+            allocation
+                .suggestions
+                .iter()
+                .all(|s| s.name.as_deref() == Some(allocation.group.name.as_str()))
+        );
+    }
+}
+
+#[test]
+fn test_suggest_groups_impossible_group_is_partial() {
+    let groups = parse_group_spec("big:4c,web:1").unwrap();
+    let allocations = suggest_groups_in(
+        &HashSet::new(),
+        &groups,
+        &[(100, 102)],
+        &Provenance::default(),
+    )
+    .unwrap();
+    assert!(allocations[0].suggestions.is_empty());
+    assert_eq!(allocations[0].summary().found, 0);
+    assert_eq!(allocations[1].suggestions[0].port, 100);
+}
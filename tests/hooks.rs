@@ -0,0 +1,104 @@
+#![cfg(unix)]
+
+use portpick::{PortSource, ProviderHook, discover_hooks, parse_hook_output};
+use std::collections::HashMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// A fresh directory under the system temp dir for one test's hooks.
+fn hook_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("portpick-hooks-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn install(dir: &Path, name: &str, script: &str, executable: bool) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+    let mode = if executable { 0o755 } else { 0o644 };
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+    path
+}
+
+#[test]
+fn test_parse_hook_output_reads_specs_and_skips_comments() {
+    let ports =
+        parse_hook_output("# from IPAM\n8080, 9000-9002\n\n  7000 7001 # legacy\n").unwrap();
+    let mut ports: Vec<u16> = ports.into_iter().collect();
+    ports.sort();
+    assert_eq!(ports, [7000, 7001, 8080, 9000, 9001, 9002]);
+    assert!(parse_hook_output("").unwrap().is_empty());
+
+    let error = parse_hook_output("8080\nnot-a-port\n").unwrap_err();
+    assert!(format!("{:#}", error).contains("line 2"), "{error:#}");
+}
+
+#[test]
+fn test_discover_hooks_finds_executables_by_name() {
+    let dir = hook_dir("discover");
+    install(&dir, "b-registry", "echo 1", true);
+    install(&dir, "a-ipam.sh", "echo 1", true);
+    install(&dir, "README", "echo 1", false);
+    install(&dir, ".hidden", "echo 1", true);
+    install(&dir, "a-ipam.sh~", "echo 1", true);
+    assert_eq!(
+        discover_hooks(&dir).unwrap(),
+        [dir.join("a-ipam.sh"), dir.join("b-registry")]
+    );
+    assert!(discover_hooks(&dir.join("missing")).unwrap().is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_hook_gets_its_environment_and_names_its_ports() {
+    let dir = hook_dir("env");
+    // Echoes the first port of its ranges, so the environment is visible in the result.
+    let path = install(
+        &dir,
+        "ipam.sh",
+        r#"[ "$PORTPICK_PROTOCOL" = tcp ] && [ "$PORTPICK_ADDRESS" = 10.0.0.5 ] || exit 9
+echo "${PORTPICK_RANGES%%-*}""#,
+        true,
+    );
+    let mut hook =
+        ProviderHook::new(&path).with_context("tcp", &[(41000, 41999), (43000, 43000)], "10.0.0.5");
+    assert_eq!(hook.name(), "ipam");
+    let mut names = HashMap::new();
+    let ports = hook.collect(&mut names).unwrap();
+    assert_eq!(ports.into_iter().collect::<Vec<_>>(), [41000]);
+    assert_eq!(names[&41000], ["ipam"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_failing_and_slow_hooks_are_errors() {
+    let dir = hook_dir("failing");
+    let failing = install(
+        &dir,
+        "broken",
+        "echo 'registry unreachable' >&2; exit 3",
+        true,
+    );
+    let error = ProviderHook::new(&failing)
+        .collect(&mut HashMap::new())
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("registry unreachable"),
+        "{error}"
+    );
+
+    let slow = install(&dir, "slow", "sleep 30", true);
+    let started = Instant::now();
+    let error = ProviderHook::new(&slow)
+        .with_timeout(Duration::from_millis(200))
+        .collect(&mut HashMap::new())
+        .unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(
+        error.to_string().contains("did not finish within 0.2s"),
+        "{error}"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
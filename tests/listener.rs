@@ -0,0 +1,157 @@
+use portpick::{
+    Listener, ProcessHint, addresses_conflict, parse_lsof_listeners, parse_proc_net_listeners,
+    parse_ss_listener_processes, parse_ss_listeners, ports_conflicting_with,
+};
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+#[test]
+fn test_specific_addresses_conflict_only_with_themselves() {
+    assert!(addresses_conflict(ip("127.0.0.1"), ip("127.0.0.1")));
+    assert!(!addresses_conflict(ip("192.168.1.5"), ip("127.0.0.1")));
+    assert!(!addresses_conflict(ip("::1"), ip("127.0.0.1")));
+    assert!(!addresses_conflict(ip("::1"), ip("fe80::1")));
+    assert!(addresses_conflict(ip("::1"), ip("::1")));
+}
+
+#[test]
+fn test_ipv4_wildcard_conflicts_within_ipv4_only() {
+    assert!(addresses_conflict(ip("0.0.0.0"), ip("127.0.0.1")));
+    assert!(addresses_conflict(ip("127.0.0.1"), ip("0.0.0.0")));
+    assert!(addresses_conflict(ip("0.0.0.0"), ip("0.0.0.0")));
+    assert!(!addresses_conflict(ip("0.0.0.0"), ip("::1")));
+}
+
+#[test]
+fn test_ipv6_wildcard_is_dual_stack() {
+    assert!(addresses_conflict(ip("::"), ip("::1")));
+    assert!(addresses_conflict(ip("::"), ip("127.0.0.1")));
+    assert!(addresses_conflict(ip("127.0.0.1"), ip("::")));
+    assert!(addresses_conflict(ip("0.0.0.0"), ip("::")));
+    assert!(addresses_conflict(ip("fe80::1"), ip("::")));
+}
+
+#[test]
+fn test_ipv4_mapped_addresses_count_as_ipv4() {
+    assert!(addresses_conflict(ip("::ffff:127.0.0.1"), ip("127.0.0.1")));
+    assert!(addresses_conflict(ip("127.0.0.1"), ip("::ffff:127.0.0.1")));
+    assert!(addresses_conflict(ip("::ffff:0.0.0.0"), ip("10.0.0.1")));
+    assert!(!addresses_conflict(ip("::ffff:10.0.0.2"), ip("10.0.0.1")));
+}
+
+#[test]
+fn test_parse_ss_listeners_fixture() {
+    let listeners = parse_ss_listeners(include_str!("fixtures/ss_listening.txt"));
+    assert_eq!(listeners.len(), 8);
+    assert_eq!(
+        listeners[0],
+        Listener {
+            address: ip("127.0.0.53"),
+            port: 53
+        }
+    );
+    assert_eq!(listeners[4].address, ip("::"));
+    assert_eq!(
+        listeners[6].address,
+        ip("::"),
+        "`*` is the dual-stack wildcard"
+    );
+    assert_eq!(listeners[7].address, ip("fe80::1"));
+}
+
+#[test]
+fn test_ports_conflicting_with_loopback() {
+    let listeners = parse_ss_listeners(include_str!("fixtures/ss_listening.txt"));
+    let blocked = ports_conflicting_with(&listeners, ip("127.0.0.1"));
+    // 8080 is bound only to 192.168.1.5, 53 to 127.0.0.53, 6379 to ::1 and 9000 to fe80::1.
+    let expected: HashSet<u16> = [22, 5432, 631].into_iter().collect();
+    assert_eq!(blocked, expected);
+
+    let everything = ports_conflicting_with(&listeners, ip("0.0.0.0"));
+    assert!(everything.contains(&8080));
+    assert!(!everything.contains(&6379));
+}
+
+#[test]
+fn test_parse_proc_net_listeners_decodes_addresses() {
+    let listeners = parse_proc_net_listeners(include_str!("fixtures/proc_net_tcp"));
+    assert_eq!(
+        listeners,
+        vec![
+            Listener {
+                address: ip("0.0.0.0"),
+                port: 8080
+            },
+            Listener {
+                address: ip("127.0.0.1"),
+                port: 5432
+            },
+            Listener {
+                address: ip("::"),
+                port: 6379
+            },
+        ]
+    );
+    let ipv6_loopback =
+        "   0: 00000000000000000000000001000000:0016 00000000000000000000000000000000:0000 0A";
+    assert_eq!(
+        parse_proc_net_listeners(ipv6_loopback)[0].address,
+        ip("::1")
+    );
+}
+
+fn hint(program: &str, pid: u32) -> Option<ProcessHint> {
+    Some(ProcessHint {
+        program: program.to_string(),
+        pid: Some(pid),
+    })
+}
+
+#[test]
+fn test_parse_ss_listener_processes_with_and_without_users() {
+    let entries = parse_ss_listener_processes(include_str!("fixtures/ss_listening_processes.txt"));
+    let summary: Vec<(u16, Option<ProcessHint>)> = entries
+        .iter()
+        .map(|entry| (entry.listener.port, entry.process.clone()))
+        .collect();
+    // Without root, ss leaves out the users column for other users' sockets.
+    assert_eq!(
+        summary,
+        vec![
+            (53, None),
+            (22, None),
+            (8080, hint("nginx", 812)),
+            (5432, hint("postgres", 1234)),
+            (6379, hint("redis-server", 977)),
+        ]
+    );
+    assert_eq!(entries[4].listener.address, ip("::1"));
+
+    // Plain `ss -Hlnt` output parses the same, just without processes.
+    let plain = parse_ss_listener_processes(include_str!("fixtures/ss_listening.txt"));
+    assert_eq!(plain.len(), 8);
+    assert!(plain.iter().all(|entry| entry.process.is_none()));
+}
+
+#[test]
+fn test_parse_lsof_listeners_fixture() {
+    let entries = parse_lsof_listeners(include_str!("fixtures/lsof_listening.txt"));
+    let summary: Vec<(IpAddr, u16, Option<ProcessHint>)> = entries
+        .into_iter()
+        .map(|entry| (entry.listener.address, entry.listener.port, entry.process))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (ip("::"), 8080, hint("nginx", 812)),
+            (ip("127.0.0.1"), 5432, hint("postgres", 1234)),
+            (ip("::1"), 6379, hint("redis-ser", 977)),
+            (ip("fe80::1"), 9000, hint("My App", 2001)),
+        ]
+    );
+    assert!(parse_lsof_listeners("").is_empty());
+}
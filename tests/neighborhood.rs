@@ -0,0 +1,62 @@
+use portpick::{
+    DEFAULT_NEIGHBORHOOD, NEIGHBORHOOD_KEY, Provenance, RejectionReason, neighbor_code,
+    render_neighborhood,
+};
+use std::collections::HashSet;
+
+/// Forbidden ports with their reasons; a port without reasons was excluded.
+fn forbidden(entries: &[(u16, &[RejectionReason])]) -> (HashSet<u16>, Provenance) {
+    let mut provenance = Provenance::default();
+    for (port, reasons) in entries {
+        for reason in *reasons {
+            provenance.record(*port, reason.clone());
+        }
+    }
+    (entries.iter().map(|(port, _)| *port).collect(), provenance)
+}
+
+#[test]
+fn test_render_neighborhood_matches_golden() {
+    use RejectionReason::{KnownService, LocallyInUse};
+    let (forbidden_ports, provenance) = forbidden(&[
+        (39995, &[KnownService]),
+        (39999, &[LocallyInUse]),
+        (40010, &[]),
+        (40011, &[KnownService, LocallyInUse]),
+        (8080, &[KnownService]),
+        (8082, &[LocallyInUse]),
+        (65535, &[]),
+    ]);
+    let suggested: HashSet<u16> = [40003, 40004, 8081, 65533, 2].into_iter().collect();
+    let rendered: Vec<String> = [
+        (40003, DEFAULT_NEIGHBORHOOD),
+        (40004, DEFAULT_NEIGHBORHOOD),
+        (8081, 3),
+        (65533, 5),
+        (2, 4),
+    ]
+    .into_iter()
+    .map(|(port, radius)| {
+        render_neighborhood(port, radius, &suggested, &forbidden_ports, &provenance)
+    })
+    .collect();
+    assert_eq!(
+        format!("{}\n{}\n", rendered.join("\n"), NEIGHBORHOOD_KEY),
+        include_str!("fixtures/neighborhood.golden.txt")
+    );
+}
+
+#[test]
+fn test_neighbor_code_prefers_local_use() {
+    use RejectionReason::{KnownService, LocallyInUse};
+    let (forbidden_ports, provenance) = forbidden(&[
+        (1, &[KnownService, LocallyInUse]),
+        (2, &[KnownService]),
+        (3, &[]),
+    ]);
+    let suggested: HashSet<u16> = [5].into_iter().collect();
+    let codes: String = (1..=5)
+        .map(|port| neighbor_code(port, &suggested, &forbidden_ports, &provenance))
+        .collect();
+    assert_eq!(codes, "LSX.+");
+}
@@ -0,0 +1,322 @@
+use indicatif::ProgressBar;
+use portpick::{
+    CacheFs, DEFAULT_MAX_DOWNLOAD_SIZE, NmapCache, Refresh, Reporter, SystemFs, client_builder,
+    describe_age, download_services_to, sha256_hex,
+};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+const OLD: &str = "http\t80/tcp\t0.484143\n";
+const NEW: &str = "http\t80/tcp\t0.484143\nssh\t22/tcp\t0.182286\n";
+
+fn cache_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "portpick-nmapcache-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Writes like the real filesystem, then fails to rename, as a crash between the two would.
+struct FailingRename;
+
+impl CacheFs for FailingRename {
+    fn write_synced(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        SystemFs.write_synced(path, contents)
+    }
+
+    fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        Err(io::Error::other("simulated crash"))
+    }
+}
+
+#[test]
+fn test_nmap_cache_save_replaces_content() {
+    let dir = cache_dir("save");
+    let cache = NmapCache::new(dir.join("nested").join("nmap-services.cache"));
+    cache.save(OLD.as_bytes()).unwrap();
+    cache.save(NEW.as_bytes()).unwrap();
+    assert_eq!(fs::read_to_string(cache.path()).unwrap(), NEW);
+    // Only the cache and its recorded SHA-256 are left behind.
+    let entries: Vec<_> = fs::read_dir(dir.join("nested")).unwrap().collect();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(
+        fs::read_to_string(cache.digest_path()).unwrap(),
+        sha256_hex(NEW.as_bytes())
+    );
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&dir.join("nested")), 0o700);
+        assert_eq!(mode(cache.path()) & 0o600, 0o600);
+        assert_eq!(mode(cache.path()) & 0o022, 0);
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_nmap_cache_survives_failure_before_rename() {
+    let dir = cache_dir("crash");
+    let cache = NmapCache::new(dir.join("nmap-services.cache"));
+    cache.save(OLD.as_bytes()).unwrap();
+
+    let err = cache.save_with(&FailingRename, NEW.as_bytes()).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("simulated crash"),
+        "{:#}",
+        err
+    );
+    assert_eq!(fs::read_to_string(cache.path()).unwrap(), OLD);
+    // The temporary file is cleaned up.
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_nmap_cache_load_checks_recorded_sha256() {
+    let dir = cache_dir("sha256");
+    let cache = NmapCache::new(dir.join("nmap-services.cache"));
+    cache.save(OLD.as_bytes()).unwrap();
+    let load = || cache.load(Duration::from_secs(1), &Reporter::silent());
+    assert_eq!(load().unwrap(), OLD.as_bytes());
+
+    // Changed behind portpick's back.
+    fs::write(cache.path(), NEW).unwrap();
+    let err = load().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(
+        err.to_string().contains(&sha256_hex(OLD.as_bytes())),
+        "{}",
+        err
+    );
+
+    // A cache saved before hashes were recorded is read as it is.
+    fs::remove_file(cache.digest_path()).unwrap();
+    assert_eq!(load().unwrap(), NEW.as_bytes());
+
+    cache.save(OLD.as_bytes()).unwrap();
+    cache.remove().unwrap();
+    assert!(!cache.path().exists());
+    assert!(!cache.digest_path().exists());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_nmap_cache_refuses_invalid_content() {
+    let dir = cache_dir("invalid");
+    let cache = NmapCache::new(dir.join("nmap-services.cache"));
+    cache.save(OLD.as_bytes()).unwrap();
+
+    for content in [
+        &b"<html><body>Sign in to continue</body></html>"[..],
+        &b"\x1f\x8b\x08\x00compressed"[..],
+        &b""[..],
+    ] {
+        let err = cache.save(content).unwrap_err();
+        assert!(err.to_string().contains("Refusing to replace"), "{}", err);
+    }
+    assert_eq!(fs::read_to_string(cache.path()).unwrap(), OLD);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_nmap_cache_age() {
+    let dir = cache_dir("age");
+    let cache = NmapCache::new(dir.join("nmap-services.cache"));
+    let now = SystemTime::now();
+    assert_eq!(cache.age(now), None);
+    assert!(!cache.is_stale(Duration::ZERO, now));
+
+    cache.save(OLD.as_bytes()).unwrap();
+    let week = Duration::from_secs(7 * 24 * 60 * 60);
+    fs::File::options()
+        .write(true)
+        .open(cache.path())
+        .unwrap()
+        .set_modified(now - week - Duration::from_secs(60))
+        .unwrap();
+    let age = cache.age(now).unwrap();
+    assert!(age > week, "{:?}", age);
+    assert!(cache.is_stale(week, now));
+    assert!(!cache.is_stale(2 * week, now));
+    assert_eq!(describe_age(age), "7 days");
+    assert_eq!(describe_age(Duration::from_secs(5 * 60 * 60)), "5 hours");
+    assert_eq!(describe_age(Duration::from_secs(90)), "1 minute");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+fn download(url: &str, max_size: u64, sink: &mut dyn Write) -> anyhow::Result<Vec<u8>> {
+    let client = client_builder().build()?;
+    download_services_to(&client, url, &ProgressBar::hidden(), max_size, sink)
+        .map(|downloaded| downloaded.content)
+}
+
+/// Serves `NEW` to every request, slowly enough that concurrent refreshes overlap, counting
+/// the requests. Returns the URL.
+fn serve_counting(requests: Arc<AtomicUsize>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/nmap-services", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            requests.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(300));
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                NEW.len(),
+                NEW
+            );
+        }
+    });
+    url
+}
+
+#[test]
+fn test_concurrent_refreshes_download_once() {
+    let dir = cache_dir("concurrent");
+    let requests = Arc::new(AtomicUsize::new(0));
+    let url = serve_counting(Arc::clone(&requests));
+    let barrier = Arc::new(Barrier::new(2));
+
+    let refreshes: Vec<_> = (0..2)
+        .map(|_| {
+            let cache = NmapCache::new(dir.join("nmap-services.cache"));
+            let url = url.clone();
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                cache
+                    .refresh(Duration::from_secs(10), &Reporter::silent(), |sink| {
+                        download(&url, DEFAULT_MAX_DOWNLOAD_SIZE, sink)
+                    })
+                    .unwrap()
+            })
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect();
+
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    let fetched = refreshes
+        .iter()
+        .filter(|refresh| matches!(refresh, Refresh::Fetched { saved: Ok(()), .. }))
+        .count();
+    let reused = refreshes
+        .iter()
+        .filter(|refresh| matches!(refresh, Refresh::Reused(_)))
+        .count();
+    assert_eq!((fetched, reused), (1, 1));
+    for refresh in &refreshes {
+        assert_eq!(refresh.content(), NEW.as_bytes());
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_refresh_streams_into_cache_and_keeps_it_on_oversized_download() {
+    let dir = cache_dir("stream");
+    let cache = NmapCache::new(dir.join("nmap-services.cache"));
+    cache.save(OLD.as_bytes()).unwrap();
+    let url = serve_counting(Arc::new(AtomicUsize::new(0)));
+
+    let err = cache
+        .refresh(Duration::from_secs(10), &Reporter::silent(), |sink| {
+            download(&url, 16, sink)
+        })
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("larger than the download limit"),
+        "{:#}",
+        err
+    );
+    assert_eq!(fs::read_to_string(cache.path()).unwrap(), OLD);
+    // The cache, its SHA-256, and its lock, without a temporary file.
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+
+    let refresh = cache
+        .refresh(Duration::from_secs(10), &Reporter::silent(), |sink| {
+            download(&url, DEFAULT_MAX_DOWNLOAD_SIZE, sink)
+        })
+        .unwrap();
+    assert!(matches!(refresh, Refresh::Fetched { saved: Ok(()), .. }));
+    assert_eq!(fs::read_to_string(cache.path()).unwrap(), NEW);
+    assert_eq!(
+        fs::read_to_string(cache.digest_path()).unwrap(),
+        sha256_hex(NEW.as_bytes())
+    );
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_refresh_gives_up_after_bounded_wait() {
+    let dir = cache_dir("timeout");
+    let cache = NmapCache::new(dir.join("nmap-services.cache"));
+    cache.save(OLD.as_bytes()).unwrap();
+    // Hold the refresh lock as another run would.
+    let lock = fs::File::create(dir.join("nmap-services.cache.lock")).unwrap();
+    lock.lock().unwrap();
+
+    let out = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let writer = SharedWriter(Arc::clone(&out));
+    let reporter = Reporter::with_writer(false, Box::new(writer));
+    let err = cache
+        .refresh(Duration::from_millis(200), &reporter, |_| {
+            panic!("must not download while another run refreshes")
+        })
+        .unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("still refreshing the cache"),
+        "{:#}",
+        err
+    );
+    let messages = String::from_utf8(out.lock().unwrap().clone()).unwrap();
+    assert!(
+        messages.contains("Another portpick is refreshing the cache"),
+        "{}",
+        messages
+    );
+    // Readers wait for the writer too.
+    let err = cache
+        .load(Duration::from_millis(100), &Reporter::silent())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    drop(lock);
+    assert_eq!(
+        cache
+            .load(Duration::from_millis(100), &Reporter::silent())
+            .unwrap(),
+        OLD.as_bytes()
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+struct SharedWriter(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::{Result, bail};
+use portpick::{
+    Announced, CompletionMessage, DEFAULT_NOTIFY_AFTER, Notifier, notify_completion, should_notify,
+};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Records what it was asked to show.
+#[derive(Default)]
+struct Recorder(Mutex<Vec<(String, String)>>);
+
+impl Notifier for Recorder {
+    fn notify(&self, summary: &str, body: &str) -> Result<()> {
+        self.0
+            .lock()
+            .unwrap()
+            .push((summary.to_string(), body.to_string()));
+        Ok(())
+    }
+}
+
+/// A desktop with no notification daemon.
+struct Unreachable;
+
+impl Notifier for Unreachable {
+    fn notify(&self, _summary: &str, _body: &str) -> Result<()> {
+        bail!("org.freedesktop.Notifications was not provided")
+    }
+}
+
+#[test]
+fn test_should_notify_at_the_threshold() {
+    assert_eq!(DEFAULT_NOTIFY_AFTER, Duration::from_secs(10));
+    assert!(!should_notify(
+        Duration::from_millis(9999),
+        DEFAULT_NOTIFY_AFTER
+    ));
+    assert!(should_notify(Duration::from_secs(10), DEFAULT_NOTIFY_AFTER));
+    assert!(should_notify(Duration::ZERO, Duration::ZERO));
+}
+
+#[test]
+fn test_completion_message() {
+    let message = CompletionMessage::new(&[8000, 8001, 8002], Duration::from_millis(14_600));
+    assert_eq!(message.summary, "portpick finished in 14s");
+    assert_eq!(message.body, "Suggested ports: 8000, 8001, 8002");
+    let message = CompletionMessage::new(&[8080], Duration::from_secs(125));
+    assert_eq!(message.summary, "portpick finished in 2m 5s");
+    assert_eq!(message.body, "Suggested port: 8080");
+    assert_eq!(
+        CompletionMessage::new(&[], Duration::from_secs(60)).body,
+        "No free ports found."
+    );
+}
+
+#[test]
+fn test_notify_completion_only_after_the_threshold() {
+    let recorder = Recorder::default();
+    let mut bell = Vec::new();
+    let threshold = Duration::from_secs(10);
+
+    assert_eq!(
+        notify_completion(
+            &recorder,
+            &mut bell,
+            &[8000],
+            Duration::from_secs(3),
+            threshold
+        ),
+        Announced::NotNeeded
+    );
+    assert!(recorder.0.lock().unwrap().is_empty());
+
+    assert_eq!(
+        notify_completion(
+            &recorder,
+            &mut bell,
+            &[8000],
+            Duration::from_secs(12),
+            threshold
+        ),
+        Announced::Notification
+    );
+    assert_eq!(
+        *recorder.0.lock().unwrap(),
+        [(
+            "portpick finished in 12s".to_string(),
+            "Suggested port: 8000".to_string()
+        )]
+    );
+    assert!(bell.is_empty());
+}
+
+#[test]
+fn test_notify_completion_rings_the_bell_when_unreachable() {
+    let mut bell = Vec::new();
+    assert_eq!(
+        notify_completion(
+            &Unreachable,
+            &mut bell,
+            &[8000],
+            Duration::from_secs(30),
+            Duration::from_secs(10)
+        ),
+        Announced::Bell("org.freedesktop.Notifications was not provided".to_string())
+    );
+    assert_eq!(bell, b"\x07");
+}
@@ -0,0 +1,523 @@
+use portpick::{
+    DEFAULT_ENV_TEMPLATE, EnvCase, EnvNaming, Suggestion, VerificationStatus, env_var_name,
+    env_var_names, render_caddy, render_compose, render_docker_mappings, render_env, render_grep,
+    render_markdown, render_nginx, render_systemd_units, render_template, systemd_escape,
+    template_placeholders, update_env_file, update_managed_block,
+};
+
+fn named(port: u16, name: &str) -> Suggestion {
+    Suggestion {
+        name: Some(name.to_string()),
+        ..Suggestion::new(port)
+    }
+}
+
+#[test]
+fn test_render_compose_two_services() {
+    let suggestions = [named(8080, "web"), named(15432, "db")];
+    let rendered = render_compose(&suggestions, &[80, 5432]);
+    assert_eq!(
+        rendered,
+        "services:\n  web:\n    ports:\n      - \"8080:80\"\n  db:\n    ports:\n      - \"15432:5432\"\n"
+    );
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&rendered).unwrap();
+    assert_eq!(parsed["services"]["web"]["ports"][0], "8080:80");
+    assert_eq!(parsed["services"]["db"]["ports"][0], "15432:5432");
+}
+
+#[test]
+fn test_render_compose_defaults_container_port_to_host() {
+    let rendered = render_compose(&[named(2222, "ssh")], &[]);
+    assert_eq!(
+        rendered,
+        "services:\n  ssh:\n    ports:\n      - \"2222:2222\"\n"
+    );
+}
+
+#[test]
+fn test_render_compose_groups_repeated_names_and_quotes_odd_keys() {
+    let suggestions = [
+        named(9000, "workers"),
+        named(9001, "workers"),
+        named(9002, "yes"),
+        Suggestion::new(9003),
+    ];
+    let rendered = render_compose(&suggestions, &[]);
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&rendered).unwrap();
+    let services = parsed["services"].as_mapping().unwrap();
+    assert_eq!(services.len(), 3);
+    assert_eq!(parsed["services"]["workers"]["ports"][1], "9001:9001");
+    assert_eq!(parsed["services"]["yes"]["ports"][0], "9002:9002");
+    assert_eq!(parsed["services"]["service4"]["ports"][0], "9003:9003");
+    assert!(rendered.contains("  \"yes\":\n"));
+}
+
+#[test]
+fn test_render_markdown_named_golden() {
+    let suggestions = [
+        named(8080, "web"),
+        Suggestion {
+            verification: VerificationStatus::Verified,
+            ..named(15432, "db|replica")
+        },
+    ];
+    assert_eq!(
+        render_markdown(&suggestions, &[80, 5432], 2, false),
+        include_str!("fixtures/markdown_named.golden.md")
+    );
+}
+
+#[test]
+fn test_render_markdown_unnamed_golden() {
+    let suggestions = [
+        Suggestion::new(443),
+        Suggestion::new(40001),
+        Suggestion::new(40003),
+    ];
+    assert_eq!(
+        render_markdown(&suggestions, &[], 4, false),
+        include_str!("fixtures/markdown_unnamed.golden.md")
+    );
+    assert_eq!(
+        render_markdown(&[], &[], 2, false),
+        "No available ports found (2 requested).\n"
+    );
+}
+
+#[test]
+fn test_render_markdown_continuous_golden() {
+    let suggestions = [
+        named(8100, "api"),
+        named(8101, "api"),
+        named(8102, "metrics"),
+    ];
+    assert_eq!(
+        render_markdown(&suggestions, &[], 3, true),
+        include_str!("fixtures/markdown_continuous.golden.md")
+    );
+    // A continuous run that fell back to separate ports has no block to note.
+    let scattered = [Suggestion::new(8100), Suggestion::new(8102)];
+    assert!(!render_markdown(&scattered, &[], 3, true).contains("continuous block"));
+}
+
+#[test]
+fn test_render_grep_fields() {
+    let sources = ["system".to_string(), "local".to_string()];
+    assert_eq!(
+        render_grep(
+            "127.0.0.1",
+            &[
+                Suggestion::new(8080),
+                Suggestion::new(8081),
+                Suggestion::new(8082)
+            ],
+            &sources[..1],
+            1342
+        ),
+        "Host: 127.0.0.1 Ports: 8080,8081,8082 Continuous: yes Source: system Forbidden: 1342\n"
+    );
+    assert_eq!(
+        render_grep(
+            "::1",
+            &[Suggestion::new(9000), Suggestion::new(8999)],
+            &sources,
+            7
+        ),
+        "Host: ::1 Ports: 9000,8999 Continuous: no Source: system,local Forbidden: 7\n"
+    );
+    assert_eq!(
+        render_grep("localhost", &[], &[], 0),
+        "Host: localhost Ports: - Continuous: no Source: - Forbidden: 0\n"
+    );
+}
+
+#[test]
+fn test_render_systemd_units_one_per_port() {
+    let units = render_systemd_units(&[named(8080, "web"), Suggestion::new(9090)], false);
+    assert_eq!(units.len(), 2);
+    assert_eq!(units[0].file_name, "web.socket");
+    assert_eq!(
+        units[0].contents,
+        "[Unit]\nDescription=portpick socket for web\n\n[Socket]\nListenStream=8080\n\n[Install]\nWantedBy=sockets.target\n"
+    );
+    assert_eq!(units[1].file_name, "portpick-9090.socket");
+    assert!(units[1].contents.contains("ListenStream=9090\n"));
+}
+
+#[test]
+fn test_render_systemd_units_one_unit() {
+    let units = render_systemd_units(&[named(8080, "cluster"), named(8081, "other")], true);
+    assert_eq!(units.len(), 1);
+    assert_eq!(units[0].file_name, "cluster.socket");
+    assert_eq!(
+        units[0].contents,
+        "[Unit]\nDescription=portpick socket for cluster\n\n[Socket]\nListenStream=8080\nListenStream=8081\n\n[Install]\nWantedBy=sockets.target\n"
+    );
+    assert!(render_systemd_units(&[], true).is_empty());
+}
+
+#[test]
+fn test_systemd_escape_unusual_names() {
+    assert_eq!(systemd_escape("web"), "web");
+    assert_eq!(systemd_escape("my-app"), "my\\x2dapp");
+    assert_eq!(systemd_escape("a b/c"), "a\\x20b-c");
+    assert_eq!(systemd_escape(".hidden.v2"), "\\x2ehidden.v2");
+    assert_eq!(systemd_escape("café"), "caf\\xc3\\xa9");
+
+    let units = render_systemd_units(&[named(8080, "api\nv2")], false);
+    assert_eq!(units[0].file_name, "api\\x0av2.socket");
+    assert!(
+        units[0]
+            .contents
+            .contains("Description=portpick socket for api v2\n")
+    );
+}
+
+#[test]
+fn test_render_nginx_single_unnamed() {
+    assert_eq!(
+        render_nginx(&[Suggestion::new(8080)], "example.com"),
+        "upstream portpick_8080 {\n    server 127.0.0.1:8080;\n}\n\nserver {\n    listen 80;\n    server_name example.com;\n\n    location / {\n        proxy_pass http://portpick_8080;\n    }\n}\n"
+    );
+}
+
+#[test]
+fn test_render_nginx_one_block_per_name() {
+    let rendered = render_nginx(
+        &[named(8080, "web"), named(8081, "Admin UI")],
+        "example.com",
+    );
+    assert_eq!(
+        rendered,
+        "upstream web {\n    server 127.0.0.1:8080;\n}\n\nserver {\n    listen 80;\n    server_name web.example.com;\n\n    location / {\n        proxy_pass http://web;\n    }\n}\n\nupstream admin_ui {\n    server 127.0.0.1:8081;\n}\n\nserver {\n    listen 80;\n    server_name admin-ui.example.com;\n\n    location / {\n        proxy_pass http://admin_ui;\n    }\n}\n"
+    );
+}
+
+#[test]
+fn test_render_caddy() {
+    assert_eq!(
+        render_caddy(&[named(8080, "web")], "localhost"),
+        "localhost {\n    reverse_proxy :8080\n}\n"
+    );
+    assert_eq!(
+        render_caddy(&[named(8080, "web"), named(5433, "db")], "dev.test"),
+        "web.dev.test {\n    reverse_proxy :8080\n}\n\ndb.dev.test {\n    reverse_proxy :5433\n}\n"
+    );
+}
+
+#[test]
+fn test_env_var_name_matrix() {
+    let naming = |prefix: &str, case: EnvCase, template: &str| EnvNaming {
+        prefix: prefix.to_string(),
+        case,
+        template: template.to_string(),
+    };
+    let default = EnvNaming::default();
+    let cases = [
+        ("web", &default, "WEB_PORT"),
+        ("my-app", &default, "MY_APP_PORT"),
+        ("api.v2 beta", &default, "API_V2_BETA_PORT"),
+        ("3d-viewer", &default, "_3D_VIEWER_PORT"),
+        ("", &default, "PORT"),
+        (
+            "web",
+            &naming("APP_", EnvCase::Upper, DEFAULT_ENV_TEMPLATE),
+            "APP_WEB_PORT",
+        ),
+        (
+            "Web",
+            &naming("", EnvCase::Preserve, DEFAULT_ENV_TEMPLATE),
+            "Web_PORT",
+        ),
+        (
+            "WEB",
+            &naming("vite_", EnvCase::Lower, DEFAULT_ENV_TEMPLATE),
+            "vite_web_port",
+        ),
+        (
+            "web",
+            &naming("", EnvCase::Upper, "PORT_{NAME}"),
+            "PORT_WEB",
+        ),
+        ("", &naming("", EnvCase::Upper, "PORT_{NAME}"), "PORT"),
+        (
+            "vite",
+            &naming("", EnvCase::Upper, "{NAME}_PORT"),
+            "VITE_PORT",
+        ),
+        ("9", &naming("", EnvCase::Upper, "{NAME}"), "_9"),
+    ];
+    for (name, naming, expected) in cases {
+        assert_eq!(env_var_name(name, naming), expected, "name {:?}", name);
+    }
+}
+
+#[test]
+fn test_env_var_names_for_unnamed_suggestions() {
+    let naming = EnvNaming::default();
+    assert_eq!(env_var_names(&[Suggestion::new(8080)], &naming), ["PORT"]);
+    assert_eq!(
+        env_var_names(&[Suggestion::new(8080), Suggestion::new(8081)], &naming),
+        ["PORT_1", "PORT_2"]
+    );
+}
+
+#[test]
+fn test_grouped_names_render_per_group() {
+    let suggestions = [
+        named(8080, "web"),
+        named(9000, "workers"),
+        named(9001, "workers"),
+    ];
+    assert_eq!(
+        env_var_names(&suggestions, &EnvNaming::default()),
+        ["WEB_PORT", "WORKERS_PORT_1", "WORKERS_PORT_2"]
+    );
+    assert_eq!(
+        render_caddy(&suggestions, "dev.test"),
+        "web.dev.test {\n    reverse_proxy :8080\n}\n\nworkers.dev.test {\n    reverse_proxy :9000 :9001\n}\n"
+    );
+    assert!(render_nginx(&suggestions, "dev.test").contains(
+        "upstream workers {\n    server 127.0.0.1:9000;\n    server 127.0.0.1:9001;\n}\n"
+    ));
+    let units = render_systemd_units(&suggestions, false);
+    assert_eq!(units.len(), 2);
+    assert_eq!(units[1].file_name, "workers.socket");
+    assert!(
+        units[1]
+            .contents
+            .contains("ListenStream=9000\nListenStream=9001\n")
+    );
+}
+
+#[test]
+fn test_render_env_and_shell() {
+    let suggestions = [named(8080, "web"), named(5433, "db")];
+    let naming = EnvNaming::default();
+    assert_eq!(
+        render_env(&suggestions, &naming, false),
+        "WEB_PORT=8080\nDB_PORT=5433\n"
+    );
+    assert_eq!(
+        render_env(&suggestions, &naming, true),
+        "export WEB_PORT=8080\nexport DB_PORT=5433\n"
+    );
+}
+
+#[test]
+fn test_update_env_file_replaces_and_appends() {
+    let existing = "# app settings\nDEBUG=1\nWEB_PORT=3000\nexport DB_PORT=5432\n";
+    let updated = update_env_file(
+        existing,
+        &[
+            ("WEB_PORT".to_string(), 8080),
+            ("DB_PORT".to_string(), 5433),
+            ("CACHE_PORT".to_string(), 6380),
+        ],
+    )
+    .unwrap();
+    assert_eq!(
+        updated,
+        "# app settings\nDEBUG=1\nWEB_PORT=8080\nexport DB_PORT=5433\n\
+         # portpick begin\nCACHE_PORT=6380\n# portpick end\n"
+    );
+    // Entries in the block are rewritten with it; a stale copy outside it is updated in place.
+    let updated = update_env_file(
+        &updated,
+        &[
+            ("CACHE_PORT".to_string(), 6381),
+            ("QUEUE_PORT".to_string(), 5673),
+        ],
+    )
+    .unwrap();
+    assert_eq!(
+        updated,
+        "# app settings\nDEBUG=1\nWEB_PORT=8080\nexport DB_PORT=5433\n\
+         # portpick begin\nCACHE_PORT=6381\nQUEUE_PORT=5673\n# portpick end\n"
+    );
+}
+
+#[test]
+fn test_update_env_file_keeps_crlf_and_leaves_files_without_new_entries_alone() {
+    let existing = "DEBUG=1\r\nWEB_PORT=3000\r\n";
+    let updated = update_env_file(existing, &[("WEB_PORT".to_string(), 8080)]).unwrap();
+    assert_eq!(updated, "DEBUG=1\r\nWEB_PORT=8080\r\n");
+}
+
+fn exports(lines: &[&str]) -> Vec<String> {
+    lines.iter().map(|line| line.to_string()).collect()
+}
+
+#[test]
+fn test_update_managed_block_creates_and_replaces_block() {
+    let created = update_managed_block("", &exports(&["export WEB_PORT=8080"])).unwrap();
+    assert_eq!(
+        created,
+        "# portpick begin\nexport WEB_PORT=8080\n# portpick end\n"
+    );
+
+    // Everything around the block survives byte for byte, including a missing final newline.
+    let existing = "use flake\n\n# portpick begin\nexport OLD_PORT=1\n# portpick end\nexport PATH=$PWD/bin:$PATH";
+    let updated = update_managed_block(
+        existing,
+        &exports(&["export WEB_PORT=8080", "export DB_PORT=5433"]),
+    )
+    .unwrap();
+    assert_eq!(
+        updated,
+        "use flake\n\n# portpick begin\nexport WEB_PORT=8080\nexport DB_PORT=5433\n# portpick end\nexport PATH=$PWD/bin:$PATH"
+    );
+
+    let appended = update_managed_block("dotenv", &exports(&["export WEB_PORT=8080"])).unwrap();
+    assert_eq!(
+        appended,
+        "dotenv\n# portpick begin\nexport WEB_PORT=8080\n# portpick end\n"
+    );
+}
+
+#[test]
+fn test_update_managed_block_keeps_crlf_line_endings() {
+    let existing =
+        "use nix\r\n# portpick begin\r\nexport OLD_PORT=1\r\n# portpick end\r\nlayout python\r\n";
+    let updated = update_managed_block(existing, &exports(&["export WEB_PORT=8080"])).unwrap();
+    assert_eq!(
+        updated,
+        "use nix\r\n# portpick begin\r\nexport WEB_PORT=8080\r\n# portpick end\r\nlayout python\r\n"
+    );
+    let appended =
+        update_managed_block("use nix\r\n", &exports(&["export WEB_PORT=8080"])).unwrap();
+    assert_eq!(
+        appended,
+        "use nix\r\n# portpick begin\r\nexport WEB_PORT=8080\r\n# portpick end\r\n"
+    );
+}
+
+#[test]
+fn test_update_managed_block_rejects_malformed_markers() {
+    let body = exports(&["export WEB_PORT=8080"]);
+    for (content, expected) in [
+        (
+            "# portpick begin\n# portpick begin\nexport A=1\n# portpick end\n",
+            "'# portpick begin' on line 2 is inside the block opened on line 1",
+        ),
+        (
+            "use nix\n# portpick begin\nexport A=1\n",
+            "'# portpick begin' on line 2 has no matching '# portpick end'",
+        ),
+        (
+            "export A=1\n# portpick end\n",
+            "'# portpick end' on line 2 has no '# portpick begin' before it",
+        ),
+        (
+            "# portpick begin\n# portpick end\n# portpick begin\n# portpick end\n",
+            "A second '# portpick begin' block starts on line 3",
+        ),
+    ] {
+        let err = update_managed_block(content, &body).unwrap_err();
+        assert!(err.to_string().contains(expected), "{}", err);
+        assert!(update_env_file(content, &[("A".to_string(), 1)]).is_err());
+    }
+}
+
+#[test]
+fn test_template_placeholders_number_shared_names() {
+    let suggestions = [
+        named(8080, "web"),
+        named(9000, "workers"),
+        named(9001, "workers"),
+    ];
+    assert_eq!(
+        template_placeholders(&suggestions),
+        vec![
+            ("PORT_WEB".to_string(), 8080),
+            ("PORT_WORKERS_1".to_string(), 9000),
+            ("PORT_WORKERS_2".to_string(), 9001),
+        ]
+    );
+}
+
+#[test]
+fn test_render_template_substitutes_every_occurrence() {
+    let values = template_placeholders(&[named(8080, "web"), named(15432, "db")]);
+    let template = "web: {{PORT_WEB}}:80\nhealth: localhost:{{ PORT_WEB }}\ndb: {{PORT_DB}}\n";
+    assert_eq!(
+        render_template(template, &values, false).unwrap(),
+        "web: 8080:80\nhealth: localhost:8080\ndb: 15432\n"
+    );
+}
+
+#[test]
+fn test_render_template_unknown_placeholders() {
+    let values = template_placeholders(&[named(8080, "web")]);
+    let template = "{{PORT_WEB}} {{PORT_API}} {{PORT_DB}} {{PORT_API}}";
+    let err = render_template(template, &values, false).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Unknown placeholder(s) {{PORT_API}}, {{PORT_DB}} (known: PORT_WEB)"
+    );
+    assert_eq!(
+        render_template(template, &values, true).unwrap(),
+        "8080 {{PORT_API}} {{PORT_DB}} {{PORT_API}}"
+    );
+}
+
+#[test]
+fn test_render_template_leaves_other_braces_alone() {
+    let values = template_placeholders(&[named(8080, "web")]);
+    let template = "{{ .Name }} {{}} {{PORT_WEB}} unclosed {{PORT_WEB";
+    assert_eq!(
+        render_template(template, &values, false).unwrap(),
+        "{{ .Name }} {{}} 8080 unclosed {{PORT_WEB"
+    );
+}
+
+#[test]
+fn test_render_docker_mappings_without_base_leaves_container_side_empty() {
+    assert_eq!(
+        render_docker_mappings(&[8080, 8081], None, false).unwrap(),
+        ["8080:", "8081:"]
+    );
+}
+
+#[test]
+fn test_render_docker_mappings_pairs_in_output_order() {
+    // Container ports count up in the order the host ports are given, gaps or not.
+    assert_eq!(
+        render_docker_mappings(&[40003, 40000, 40001], Some(8000), false).unwrap(),
+        ["40003:8000", "40000:8001", "40001:8002"]
+    );
+}
+
+#[test]
+fn test_render_docker_mappings_range_syntax() {
+    assert_eq!(
+        render_docker_mappings(&[40006, 40007, 40008], Some(8000), true).unwrap(),
+        ["40006-40008:8000-8002"]
+    );
+    assert_eq!(
+        render_docker_mappings(&[40006, 40007, 40008], Some(8000), false).unwrap(),
+        ["40006:8000", "40007:8001", "40008:8002"]
+    );
+    // Gaps and descending order can't be written as one range.
+    assert_eq!(
+        render_docker_mappings(&[40000, 40001, 40003], Some(8000), true).unwrap(),
+        ["40000:8000", "40001:8001", "40003:8002"]
+    );
+    assert_eq!(
+        render_docker_mappings(&[40001, 40000], Some(8000), true).unwrap(),
+        ["40001:8000", "40000:8001"]
+    );
+    assert_eq!(
+        render_docker_mappings(&[40000], Some(8000), true).unwrap(),
+        ["40000:8000"]
+    );
+}
+
+#[test]
+fn test_render_docker_mappings_rejects_container_overflow() {
+    assert!(render_docker_mappings(&[40000, 40001], Some(65535), false).is_err());
+    assert_eq!(
+        render_docker_mappings(&[40000], Some(65535), false).unwrap(),
+        ["40000:65535"]
+    );
+}
@@ -0,0 +1,611 @@
+use anyhow::Result;
+use portpick::{
+    BlockStrategy, PickOptions, PortSource, Protocol, RejectionReason, Reporter, ScannerChoice,
+    SortOrder, SourceKind, Suggestion, timing_summary,
+};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A source answering with a fixed set of ports.
+struct Fixed {
+    name: &'static str,
+    reason: RejectionReason,
+    ports: Vec<u16>,
+}
+
+impl PortSource for Fixed {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn rejection_reason(&self) -> RejectionReason {
+        self.reason.clone()
+    }
+
+    fn collect(&mut self, _names: &mut HashMap<u16, Vec<String>>) -> Result<HashSet<u16>> {
+        Ok(self.ports.iter().copied().collect())
+    }
+}
+
+/// A source that takes `delay` to answer with no ports, like a slow scan.
+struct Slow {
+    delay: Duration,
+}
+
+impl PortSource for Slow {
+    fn name(&self) -> &str {
+        "slow"
+    }
+
+    fn rejection_reason(&self) -> RejectionReason {
+        RejectionReason::LocallyInUse
+    }
+
+    fn collect(&mut self, _names: &mut HashMap<u16, Vec<String>>) -> Result<HashSet<u16>> {
+        std::thread::sleep(self.delay);
+        Ok(HashSet::new())
+    }
+}
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+#[test]
+fn test_empty_document_is_the_default() {
+    let options: PickOptions = serde_yaml::from_str("{}").unwrap();
+    assert_eq!(options, PickOptions::default());
+    assert_eq!(options.sources, vec![SourceKind::System, SourceKind::Local]);
+    assert_eq!(options.requested().unwrap(), 1);
+}
+
+#[test]
+fn test_serde_round_trip() {
+    let options = PickOptions {
+        sources: vec![SourceKind::System, SourceKind::Nmap],
+        nmap_services_file: Some(PathBuf::from("/tmp/nmap-services")),
+        bind_address: Some("127.0.0.1".parse().unwrap()),
+        scanner: ScannerChoice::Ss,
+        protocol: Protocol::Udp,
+        within: Some("9000-9999".to_string()),
+        exclude: Some("9100".to_string()),
+        count: Some(3),
+        continuous: true,
+        strategy: BlockStrategy::Best,
+        seed: Some(7),
+        sort: SortOrder::Desc,
+        avoid_family: 2,
+        ..PickOptions::default()
+    };
+    let yaml = serde_yaml::to_string(&options).unwrap();
+    assert_eq!(serde_yaml::from_str::<PickOptions>(&yaml).unwrap(), options);
+    let json = serde_json::to_string(&options).unwrap();
+    assert_eq!(serde_json::from_str::<PickOptions>(&json).unwrap(), options);
+    assert!(yaml.contains("strategy: best"), "{}", yaml);
+}
+
+#[test]
+fn test_from_config_file_style_yaml() {
+    let options: PickOptions = serde_yaml::from_str(
+        "sources: [system]\nwithin: 40000-40009\nnames: [web, db]\nsort: desc\n",
+    )
+    .unwrap();
+    assert_eq!(options.requested().unwrap(), 2);
+    assert_eq!(options.ranges().unwrap(), vec![(40000, 40009)]);
+    assert!(serde_yaml::from_str::<PickOptions>("colour: red\n").is_err());
+    assert!(serde_yaml::from_str::<PickOptions>("strategy: widest\n").is_err());
+}
+
+#[test]
+fn test_allow_privileged_widens_only_the_default_ranges() {
+    let privileged = PickOptions {
+        allow_privileged: true,
+        ..PickOptions::default()
+    };
+    assert_eq!(
+        privileged.ranges().unwrap(),
+        vec![(1, 49151), (49152, 65535)]
+    );
+    let within = PickOptions {
+        within: Some("40000-40009".to_string()),
+        ..privileged
+    };
+    assert_eq!(within.ranges().unwrap(), vec![(40000, 40009)]);
+    assert_eq!(
+        PickOptions::default().ranges().unwrap(),
+        vec![(1024, 49151), (49152, 65535)]
+    );
+}
+
+#[test]
+fn test_default_search_starts_at_unprivileged_port_start() {
+    let lowered = PickOptions {
+        unprivileged_port_start: Some(80),
+        ..PickOptions::default()
+    };
+    assert_eq!(lowered.ranges().unwrap(), vec![(80, 49151), (49152, 65535)]);
+    assert_eq!(
+        lowered.default_search_start(),
+        (80, "net.ipv4.ip_unprivileged_port_start is 80".to_string())
+    );
+    // Rootless containers allow every port.
+    let rootless = PickOptions {
+        unprivileged_port_start: Some(0),
+        ..PickOptions::default()
+    };
+    assert_eq!(rootless.ranges().unwrap()[0], (1, 49151));
+    let raised = PickOptions {
+        unprivileged_port_start: Some(2000),
+        ..PickOptions::default()
+    };
+    assert_eq!(raised.ranges().unwrap()[0], (2000, 49151));
+
+    // --allow-privileged and --within both win over it.
+    let privileged = PickOptions {
+        allow_privileged: true,
+        ..lowered.clone()
+    };
+    assert_eq!(privileged.ranges().unwrap()[0], (1, 49151));
+    assert_eq!(privileged.default_search_start().1, "--allow-privileged");
+    let within = PickOptions {
+        within: Some("3000-3999".to_string()),
+        ..lowered
+    };
+    assert_eq!(within.ranges().unwrap(), vec![(3000, 3999)]);
+    assert_eq!(
+        PickOptions::default().default_search_start(),
+        (1024, "ports below it need privileges to bind".to_string())
+    );
+}
+
+#[test]
+fn test_requested_follows_names_and_spec() {
+    let named = PickOptions {
+        count: Some(3),
+        names: vec!["a".to_string()],
+        ..PickOptions::default()
+    };
+    assert!(named.requested().is_err());
+    let spec = PickOptions {
+        spec: Some("web:1,workers:3c".to_string()),
+        ..PickOptions::default()
+    };
+    assert_eq!(spec.requested().unwrap(), 4);
+}
+
+#[test]
+fn test_resolve_with_in_memory_sources() {
+    let options = PickOptions {
+        within: Some("40000-40009".to_string()),
+        exclude: Some("40004".to_string()),
+        names: vec!["web".to_string(), "db".to_string(), "cache".to_string()],
+        sort: SortOrder::Desc,
+        ..PickOptions::default()
+    };
+    let sources: Vec<Box<dyn PortSource>> = vec![
+        Box::new(Fixed {
+            name: "system",
+            reason: RejectionReason::KnownService,
+            ports: vec![40001, 40003],
+        }),
+        Box::new(Fixed {
+            name: "local",
+            reason: RejectionReason::LocallyInUse,
+            ports: vec![40000, 40003],
+        }),
+    ];
+    let outcome = options.resolve_with(sources, &Reporter::silent()).unwrap();
+
+    let picked: Vec<(u16, Option<&str>)> = outcome
+        .suggestions
+        .iter()
+        .map(|suggestion| (suggestion.port, suggestion.name.as_deref()))
+        .collect();
+    // First fit is 40002, 40005, 40006, named in that order, then sorted descending.
+    assert_eq!(
+        picked,
+        vec![
+            (40006, Some("cache")),
+            (40005, Some("db")),
+            (40002, Some("web"))
+        ]
+    );
+    assert_eq!(outcome.requested, 3);
+    let breakdown = outcome.sources.unwrap();
+    assert_eq!(breakdown.summary(), "system 2, local 2; overlap 1; total 3");
+}
+
+#[test]
+fn test_resolve_times_each_phase() {
+    let options = PickOptions {
+        within: Some("40000-40009".to_string()),
+        ..PickOptions::default()
+    };
+    let sources: Vec<Box<dyn PortSource>> = vec![
+        Box::new(Fixed {
+            name: "system",
+            reason: RejectionReason::KnownService,
+            ports: vec![40001],
+        }),
+        Box::new(Slow {
+            delay: Duration::from_millis(300),
+        }),
+    ];
+    let outcome = options.resolve_with(sources, &Reporter::silent()).unwrap();
+
+    let phases: Vec<&str> = outcome
+        .timings
+        .iter()
+        .map(|timing| timing.phase.as_str())
+        .collect();
+    assert_eq!(phases, vec!["system", "slow", "selection"]);
+    let slow = outcome.timings[1].duration();
+    assert!(slow >= Duration::from_millis(300), "{:?}", outcome.timings);
+    let rest: Duration = outcome
+        .timings
+        .iter()
+        .filter(|timing| timing.phase != "slow")
+        .map(|timing| timing.duration())
+        .sum();
+    assert!(slow > rest * 10, "{:?}", outcome.timings);
+    assert!(
+        timing_summary(&outcome.timings).starts_with("slow: 3"),
+        "{}",
+        timing_summary(&outcome.timings)
+    );
+}
+
+#[test]
+fn test_resolve_reads_listed_services_files() {
+    let options = PickOptions {
+        sources: vec![SourceKind::System],
+        services_file: fixture("services"),
+        within: Some("40000-40009".to_string()),
+        count: Some(3),
+        continuous: true,
+        ..PickOptions::default()
+    };
+    let ports: Vec<u16> = options
+        .resolve()
+        .unwrap()
+        .suggestions
+        .iter()
+        .map(|suggestion| suggestion.port)
+        .collect();
+    // The fixture forbids 40002 and 40005 over TCP.
+    assert_eq!(ports, vec![40006, 40007, 40008]);
+
+    let udp = PickOptions {
+        protocol: Protocol::Udp,
+        count: Some(1),
+        within: Some("40007-40008".to_string()),
+        ..options.clone()
+    };
+    assert_eq!(udp.resolve().unwrap().suggestions[0].port, 40008);
+
+    let udp_with_scan = PickOptions {
+        sources: vec![SourceKind::Local],
+        ..udp
+    };
+    assert!(udp_with_scan.resolve().is_err());
+}
+
+#[test]
+fn test_resolve_sorts_by_frequency_from_the_services_file() {
+    let options = PickOptions {
+        sources: vec![SourceKind::System],
+        services_file: fixture("nmap-services-frequencies"),
+        within: Some("8077-8083".to_string()),
+        count: Some(4),
+        sort: SortOrder::Frequency,
+        ..PickOptions::default()
+    };
+    let ports: Vec<u16> = options
+        .resolve()
+        .unwrap()
+        .suggestions
+        .iter()
+        .map(|suggestion| suggestion.port)
+        .collect();
+    assert_eq!(ports, vec![8077, 8078, 8079, 8082]);
+
+    let without_frequencies = PickOptions {
+        services_file: fixture("services"),
+        ..options
+    };
+    assert!(without_frequencies.resolve().is_err());
+}
+
+/// A check answering from `busy`, counting the ports it was asked about.
+fn counting_check(
+    busy: HashSet<u16>,
+    asked: &mut Vec<u16>,
+) -> impl FnMut(&[u16]) -> Result<HashSet<u16>> + '_ {
+    move |ports: &[u16]| {
+        asked.extend_from_slice(ports);
+        Ok(ports
+            .iter()
+            .copied()
+            .filter(|port| busy.contains(port))
+            .collect())
+    }
+}
+
+#[test]
+fn test_pick_fast_checks_few_ports_and_avoids_busy_ones() {
+    let options = PickOptions {
+        count: Some(3),
+        ..PickOptions::default()
+    };
+    // The first 40 ports of the default ranges are in use.
+    let busy: HashSet<u16> = (1024..1064).collect();
+    let mut asked = Vec::new();
+    let (suggestions, _, stats) = options
+        .pick_fast(
+            &HashSet::from([1030]),
+            &Default::default(),
+            &HashMap::new(),
+            &Reporter::silent(),
+            &mut counting_check(busy.clone(), &mut asked),
+        )
+        .unwrap();
+    let ports: Vec<u16> = suggestions
+        .iter()
+        .map(|suggestion| suggestion.port)
+        .collect();
+    assert_eq!(ports, vec![1064, 1065, 1066]);
+    assert_eq!(stats.checked, asked.len());
+    assert_eq!(stats.busy, 39);
+    assert!(asked.len() < 200, "checked {} ports", asked.len());
+    assert!(
+        !asked.contains(&1030),
+        "known forbidden ports are not checked"
+    );
+    // Busy neighbors are explained like a full scan would.
+    assert_eq!(
+        suggestions[0].rejected_neighbors[0].reasons,
+        vec![RejectionReason::LocallyInUse]
+    );
+}
+
+#[test]
+fn test_pick_fast_matches_a_full_scan() {
+    let busy: HashSet<u16> = [8001, 8004, 8005, 8009, 8010].into();
+    for options in [
+        PickOptions {
+            count: Some(3),
+            within: Some("8000-8020".to_string()),
+            continuous: true,
+            ..PickOptions::default()
+        },
+        PickOptions {
+            spec: Some("web:2c,db:1".to_string()),
+            within: Some("8000-8020".to_string()),
+            ..PickOptions::default()
+        },
+        PickOptions {
+            count: Some(4),
+            within: Some("8000-8020".to_string()),
+            random: true,
+            seed: Some(7),
+            ..PickOptions::default()
+        },
+    ] {
+        let mut asked = Vec::new();
+        let (fast, _, _) = options
+            .pick_fast(
+                &HashSet::new(),
+                &Default::default(),
+                &HashMap::new(),
+                &Reporter::silent(),
+                &mut counting_check(busy.clone(), &mut asked),
+            )
+            .unwrap();
+        let (full, _) = options
+            .pick(
+                &busy,
+                &Default::default(),
+                &HashMap::new(),
+                &Reporter::silent(),
+            )
+            .unwrap();
+        let ports = |suggestions: &[portpick::Suggestion]| -> Vec<u16> {
+            suggestions
+                .iter()
+                .map(|suggestion| suggestion.port)
+                .collect()
+        };
+        assert_eq!(ports(&fast), ports(&full), "{options:?}");
+        assert!(ports(&fast).iter().all(|port| asked.contains(port)));
+    }
+}
+
+#[test]
+fn test_pick_fast_random_without_seed_never_picks_busy_ports() {
+    let options = PickOptions {
+        count: Some(5),
+        within: Some("9000-9099".to_string()),
+        random: true,
+        ..PickOptions::default()
+    };
+    let busy: HashSet<u16> = (9000..9100).filter(|port| port % 3 != 0).collect();
+    let mut asked = Vec::new();
+    let (suggestions, _, _) = options
+        .pick_fast(
+            &HashSet::new(),
+            &Default::default(),
+            &HashMap::new(),
+            &Reporter::silent(),
+            &mut counting_check(busy.clone(), &mut asked),
+        )
+        .unwrap();
+    assert_eq!(suggestions.len(), 5);
+    for suggestion in &suggestions {
+        assert!(!busy.contains(&suggestion.port));
+        assert!(asked.contains(&suggestion.port));
+    }
+}
+
+/// Every suggested port and alternate, in report order.
+fn picked_and_alternates(suggestions: &[Suggestion]) -> Vec<u16> {
+    suggestions
+        .iter()
+        .flat_map(|suggestion| {
+            std::iter::once(suggestion.port).chain(suggestion.alternates.iter().copied())
+        })
+        .collect()
+}
+
+fn pick_with(options: PickOptions, forbidden: &[u16]) -> Vec<Suggestion> {
+    let forbidden: HashSet<u16> = forbidden.iter().copied().collect();
+    let (suggestions, _) = options
+        .pick(
+            &forbidden,
+            &Default::default(),
+            &HashMap::new(),
+            &Reporter::silent(),
+        )
+        .unwrap();
+    suggestions
+}
+
+#[test]
+fn test_alternates_overlap_nothing() {
+    let forbidden = [40002, 40005];
+    let suggestions = pick_with(
+        PickOptions {
+            within: Some("40000-40019".to_string()),
+            count: Some(3),
+            alternates: 2,
+            ..PickOptions::default()
+        },
+        &forbidden,
+    );
+    let all = picked_and_alternates(&suggestions);
+    assert_eq!(all.len(), 9);
+    assert_eq!(all.iter().collect::<HashSet<_>>().len(), all.len());
+    assert!(all.iter().all(|port| !forbidden.contains(port)));
+    // Dealt a round at a time, first fit.
+    let alternates: Vec<&[u16]> = suggestions
+        .iter()
+        .map(|s| s.alternates.as_slice())
+        .collect();
+    assert_eq!(
+        alternates,
+        vec![
+            &[40004, 40008][..],
+            &[40006, 40009][..],
+            &[40007, 40010][..]
+        ]
+    );
+}
+
+#[test]
+fn test_alternates_of_a_block_are_blocks() {
+    let suggestions = pick_with(
+        PickOptions {
+            within: Some("40000-40019".to_string()),
+            count: Some(3),
+            continuous: true,
+            alternates: 2,
+            sort: SortOrder::Desc,
+            ..PickOptions::default()
+        },
+        &[40004],
+    );
+    let ports: Vec<u16> = suggestions.iter().map(|s| s.port).collect();
+    assert_eq!(ports, vec![40002, 40001, 40000]);
+    // Each primary keeps its offset in the backup block, whatever the sort order.
+    let alternates: Vec<&[u16]> = suggestions
+        .iter()
+        .map(|s| s.alternates.as_slice())
+        .collect();
+    assert_eq!(
+        alternates,
+        vec![
+            &[40007, 40010][..],
+            &[40006, 40009][..],
+            &[40005, 40008][..]
+        ]
+    );
+}
+
+#[test]
+fn test_alternates_of_groups_avoid_every_group() {
+    let suggestions = pick_with(
+        PickOptions {
+            within: Some("40000-40029".to_string()),
+            spec: Some("web:1,workers:3c".to_string()),
+            alternates: 2,
+            ..PickOptions::default()
+        },
+        &[],
+    );
+    let all = picked_and_alternates(&suggestions);
+    assert_eq!(all.len(), 12);
+    assert_eq!(all.iter().collect::<HashSet<_>>().len(), all.len());
+    let workers = &suggestions[1..];
+    for round in 0..2 {
+        let block: Vec<u16> = workers.iter().map(|s| s.alternates[round]).collect();
+        assert!(
+            block.windows(2).all(|pair| pair[1] == pair[0] + 1),
+            "{:?}",
+            block
+        );
+    }
+}
+
+#[test]
+fn test_alternates_stop_when_the_ranges_run_out() {
+    let suggestions = pick_with(
+        PickOptions {
+            within: Some("40000-40005".to_string()),
+            count: Some(2),
+            alternates: 3,
+            ..PickOptions::default()
+        },
+        &[40003],
+    );
+    let all = picked_and_alternates(&suggestions);
+    assert_eq!(all.iter().collect::<HashSet<_>>().len(), all.len());
+    assert_eq!(suggestions[0].alternates, vec![40002, 40005]);
+    assert_eq!(suggestions[1].alternates, vec![40004]);
+
+    // A block with no room for a backup block gets none.
+    let suggestions = pick_with(
+        PickOptions {
+            within: Some("40000-40005".to_string()),
+            count: Some(3),
+            continuous: true,
+            alternates: 1,
+            ..PickOptions::default()
+        },
+        &[40003],
+    );
+    assert!(suggestions.iter().all(|s| s.alternates.is_empty()));
+}
+
+#[test]
+fn test_pick_fast_checks_alternates_too() {
+    let options = PickOptions {
+        count: Some(1),
+        alternates: 2,
+        ..PickOptions::default()
+    };
+    let busy: HashSet<u16> = [1025, 1026].into_iter().collect();
+    let mut asked = Vec::new();
+    let (suggestions, _, _) = options
+        .pick_fast(
+            &HashSet::new(),
+            &Default::default(),
+            &HashMap::new(),
+            &Reporter::silent(),
+            &mut counting_check(busy.clone(), &mut asked),
+        )
+        .unwrap();
+    assert_eq!(suggestions[0].port, 1024);
+    assert_eq!(suggestions[0].alternates, vec![1027, 1028]);
+}
@@ -0,0 +1,131 @@
+use portpick::{
+    BlockStrategy, BuiltinServicesSource, PickOptions, PortSource, ProfileSource, Reporter,
+    ScannerChoice, SortOrder, SourceKind,
+};
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[test]
+fn test_plan_lists_sources_without_running_them() {
+    let options = PickOptions {
+        sources: vec![SourceKind::System, SourceKind::Local],
+        services_file: PathBuf::from("/nonexistent/services"),
+        address: Some("10.0.0.5".to_string()),
+        scanner: ScannerChoice::Nmap,
+        within: Some("3000-3999".to_string()),
+        exclude: Some("3000-3009".to_string()),
+        profiles: vec!["devtools".to_string()],
+        count: Some(2),
+        ..PickOptions::default()
+    };
+    let sources = options.sources(&Arc::new(Reporter::silent())).unwrap();
+    // Planning reads nothing, so a missing services file is no error.
+    let plan = options.plan(&sources).unwrap();
+
+    let located: Vec<(&str, Option<&str>)> = plan
+        .sources
+        .iter()
+        .map(|source| (source.name.as_str(), source.location.as_deref()))
+        .collect();
+    assert_eq!(
+        located,
+        vec![
+            ("system", Some("/nonexistent/services")),
+            ("local", Some("nmap on 10.0.0.5")),
+            ("profile", Some("profiles devtools (18 ports)")),
+        ]
+    );
+    // devtools holds 3000 and 3306.
+    assert_eq!(plan.ranges, "3010-3305,3307-3999");
+    assert_eq!(plan.searchable, 989);
+    assert_eq!(plan.requested, 2);
+    assert_eq!(plan.selection, "first fit, sorted asc");
+    assert!(plan.render().contains("  local     nmap on 10.0.0.5\n"));
+}
+
+#[test]
+fn test_plan_describes_selection() {
+    let sources: Vec<Box<dyn PortSource>> = vec![
+        Box::new(BuiltinServicesSource),
+        Box::new(ProfileSource::new(&["devtools"]).unwrap()),
+    ];
+    let selection = |options: PickOptions| {
+        let options = PickOptions {
+            within: Some("40000-40009".to_string()),
+            ..options
+        };
+        options.plan(&sources).unwrap().selection
+    };
+
+    assert_eq!(
+        selection(PickOptions {
+            continuous: true,
+            strategy: BlockStrategy::Best,
+            ..PickOptions::default()
+        }),
+        "continuous block, best fit, sorted asc"
+    );
+    assert_eq!(
+        selection(PickOptions {
+            random: true,
+            seed: Some(7),
+            ..PickOptions::default()
+        }),
+        "at random, seed 7, sorted asc"
+    );
+    assert_eq!(
+        selection(PickOptions {
+            verify: true,
+            continuous: true,
+            avoid_family: 2,
+            sort: SortOrder::Desc,
+            ..PickOptions::default()
+        }),
+        "first fit, bind-checked, continuous, avoiding 2 around services, sorted desc"
+    );
+    assert_eq!(
+        selection(PickOptions {
+            alternates: 2,
+            ..PickOptions::default()
+        }),
+        "first fit, 2 alternate(s) each, sorted asc"
+    );
+    assert_eq!(
+        selection(PickOptions {
+            spec: Some("web:1,workers:3c".to_string()),
+            ..PickOptions::default()
+        }),
+        "per group of 'web:1,workers:3c', sorted asc"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_planned_scanner_finds_auto_candidate_on_path() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("portpick-plan-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let empty = OsStr::new("");
+    assert_eq!(
+        ScannerChoice::Auto.planned(None, Some(empty)),
+        "none (rustscan, nmap, and masscan aren't installed)"
+    );
+    let masscan = dir.join("masscan");
+    std::fs::write(&masscan, "#!/bin/sh\n").unwrap();
+    std::fs::set_permissions(&masscan, std::fs::Permissions::from_mode(0o755)).unwrap();
+    assert_eq!(
+        ScannerChoice::Auto.planned(None, Some(dir.as_os_str())),
+        "masscan"
+    );
+    assert_eq!(
+        ScannerChoice::Auto.planned(Some("127.0.0.1".parse().unwrap()), Some(empty)),
+        "ss, bind address 127.0.0.1"
+    );
+    assert_eq!(
+        ScannerChoice::Rustscan.planned(None, Some(empty)),
+        "rustscan"
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,262 @@
+use portpick::{PortSet, parse_port_spec};
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
+fn ranges(set: &PortSet) -> Vec<RangeInclusive<u16>> {
+    set.ranges().collect()
+}
+
+#[test]
+fn test_insert_coalesces_adjacent_ports() {
+    let mut set = PortSet::new();
+    set.insert(10);
+    set.insert(12);
+    assert_eq!(ranges(&set), vec![10..=10, 12..=12]);
+    set.insert(11);
+    assert_eq!(ranges(&set), vec![10..=12]);
+    assert_eq!(set.len(), 3);
+}
+
+#[test]
+fn test_insert_range_merges_adjacent_ranges() {
+    let mut set = PortSet::new();
+    set.insert_range(100..=199);
+    set.insert_range(200..=299);
+    set.insert_range(50..=99);
+    assert_eq!(ranges(&set), vec![50..=299]);
+}
+
+#[test]
+fn test_insert_range_overlapping_and_bridging() {
+    let mut set = PortSet::new();
+    set.insert_range(10..=20);
+    set.insert_range(30..=40);
+    set.insert_range(50..=60);
+    set.insert_range(15..=35);
+    assert_eq!(ranges(&set), vec![10..=40, 50..=60]);
+    // Swallowing several existing ranges at once.
+    set.insert_range(0..=100);
+    assert_eq!(ranges(&set), vec![0..=100]);
+    // Fully contained inserts change nothing.
+    set.insert_range(20..=30);
+    set.insert(77);
+    assert_eq!(ranges(&set), vec![0..=100]);
+}
+
+#[test]
+fn test_insert_range_ignores_empty_range() {
+    let mut set = PortSet::new();
+    #[allow(clippy::reversed_empty_ranges)]
+    set.insert_range(20..=10);
+    assert!(set.is_empty());
+    assert_eq!(set.len(), 0);
+}
+
+#[test]
+fn test_u16_boundaries() {
+    let mut set = PortSet::new();
+    set.insert(u16::MAX);
+    set.insert(0);
+    assert!(set.contains(0));
+    assert!(set.contains(u16::MAX));
+    assert!(!set.contains(1));
+    set.insert_range(65000..=65534);
+    assert_eq!(ranges(&set), vec![0..=0, 65000..=65535]);
+    set.insert_range(0..=u16::MAX);
+    assert_eq!(ranges(&set), vec![0..=65535]);
+    assert_eq!(set.len(), 65536);
+    assert_eq!(set.iter_free_within(&[(0, 65535)]).count(), 0);
+}
+
+#[test]
+fn test_contains() {
+    let set: PortSet = [5, 6, 7, 20].into_iter().collect();
+    assert!(!set.contains(4));
+    assert!(set.contains(5));
+    assert!(set.contains(7));
+    assert!(!set.contains(8));
+    assert!(set.contains(20));
+    assert!(!set.contains(21));
+    assert!(!PortSet::new().contains(0));
+}
+
+#[test]
+fn test_union() {
+    let a: PortSet = [1, 2, 3, 10].into_iter().collect();
+    let mut b = PortSet::new();
+    b.insert_range(4..=9);
+    b.insert(100);
+    assert_eq!(ranges(&a.union(&b)), vec![1..=10, 100..=100]);
+    assert_eq!(a.union(&PortSet::new()), a);
+}
+
+#[test]
+fn test_expand() {
+    let set: PortSet = [5432, 5439, 100].into_iter().collect();
+    assert_eq!(ranges(&set.expand(3)), vec![97..=103, 5429..=5442]);
+    assert_eq!(set.expand(0), set);
+    let edges: PortSet = [1, u16::MAX].into_iter().collect();
+    assert_eq!(ranges(&edges.expand(5)), vec![0..=6, 65530..=u16::MAX]);
+}
+
+#[test]
+fn test_iter_free_within() {
+    let mut set = PortSet::new();
+    set.insert_range(1000..=1009);
+    set.insert(1020);
+    let free: Vec<_> = set.iter_free_within(&[(995, 1025)]).collect();
+    assert_eq!(free, vec![995..=999, 1010..=1019, 1021..=1025]);
+    // Ranges are walked in caller order and runs don't span them.
+    let free: Vec<_> = set.iter_free_within(&[(2000, 2001), (1, 2)]).collect();
+    assert_eq!(free, vec![2000..=2001, 1..=2]);
+    // Entirely forbidden or reversed ranges contribute nothing.
+    assert_eq!(set.iter_free_within(&[(1000, 1009), (9, 3)]).count(), 0);
+    // Free run reaching the top of the port space.
+    let free: Vec<_> = set.iter_free_within(&[(65530, 65535)]).collect();
+    assert_eq!(free, vec![65530..=65535]);
+}
+
+#[test]
+fn test_hash_set_round_trip() {
+    let ports: HashSet<u16> = [443, 80, 81, 82, 65535, 0].into_iter().collect();
+    let set = PortSet::from(&ports);
+    assert_eq!(ranges(&set), vec![0..=0, 80..=82, 443..=443, 65535..=65535]);
+    assert_eq!(set.to_hash_set(), ports);
+    assert_eq!(
+        set.iter().collect::<Vec<_>>(),
+        vec![0, 80, 81, 82, 443, 65535]
+    );
+}
+
+#[test]
+fn test_serde_as_range_pairs() {
+    let set: PortSet = [80, 81, 82, 443].into_iter().collect();
+    let json = serde_json::to_string(&set).unwrap();
+    assert_eq!(json, "[[80,82],[443,443]]");
+    assert_eq!(serde_json::from_str::<PortSet>(&json).unwrap(), set);
+
+    // Hand-edited input is normalized.
+    let messy: PortSet = serde_json::from_str("[[443,443],[82,80],[81,83]]").unwrap();
+    assert_eq!(ranges(&messy), vec![80..=83, 443..=443]);
+}
+
+fn set(ranges: &[(u16, u16)]) -> PortSet {
+    PortSet::from(ranges.to_vec())
+}
+
+#[test]
+fn test_union_coalesces_adjacent_and_overlapping_ranges() {
+    let a = set(&[(1, 3), (10, 20), (40, 40)]);
+    let b = set(&[(4, 5), (15, 30), (u16::MAX, u16::MAX)]);
+    assert_eq!(
+        ranges(&a.union(&b)),
+        vec![1..=5, 10..=30, 40..=40, u16::MAX..=u16::MAX]
+    );
+    assert_eq!(a.union(&PortSet::new()), a);
+    assert_eq!(PortSet::new().union(&a), a);
+}
+
+#[test]
+fn test_intersection() {
+    let a = set(&[(1, 10), (20, 30)]);
+    let b = set(&[(5, 25), (30, 40)]);
+    assert_eq!(ranges(&a.intersection(&b)), vec![5..=10, 20..=25, 30..=30]);
+    // Adjacent but not overlapping ranges share nothing.
+    assert!(set(&[(1, 4)]).intersection(&set(&[(5, 9)])).is_empty());
+    assert!(a.intersection(&PortSet::new()).is_empty());
+    assert_eq!(a.intersection(&a), a);
+}
+
+#[test]
+fn test_overlap() {
+    let a = set(&[(1, 10), (20, 30)]);
+    let b = set(&[(5, 25), (30, 40)]);
+    assert_eq!(a.overlap(&b), 6 + 6 + 1);
+    assert_eq!(b.overlap(&a), a.intersection(&b).len());
+    assert_eq!(a.overlap(&PortSet::new()), 0);
+    assert_eq!(set(&[(0, u16::MAX)]).overlap(&set(&[(0, u16::MAX)])), 65536);
+}
+
+#[test]
+fn test_to_spec() {
+    assert_eq!(
+        set(&[(80, 80), (8000, 8010), (65535, 65535)]).to_spec(),
+        "80,8000-8010,65535"
+    );
+    assert_eq!(PortSet::new().to_spec(), "");
+    let spec = "22,80-81,1024-2048";
+    assert_eq!(
+        PortSet::from(parse_port_spec(spec).unwrap()).to_spec(),
+        spec
+    );
+}
+
+#[test]
+fn test_difference() {
+    let a = set(&[(1, 10), (20, 30)]);
+    let b = set(&[(3, 4), (10, 22), (30, 30)]);
+    assert_eq!(ranges(&a.difference(&b)), vec![1..=2, 5..=9, 23..=29]);
+    assert_eq!(a.difference(&PortSet::new()), a);
+    assert!(a.difference(&a).is_empty());
+    assert!(PortSet::new().difference(&a).is_empty());
+    assert_eq!(
+        ranges(&set(&[(65530, u16::MAX)]).difference(&set(&[(u16::MAX, u16::MAX)]))),
+        vec![65530..=65534]
+    );
+}
+
+#[test]
+fn test_complement_within() {
+    let a = set(&[(8000, 8009), (8500, 8500)]);
+    assert_eq!(
+        ranges(&a.complement_within(8000..=8999)),
+        vec![8010..=8499, 8501..=8999]
+    );
+    assert_eq!(
+        ranges(&PortSet::new().complement_within(65000..=u16::MAX)),
+        vec![65000..=u16::MAX]
+    );
+    assert!(a.complement_within(8000..=8009).is_empty());
+}
+
+#[test]
+fn test_policy_composition() {
+    // (system ∪ nmap ∪ local) − allowlist, restricted to 8000-8999.
+    let system = PortSet::from(HashSet::from([8080, 8443, 9000]));
+    let nmap: PortSet = [8000, 8008, 8080, 8081].into_iter().collect();
+    let local = PortSet::from(8100..=8110);
+    let allowlist = PortSet::from(8080..=8081);
+    let forbidden = system
+        .union(&nmap)
+        .union(&local)
+        .difference(&allowlist)
+        .intersection(&PortSet::from(8000..=8999));
+    assert_eq!(
+        ranges(&forbidden),
+        vec![8000..=8000, 8008..=8008, 8100..=8110, 8443..=8443]
+    );
+    assert!(forbidden.contains(8105) && !forbidden.contains(8080));
+}
+
+#[test]
+fn test_set_operations_match_hash_sets() {
+    // Pairs of sets drawn from 0-11. Comparing whole PortSets also checks the results are
+    // coalesced the same way as sets built port by port.
+    let sets: Vec<HashSet<u16>> = (0..4096u16)
+        .step_by(37)
+        .map(|bits| (0..12).filter(|port| bits & (1 << port) != 0).collect())
+        .collect();
+    for a in &sets {
+        for b in &sets {
+            let (pa, pb) = (PortSet::from(a), PortSet::from(b));
+            assert_eq!(pa.union(&pb), PortSet::from(a | b));
+            assert_eq!(pa.intersection(&pb), PortSet::from(a & b));
+            assert_eq!(pa.difference(&pb), PortSet::from(a - b));
+            assert_eq!(pa.overlap(&pb), (a & b).len());
+            assert_eq!(
+                pa.complement_within(0..=11),
+                (0..12).filter(|port| !a.contains(port)).collect()
+            );
+        }
+    }
+}
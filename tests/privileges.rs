@@ -0,0 +1,131 @@
+use portpick::{
+    CAP_NET_BIND_SERVICE, CAP_NET_RAW, Privileges, Reporter, Suggestion, WarningCode,
+    parse_cap_eff, parse_unprivileged_port_start, warn_unbindable,
+};
+
+const UNPRIVILEGED: &str = include_str!("fixtures/proc_self_status");
+const BIND_SERVICE: &str = include_str!("fixtures/proc_self_status_bind_service");
+const ROOT: &str = include_str!("fixtures/proc_self_status_root");
+
+#[test]
+fn test_parse_cap_eff() {
+    assert_eq!(parse_cap_eff(UNPRIVILEGED), Some(0));
+    assert_eq!(parse_cap_eff(BIND_SERVICE), Some(1 << CAP_NET_BIND_SERVICE));
+    assert_eq!(parse_cap_eff(ROOT), Some(0x1ff_ffff_ffff));
+    assert_eq!(parse_cap_eff("Name:\tportpick\n"), None);
+    assert_eq!(parse_cap_eff("CapEff:\tnot-hex\n"), None);
+}
+
+#[test]
+fn test_privileges_from_capabilities() {
+    let unprivileged = Privileges::from_status(Some(1000), UNPRIVILEGED);
+    assert!(!unprivileged.can_bind_privileged_ports());
+    assert!(!unprivileged.can_send_raw_packets());
+    assert_eq!(
+        unprivileged.to_string(),
+        "euid 1000, no CAP_NET_BIND_SERVICE, no CAP_NET_RAW"
+    );
+
+    let bind_service = Privileges::from_status(Some(1000), BIND_SERVICE);
+    assert!(bind_service.can_bind_privileged_ports());
+    assert!(!bind_service.can_send_raw_packets());
+    assert_eq!(bind_service.has_capability(CAP_NET_RAW), Some(false));
+
+    let root = Privileges::from_status(Some(0), ROOT);
+    assert!(root.is_root());
+    assert!(root.can_bind_privileged_ports() && root.can_send_raw_packets());
+    assert_eq!(
+        root.to_string(),
+        "euid 0, CAP_NET_BIND_SERVICE, CAP_NET_RAW"
+    );
+
+    // Root with its capabilities dropped, as in a restricted container.
+    let dropped = Privileges::from_status(Some(0), UNPRIVILEGED);
+    assert!(dropped.is_root());
+    assert!(!dropped.can_bind_privileged_ports());
+}
+
+#[test]
+fn test_privileges_without_capability_set() {
+    // Without /proc, being root is all there is to go on.
+    let user = Privileges::from_status(Some(1000), "");
+    assert!(!user.can_bind_privileged_ports());
+    assert_eq!(user.to_string(), "euid 1000, capabilities unknown");
+    assert!(Privileges::from_status(Some(0), "").can_send_raw_packets());
+    // Knowing nothing, nothing is refused.
+    assert!(Privileges::default().can_bind_privileged_ports());
+    assert!(Privileges::default().can_send_raw_packets());
+}
+
+#[test]
+fn test_unprivileged_port_start() {
+    assert_eq!(parse_unprivileged_port_start("80\n"), Some(80));
+    assert_eq!(parse_unprivileged_port_start("0"), Some(0));
+    assert_eq!(parse_unprivileged_port_start(""), None);
+    assert_eq!(parse_unprivileged_port_start("70000\n"), None);
+
+    let lowered = Privileges {
+        unprivileged_port_start: Some(80),
+        ..Privileges::from_status(Some(1000), UNPRIVILEGED)
+    };
+    assert!(lowered.can_bind(80) && lowered.can_bind(1023));
+    assert!(!lowered.can_bind(79));
+    assert_eq!(
+        lowered.to_string(),
+        "euid 1000, unprivileged ports from 80, no CAP_NET_BIND_SERVICE, no CAP_NET_RAW"
+    );
+
+    let reporter = Reporter::silent();
+    warn_unbindable(
+        &[Suggestion::new(22), Suggestion::new(80)],
+        &lowered,
+        &reporter,
+    );
+    let warnings = reporter.warnings();
+    assert_eq!(warnings.len(), 1);
+    assert!(
+        warnings[0]
+            .message
+            .starts_with("Binding 22 will fail: ports below 80 need root"),
+        "{}",
+        warnings[0].message
+    );
+}
+
+#[test]
+fn test_warn_unbindable_names_privileged_suggestions() {
+    let suggestions = [
+        Suggestion::new(80),
+        Suggestion::new(8080),
+        Suggestion::new(443),
+    ];
+
+    let reporter = Reporter::silent();
+    warn_unbindable(
+        &suggestions,
+        &Privileges::from_status(Some(1000), UNPRIVILEGED),
+        &reporter,
+    );
+    let warnings = reporter.warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].code, WarningCode::PrivilegedPort);
+    assert_eq!(
+        warnings[0].message,
+        "Binding 80, 443 will fail: ports below 1024 need root or CAP_NET_BIND_SERVICE, and this process has neither (euid 1000, no CAP_NET_BIND_SERVICE, no CAP_NET_RAW)."
+    );
+
+    for (privileges, suggestions) in [
+        (
+            Privileges::from_status(Some(1000), BIND_SERVICE),
+            &suggestions[..],
+        ),
+        (
+            Privileges::from_status(Some(1000), UNPRIVILEGED),
+            &suggestions[1..2],
+        ),
+    ] {
+        let reporter = Reporter::silent();
+        warn_unbindable(suggestions, &privileges, &reporter);
+        assert!(reporter.warnings().is_empty());
+    }
+}
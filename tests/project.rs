@@ -0,0 +1,108 @@
+use portpick::{EnvNaming, ProjectSpec, ResolvedEntry, parse_env_assignments};
+use std::collections::HashSet;
+
+fn fixture() -> ProjectSpec {
+    ProjectSpec::from_yaml(include_str!("fixtures/portpick.yaml")).unwrap()
+}
+
+#[test]
+fn test_resolve_in_file_order() {
+    // 40002 and 40005 are taken, so workers can't start at 40000 or 40003.
+    let forbidden: HashSet<u16> = [40002, 40005].into_iter().collect();
+    let resolved = fixture().resolve(&forbidden).unwrap();
+    assert_eq!(
+        resolved,
+        vec![
+            ResolvedEntry {
+                name: "web".to_string(),
+                ports: vec![40000],
+                container_port: Some(8080),
+            },
+            ResolvedEntry {
+                name: "db".to_string(),
+                ports: vec![40006],
+                container_port: None,
+            },
+            ResolvedEntry {
+                name: "workers".to_string(),
+                ports: vec![40003, 40004],
+                container_port: None,
+            },
+        ]
+    );
+    assert_eq!(fixture().resolve(&forbidden).unwrap(), resolved);
+}
+
+#[test]
+fn test_resolve_near_prefers_lower_on_ties() {
+    let forbidden: HashSet<u16> = [40006].into_iter().collect();
+    let resolved = fixture().resolve(&forbidden).unwrap();
+    assert_eq!(resolved[1].ports, vec![40005]);
+}
+
+#[test]
+fn test_resolve_fails_when_an_entry_cannot_fit() {
+    let forbidden: HashSet<u16> = (40000..=40009).step_by(2).collect();
+    let err = fixture().resolve(&forbidden).unwrap_err();
+    assert!(err.to_string().contains("'workers'"), "{}", err);
+}
+
+#[test]
+fn test_env_assignments() {
+    let spec = fixture();
+    let resolved = spec.resolve(&HashSet::new()).unwrap();
+    assert_eq!(
+        spec.env_assignments(&resolved, &EnvNaming::default()),
+        vec![
+            ("WEB_PORT".to_string(), 40000),
+            ("WEB_CONTAINER_PORT".to_string(), 8080),
+            ("DB_PORT".to_string(), 40006),
+            ("WORKERS_PORT_1".to_string(), 40001),
+            ("WORKERS_PORT_2".to_string(), 40002),
+        ]
+    );
+}
+
+#[test]
+fn test_validate_rejects_unsatisfiable_files() {
+    for (yaml, message) in [
+        ("ports:\n  - name: a\n  - name: a\n", "appears twice"),
+        ("ports:\n  - name: a\n    count: 0\n", "0 ports"),
+        ("ports:\n  - name: ''\n", "empty name"),
+        (
+            "within: 40000-40001\nports:\n  - name: a\n    count: 3\n",
+            "can never be satisfied",
+        ),
+        (
+            "ports:\n  - name: a\n    within: 40000-40002\n    count: 3\n  - name: b\n    within: 40002-40003\n    count: 2\n",
+            "Entries a, b need 5 ports in total, but their overlapping ranges only hold 4",
+        ),
+        ("ports:\n  - name: a\n    colour: red\n", "Failed to parse"),
+    ] {
+        let err = ProjectSpec::from_yaml(yaml).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains(message),
+            "{:?}: {:#}",
+            yaml,
+            err
+        );
+    }
+    // Disjoint windows are checked separately.
+    ProjectSpec::from_yaml(
+        "ports:\n  - name: a\n    within: 40000-40001\n    count: 2\n  - name: b\n    within: 40002-40003\n    count: 2\n",
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_parse_env_assignments() {
+    let content = "# ports\nWEB_PORT=40000\nexport DB_PORT=\"40006\"\nNAME=web\n\nOTHER = 5\n";
+    assert_eq!(
+        parse_env_assignments(content),
+        vec![
+            ("WEB_PORT".to_string(), 40000),
+            ("DB_PORT".to_string(), 40006),
+            ("OTHER".to_string(), 5),
+        ]
+    );
+}
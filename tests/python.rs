@@ -0,0 +1,71 @@
+#![cfg(feature = "python")]
+
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyModule};
+use std::collections::HashSet;
+use std::ffi::CString;
+
+/// Runs `code` with the `portpick` module bound to `portpick` and returns its `result`.
+fn run<T>(code: &str) -> PyResult<T>
+where
+    T: for<'py> FromPyObject<'py>,
+{
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| {
+        let module = PyModule::new(py, "portpick")?;
+        portpick::python::portpick(&module)?;
+        let locals = PyDict::new(py);
+        locals.set_item("portpick", module)?;
+        py.run(&CString::new(code).unwrap(), None, Some(&locals))?;
+        locals.get_item("result")?.unwrap().extract()
+    })
+}
+
+#[test]
+fn test_python_parse_services() {
+    let ports: HashSet<u16> = run(
+        "result = portpick.parse_services('http 80/tcp www\\nntp 123/udp\\nunknown 9999/tcp\\n')",
+    )
+    .unwrap();
+    assert_eq!(ports, HashSet::from([80]));
+}
+
+#[test]
+fn test_python_find_available() {
+    let ports: Vec<u16> = run("result = portpick.find_available({1024, 1026}, 2)").unwrap();
+    assert_eq!(ports, vec![1025, 1027]);
+    let ports: Vec<u16> =
+        run("result = portpick.find_available({1024, 1026}, 2, continuous=True)").unwrap();
+    assert_eq!(ports, vec![1027, 1028]);
+}
+
+#[test]
+fn test_python_port_picker_chains_settings() {
+    let ports: Vec<u16> = run(
+        "result = portpick.PortPicker().within('8000-8010').exclude('8001').count(3).continuous().pick({8004})",
+    )
+    .unwrap();
+    assert_eq!(ports, vec![8005, 8006, 8007]);
+}
+
+#[test]
+fn test_python_port_picker_resolve_reads_services_file() {
+    let ports: Vec<u16> = run("result = (portpick.PortPicker().sources(['system'])\n\
+         .services_file('tests/fixtures/services').within('40000-40009').count(3).resolve())")
+    .unwrap();
+    assert_eq!(ports, vec![40000, 40001, 40003]);
+}
+
+#[test]
+fn test_python_errors_become_value_errors() {
+    let message: String = run(
+        "try:\n    portpick.PortPicker().sources(['nowhere'])\nexcept ValueError as error:\n    result = str(error)\n",
+    )
+    .unwrap();
+    assert!(message.contains("Unknown source 'nowhere'"), "{message}");
+    let message: String = run(
+        "try:\n    portpick.PortPicker().within('9000-8000').pick()\nexcept ValueError as error:\n    result = str(error)\n",
+    )
+    .unwrap();
+    assert!(!message.is_empty());
+}
@@ -0,0 +1,228 @@
+use portpick::{
+    DEFAULT_PORT_RANGES, FreeCount, PortSet, RequestError, count_free, free_intervals,
+    occupancy_bands, parse_port_spec, validate_request,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
+#[test]
+fn test_parse_port_spec_single_ports_and_ranges() {
+    assert_eq!(
+        parse_port_spec("80, 8000-8100,20000-29999").unwrap(),
+        vec![(80, 80), (8000, 8100), (20000, 29999)]
+    );
+}
+
+#[test]
+fn test_parse_port_spec_rejects_malformed_entries() {
+    assert!(parse_port_spec("").is_err());
+    assert!(parse_port_spec("80,").is_err());
+    assert!(parse_port_spec("9000-8000").is_err());
+    assert!(parse_port_spec("1-70000").is_err());
+    assert!(parse_port_spec("http").is_err());
+}
+
+#[test]
+fn test_count_free_splits_around_forbidden_ports() {
+    let forbidden: HashSet<u16> = [40002, 40005].into_iter().collect();
+    // Free runs: 40000-40001, 40003-40004, 40006-40009.
+    assert_eq!(
+        count_free(&forbidden, &[(40000, 40009)], 2),
+        FreeCount {
+            ranges: vec![(40000, 40009)],
+            free_ports: 8,
+            block_size: 2,
+            free_blocks: 4,
+        }
+    );
+    assert_eq!(count_free(&forbidden, &[(40000, 40009)], 3).free_blocks, 1);
+    assert_eq!(count_free(&forbidden, &[(40000, 40009)], 5).free_blocks, 0);
+}
+
+#[test]
+fn test_count_free_edges_of_range_and_full_range() {
+    let forbidden: HashSet<u16> = [0, 65535].into_iter().collect();
+    let count = count_free(&forbidden, &[(0, 65535)], 1);
+    assert_eq!(count.free_ports, 65534);
+    assert_eq!(count.free_blocks, 65534);
+
+    let all_free = count_free(&HashSet::new(), &[(65530, 65535)], 3);
+    assert_eq!(all_free.free_ports, 6);
+    assert_eq!(all_free.free_blocks, 2);
+}
+
+#[test]
+fn test_count_free_runs_do_not_span_separate_ranges() {
+    // 100-101 and 102-103 are adjacent but given separately, so a block of 4 can't straddle them.
+    let count = count_free(&HashSet::new(), &[(100, 101), (102, 103)], 4);
+    assert_eq!(count.free_ports, 4);
+    assert_eq!(count.free_blocks, 0);
+}
+
+#[test]
+fn test_occupancy_bands_boundaries() {
+    let bands = occupancy_bands(&HashSet::new());
+    assert_eq!(bands.first(), Some(&(1024..=1999, 0)));
+    assert_eq!(bands.get(1), Some(&(2000..=2999, 0)));
+    assert_eq!(bands.last(), Some(&(65000..=65535, 0)));
+    assert_eq!(bands.len(), 65);
+    // Bands tile the default search space without gaps or overlap.
+    for pair in bands.windows(2) {
+        assert_eq!(*pair[0].0.end() + 1, *pair[1].0.start());
+    }
+}
+
+#[test]
+fn test_occupancy_bands_counts_forbidden_ports() {
+    let forbidden: HashSet<u16> = [80, 1024, 1999, 2000, 65535].into_iter().collect();
+    let bands = occupancy_bands(&forbidden);
+    assert_eq!(bands[0], (1024..=1999, 2));
+    assert_eq!(bands[1], (2000..=2999, 1));
+    assert_eq!(bands[64], (65000..=65535, 1));
+    // Port 80 is outside the default ranges and not counted anywhere.
+    assert_eq!(bands.iter().map(|(_, count)| count).sum::<usize>(), 4);
+}
+
+#[test]
+fn test_validate_request_exactly_fits_and_one_over() {
+    let ranges = [(3000, 3009), (5000, 5004)];
+    assert_eq!(validate_request(10, true, &ranges), Ok(()));
+    assert_eq!(
+        validate_request(11, true, &ranges),
+        Err(RequestError::BlockTooLarge {
+            requested: 11,
+            max_block: 10,
+        })
+    );
+    assert_eq!(validate_request(15, false, &ranges), Ok(()));
+    assert_eq!(
+        validate_request(16, false, &ranges),
+        Err(RequestError::TooManyPorts {
+            requested: 16,
+            searchable: 15,
+        })
+    );
+}
+
+#[test]
+fn test_validate_request_default_ranges() {
+    assert_eq!(validate_request(48128, true, &DEFAULT_PORT_RANGES), Ok(()));
+    assert!(validate_request(48129, true, &DEFAULT_PORT_RANGES).is_err());
+    assert_eq!(validate_request(64512, false, &DEFAULT_PORT_RANGES), Ok(()));
+    assert!(validate_request(64513, false, &DEFAULT_PORT_RANGES).is_err());
+}
+
+#[test]
+fn test_validate_request_zero_width_ranges() {
+    assert_eq!(
+        validate_request(1, true, &[]),
+        Err(RequestError::BlockTooLarge {
+            requested: 1,
+            max_block: 0,
+        })
+    );
+    assert_eq!(
+        validate_request(1, false, &[]),
+        Err(RequestError::TooManyPorts {
+            requested: 1,
+            searchable: 0,
+        })
+    );
+    // A single-port range fits exactly one port.
+    assert_eq!(validate_request(1, true, &[(8080, 8080)]), Ok(()));
+    assert_eq!(
+        validate_request(1, false, &[(8080, 8079)]),
+        Err(RequestError::InvalidRange {
+            start: 8080,
+            end: 8079,
+        })
+    );
+}
+
+#[test]
+fn test_validate_request_overlapping_ranges_count_once() {
+    let ranges = [(100, 199), (150, 249)];
+    assert_eq!(validate_request(150, false, &ranges), Ok(()));
+    assert!(validate_request(151, false, &ranges).is_err());
+}
+
+#[test]
+fn test_free_intervals_edge_cases() {
+    let forbidden: PortSet = [10, 11, 15].into_iter().collect();
+    assert_eq!(
+        free_intervals(&forbidden, &[(8, 20)]),
+        vec![8..=9, 12..=14, 16..=20]
+    );
+    assert_eq!(free_intervals(&PortSet::new(), &[(5, 7)]), vec![5..=7]);
+    assert!(free_intervals(&forbidden, &[(10, 11)]).is_empty());
+    assert!(free_intervals(&forbidden, &[]).is_empty());
+    assert_eq!(
+        free_intervals(&PortSet::new(), &[(65530, u16::MAX)]),
+        vec![65530..=u16::MAX]
+    );
+    let top: PortSet = [u16::MAX].into_iter().collect();
+    assert_eq!(
+        free_intervals(&top, &[(65530, u16::MAX)]),
+        vec![65530..=65534]
+    );
+    // Touching and overlapping entries, in any order, give one maximal run.
+    assert_eq!(
+        free_intervals(&PortSet::new(), &[(30, 40), (20, 29), (25, 35)]),
+        vec![20..=40]
+    );
+}
+
+/// Free runs found by checking each port of `within` on its own.
+fn brute_force_free(forbidden: &HashSet<u16>, within: &[(u16, u16)]) -> Vec<RangeInclusive<u16>> {
+    let mut runs: Vec<RangeInclusive<u16>> = Vec::new();
+    for port in 0..=u16::MAX {
+        let searched = within
+            .iter()
+            .any(|&(start, end)| (start..=end).contains(&port));
+        if !searched || forbidden.contains(&port) {
+            continue;
+        }
+        match runs.last_mut() {
+            Some(run) if *run.end() + 1 == port => *run = *run.start()..=port,
+            _ => runs.push(port..=port),
+        }
+    }
+    runs
+}
+
+#[test]
+fn test_free_intervals_match_brute_force() {
+    let mut rng = StdRng::seed_from_u64(666);
+    for _ in 0..200 {
+        // Small windows near both ends of the port range, where off-by-ones hide.
+        let base: u16 = if rng.random_bool(0.5) {
+            0
+        } else {
+            u16::MAX - 63
+        };
+        let port = |rng: &mut StdRng| base + rng.random_range(0..64);
+        let forbidden: HashSet<u16> = (0..rng.random_range(0..40))
+            .map(|_| port(&mut rng))
+            .collect();
+        let within: Vec<(u16, u16)> = (0..rng.random_range(0..4))
+            .map(|_| {
+                let (a, b) = (port(&mut rng), port(&mut rng));
+                (a.min(b), a.max(b))
+            })
+            .collect();
+
+        let intervals = free_intervals(&PortSet::from(&forbidden), &within);
+        assert_eq!(
+            intervals,
+            brute_force_free(&forbidden, &within),
+            "{:?}",
+            within
+        );
+        for pair in intervals.windows(2) {
+            // Sorted, and a gap of at least one port: otherwise they weren't maximal.
+            assert!(*pair[0].end() + 1 < *pair[1].start(), "{:?}", pair);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use portpick::{CachedScan, ScanCache};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn cache_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "portpick-scancache-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+#[test]
+fn test_scan_cache_hit_within_ttl_and_stale_after() {
+    let dir = cache_dir("ttl");
+    let cache = ScanCache::new(&dir, Duration::from_secs(30));
+    let ports: HashSet<u16> = [22, 8080].into_iter().collect();
+    cache.store("rustscan:127.0.0.1", &ports, at(1000)).unwrap();
+
+    assert_eq!(
+        cache.load("rustscan:127.0.0.1", at(1012)).unwrap(),
+        Some(CachedScan {
+            ports: ports.clone(),
+            age: Duration::from_secs(12),
+        })
+    );
+    assert!(
+        cache
+            .load("rustscan:127.0.0.1", at(1030))
+            .unwrap()
+            .is_some()
+    );
+    assert_eq!(cache.load("rustscan:127.0.0.1", at(1031)).unwrap(), None);
+    // A timestamp from the future (the clock went backwards) is not trusted either.
+    assert_eq!(cache.load("rustscan:127.0.0.1", at(999)).unwrap(), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_scan_cache_is_keyed_by_target_and_backend() {
+    let dir = cache_dir("keys");
+    let cache = ScanCache::new(&dir, Duration::from_secs(30));
+    cache
+        .store("rustscan:127.0.0.1", &[22].into_iter().collect(), at(1000))
+        .unwrap();
+
+    assert_eq!(cache.load("rustscan:10.0.0.1", at(1001)).unwrap(), None);
+    assert_eq!(cache.load("ss:127.0.0.1", at(1001)).unwrap(), None);
+    // Keys that sanitize to the same file name must not answer for each other.
+    assert_eq!(cache.load("rustscan_127.0.0.1", at(1001)).unwrap(), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_scan_cache_zero_ttl_reads_and_writes_nothing() {
+    let dir = cache_dir("disabled");
+    let cache = ScanCache::new(&dir, Duration::ZERO);
+    assert!(!cache.is_enabled());
+    cache
+        .store("rustscan:127.0.0.1", &[22].into_iter().collect(), at(1000))
+        .unwrap();
+    assert_eq!(cache.load("rustscan:127.0.0.1", at(1000)).unwrap(), None);
+    assert!(!dir.exists());
+}
+
+#[test]
+fn test_scan_cache_concurrent_writers_leave_a_whole_entry() {
+    let dir = cache_dir("concurrent");
+    let cache = ScanCache::new(&dir, Duration::from_secs(30));
+    let entries: Vec<HashSet<u16>> = (0..8u16)
+        .map(|i| (i * 100..i * 100 + 50).collect())
+        .collect();
+
+    std::thread::scope(|scope| {
+        let (cache, entries) = (&cache, &entries);
+        for ports in entries {
+            scope.spawn(move || {
+                for _ in 0..20 {
+                    cache.store("shared", ports, at(1000)).unwrap();
+                    let loaded = cache.load("shared", at(1001)).unwrap().unwrap();
+                    assert!(entries.contains(&loaded.ports));
+                }
+            });
+        }
+    });
+    let last = cache.load("shared", at(1001)).unwrap().unwrap();
+    assert!(entries.contains(&last.ports));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
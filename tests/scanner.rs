@@ -0,0 +1,973 @@
+use portpick::{
+    CommandOutput, CommandRunner, ContainerScanner, DEFAULT_MASSCAN_RATE, FallbackScanner,
+    MasscanScanner, NmapScanner, PortScanner, PortSet, Privileges, RUSTSCAN_ARG_SETS,
+    RUSTSCAN_LATEST_KNOWN, Reporter, RustScanArgSet, RustScanRun, RustScanScanner, ScanTarget,
+    ScannerNeedsPrivileges, SsScanner, SystemCommandRunner, ToolVersion, WarningCode,
+    WindowsHostScanner, detect_ci, is_wsl, judge_rustscan_run, listening_processes,
+    parse_excluded_port_ranges, parse_masscan_output, parse_netstat_listening, parse_nmap_grepable,
+    parse_proc_net_tcp, parse_rustscan_output, parse_tool_version,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+type CallLog = Arc<Mutex<Vec<(String, Vec<String>)>>>;
+
+/// Returns canned output and records the invocations it received.
+struct FakeRunner {
+    result: Result<CommandOutput, std::io::ErrorKind>,
+    calls: CallLog,
+}
+
+impl CommandRunner for FakeRunner {
+    fn run(&self, program: &str, args: &[String]) -> std::io::Result<CommandOutput> {
+        self.calls
+            .lock()
+            .unwrap()
+            .push((program.to_string(), args.to_vec()));
+        self.result.clone().map_err(std::io::Error::from)
+    }
+}
+
+/// Answers `--version` with `version` and hands everything else to `inner`.
+struct VersionedRunner {
+    version: CommandOutput,
+    inner: FakeRunner,
+}
+
+impl CommandRunner for VersionedRunner {
+    fn run(&self, program: &str, args: &[String]) -> std::io::Result<CommandOutput> {
+        match args == ["--version"] {
+            true => Ok(self.version.clone()),
+            false => self.inner.run(program, args),
+        }
+    }
+}
+
+fn fake_scanner(result: Result<CommandOutput, std::io::ErrorKind>) -> (RustScanScanner, CallLog) {
+    fake_scanner_reporting("rustscan 2.4.1\n", result, Arc::new(Reporter::silent()))
+}
+
+/// A rustscan whose `--version` prints `version`.
+fn fake_scanner_reporting(
+    version: &str,
+    result: Result<CommandOutput, std::io::ErrorKind>,
+    reporter: Arc<Reporter>,
+) -> (RustScanScanner, CallLog) {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let runner = VersionedRunner {
+        version: CommandOutput {
+            code: Some(0),
+            stdout: version.to_string(),
+            stderr: String::new(),
+        },
+        inner: FakeRunner {
+            result,
+            calls: Arc::clone(&calls),
+        },
+    };
+    (
+        RustScanScanner::with_runner(Box::new(runner), reporter),
+        calls,
+    )
+}
+
+#[test]
+fn test_parse_tool_version() {
+    let version = |major, minor, patch| Some(ToolVersion::new(major, minor, patch));
+    assert_eq!(parse_tool_version("rustscan 2.4.1\n"), version(2, 4, 1));
+    assert_eq!(parse_tool_version("RustScan v1.10.1"), version(1, 10, 1));
+    assert_eq!(parse_tool_version("rustscan 2.0"), version(2, 0, 0));
+    assert_eq!(
+        parse_tool_version("rustscan 2.1.0-beta.2"),
+        version(2, 1, 0)
+    );
+    assert_eq!(parse_tool_version("rustscan 1.2.3.4"), None);
+    assert_eq!(parse_tool_version("rustscan (unknown)"), None);
+    assert!(ToolVersion::new(1, 10, 0) > ToolVersion::new(1, 9, 9));
+    assert_eq!(ToolVersion::new(2, 4, 1).to_string(), "2.4.1");
+}
+
+#[test]
+fn test_rustscan_arg_set_for_each_known_range() {
+    let set =
+        |major, minor, patch| RustScanArgSet::for_version(ToolVersion::new(major, minor, patch));
+    let args = |set: Option<&RustScanArgSet>| set.unwrap().args(&ScanTarget::default()).join(" ");
+
+    for version in [set(1, 0, 0), set(1, 9, 2)] {
+        assert_eq!(
+            args(version),
+            "-a 127.0.0.1 --range 1-65535 -g -b 1000 -T 1500 -- /bin/true"
+        );
+    }
+    for version in [set(1, 10, 0), set(1, 10, 1)] {
+        assert_eq!(
+            args(version),
+            "-a 127.0.0.1 --range 1-65535 --accessible --scan-order serial -b 1000 -T 1500 -- /bin/true"
+        );
+    }
+    for version in [set(2, 0, 0), set(2, 4, 1)] {
+        assert_eq!(
+            args(version),
+            "-a 127.0.0.1 --range 1-65535 --accessible --scan-order serial -b 1000 -t 1500 -- /bin/true"
+        );
+    }
+    assert_eq!(set(2, 4, 1), Some(RustScanArgSet::latest()));
+    assert_eq!(RustScanArgSet::latest().since, RUSTSCAN_ARG_SETS[2].since);
+    assert!(RUSTSCAN_LATEST_KNOWN >= RustScanArgSet::latest().since);
+    // Too old and too new are both unknown.
+    assert_eq!(set(0, 9, 0), None);
+    assert_eq!(set(2, 4, 2), None);
+    assert_eq!(set(3, 0, 0), None);
+}
+
+#[test]
+fn test_rustscan_scanner_adapts_to_detected_version() {
+    let (scanner, calls) = fake_scanner_reporting(
+        "rustscan 1.8.0\n",
+        Ok(CommandOutput {
+            code: Some(0),
+            stdout: "127.0.0.1 -> [22,8080]\n".to_string(),
+            stderr: String::new(),
+        }),
+        Arc::new(Reporter::silent()),
+    );
+    let ports = scanner.scan(&ScanTarget::default()).unwrap();
+    assert_eq!(ports, [22, 8080].into_iter().collect());
+    scanner.scan(&ScanTarget::default()).unwrap();
+    let calls = calls.lock().unwrap();
+    assert_eq!(calls.len(), 2);
+    assert!(calls[0].1.contains(&"-g".to_string()), "{:?}", calls[0].1);
+    assert!(calls[0].1.contains(&"-T".to_string()), "{:?}", calls[0].1);
+    assert_eq!(
+        scanner.version(None).unwrap(),
+        Some(ToolVersion::new(1, 8, 0))
+    );
+}
+
+#[test]
+fn test_rustscan_scanner_detects_version_once() {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let runner = FakeRunner {
+        result: Ok(CommandOutput {
+            code: Some(0),
+            stdout: "rustscan 2.1.1\n".to_string(),
+            stderr: String::new(),
+        }),
+        calls: Arc::clone(&calls),
+    };
+    let scanner = RustScanScanner::with_runner(Box::new(runner), Arc::new(Reporter::silent()));
+    scanner.scan(&ScanTarget::default()).unwrap();
+    scanner.scan(&ScanTarget::default()).unwrap();
+    let calls = calls.lock().unwrap();
+    let version_calls = calls
+        .iter()
+        .filter(|(_, args)| args == &["--version"])
+        .count();
+    assert_eq!((calls.len(), version_calls), (3, 1));
+}
+
+#[test]
+fn test_rustscan_scanner_warns_about_unknown_versions() {
+    for (version, expected) in [
+        (
+            "rustscan 3.0.0\n",
+            "Warning: rustscan 3.0.0 is not a release portpick knows (latest known: 2.4.1); using the arguments for 2.4.1.",
+        ),
+        (
+            "rustscan nightly\n",
+            "Warning: Could not tell which rustscan version 'rustscan' is; using the arguments for 2.4.1.",
+        ),
+    ] {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let reporter = Reporter::with_writer(false, Box::new(SharedWriter(Arc::clone(&out))));
+        let (scanner, calls) = fake_scanner_reporting(
+            version,
+            Ok(CommandOutput {
+                code: Some(0),
+                stdout: "22\n".to_string(),
+                stderr: String::new(),
+            }),
+            Arc::new(reporter),
+        );
+        scanner.scan(&ScanTarget::default()).unwrap();
+        assert_eq!(
+            calls.lock().unwrap()[0].1,
+            RustScanScanner::args(&ScanTarget::default())
+        );
+        let messages = String::from_utf8(out.lock().unwrap().clone()).unwrap();
+        assert_eq!(messages.trim_end(), expected);
+    }
+}
+
+#[test]
+fn test_rustscan_scanner_names_version_when_arguments_are_rejected() {
+    let (scanner, _) = fake_scanner_reporting(
+        "rustscan 2.4.1\n",
+        Ok(CommandOutput {
+            code: Some(2),
+            stdout: String::new(),
+            stderr: "error: unexpected argument '--accessible' found\n".to_string(),
+        }),
+        Arc::new(Reporter::silent()),
+    );
+    let err = scanner.scan(&ScanTarget::default()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "rustscan 2.4.1 rejected the arguments portpick passed for releases since 2.0.0: error: unexpected argument '--accessible' found"
+    );
+}
+
+#[test]
+fn test_rustscan_scanner_stops_past_relevant_ports() {
+    let (scanner, _) = fake_scanner(Ok(CommandOutput {
+        code: Some(0),
+        stdout: "22\n8005\n9500\n12000\n".to_string(),
+        stderr: String::new(),
+    }));
+    let target = ScanTarget::default().with_relevant(PortSet::from(8000..=8100));
+    let ports = scanner.scan(&target).unwrap();
+    // Reading stops at 9500, the first port past the relevant ones.
+    assert_eq!(ports, [22, 8005, 9500].into_iter().collect());
+}
+
+#[test]
+fn test_rustscan_scanner_reads_everything_without_relevant_ports() {
+    let (scanner, _) = fake_scanner(Ok(CommandOutput {
+        code: Some(0),
+        stdout: "22\n8005\n9500\n12000\n".to_string(),
+        stderr: String::new(),
+    }));
+    let ports = scanner.scan(&ScanTarget::default()).unwrap();
+    assert_eq!(ports.len(), 4);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_system_command_runner_stops_streaming_when_asked() {
+    let args = ["-c", "echo 1; echo 2; sleep 30; echo 3"].map(String::from);
+    let started = Instant::now();
+    let streamed = SystemCommandRunner
+        .run_streaming("sh", &args, None, &mut |line| {
+            (line == "2").then(Instant::now)
+        })
+        .unwrap();
+    assert!(started.elapsed() < Duration::from_secs(10));
+    assert!(streamed.stopped);
+    assert_eq!(streamed.output.stdout, "1\n2\n");
+    assert_eq!(streamed.output.code, None);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_system_command_runner_streams_to_completion() {
+    let args = ["-c", "echo 1; echo 2"].map(String::from);
+    let mut seen = Vec::new();
+    let streamed = SystemCommandRunner
+        .run_streaming("sh", &args, None, &mut |line| {
+            seen.push(line.to_string());
+            None
+        })
+        .unwrap();
+    assert!(!streamed.stopped);
+    assert!(streamed.output.success());
+    assert_eq!(streamed.output.stdout, "1\n2\n");
+    assert_eq!(seen, ["1", "2"]);
+}
+
+#[test]
+fn test_parse_rustscan_output_plain_and_open_lines() {
+    let output = "22\n  8080  \nOpen 127.0.0.1:5432\nOpen [::1]:6379\n\nnot a port\n";
+    let ports = parse_rustscan_output(output, &Reporter::silent());
+    assert_eq!(ports.len(), 4);
+    for port in [22, 8080, 5432, 6379] {
+        assert!(ports.contains(&port), "missing port {}", port);
+    }
+}
+
+#[test]
+fn test_parse_rustscan_output_greppable_lines() {
+    let ports = parse_rustscan_output("127.0.0.1 -> [22,631,70000]\n", &Reporter::silent());
+    assert_eq!(ports, [22, 631].into_iter().collect());
+}
+
+#[test]
+fn test_parse_rustscan_output_ignores_out_of_range_values() {
+    let ports = parse_rustscan_output("70000\nOpen 127.0.0.1:99999\n-1\n", &Reporter::silent());
+    assert!(ports.is_empty());
+}
+
+#[test]
+fn test_rustscan_scanner_uses_target_address() {
+    let (scanner, calls) = fake_scanner(Ok(CommandOutput {
+        code: Some(0),
+        stdout: "3000\n".to_string(),
+        stderr: String::new(),
+    }));
+    let ports = scanner.scan(&ScanTarget::new("example.com")).unwrap();
+    assert_eq!(ports.into_iter().collect::<Vec<_>>(), vec![3000]);
+
+    let calls = calls.lock().unwrap();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].0, "rustscan");
+    assert_eq!(
+        calls[0].1[..2],
+        ["-a".to_string(), "example.com".to_string()]
+    );
+    assert!(calls[0].1.contains(&"--accessible".to_string()));
+}
+
+#[test]
+fn test_rustscan_scanner_scans_only_target_ports() {
+    let (scanner, calls) = fake_scanner(Ok(CommandOutput {
+        code: Some(0),
+        stdout: "3000\n8001\n".to_string(),
+        stderr: String::new(),
+    }));
+    let target = ScanTarget::new("127.0.0.1").with_ports(vec![8000, 8001, 8003]);
+    let ports = scanner.scan(&target).unwrap();
+    assert_eq!(ports.into_iter().collect::<Vec<_>>(), vec![8001]);
+
+    let args = &calls.lock().unwrap()[0].1;
+    let list = args.iter().position(|arg| arg == "-p").unwrap() + 1;
+    assert_eq!(args[list], "8000,8001,8003");
+    assert!(!args.contains(&"--range".to_string()));
+}
+
+#[test]
+fn test_rustscan_scanner_reports_failed_status() {
+    let (scanner, _) = fake_scanner(Ok(CommandOutput {
+        code: Some(1),
+        stdout: "partial".to_string(),
+        stderr: "boom".to_string(),
+    }));
+    let err = scanner.scan(&ScanTarget::default()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "rustscan command failed with status: exit status: 1.\nStdout: partial\nStderr: boom"
+    );
+}
+
+#[test]
+fn test_judge_rustscan_run() {
+    let judge = |code: Option<i32>, stdout: &str, stderr: &str| {
+        judge_rustscan_run(&CommandOutput {
+            code,
+            stdout: stdout.to_string(),
+            stderr: stderr.to_string(),
+        })
+    };
+    assert_eq!(judge(Some(0), "22\n8080\n", ""), RustScanRun::Complete);
+    assert_eq!(judge(Some(0), "", ""), RustScanRun::Complete);
+    assert_eq!(
+        judge(Some(1), "22\n8080\n", "error: eth1: no such device"),
+        RustScanRun::Partial { reported: 2 }
+    );
+    assert_eq!(
+        judge(None, "Open 127.0.0.1:5432\n", ""),
+        RustScanRun::Partial { reported: 1 }
+    );
+    assert_eq!(judge(Some(1), "partial", "boom"), RustScanRun::Failed);
+    assert_eq!(judge(Some(101), "", ""), RustScanRun::Failed);
+    assert_eq!(
+        judge(
+            Some(2),
+            "",
+            "error: unexpected argument '--accessible' found"
+        ),
+        RustScanRun::RejectedArguments
+    );
+}
+
+#[test]
+fn test_rustscan_scanner_keeps_ports_from_failed_run() {
+    let reporter = Arc::new(Reporter::silent());
+    let (scanner, _) = fake_scanner_reporting(
+        "rustscan 2.4.1\n",
+        Ok(CommandOutput {
+            code: Some(1),
+            stdout: "22\n8080\n".to_string(),
+            stderr: "error: eth1: no such device\n".to_string(),
+        }),
+        Arc::clone(&reporter),
+    );
+    let ports = scanner.scan(&ScanTarget::default()).unwrap();
+    assert_eq!(ports, [22, 8080].into_iter().collect());
+    let warnings = reporter.warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].code, WarningCode::PartialScan);
+    assert_eq!(
+        warnings[0].message,
+        "RustScan failed with exit status: 1 after reporting 2 open ports; using them, but local port data may be incomplete. Stderr: error: eth1: no such device"
+    );
+}
+
+#[test]
+fn test_rustscan_scanner_reports_missing_binary() {
+    let (scanner, _) = fake_scanner(Err(std::io::ErrorKind::NotFound));
+    let err = scanner.scan(&ScanTarget::default()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Failed to execute rustscan command. Make sure rustscan is installed and in PATH."
+    );
+}
+
+#[test]
+fn test_rustscan_scanner_with_program_override() {
+    let (scanner, calls) = fake_scanner(Ok(CommandOutput {
+        code: Some(0),
+        ..CommandOutput::default()
+    }));
+    let scanner = scanner.with_program("/opt/bin/rustscan");
+    scanner.scan(&ScanTarget::default()).unwrap();
+    assert_eq!(calls.lock().unwrap()[0].0, "/opt/bin/rustscan");
+}
+
+const MASSCAN_LIST: &str = include_str!("fixtures/masscan_list.txt");
+
+fn fake_masscan_scanner(
+    result: Result<CommandOutput, std::io::ErrorKind>,
+) -> (MasscanScanner, CallLog) {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let runner = FakeRunner {
+        result,
+        calls: Arc::clone(&calls),
+    };
+    (
+        MasscanScanner::with_runner(Box::new(runner), Arc::new(Reporter::silent())),
+        calls,
+    )
+}
+
+#[test]
+fn test_parse_masscan_output_fixture() {
+    // The banner, UDP, closed, and out-of-range lines don't count.
+    assert_eq!(
+        parse_masscan_output(MASSCAN_LIST),
+        [22, 5432, 8080].into_iter().collect()
+    );
+    assert!(parse_masscan_output("#masscan\n# end\n").is_empty());
+}
+
+#[test]
+fn test_masscan_scanner_runs_list_output_scan() {
+    let (scanner, calls) = fake_masscan_scanner(Ok(CommandOutput {
+        code: Some(0),
+        stdout: MASSCAN_LIST.to_string(),
+        stderr: String::new(),
+    }));
+    let ports = scanner.scan(&ScanTarget::new("10.0.0.5")).unwrap();
+    assert_eq!(ports.len(), 3);
+
+    let calls = calls.lock().unwrap();
+    assert_eq!(calls[0].0, "masscan");
+    let rate = DEFAULT_MASSCAN_RATE.to_string();
+    assert_eq!(
+        calls[0].1,
+        ["-p1-65535", "10.0.0.5", "--rate", &rate, "-oL", "-"]
+    );
+}
+
+#[test]
+fn test_masscan_scanner_rate_and_target_ports() {
+    let (scanner, calls) = fake_masscan_scanner(Ok(CommandOutput {
+        code: Some(0),
+        stdout: MASSCAN_LIST.to_string(),
+        stderr: String::new(),
+    }));
+    let target = ScanTarget::default().with_ports(vec![22, 8080, 8081]);
+    let ports = scanner.with_rate(250).scan(&target).unwrap();
+    assert_eq!(ports, [22, 8080].into_iter().collect());
+    assert_eq!(
+        calls.lock().unwrap()[0].1,
+        ["-p22,8080,8081", "127.0.0.1", "--rate", "250", "-oL", "-"]
+    );
+}
+
+#[test]
+fn test_masscan_scanner_distinguishes_missing_privileges() {
+    let (scanner, _) = fake_masscan_scanner(Ok(CommandOutput {
+        code: Some(1),
+        stdout: String::new(),
+        stderr: "FAIL: permission denied\n [hint] need to sudo or run as root or something\n"
+            .to_string(),
+    }));
+    let err = scanner.scan(&ScanTarget::default()).unwrap_err();
+    let needs = err.downcast_ref::<ScannerNeedsPrivileges>().unwrap();
+    assert_eq!(needs.scanner, "masscan");
+    assert!(
+        err.to_string()
+            .starts_with("masscan needs root or the CAP_NET_RAW capability"),
+        "{}",
+        err
+    );
+
+    // Other failures are ordinary errors.
+    let (scanner, _) = fake_masscan_scanner(Ok(CommandOutput {
+        code: Some(1),
+        stdout: String::new(),
+        stderr: "FAIL: unknown adapter\n".to_string(),
+    }));
+    let err = scanner.scan(&ScanTarget::default()).unwrap_err();
+    assert!(err.downcast_ref::<ScannerNeedsPrivileges>().is_none());
+    assert!(err.to_string().contains("unknown adapter"), "{}", err);
+}
+
+#[test]
+fn test_masscan_scanner_checks_privileges_before_running() {
+    let (scanner, calls) = fake_masscan_scanner(Ok(CommandOutput::default()));
+    let scanner = scanner.with_privileges(Privileges::from_status(
+        Some(1000),
+        "CapEff:\t0000000000000400\n",
+    ));
+    let err = scanner.scan(&ScanTarget::default()).unwrap_err();
+    assert!(err.downcast_ref::<ScannerNeedsPrivileges>().is_some());
+    assert_eq!(
+        err.to_string(),
+        "masscan needs root or the CAP_NET_RAW capability to send raw packets: this process has neither (euid 1000, CAP_NET_BIND_SERVICE, no CAP_NET_RAW)"
+    );
+    assert!(calls.lock().unwrap().is_empty());
+
+    let (scanner, calls) = fake_masscan_scanner(Ok(CommandOutput {
+        code: Some(0),
+        stdout: MASSCAN_LIST.to_string(),
+        stderr: String::new(),
+    }));
+    let scanner = scanner.with_privileges(Privileges::from_status(
+        Some(1000),
+        "CapEff:\t0000000000002000\n",
+    ));
+    assert!(scanner.scan(&ScanTarget::default()).is_ok());
+    assert_eq!(calls.lock().unwrap().len(), 1);
+}
+
+const NMAP_GREPABLE: &str = include_str!("fixtures/nmap_grepable.txt");
+const NMAP_GREPABLE_HOSTS: &str = include_str!("fixtures/nmap_grepable_hosts.txt");
+
+fn fake_nmap_scanner(result: Result<CommandOutput, std::io::ErrorKind>) -> (NmapScanner, CallLog) {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let runner = FakeRunner {
+        result,
+        calls: Arc::clone(&calls),
+    };
+    (
+        NmapScanner::with_runner(Box::new(runner), Arc::new(Reporter::silent())),
+        calls,
+    )
+}
+
+#[test]
+fn test_parse_nmap_grepable_fixtures() {
+    assert_eq!(
+        parse_nmap_grepable(NMAP_GREPABLE),
+        [22, 631, 5432, 8080].into_iter().collect()
+    );
+    // Every host's ports line counts; UDP and filtered entries don't.
+    assert_eq!(
+        parse_nmap_grepable(NMAP_GREPABLE_HOSTS),
+        [80, 443, 2049].into_iter().collect()
+    );
+    assert!(parse_nmap_grepable("Host: 10.0.0.9 ()\tStatus: Up\n").is_empty());
+}
+
+#[test]
+fn test_nmap_scanner_runs_connect_scan() {
+    let (scanner, calls) = fake_nmap_scanner(Ok(CommandOutput {
+        code: Some(0),
+        stdout: NMAP_GREPABLE.to_string(),
+        stderr: String::new(),
+    }));
+    let ports = scanner.scan(&ScanTarget::default()).unwrap();
+    assert_eq!(ports.len(), 4);
+    let calls = calls.lock().unwrap();
+    assert_eq!(calls[0].0, "nmap");
+    assert_eq!(
+        calls[0].1,
+        ["-p-", "-sT", "--open", "-oG", "-", "127.0.0.1"]
+    );
+}
+
+#[test]
+fn test_nmap_scanner_passes_host_timeout_and_target_ports() {
+    let (scanner, calls) = fake_nmap_scanner(Ok(CommandOutput {
+        code: Some(0),
+        stdout: NMAP_GREPABLE.to_string(),
+        stderr: String::new(),
+    }));
+    let target = ScanTarget::new("db.internal").with_ports(vec![22, 23]);
+    let ports = scanner
+        .with_host_timeout(Duration::from_secs(30))
+        .scan(&target)
+        .unwrap();
+    assert_eq!(ports, [22].into_iter().collect());
+    assert_eq!(
+        calls.lock().unwrap()[0].1,
+        [
+            "-p22,23",
+            "-sT",
+            "--open",
+            "-oG",
+            "-",
+            "--host-timeout",
+            "30000ms",
+            "db.internal"
+        ]
+    );
+}
+
+#[test]
+fn test_nmap_scanner_reports_host_timeout() {
+    let (scanner, _) = fake_nmap_scanner(Ok(CommandOutput {
+        code: Some(0),
+        stdout: "Host: 10.0.0.9 ()\tStatus: Timeout\n".to_string(),
+        stderr: String::new(),
+    }));
+    let err = scanner
+        .with_host_timeout(Duration::from_secs(5))
+        .scan(&ScanTarget::new("10.0.0.9"))
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "nmap gave up on 10.0.0.9 after the host timeout of 5.0s"
+    );
+}
+
+#[test]
+fn test_fallback_scanner_moves_past_unavailable_scanners() {
+    let reporter = Arc::new(Reporter::silent());
+    let (missing, _) = fake_scanner(Err(std::io::ErrorKind::NotFound));
+    let (unprivileged, _) = fake_masscan_scanner(Ok(CommandOutput {
+        code: Some(1),
+        stderr: "FAIL: permission denied\n".to_string(),
+        ..CommandOutput::default()
+    }));
+    let (nmap, nmap_calls) = fake_nmap_scanner(Ok(CommandOutput {
+        code: Some(0),
+        stdout: NMAP_GREPABLE.to_string(),
+        stderr: String::new(),
+    }));
+    let chain = FallbackScanner::new(Arc::clone(&reporter))
+        .then("rustscan", Box::new(missing))
+        .then("masscan", Box::new(unprivileged))
+        .then("nmap", Box::new(nmap));
+    assert_eq!(chain.scan(&ScanTarget::default()).unwrap().len(), 4);
+    assert_eq!(nmap_calls.lock().unwrap().len(), 1);
+
+    // A scanner that runs and fails ends the chain.
+    let (broken, _) = fake_scanner(Ok(CommandOutput {
+        code: Some(1),
+        stderr: "boom".to_string(),
+        ..CommandOutput::default()
+    }));
+    let (nmap, nmap_calls) = fake_nmap_scanner(Ok(CommandOutput::default()));
+    let chain = FallbackScanner::new(Arc::clone(&reporter))
+        .then("rustscan", Box::new(broken))
+        .then("nmap", Box::new(nmap));
+    let err = chain.scan(&ScanTarget::default()).unwrap_err();
+    assert!(err.to_string().contains("boom"), "{}", err);
+    assert!(nmap_calls.lock().unwrap().is_empty());
+
+    let (missing, _) = fake_scanner(Err(std::io::ErrorKind::NotFound));
+    let (missing_nmap, _) = fake_nmap_scanner(Err(std::io::ErrorKind::NotFound));
+    let chain = FallbackScanner::new(reporter)
+        .then("rustscan", Box::new(missing))
+        .then("nmap", Box::new(missing_nmap));
+    assert_eq!(
+        chain.scan(&ScanTarget::default()).unwrap_err().to_string(),
+        "No port scanner could run (tried rustscan, nmap); install one of them"
+    );
+}
+
+fn fake_container_scanner(
+    result: Result<CommandOutput, std::io::ErrorKind>,
+) -> (ContainerScanner, CallLog) {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let runner = FakeRunner {
+        result,
+        calls: Arc::clone(&calls),
+    };
+    (
+        ContainerScanner::with_runner("db-1", Box::new(runner), Arc::new(Reporter::silent())),
+        calls,
+    )
+}
+
+const PROC_NET_TCP: &str = include_str!("fixtures/proc_net_tcp");
+
+#[test]
+fn test_parse_proc_net_tcp_listening_only() {
+    let mut ports: Vec<u16> = parse_proc_net_tcp(PROC_NET_TCP).into_iter().collect();
+    ports.sort_unstable();
+    // 8080 and 5432 listen over IPv4, 6379 over IPv6; the established 5432 row and the
+    // TIME_WAIT 50000 row are not listeners.
+    assert_eq!(ports, vec![5432, 6379, 8080]);
+}
+
+#[test]
+fn test_parse_proc_net_tcp_skips_malformed_rows() {
+    let ports =
+        parse_proc_net_tcp("garbage\n 0: 0100007F:ZZZZ 00000000:0000 0A\n 1: nocolon x 0A\n");
+    assert!(ports.is_empty());
+}
+
+#[test]
+fn test_container_scanner_runs_docker_exec() {
+    let (scanner, calls) = fake_container_scanner(Ok(CommandOutput {
+        code: Some(0),
+        stdout: PROC_NET_TCP.to_string(),
+        stderr: String::new(),
+    }));
+    let ports = scanner.scan(&ScanTarget::default()).unwrap();
+    assert_eq!(ports.len(), 3);
+
+    let calls = calls.lock().unwrap();
+    assert_eq!(calls[0].0, "docker");
+    assert_eq!(
+        calls[0].1,
+        ["exec", "db-1", "cat", "/proc/net/tcp", "/proc/net/tcp6"]
+    );
+}
+
+#[test]
+fn test_container_scanner_tolerates_missing_tcp6() {
+    let ipv4_only: String = PROC_NET_TCP.lines().take(4).collect::<Vec<_>>().join("\n");
+    let (scanner, _) = fake_container_scanner(Ok(CommandOutput {
+        code: Some(1),
+        stdout: ipv4_only,
+        stderr: "cat: /proc/net/tcp6: No such file or directory".to_string(),
+    }));
+    let ports = scanner.scan(&ScanTarget::default()).unwrap();
+    assert_eq!(ports.len(), 2);
+}
+
+#[test]
+fn test_container_scanner_reports_missing_container() {
+    let (scanner, _) = fake_container_scanner(Ok(CommandOutput {
+        code: Some(1),
+        stdout: String::new(),
+        stderr: "Error response from daemon: No such container: db-1".to_string(),
+    }));
+    let error = scanner
+        .scan(&ScanTarget::default())
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("container 'db-1'"), "{}", error);
+    assert!(error.contains("No such container"), "{}", error);
+}
+
+#[test]
+fn test_container_scanner_reports_missing_docker() {
+    let (scanner, _) = fake_container_scanner(Err(std::io::ErrorKind::NotFound));
+    let error = scanner
+        .scan(&ScanTarget::default())
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.contains("Failed to execute docker command"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_is_wsl_detection() {
+    let wsl2 = "Linux version 5.15.153.1-microsoft-standard-WSL2 (root@1234) (gcc (GCC) 11.2.0)";
+    let wsl1 = "Linux version 4.4.0-19041-Microsoft (Microsoft@Microsoft.com) (gcc version 5.4.0)";
+    let native =
+        "Linux version 6.8.0-45-generic (buildd@lcy02-amd64-075) (x86_64-linux-gnu-gcc-13)";
+    assert!(is_wsl(Some(wsl2), None));
+    assert!(is_wsl(Some(wsl1), None));
+    assert!(!is_wsl(Some(native), None));
+    assert!(!is_wsl(None, None));
+    assert!(is_wsl(Some(native), Some("Ubuntu-22.04")));
+    assert!(!is_wsl(Some(native), Some("")));
+}
+
+#[test]
+fn test_detect_ci() {
+    let env = |pairs: &'static [(&'static str, &'static str)]| {
+        move |name: &str| {
+            pairs
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        }
+    };
+    assert_eq!(detect_ci(env(&[])), None);
+    assert_eq!(detect_ci(env(&[("CI", "true")])), Some("CI"));
+    assert_eq!(detect_ci(env(&[("CI", "1")])), Some("CI"));
+    assert_eq!(detect_ci(env(&[("CI", "false")])), None);
+    assert_eq!(detect_ci(env(&[("CI", "")])), None);
+    assert_eq!(
+        detect_ci(env(&[("CI", "true"), ("GITHUB_ACTIONS", "true")])),
+        Some("GITHUB_ACTIONS")
+    );
+    assert_eq!(detect_ci(env(&[("GITLAB_CI", "yes")])), Some("GITLAB_CI"));
+    assert_eq!(detect_ci(env(&[("BUILDKITE", "true")])), Some("BUILDKITE"));
+}
+
+#[test]
+fn test_parse_netstat_listening_windows_fixture() {
+    let output = include_str!("fixtures/netstat_windows.txt");
+    let mut ports: Vec<u16> = parse_netstat_listening(output).into_iter().collect();
+    ports.sort_unstable();
+    assert_eq!(ports, vec![135, 445, 3000, 9229]);
+}
+
+#[test]
+fn test_parse_excluded_port_ranges_windows_fixture() {
+    let output = include_str!("fixtures/netsh_excludedportrange.txt");
+    assert_eq!(
+        parse_excluded_port_ranges(output),
+        vec![(5357, 5357), (50000, 50059)]
+    );
+}
+
+/// Answers netstat.exe and netsh.exe with canned results.
+struct WindowsFakeRunner {
+    netsh: Option<&'static str>,
+}
+
+impl CommandRunner for WindowsFakeRunner {
+    fn run(&self, program: &str, _args: &[String]) -> std::io::Result<CommandOutput> {
+        let stdout = match program {
+            "netstat.exe" => include_str!("fixtures/netstat_windows.txt"),
+            "netsh.exe" => self.netsh.ok_or(std::io::ErrorKind::NotFound)?,
+            _ => return Err(std::io::ErrorKind::NotFound.into()),
+        };
+        Ok(CommandOutput {
+            code: Some(0),
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+        })
+    }
+}
+
+#[test]
+fn test_windows_host_scanner_unions_listeners_and_exclusions() {
+    let runner = WindowsFakeRunner {
+        netsh: Some(include_str!("fixtures/netsh_excludedportrange.txt")),
+    };
+    let scanner = WindowsHostScanner::with_runner(Box::new(runner), Arc::new(Reporter::silent()));
+    let ports = scanner.scan(&ScanTarget::default()).unwrap();
+    assert_eq!(ports.len(), 4 + 1 + 60);
+    assert!(ports.contains(&9229));
+    assert!(ports.contains(&5357));
+    assert!(ports.contains(&50059));
+}
+
+#[test]
+fn test_windows_host_scanner_degrades_without_netsh() {
+    let scanner = WindowsHostScanner::with_runner(
+        Box::new(WindowsFakeRunner { netsh: None }),
+        Arc::new(Reporter::silent()),
+    );
+    assert_eq!(scanner.scan(&ScanTarget::default()).unwrap().len(), 4);
+}
+
+#[test]
+fn test_windows_host_scanner_fails_without_interop() {
+    let runner = FakeRunner {
+        result: Err(std::io::ErrorKind::PermissionDenied),
+        calls: Arc::new(Mutex::new(Vec::new())),
+    };
+    let scanner = WindowsHostScanner::with_runner(Box::new(runner), Arc::new(Reporter::silent()));
+    let error = scanner
+        .scan(&ScanTarget::default())
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("interop"), "{}", error);
+}
+
+#[test]
+fn test_ss_scanner_filters_by_bind_address() {
+    let calls: CallLog = Arc::new(Mutex::new(Vec::new()));
+    let runner = FakeRunner {
+        result: Ok(CommandOutput {
+            code: Some(0),
+            stdout: include_str!("fixtures/ss_listening.txt").to_string(),
+            stderr: String::new(),
+        }),
+        calls: Arc::clone(&calls),
+    };
+    let scanner = SsScanner::with_runner(Box::new(runner), Arc::new(Reporter::silent()))
+        .with_bind_address("127.0.0.1".parse().unwrap());
+    let mut ports: Vec<u16> = scanner
+        .scan(&ScanTarget::default())
+        .unwrap()
+        .into_iter()
+        .collect();
+    ports.sort_unstable();
+    assert_eq!(ports, vec![22, 631, 5432]);
+    assert_eq!(
+        calls.lock().unwrap()[0],
+        ("ss".to_string(), vec!["-Hlnt".to_string()])
+    );
+}
+
+/// Answers each program with its own canned output; unknown programs are missing.
+struct ProgramRunner(HashMap<&'static str, CommandOutput>);
+
+impl CommandRunner for ProgramRunner {
+    fn run(&self, program: &str, _args: &[String]) -> std::io::Result<CommandOutput> {
+        self.0
+            .get(program)
+            .cloned()
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))
+    }
+}
+
+fn output(code: i32, stdout: &str) -> CommandOutput {
+    CommandOutput {
+        code: Some(code),
+        stdout: stdout.to_string(),
+        stderr: String::new(),
+    }
+}
+
+#[test]
+fn test_listening_processes_prefers_ss_and_falls_back_to_lsof() {
+    let ss = ProgramRunner(HashMap::from([(
+        "ss",
+        output(0, include_str!("fixtures/ss_listening_processes.txt")),
+    )]));
+    let processes = listening_processes(&ss, None).unwrap();
+    let mut ports: Vec<u16> = processes.keys().copied().collect();
+    ports.sort_unstable();
+    assert_eq!(ports, vec![5432, 6379, 8080]);
+    assert_eq!(processes[&8080].pid, Some(812));
+
+    let lsof = ProgramRunner(HashMap::from([
+        ("ss", output(1, "")),
+        (
+            "lsof",
+            output(0, include_str!("fixtures/lsof_listening.txt")),
+        ),
+    ]));
+    assert_eq!(
+        listening_processes(&lsof, None).unwrap()[&9000].program,
+        "My App"
+    );
+
+    // lsof exits with 1 when nothing listens.
+    let nothing = ProgramRunner(HashMap::from([("lsof", output(1, ""))]));
+    assert!(listening_processes(&nothing, None).unwrap().is_empty());
+
+    let error = listening_processes(&ProgramRunner(HashMap::new()), None)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("ss") && error.contains("lsof"), "{}", error);
+}
+
+struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
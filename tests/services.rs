@@ -0,0 +1,140 @@
+use portpick::{
+    Protocol, Reporter, decode_services_text, parse_service_frequencies, parse_services_content,
+    parse_services_map, ports_above_frequency, validate_services_content,
+};
+use std::collections::HashSet;
+
+const CONTENT: &str = "\
+# /etc/services excerpt
+http\t\t80/tcp\t\twww www-http\t# WorldWideWeb HTTP
+http\t\t80/udp\t\twww
+http-alt\t8080/tcp\twebcache
+tproxy\t\t8080/tcp\t\t\t# transparent proxy
+http-alt\t8080/tcp\t# listed twice
+domain\t\t53/udp
+unknown\t\t9999/tcp
+";
+
+#[test]
+fn test_parse_services_map_collects_aliases_and_duplicates() {
+    let tcp = parse_services_map(CONTENT, Protocol::Tcp, false).unwrap();
+    assert_eq!(tcp.len(), 2);
+    assert_eq!(tcp[&80], vec!["http", "www", "www-http"]);
+    // Different names for the same port are kept in file order, repeats only once.
+    assert_eq!(tcp[&8080], vec!["http-alt", "webcache", "tproxy"]);
+}
+
+#[test]
+fn test_parse_services_map_ignores_inline_comments() {
+    let tcp = parse_services_map(CONTENT, Protocol::Tcp, false).unwrap();
+    assert!(tcp.values().flatten().all(|name| !name.contains('#')));
+    assert!(!tcp[&80].iter().any(|name| name == "WorldWideWeb"));
+}
+
+#[test]
+fn test_parse_services_map_reads_crlf_lines() {
+    let crlf = CONTENT.replace('\n', "\r\n");
+    assert_eq!(
+        parse_services_map(&crlf, Protocol::Tcp, false).unwrap(),
+        parse_services_map(CONTENT, Protocol::Tcp, false).unwrap()
+    );
+    // Stray carriage returns at the end of a line are dropped too.
+    let tcp = parse_services_map("ssh 22/tcp\r\r\n", Protocol::Tcp, false).unwrap();
+    assert_eq!(tcp[&22], vec!["ssh"]);
+}
+
+#[test]
+fn test_parse_services_map_skips_byte_order_mark() {
+    let tcp = parse_services_map("\u{feff}ssh\t22/tcp\n", Protocol::Tcp, false).unwrap();
+    assert_eq!(tcp[&22], vec!["ssh"]);
+
+    let (text, lossy) = decode_services_text(b"\xEF\xBB\xBFssh\t22/tcp\r\n");
+    assert_eq!(text, "ssh\t22/tcp\r\n");
+    assert!(!lossy);
+}
+
+#[test]
+fn test_decode_services_text_replaces_latin1_bytes() {
+    // "# Caf\xe9" is a Latin-1 comment, not UTF-8.
+    let (text, lossy) = decode_services_text(b"# Caf\xe9 services\nhttp\t80/tcp\n");
+    assert!(lossy);
+    assert!(text.starts_with("# Caf\u{fffd} services"));
+    let tcp = parse_services_map(&text, Protocol::Tcp, false).unwrap();
+    assert_eq!(tcp[&80], vec!["http"]);
+}
+
+#[test]
+fn test_parse_services_map_by_protocol() {
+    let udp = parse_services_map(CONTENT, Protocol::Udp, false).unwrap();
+    assert_eq!(udp[&80], vec!["http", "www"]);
+    assert_eq!(udp[&53], vec!["domain"]);
+    assert!(!udp.contains_key(&8080));
+}
+
+#[test]
+fn test_parse_services_map_unknown_entries() {
+    let without = parse_services_map(CONTENT, Protocol::Tcp, false).unwrap();
+    assert!(!without.contains_key(&9999));
+    let with = parse_services_map(CONTENT, Protocol::Tcp, true).unwrap();
+    assert_eq!(with[&9999], vec!["unknown"]);
+}
+
+#[test]
+fn test_parse_services_content_is_the_map_key_set() {
+    for include_unknown in [false, true] {
+        let ports =
+            parse_services_content(CONTENT, "test", &Reporter::silent(), include_unknown).unwrap();
+        let keys: HashSet<u16> = parse_services_map(CONTENT, Protocol::Tcp, include_unknown)
+            .unwrap()
+            .into_keys()
+            .collect();
+        assert_eq!(ports, keys);
+    }
+}
+
+const NMAP: &str = include_str!("fixtures/nmap-services-frequencies");
+
+#[test]
+fn test_parse_service_frequencies() {
+    let tcp = parse_service_frequencies(NMAP, Protocol::Tcp);
+    assert_eq!(tcp.len(), 7);
+    assert_eq!(tcp[&80], 0.484143);
+    // A port listed twice keeps its highest frequency.
+    assert_eq!(tcp[&8081], 0.0006);
+    assert_eq!(
+        parse_service_frequencies(NMAP, Protocol::Udp)[&80],
+        0.035767
+    );
+    // /etc/services has no frequencies.
+    assert!(parse_service_frequencies(CONTENT, Protocol::Tcp).is_empty());
+}
+
+#[test]
+fn test_frequency_is_not_an_alias() {
+    let tcp = parse_services_map(NMAP, Protocol::Tcp, false).unwrap();
+    assert_eq!(tcp[&5432], vec!["postgresql"]);
+    assert_eq!(tcp[&8081], vec!["blackice-icecap"]);
+}
+
+#[test]
+fn test_ports_above_frequency() {
+    let frequencies = parse_service_frequencies(NMAP, Protocol::Tcp);
+    let above: HashSet<u16> = ports_above_frequency(&frequencies, 0.0005);
+    // 8082 sits exactly on the threshold, 8888 and 40000 below it; unnamed 8081 counts.
+    assert_eq!(above, [80, 5432, 8080, 8081].into_iter().collect());
+    assert!(ports_above_frequency(&frequencies, 1.0).is_empty());
+    assert_eq!(ports_above_frequency(&frequencies, 0.0).len(), 7);
+    // Ports nmap doesn't list count as frequency 0.
+    assert!(!ports_above_frequency(&frequencies, 0.0).contains(&12345));
+}
+
+#[test]
+fn test_validate_services_content() {
+    assert_eq!(
+        validate_services_content(b"http 80/tcp\nhttps 443/tcp\n").unwrap(),
+        2
+    );
+    assert!(validate_services_content(b"\x00\x01\x02binary").is_err());
+    assert!(validate_services_content(b"<html><body>Sign in</body></html>").is_err());
+    assert!(validate_services_content(b"").is_err());
+}
@@ -0,0 +1,91 @@
+use portpick::{FileStamp, ParsedServices, Reporter, ServicesCache};
+use std::path::{Path, PathBuf};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "portpick-servicescache-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn parse(path: &Path) -> ParsedServices {
+    let content = std::fs::read_to_string(path).unwrap();
+    ParsedServices::parse(&content, "test services", &Reporter::silent(), false).unwrap()
+}
+
+/// Parses `path` and stores the result, as a first run would.
+fn parse_and_store(cache: &ServicesCache, path: &Path) -> ParsedServices {
+    let stamp = FileStamp::of(path).unwrap();
+    let parsed = parse(path);
+    cache.store(path, false, stamp, &parsed).unwrap();
+    parsed
+}
+
+#[test]
+fn test_services_cache_hit_while_file_is_unchanged() {
+    let dir = temp_dir("hit");
+    let services = dir.join("services");
+    std::fs::write(
+        &services,
+        "http 80/tcp www\nhttps 443/tcp\nunknown 9999/tcp\n",
+    )
+    .unwrap();
+    let cache = ServicesCache::new(dir.join("state"));
+    assert_eq!(cache.load(&services, false).unwrap(), None);
+
+    let parsed = parse_and_store(&cache, &services);
+    assert_eq!(parsed.ports, [80, 443].into_iter().collect());
+    assert_eq!(parsed.names[&80], vec!["http", "www"]);
+    assert_eq!(cache.load(&services, false).unwrap(), Some(parsed));
+    // Counting `unknown` entries is a different parse.
+    assert_eq!(cache.load(&services, true).unwrap(), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_services_cache_misses_after_the_file_changes() {
+    let dir = temp_dir("modified");
+    let services = dir.join("services");
+    std::fs::write(&services, "http 80/tcp\n").unwrap();
+    let cache = ServicesCache::new(dir.join("state"));
+    parse_and_store(&cache, &services);
+
+    // A longer file, so the stamp differs even where mtimes are coarse.
+    std::fs::write(&services, "http 80/tcp\npostgresql 5432/tcp\n").unwrap();
+    assert_eq!(cache.load(&services, false).unwrap(), None);
+    let reparsed = parse_and_store(&cache, &services);
+    assert_eq!(reparsed.ports, [80, 5432].into_iter().collect());
+    assert_eq!(cache.load(&services, false).unwrap(), Some(reparsed));
+
+    std::fs::remove_file(&services).unwrap();
+    assert_eq!(cache.load(&services, false).unwrap(), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_services_cache_recovers_from_a_corrupted_entry() {
+    let dir = temp_dir("corrupt");
+    let services = dir.join("services");
+    std::fs::write(&services, "ssh 22/tcp\n").unwrap();
+    let state = dir.join("state");
+    let cache = ServicesCache::new(&state);
+    parse_and_store(&cache, &services);
+
+    for entry in std::fs::read_dir(&state).unwrap() {
+        std::fs::write(entry.unwrap().path(), "{\"path\": truncated").unwrap();
+    }
+    let err = cache.load(&services, false).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("Failed to parse services cache"),
+        "{:#}",
+        err
+    );
+    // Storing a fresh parse replaces the broken entry.
+    let parsed = parse_and_store(&cache, &services);
+    assert_eq!(cache.load(&services, false).unwrap(), Some(parsed));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,48 @@
+use portpick::{
+    PortSet, SESSION_FORBID_ENV, parse_session_forbid, session_export_line, session_forbid_value,
+};
+
+#[test]
+fn test_parse_session_forbid() {
+    assert!(parse_session_forbid("").unwrap().is_empty());
+    assert!(parse_session_forbid("  ").unwrap().is_empty());
+    let session = parse_session_forbid("8000-8002,9000").unwrap();
+    assert_eq!(
+        session.iter().collect::<Vec<_>>(),
+        vec![8000, 8001, 8002, 9000]
+    );
+    assert!(parse_session_forbid("8000,web").is_err());
+    assert!(parse_session_forbid("9000-8000").is_err());
+}
+
+#[test]
+fn test_session_forbid_value_coalesces_runs() {
+    let session = parse_session_forbid("8000-8001,9000").unwrap();
+    assert_eq!(
+        session_forbid_value(&session, [8002, 8500, 8999]),
+        "8000-8002,8500,8999-9000"
+    );
+    assert_eq!(session_forbid_value(&PortSet::new(), [3000]), "3000");
+    assert_eq!(session_forbid_value(&PortSet::new(), []), "");
+    // Picking a port already listed changes nothing.
+    assert_eq!(session_forbid_value(&session, [8001]), "8000-8001,9000");
+}
+
+#[test]
+fn test_session_export_line_format() {
+    assert_eq!(SESSION_FORBID_ENV, "PORTPICK_SESSION_FORBID");
+    assert_eq!(
+        session_export_line("8000-8002,9000"),
+        "export PORTPICK_SESSION_FORBID=8000-8002,9000"
+    );
+    assert_eq!(session_export_line(""), "export PORTPICK_SESSION_FORBID=");
+    // What a run exports is what the next one reads back.
+    let value = session_forbid_value(&PortSet::new(), [8080, 8081, 65535]);
+    assert_eq!(
+        parse_session_forbid(&value)
+            .unwrap()
+            .iter()
+            .collect::<Vec<_>>(),
+        vec![8080, 8081, 65535]
+    );
+}
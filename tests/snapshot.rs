@@ -0,0 +1,63 @@
+use portpick::{PortChange, PortSet, Provenance, RejectionReason, Snapshot};
+use std::collections::HashSet;
+
+use RejectionReason::{KnownService, LocallyInUse};
+
+fn snapshot(known: &[u16], local: &[u16]) -> Snapshot {
+    let mut provenance = Provenance::default();
+    provenance.record_all(known, KnownService);
+    provenance.record_all(local, LocallyInUse);
+    let forbidden: HashSet<u16> = known.iter().chain(local).copied().collect();
+    Snapshot::new(
+        &forbidden,
+        &provenance,
+        "host".to_string(),
+        Some("127.0.0.1".to_string()),
+        vec!["system".to_string(), "local".to_string()],
+        1_700_000_000,
+    )
+}
+
+fn change(port: u16, before: &[RejectionReason], after: &[RejectionReason]) -> PortChange {
+    PortChange {
+        port,
+        before: before.to_vec(),
+        after: after.to_vec(),
+    }
+}
+
+#[test]
+fn test_snapshot_records_provenance() {
+    let snapshot = snapshot(&[5432, 8080], &[8080, 9000]);
+    assert_eq!(snapshot.forbidden, [5432, 8080, 9000].into_iter().collect());
+    assert_eq!(snapshot.reasons(8080), vec![KnownService, LocallyInUse]);
+    assert_eq!(snapshot.reasons(9000), vec![LocallyInUse]);
+    assert!(snapshot.reasons(9001).is_empty());
+}
+
+#[test]
+fn test_diff_categories() {
+    let before = snapshot(&[5432, 8080], &[3000, 9000]);
+    let after = snapshot(&[5432, 8080], &[8080, 9000, 9001]);
+    let diff = before.diff(&after);
+    assert_eq!(diff.became_used, vec![change(9001, &[], &[LocallyInUse])]);
+    assert_eq!(diff.became_free, vec![change(3000, &[LocallyInUse], &[])]);
+    assert_eq!(
+        diff.changed,
+        vec![change(8080, &[KnownService], &[KnownService, LocallyInUse])]
+    );
+    assert!(before.diff(&before).is_empty());
+}
+
+#[test]
+fn test_snapshot_json_round_trip() {
+    let snapshot = snapshot(&[5432], &[40000, 40001]);
+    let json = serde_json::to_string(&snapshot).unwrap();
+    assert!(
+        json.contains("\"locally_in_use\":[[40000,40001]]"),
+        "{}",
+        json
+    );
+    assert_eq!(serde_json::from_str::<Snapshot>(&json).unwrap(), snapshot);
+    assert_eq!(snapshot.known_service, PortSet::from(vec![(5432, 5432)]));
+}
@@ -0,0 +1,77 @@
+use portpick::{bind_succeeds, verified_ports_in, verify_block, verify_candidates};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, TcpListener};
+use std::time::Duration;
+
+const LOOPBACK: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+/// A port the OS just handed out and released, so very likely still free.
+fn free_port() -> u16 {
+    TcpListener::bind((LOOPBACK, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn held_listener() -> (TcpListener, u16) {
+    let listener = TcpListener::bind((LOOPBACK, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    (listener, port)
+}
+
+#[test]
+fn test_verify_candidates_skips_bound_ports_in_order() {
+    let held: Vec<(TcpListener, u16)> = (0..4).map(|_| held_listener()).collect();
+    let free: Vec<u16> = (0..4).map(|_| free_port()).collect();
+    let candidates = [
+        free[0], held[0].1, free[1], held[1].1, held[2].1, free[2], held[3].1, free[3],
+    ];
+
+    let verified = verify_candidates(candidates, 3, |port| bind_succeeds(LOOPBACK, port));
+    assert_eq!(verified, vec![free[0], free[1], free[2]]);
+    drop(held);
+}
+
+#[test]
+fn test_verify_candidates_matches_sequential_order_under_concurrency() {
+    // Uneven probe times shuffle completion order between threads.
+    let probe = |port: u16| {
+        std::thread::sleep(Duration::from_micros(u64::from(port % 7) * 50));
+        !port.is_multiple_of(3)
+    };
+    let sequential: Vec<u16> = (1..200).filter(|&port| probe(port)).take(40).collect();
+    assert_eq!(verify_candidates(1..200, 40, probe), sequential);
+    // Running out of candidates returns what was confirmed.
+    assert_eq!(verify_candidates([3, 4, 6], 5, probe), vec![4]);
+}
+
+#[test]
+fn test_verify_block_rejects_blocks_with_any_failing_member() {
+    let no_forbidden = HashSet::new();
+    let probe = |port: u16| port != 1002 && port != 1009;
+    let ranges = [(1000, 1019)];
+
+    assert_eq!(
+        verify_block(&no_forbidden, 5, &ranges, probe),
+        Some((1003..=1007).collect())
+    );
+    assert_eq!(
+        verify_block(&no_forbidden, 7, &ranges, probe),
+        Some((1010..=1016).collect())
+    );
+    assert_eq!(verify_block(&no_forbidden, 11, &ranges, probe), None);
+}
+
+#[test]
+fn test_verified_ports_in_avoids_a_bound_port() {
+    let (listener, held) = held_listener();
+    let free = free_port();
+    let ranges = [(held, held), (free, free)];
+
+    let ports = verified_ports_in(&HashSet::new(), 1, false, &ranges, LOOPBACK).unwrap();
+    assert_eq!(ports, vec![free]);
+    let block = verified_ports_in(&HashSet::new(), 1, true, &ranges, LOOPBACK).unwrap();
+    assert_eq!(block, vec![free]);
+    drop(listener);
+}
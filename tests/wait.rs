@@ -0,0 +1,143 @@
+use portpick::{Clock, WaitQuorum, wait_for_ports};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// A clock that only moves when slept on.
+struct FakeClock {
+    now: Cell<Instant>,
+    sleeps: Cell<u32>,
+}
+
+impl FakeClock {
+    fn new() -> Self {
+        FakeClock {
+            now: Cell::new(Instant::now()),
+            sleeps: Cell::new(0),
+        }
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+        self.sleeps.set(self.sleeps.get() + 1);
+    }
+}
+
+/// A probe reporting each scheduled port ready from its (1-based) poll on.
+fn ready_from(schedule: &[(u16, u32)]) -> impl FnMut(&[u16]) -> HashSet<u16> + '_ {
+    let mut polls = 0;
+    move |ports| {
+        polls += 1;
+        ports
+            .iter()
+            .copied()
+            .filter(|port| {
+                schedule
+                    .iter()
+                    .any(|&(ready, from)| ready == *port && polls >= from)
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_ready_on_first_poll_does_not_sleep() {
+    let clock = FakeClock::new();
+    let outcome = wait_for_ports(
+        &[8080],
+        WaitQuorum::All,
+        Some(Duration::from_secs(60)),
+        Duration::from_secs(1),
+        &clock,
+        ready_from(&[(8080, 1)]),
+        |_| panic!("no progress expected"),
+    );
+    assert!(outcome.ready);
+    assert_eq!(outcome.polls, 1);
+    assert_eq!(outcome.elapsed, Duration::ZERO);
+    assert_eq!(clock.sleeps.get(), 0);
+}
+
+#[test]
+fn test_early_success_before_timeout() {
+    let clock = FakeClock::new();
+    let mut progress = 0;
+    let outcome = wait_for_ports(
+        &[8080, 8081],
+        WaitQuorum::All,
+        Some(Duration::from_secs(60)),
+        Duration::from_secs(1),
+        &clock,
+        ready_from(&[(8080, 2), (8081, 4)]),
+        |outcome| {
+            progress += 1;
+            assert!(!outcome.ready);
+        },
+    );
+    assert!(outcome.ready);
+    assert_eq!(outcome.polls, 4);
+    assert_eq!(progress, 3);
+    assert_eq!(outcome.elapsed, Duration::from_secs(3));
+    assert_eq!(outcome.ready_ports, vec![8080, 8081]);
+    assert!(outcome.pending.is_empty());
+}
+
+#[test]
+fn test_any_stops_at_the_first_ready_port() {
+    let clock = FakeClock::new();
+    let outcome = wait_for_ports(
+        &[8080, 8081],
+        WaitQuorum::Any,
+        None,
+        Duration::from_millis(250),
+        &clock,
+        ready_from(&[(8081, 3), (8080, 10)]),
+        |_| {},
+    );
+    assert!(outcome.ready);
+    assert_eq!(outcome.polls, 3);
+    assert_eq!(outcome.ready_ports, vec![8081]);
+    assert_eq!(outcome.pending, vec![8080]);
+}
+
+#[test]
+fn test_timeout_polls_once_more_at_the_deadline() {
+    let clock = FakeClock::new();
+    let outcome = wait_for_ports(
+        &[8080, 8081],
+        WaitQuorum::All,
+        Some(Duration::from_millis(2500)),
+        Duration::from_secs(1),
+        &clock,
+        ready_from(&[(8080, 1)]),
+        |_| {},
+    );
+    assert!(!outcome.ready);
+    // Polls at 0s, 1s, 2s, and a last one at the 2.5s deadline.
+    assert_eq!(outcome.polls, 4);
+    assert_eq!(outcome.elapsed, Duration::from_millis(2500));
+    assert_eq!(outcome.pending, vec![8081]);
+}
+
+#[test]
+fn test_zero_timeout_polls_once() {
+    let clock = FakeClock::new();
+    let outcome = wait_for_ports(
+        &[8080],
+        WaitQuorum::All,
+        Some(Duration::ZERO),
+        Duration::from_secs(1),
+        &clock,
+        ready_from(&[]),
+        |_| {},
+    );
+    assert!(!outcome.ready);
+    assert_eq!(outcome.polls, 1);
+    assert_eq!(clock.sleeps.get(), 0);
+}
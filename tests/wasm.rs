@@ -0,0 +1,47 @@
+#![cfg(feature = "wasm")]
+
+//! Runs natively with `cargo test --features wasm`, and in a browser or Node with
+//! `wasm-pack test --node --no-default-features --features wasm`.
+
+use portpick::wasm::{JsPortSet, find_available, parse_services};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[wasm_bindgen_test(unsupported = test)]
+fn test_wasm_parse_services() {
+    let content = "http 80/tcp www\nntp 123/udp\nunknown 9999/tcp\nhttps 443/tcp\n";
+    assert_eq!(parse_services(content, false).unwrap(), vec![80, 443]);
+    assert_eq!(parse_services(content, true).unwrap(), vec![80, 443, 9999]);
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn test_wasm_find_available() {
+    assert_eq!(
+        find_available(&[1024, 1026], 2, false, None).unwrap(),
+        vec![1025, 1027]
+    );
+    assert_eq!(
+        find_available(&[8001], 3, true, Some("8000-8010".to_string())).unwrap(),
+        vec![8002, 8003, 8004]
+    );
+    assert!(
+        find_available(&[], 3, true, Some("8000-8001".to_string()))
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn test_wasm_port_set_math() {
+    let policy = JsPortSet::from_spec("8000-8099").unwrap();
+    let taken = JsPortSet::new(&[8000, 8001, 8050, 9000]);
+    let free = policy.difference(&taken);
+    assert_eq!(free.size(), 97);
+    assert!(!free.contains(8050));
+    assert_eq!(free.ranges(), vec![8002, 8049, 8051, 8099]);
+    assert_eq!(policy.intersection(&taken).ports(), vec![8000, 8001, 8050]);
+    assert_eq!(taken.union(&policy).ranges(), vec![8000, 8099, 9000, 9000]);
+    assert_eq!(
+        taken.complement_within(7998, 8002).ports(),
+        vec![7998, 7999, 8002]
+    );
+}